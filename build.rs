@@ -0,0 +1,25 @@
+//! Records the git commit the program is built from, when it is built from a git checkout, so the results can name
+//! the revision of the built-in strategies (see `strategy::build_version`).
+
+use std::path::Path;
+use std::process::Command;
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    // a crate vendored inside another repository must not take its commit
+    if !Path::new(".git").exists() {
+        return;
+    }
+    let Some(hash) = git(&["rev-parse", "--short=12", "HEAD"]) else {
+        return;
+    };
+    let dirty = git(&["status", "--porcelain", "--untracked-files=no"]).is_some_and(|status| !status.is_empty());
+    println!("cargo:rustc-env=RETROVAL_GIT_HASH={}{}", hash, if dirty { "-dirty" } else { "" });
+}
@@ -0,0 +1,26 @@
+/* C API of the retroval engine, built as `libretroval` by `cargo build --release`. */
+#ifndef RETROVAL_H
+#define RETROVAL_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Called with every trade as soon as it is closed, as a JSON object. The string is only valid during the call. */
+typedef void (*retroval_trade_callback)(const char *trade_json, void *user_data);
+
+/* Runs a backtest from a config given as a JSON string, and returns the results as a JSON string:
+ * the metrics, benchmark comparison, acceptance verdict and trades, or `{"error": "..."}`.
+ * The returned string must be released with `retroval_free_string`. */
+char *retroval_run(const char *config_json);
+
+/* Like `retroval_run`, and streams every closed trade to `on_trade`, with `user_data` passed as-is. */
+char *retroval_run_with_callback(const char *config_json, retroval_trade_callback on_trade, void *user_data);
+
+void retroval_free_string(char *string);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,41 @@
+use serde::Serialize;
+use crate::config::Config;
+use crate::testing::Metrics;
+
+/// Outcome of the acceptance criteria of the config.
+#[derive(Debug, Serialize)]
+pub struct Verdict {
+    pub passed: bool,
+    /// One line per failed criterion.
+    pub failures: Vec<String>,
+}
+
+pub fn evaluate(metrics: &Metrics, config: &Config) -> Verdict {
+    let criteria = &config.acceptance;
+    let mut failures = Vec::new();
+    if let Some(min_sharpe) = criteria.min_sharpe {
+        if metrics.sharpe_ratio < min_sharpe {
+            failures.push(format!("Sharpe ratio {:.2} is below {:.2}", metrics.sharpe_ratio, min_sharpe));
+        }
+    }
+    if let Some(max_drawdown_pct) = criteria.max_drawdown_pct {
+        let drawdown_pct = metrics.max_drawdown.abs() / config.base_funds * 100.0;
+        if drawdown_pct > max_drawdown_pct {
+            failures.push(format!("Max drawdown {:.2}% is above {:.2}%", drawdown_pct, max_drawdown_pct));
+        }
+    }
+    if let Some(min_profit_factor) = criteria.min_profit_factor {
+        if metrics.profit_factor < min_profit_factor {
+            failures.push(format!("Profit factor {:.2} is below {:.2}", metrics.profit_factor, min_profit_factor));
+        }
+    }
+    if let Some(min_trades) = criteria.min_trades {
+        if metrics.total_trades < min_trades {
+            failures.push(format!("{} trades is below {}", metrics.total_trades, min_trades));
+        }
+    }
+    Verdict {
+        passed: failures.is_empty(),
+        failures,
+    }
+}
@@ -0,0 +1,959 @@
+use std::collections::BTreeMap;
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use serde::Serialize;
+use crate::acceptance::Verdict;
+use crate::historical::Kline;
+use crate::indicators::Atr;
+use crate::locale::Formatter;
+use crate::config::{Config, Objective, RollingMetric, RollingMetricKind, SignalQualityConfig, Sweep};
+use crate::testing::{Direction, Metrics, SessionRecap, StrategyContribution, SweepResult, Trade};
+
+/// Correlation of the per-bar returns of several equity curves.
+pub struct CorrelationMatrix {
+    pub labels: Vec<String>,
+    pub values: Vec<Vec<f64>>,
+}
+
+impl CorrelationMatrix {
+    /// Pairs whose correlation is at least `threshold` in absolute value.
+    pub fn correlated_pairs(&self, threshold: f64) -> Vec<(&str, &str, f64)> {
+        let mut pairs = Vec::new();
+        for i in 0..self.labels.len() {
+            for j in (i + 1)..self.labels.len() {
+                if self.values[i][j].abs() >= threshold {
+                    pairs.push((self.labels[i].as_str(), self.labels[j].as_str(), self.values[i][j]));
+                }
+            }
+        }
+        pairs
+    }
+
+    pub fn write_csv(&self, file_path: &str, format: &Formatter) -> Result<(), Box<dyn std::error::Error>> {
+        let mut writer = format.csv_writer(file_path)?;
+        writer.write_record(std::iter::once("").chain(self.labels.iter().map(|label| label.as_str())))?;
+        for (label, row) in self.labels.iter().zip(self.values.iter()) {
+            let row: Vec<String> = row.iter().map(|value| format.csv_fixed(*value, 4)).collect();
+            writer.write_record(std::iter::once(label.as_str()).chain(row.iter().map(|value| value.as_str())))?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// One line of the summary table of a multi-strategy run.
+pub struct SummaryRow {
+    pub rank: usize,
+    pub name: String,
+    pub initial_weight: f64,
+    pub final_weight: f64,
+    pub trades: usize,
+    pub profit: f64,
+    pub return_pct: f64,
+    /// Profit in percentage of the highest equity.
+    pub peak_return_pct: f64,
+    pub win_rate: f64,
+    pub profit_factor: f64,
+    pub sharpe_ratio: f64,
+    pub max_drawdown: f64,
+    pub max_drawdown_pct: f64,
+    pub max_drawdown_peak_pct: f64,
+}
+
+/// Value of a metric by its name in objective expressions; percentages are in percent of `capital`.
+fn metric_variable(metrics: &Metrics, capital: f64, name: &str) -> Option<f64> {
+    let percent = |value: f64| if capital > 0.0 { value / capital * 100.0 } else { 0.0 };
+    Some(match name {
+        "total_profit" | "profit" => metrics.total_profit,
+        "return_pct" => percent(metrics.total_profit),
+        "win_rate" => metrics.win_rate * 100.0,
+        "profit_factor" => metrics.profit_factor,
+        "sharpe" | "sharpe_ratio" => metrics.sharpe_ratio,
+        "max_drawdown" => metrics.max_drawdown,
+        // positive, so that `max_drawdown_pct < 15` reads naturally
+        "max_drawdown_pct" => percent(-metrics.max_drawdown),
+        "trades" => metrics.total_trades as f64,
+        _ => return None,
+    })
+}
+
+/// Higher is better. `capital` is the funds the metrics were made with, for the percentages of expressions.
+pub fn objective_value(objective: &Objective, metrics: &Metrics, capital: f64) -> f64 {
+    match objective {
+        Objective::TotalProfit => metrics.total_profit,
+        Objective::SharpeRatio => metrics.sharpe_ratio,
+        Objective::ProfitFactor => metrics.profit_factor,
+        Objective::WinRate => metrics.win_rate,
+        // drawdowns are negative, so the highest one is the shallowest
+        Objective::MaxDrawdown => metrics.max_drawdown,
+        Objective::Expression { expression, constraints } => {
+            let variable = |name: &str| metric_variable(metrics, capital, name);
+            // the variables are checked by `check_objective` when the config is loaded
+            let invalid = |e: String| -> f64 { panic!("invalid objective: {}", e) };
+            for constraint in constraints.iter() {
+                match constraint.value.holds(&variable) {
+                    Ok(true) => {}
+                    Ok(false) => return f64::NEG_INFINITY,
+                    Err(e) => return invalid(e),
+                }
+            }
+            expression.value.evaluate(&variable).unwrap_or_else(invalid)
+        }
+    }
+}
+
+/// Checks that an expression objective only uses known metrics, the syntax being checked when it is parsed.
+pub fn check_objective(objective: &Objective) -> Result<(), String> {
+    if let Objective::Expression { expression, constraints } = objective {
+        let metrics = Metrics::new();
+        let variable = |name: &str| metric_variable(&metrics, 1.0, name);
+        expression.value.evaluate(&variable).map_err(|e| format!("{} in `{}`", e, expression))?;
+        for constraint in constraints.iter() {
+            constraint.value.holds(&variable).map_err(|e| format!("{} in `{}`", e, constraint))?;
+        }
+    }
+    Ok(())
+}
+
+/// One row per strategy, ranked by the objective of the config.
+pub fn summary_table(contributions: &[StrategyContribution], config: &Config) -> Vec<SummaryRow> {
+    let mut ranked: Vec<&StrategyContribution> = contributions.iter().collect();
+    ranked.sort_by(|a, b| {
+        let a = objective_value(&config.objective, &a.recap.metrics, config.base_funds * a.initial_weight);
+        let b = objective_value(&config.objective, &b.recap.metrics, config.base_funds * b.initial_weight);
+        b.partial_cmp(&a).unwrap_or(std::cmp::Ordering::Equal)
+    });
+    ranked
+        .into_iter()
+        .enumerate()
+        .map(|(i, contribution)| {
+            let metrics = &contribution.recap.metrics;
+            let capital = config.base_funds * contribution.initial_weight;
+            SummaryRow {
+                rank: i + 1,
+                name: contribution.name.clone(),
+                initial_weight: contribution.initial_weight,
+                final_weight: contribution.final_weight,
+                trades: metrics.total_trades,
+                profit: metrics.total_profit,
+                return_pct: if capital > 0.0 { metrics.total_profit / capital * 100.0 } else { 0.0 },
+                peak_return_pct: metrics.percent_of_peak.total_profit,
+                win_rate: metrics.win_rate * 100.0,
+                profit_factor: metrics.profit_factor,
+                sharpe_ratio: metrics.sharpe_ratio,
+                max_drawdown: metrics.max_drawdown,
+                max_drawdown_pct: metrics.percent_of_initial.max_drawdown,
+                max_drawdown_peak_pct: metrics.percent_of_peak.max_drawdown,
+            }
+        })
+        .collect()
+}
+
+/// Writes the summary table, so it can be sorted by any column in a spreadsheet.
+pub fn write_summary_csv(rows: &[SummaryRow], file_path: &str, format: &Formatter) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = format.csv_writer(file_path)?;
+    writer.write_record([
+        "rank",
+        "strategy",
+        "initial_weight",
+        "final_weight",
+        "trades",
+        "profit",
+        "return_pct",
+        "peak_return_pct",
+        "win_rate",
+        "profit_factor",
+        "sharpe_ratio",
+        "max_drawdown",
+        "max_drawdown_pct",
+        "max_drawdown_peak_pct",
+    ])?;
+    for row in rows.iter() {
+        writer.write_record([
+            row.rank.to_string(),
+            row.name.clone(),
+            format.csv_fixed(row.initial_weight, 4),
+            format.csv_fixed(row.final_weight, 4),
+            row.trades.to_string(),
+            format.csv_fixed(row.profit, 2),
+            format.csv_fixed(row.return_pct, 2),
+            format.csv_fixed(row.peak_return_pct, 2),
+            format.csv_fixed(row.win_rate, 2),
+            format.csv_fixed(row.profit_factor, 4),
+            format.csv_fixed(row.sharpe_ratio, 4),
+            format.csv_fixed(row.max_drawdown, 2),
+            format.csv_fixed(row.max_drawdown_pct, 2),
+            format.csv_fixed(row.max_drawdown_peak_pct, 2),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes one row per candle with its close, the return to the next close in percentage, and the value of each
+/// recorded indicator (empty when the strategy did not report it), to compare signals with what came next.
+pub fn write_indicators_csv(recap: &SessionRecap, klines: &[Kline], file_path: &str, format: &Formatter) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = format.csv_writer(file_path)?;
+    let mut header = vec!["timestamp".to_string(), "close".to_string(), "forward_return_pct".to_string()];
+    header.extend(recap.indicators.keys().cloned());
+    writer.write_record(&header)?;
+    let values: Vec<BTreeMap<NaiveDateTime, f64>> = recap.indicators.values().map(|series| series.iter().copied().collect()).collect();
+    for (index, kline) in klines.iter().enumerate() {
+        let forward_return = match klines.get(index + 1) {
+            Some(next) if kline.close != 0.0 => format.csv_fixed((next.close / kline.close - 1.0) * 100.0, 4),
+            _ => String::new(),
+        };
+        let mut record = vec![kline.timestamp.to_string(), format.csv_number(kline.close), forward_return];
+        record.extend(values.iter().map(|series| series.get(&kline.timestamp).map(|value| format.csv_number(*value)).unwrap_or_default()));
+        writer.write_record(&record)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes one row per trade, closed ones first, then the ones still open at the end of the data. The ids match the
+/// ones of the log and of the graphs.
+pub fn write_trades_csv(recap: &SessionRecap, file_path: &str, format: &Formatter) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = format.csv_writer(file_path)?;
+    writer.write_record([
+        "id", "entry_order", "exit_order", "direction", "entry_date", "entry_price", "signal_entry_price", "exit_date", "exit_price", "signal_exit_price", "allocated", "profit", "commission", "exit_reason",
+    ])?;
+    let optional = |value: Option<String>| value.unwrap_or_default();
+    for trade in recap.trades.iter().chain(recap.open_trades.iter()).chain(recap.open_lots.iter()) {
+        writer.write_record([
+            trade.id.clone(),
+            trade.entry_order.clone(),
+            optional(trade.exit_order.clone()),
+            format!("{:?}", trade.direction),
+            trade.entry_date.to_string(),
+            format.csv_number(trade.entry_price),
+            format.csv_number(trade.signal_entry_price),
+            optional(trade.exit_date.map(|date| date.to_string())),
+            optional(trade.exit_price.map(|price| format.csv_number(price))),
+            optional(trade.signal_exit_price.map(|price| format.csv_number(price))),
+            format.csv_number(trade.allocated),
+            optional(trade.profit.map(|profit| format.csv_number(profit))),
+            format.csv_number(trade.commission),
+            optional(trade.exit_reason.map(|reason| reason.to_string())),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn pearson(a: &[f64], b: &[f64]) -> f64 {
+    let n = a.len() as f64;
+    if n < 2.0 {
+        return 0.0;
+    }
+    let mean_a = a.iter().sum::<f64>() / n;
+    let mean_b = b.iter().sum::<f64>() / n;
+    let mut covariance = 0.0;
+    let mut variance_a = 0.0;
+    let mut variance_b = 0.0;
+    for (x, y) in a.iter().zip(b.iter()) {
+        covariance += (x - mean_a) * (y - mean_b);
+        variance_a += (x - mean_a).powi(2);
+        variance_b += (y - mean_b).powi(2);
+    }
+    if variance_a == 0.0 || variance_b == 0.0 {
+        0.0
+    } else {
+        covariance / (variance_a.sqrt() * variance_b.sqrt())
+    }
+}
+
+/// Correlation matrix of the per-bar returns of each strategy, computed on the timestamps all of them have equity for.
+/// Cash moved by reallocations shows up as returns on the bars it happens.
+pub fn strategy_correlations(contributions: &[StrategyContribution]) -> CorrelationMatrix {
+    let mut by_date: BTreeMap<NaiveDateTime, Vec<Option<f64>>> = BTreeMap::new();
+    for (i, contribution) in contributions.iter().enumerate() {
+        for (date, equity) in contribution.recap.equity_curve.iter() {
+            by_date.entry(*date).or_insert_with(|| vec![None; contributions.len()])[i] = Some(*equity);
+        }
+    }
+    let aligned: Vec<Vec<f64>> = by_date.into_values().filter_map(|row| row.into_iter().collect::<Option<Vec<f64>>>()).collect();
+    let returns: Vec<Vec<f64>> = (0..contributions.len())
+        .map(|i| aligned.windows(2).map(|w| if w[0][i] != 0.0 { w[1][i] / w[0][i] - 1.0 } else { 0.0 }).collect())
+        .collect();
+    let values = (0..contributions.len())
+        .map(|i| (0..contributions.len()).map(|j| if i == j { 1.0 } else { pearson(&returns[i], &returns[j]) }).collect())
+        .collect();
+    CorrelationMatrix {
+        labels: contributions.iter().map(|contribution| contribution.name.clone()).collect(),
+        values,
+    }
+}
+
+/// Number of candles in a year for a timeframe such as `15m`, `1h`, `1d` or `1w`, trading every day.
+pub fn periods_per_year(timeframe: &str) -> Result<f64, String> {
+    let (amount, unit) = timeframe.split_at(timeframe.len().saturating_sub(1));
+    let amount: f64 = match amount {
+        "" => 1.0,
+        amount => amount.parse().ok().filter(|amount: &f64| amount.is_finite() && *amount > 0.0).ok_or_else(|| format!("{} is not a positive number of periods", amount))?,
+    };
+    let minutes = match unit {
+        "m" => amount,
+        "h" => amount * 60.0,
+        "d" => amount * 60.0 * 24.0,
+        "w" => amount * 60.0 * 24.0 * 7.0,
+        "M" => return Ok(12.0 / amount),
+        _ => return Err(format!("unknown timeframe {}, expected a number followed by m, h, d, w or M", timeframe)),
+    };
+    Ok(365.0 * 24.0 * 60.0 / minutes)
+}
+
+/// Candles in a year for the returns and the financing: the calendar ones of `periods_per_year`, except for daily
+/// candles when `trading_days_per_year` is set. The timeframe was checked by `Config::validate`.
+pub fn annualization(config: &Config) -> f64 {
+    let periods = periods_per_year(&config.timeframe).expect("timeframe checked when the config was loaded");
+    match (config.trading_days_per_year, config.timeframe.ends_with('d')) {
+        (Some(days), true) => days * periods / 365.0,
+        _ => periods,
+    }
+}
+
+/// Returns between consecutive points of an equity curve.
+pub fn returns(curve: &[(NaiveDateTime, f64)]) -> Vec<f64> {
+    curve.windows(2).map(|w| if w[0].1 != 0.0 { w[1].1 / w[0].1 - 1.0 } else { 0.0 }).collect()
+}
+
+pub fn mean(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        0.0
+    } else {
+        values.iter().sum::<f64>() / values.len() as f64
+    }
+}
+
+/// Value of the metric over the last `window` closed trades, at the exit of each trade from the `window`th one on.
+/// The returns of the trades are their profit over the allocated amount.
+pub fn rolling_metric(trades: &[Trade], rule: &RollingMetric) -> Vec<(NaiveDateTime, f64)> {
+    let closed: Vec<(NaiveDateTime, f64)> = trades
+        .iter()
+        .filter_map(|trade| match (trade.exit_date, trade.profit) {
+            (Some(date), Some(profit)) => Some((date, if trade.allocated != 0.0 { profit / trade.allocated } else { 0.0 })),
+            _ => None,
+        })
+        .collect();
+    if rule.window == 0 {
+        return Vec::new();
+    }
+    closed
+        .windows(rule.window)
+        .map(|window| {
+            let returns: Vec<f64> = window.iter().map(|(_, trade_return)| *trade_return).collect();
+            let value = match rule.metric {
+                RollingMetricKind::WinRate => returns.iter().filter(|trade_return| **trade_return > 0.0).count() as f64 / returns.len() as f64 * 100.0,
+                RollingMetricKind::Sharpe => {
+                    let mean = mean(&returns);
+                    let deviation = (returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (returns.len() as f64 - 1.0).max(1.0)).sqrt();
+                    if deviation > 0.0 { mean / deviation } else { 0.0 }
+                }
+            };
+            (window[window.len() - 1].0, value)
+        })
+        .collect()
+}
+
+/// The objective over the grid of a sweep of exactly two parameters.
+pub struct ParameterSurface {
+    pub x_name: String,
+    pub y_name: String,
+    pub x_values: Vec<serde_json::Value>,
+    pub y_values: Vec<serde_json::Value>,
+    /// Objective of each variant, indexed by `[x][y]`: on the whole data, or on average over the folds with
+    /// cross-validation, like the variants were ranked.
+    pub values: Vec<Vec<f64>>,
+    pub cross_validated: bool,
+    /// Position of the best variant.
+    pub best: (usize, usize),
+    /// How far below the best variant its neighbors are on average, as a fraction of the range of the objective:
+    /// close to 0 on a broad plateau, close to 1 on a narrow spike.
+    pub neighbor_drop: f64,
+}
+
+impl ParameterSurface {
+    /// A spike is an optimum whose neighbors lose more than half of the range, it is likely overfit.
+    pub fn is_spike(&self) -> bool {
+        self.neighbor_drop > 0.5
+    }
+}
+
+/// Lays the variants of a sweep out on a grid, when exactly two parameters have several values.
+pub fn parameter_surface(sweep: &Sweep, result: &SweepResult, config: &Config) -> Option<ParameterSurface> {
+    let swept: Vec<(&String, &Vec<serde_json::Value>)> = sweep
+        .params
+        .iter()
+        .filter_map(|(name, values)| values.as_array().filter(|values| values.len() > 1).map(|values| (name, values)))
+        .collect();
+    let [(x_name, x_values), (y_name, y_values)] = swept[..] else {
+        return None;
+    };
+    let score = |variant: usize| match &result.cross_validation {
+        Some(cross_validation) => mean(&cross_validation.scores[variant]),
+        None => objective_value(&config.objective, &result.variants[variant].recap.metrics, config.base_funds),
+    };
+    // variants are listed with the last parameter changing first, and the other parameters have a single value
+    let values: Vec<Vec<f64>> = (0..x_values.len()).map(|x| (0..y_values.len()).map(|y| score(x * y_values.len() + y)).collect()).collect();
+    let mut best = (0, 0);
+    for (x, row) in values.iter().enumerate() {
+        for (y, value) in row.iter().enumerate() {
+            if *value > values[best.0][best.1] {
+                best = (x, y);
+            }
+        }
+    }
+    // variants breaking a constraint of the objective are worth -inf, they count as the worst of the others
+    let (min, max) = values.iter().flatten().filter(|value| value.is_finite()).fold((f64::MAX, f64::MIN), |(min, max), value| (min.min(*value), max.max(*value)));
+    // the up to eight variants around the best one
+    let neighbors: Vec<f64> = values
+        .iter()
+        .enumerate()
+        .filter(|(x, _)| x.abs_diff(best.0) <= 1)
+        .flat_map(|(x, row)| row.iter().enumerate().filter(move |(y, _)| y.abs_diff(best.1) <= 1 && (x, *y) != best).map(|(_, value)| value.max(min)))
+        .collect();
+    let neighbor_drop = if max > min { (max - mean(&neighbors)) / (max - min) } else { 0.0 };
+    Some(ParameterSurface {
+        x_name: x_name.clone(),
+        y_name: y_name.clone(),
+        x_values: x_values.clone(),
+        y_values: y_values.clone(),
+        values,
+        cross_validated: result.cross_validation.is_some(),
+        best,
+        neighbor_drop,
+    })
+}
+
+/// How far the fills of the closed trades were from the prices their orders were decided at, splitting what the
+/// strategy earned from what the execution cost.
+#[derive(Serialize)]
+pub struct ExecutionQuality {
+    /// Entries and exits measured, two per closed trade.
+    pub fills: usize,
+    /// Implementation shortfall of the fills in basis points of the signal price, positive when the fill was worse.
+    pub mean_shortfall_bps: f64,
+    pub median_shortfall_bps: f64,
+    pub p95_shortfall_bps: f64,
+    /// Profit of the trades filled at their signal prices, before commissions and financing.
+    pub signal_profit: f64,
+    /// Profit lost between the signal prices and the fills: slippage, spread, requotes and gaps.
+    pub execution_drag: f64,
+    pub commission: f64,
+    pub financing: f64,
+}
+
+/// Measures the execution of the closed trades, see `ExecutionQuality`. The signal profit less the execution drag,
+/// the commission and the financing is the total profit of the trades.
+pub fn execution_quality(trades: &[Trade]) -> ExecutionQuality {
+    let mut shortfalls = Vec::new();
+    let (mut signal_profit, mut execution_drag, mut commission, mut financing) = (0.0, 0.0, 0.0, 0.0);
+    for trade in trades.iter() {
+        // trades of checkpoints written before the signal prices were recorded are left out
+        let (Some(exit_price), Some(signal_exit_price), Some(profit)) = (trade.exit_price, trade.signal_exit_price, trade.profit) else {
+            continue;
+        };
+        if trade.signal_entry_price <= 0.0 {
+            continue;
+        }
+        let sign = if trade.direction == Direction::Short { -1.0 } else { 1.0 };
+        shortfalls.push(sign * (trade.entry_price / trade.signal_entry_price - 1.0) * 10_000.0);
+        shortfalls.push(sign * (1.0 - exit_price / signal_exit_price) * 10_000.0);
+        let ideal = sign * trade.allocated * (signal_exit_price / trade.signal_entry_price - 1.0);
+        signal_profit += ideal;
+        execution_drag += ideal - (profit + trade.commission + trade.financing);
+        commission += trade.commission;
+        financing += trade.financing;
+    }
+    shortfalls.sort_by(|a, b| a.total_cmp(b));
+    let at = |pct: f64| if shortfalls.is_empty() { 0.0 } else { crate::montecarlo::percentile(&shortfalls, pct) };
+    ExecutionQuality {
+        fills: shortfalls.len(),
+        mean_shortfall_bps: mean(&shortfalls),
+        median_shortfall_bps: at(50.0),
+        p95_shortfall_bps: at(95.0),
+        signal_profit,
+        execution_drag,
+        commission,
+        financing,
+    }
+}
+
+/// Results of the closed trades counted in pips, for `fx`.
+#[derive(Serialize)]
+pub struct PipSummary {
+    pub pip_size: f64,
+    pub total: f64,
+    pub average: f64,
+    pub best: f64,
+    pub worst: f64,
+}
+
+/// Adds up the pips of the closed trades, see `Trade::pips`.
+pub fn pip_summary(trades: &[Trade], pip: f64) -> PipSummary {
+    let pips: Vec<f64> = trades.iter().filter_map(|trade| trade.pips(pip)).collect();
+    PipSummary {
+        pip_size: pip,
+        total: pips.iter().sum(),
+        average: mean(&pips),
+        best: pips.iter().copied().reduce(f64::max).unwrap_or(0.0),
+        worst: pips.iter().copied().reduce(f64::min).unwrap_or(0.0),
+    }
+}
+
+/// How much the results depend on a few short windows of time.
+#[derive(Serialize)]
+pub struct ProfitConcentration {
+    /// Profit of the best weeks (by exit date of the trades, weeks starting on Monday), best first.
+    pub best_weeks: Vec<(chrono::NaiveDate, f64)>,
+    /// Share of the total profit earned in `best_weeks`, only meaningful when the total profit is positive.
+    pub best_weeks_share: f64,
+    /// Start and length in seconds of the longest time without an open position, from the first candle to the last.
+    pub longest_flat_period: Option<(NaiveDateTime, i64)>,
+    /// Median, 90th percentile and longest time between two consecutive entries, in seconds.
+    pub entry_gaps: Option<(i64, i64, i64)>,
+    /// Set when the best week alone earned more than half of a positive total profit.
+    pub single_event: bool,
+}
+
+/// Groups the closed trades by week and measures how much of the profit the `weeks` best ones hold,
+/// and how spread out in time the positions are, over the whole equity curve of the recap.
+pub fn profit_concentration(recap: &SessionRecap, weeks: usize) -> ProfitConcentration {
+    let closed: Vec<(NaiveDateTime, f64)> = recap.trades.iter().filter_map(|trade| Some((trade.exit_date?, trade.profit?))).collect();
+    let mut by_week: BTreeMap<chrono::NaiveDate, f64> = BTreeMap::new();
+    for (exit_date, profit) in closed.iter() {
+        let week = exit_date.date().week(chrono::Weekday::Mon).first_day();
+        *by_week.entry(week).or_default() += profit;
+    }
+    let mut best_weeks: Vec<(chrono::NaiveDate, f64)> = by_week.into_iter().collect();
+    best_weeks.sort_by(|a, b| b.1.total_cmp(&a.1));
+    best_weeks.truncate(weeks);
+    let total_profit: f64 = closed.iter().map(|(_, profit)| profit).sum();
+    let share = |profit: f64| if total_profit > 0.0 { profit / total_profit } else { 0.0 };
+
+    let positions: Vec<&Trade> = recap.trades.iter().chain(recap.open_trades.iter()).chain(recap.open_lots.iter()).collect();
+    let longest_flat_period = match (recap.equity_curve.first(), recap.equity_curve.last()) {
+        (Some((start, _)), Some((end, _))) => {
+            // positions that overlap are merged, the flat periods being the gaps between the merged intervals
+            let mut intervals: Vec<(NaiveDateTime, NaiveDateTime)> = positions.iter().map(|trade| (trade.entry_date, trade.exit_date.unwrap_or(*end))).collect();
+            intervals.sort();
+            let mut flat: Vec<(NaiveDateTime, i64)> = Vec::new();
+            let mut flat_since = *start;
+            for (entry, exit) in intervals {
+                if entry > flat_since {
+                    flat.push((flat_since, (entry - flat_since).num_seconds()));
+                }
+                flat_since = flat_since.max(exit);
+            }
+            if *end > flat_since {
+                flat.push((flat_since, (*end - flat_since).num_seconds()));
+            }
+            // the first of the longest ones
+            flat.into_iter().rev().max_by_key(|(_, duration)| *duration)
+        }
+        _ => None,
+    };
+    let mut entries: Vec<NaiveDateTime> = positions.iter().map(|trade| trade.entry_date).collect();
+    entries.sort();
+    let mut gaps: Vec<i64> = entries.windows(2).map(|pair| (pair[1] - pair[0]).num_seconds()).collect();
+    gaps.sort();
+    let entry_gaps = (!gaps.is_empty()).then(|| (gaps[gaps.len() / 2], gaps[(gaps.len() - 1) * 9 / 10], gaps[gaps.len() - 1]));
+
+    ProfitConcentration {
+        best_weeks_share: share(best_weeks.iter().map(|(_, profit)| profit).sum()),
+        single_event: best_weeks.first().is_some_and(|(_, profit)| share(*profit) > 0.5),
+        best_weeks,
+        longest_flat_period,
+        entry_gaps,
+    }
+}
+
+/// Benchmark value at each timestamp of the equity curve (the last one known at that time),
+/// rebased so both series start from the same value.
+pub fn align_benchmark(equity_curve: &[(NaiveDateTime, f64)], benchmark: &[(NaiveDateTime, f64)]) -> Vec<(NaiveDateTime, f64)> {
+    let (Some((_, start_equity)), Some((_, first_value))) = (equity_curve.first(), benchmark.first()) else {
+        return Vec::new();
+    };
+    let mut index = 0;
+    let mut raw: Vec<(NaiveDateTime, f64)> = Vec::with_capacity(equity_curve.len());
+    for (date, _) in equity_curve.iter() {
+        while index + 1 < benchmark.len() && benchmark[index + 1].0 <= *date {
+            index += 1;
+        }
+        let value = if benchmark[index].0 <= *date { benchmark[index].1 } else { *first_value };
+        raw.push((*date, value));
+    }
+    let start_value = raw[0].1;
+    raw.into_iter().map(|(date, value)| (date, start_equity * value / start_value)).collect()
+}
+
+/// Performance of the strategy relative to a benchmark. Alpha and tracking error are annualized.
+#[derive(Serialize)]
+pub struct BenchmarkMetrics {
+    pub benchmark_return: f64,
+    pub alpha: f64,
+    pub beta: f64,
+    pub tracking_error: f64,
+    pub information_ratio: f64,
+}
+
+/// Computes the relative metrics between an equity curve and the benchmark aligned on it by `align_benchmark`.
+pub fn benchmark_metrics(equity_curve: &[(NaiveDateTime, f64)], aligned: &[(NaiveDateTime, f64)], periods_per_year: f64) -> BenchmarkMetrics {
+    let strategy_returns = returns(equity_curve);
+    let benchmark_returns = returns(aligned);
+    let mean_strategy = mean(&strategy_returns);
+    let mean_benchmark = mean(&benchmark_returns);
+    let mut covariance = 0.0;
+    let mut variance = 0.0;
+    for (s, b) in strategy_returns.iter().zip(benchmark_returns.iter()) {
+        covariance += (s - mean_strategy) * (b - mean_benchmark);
+        variance += (b - mean_benchmark).powi(2);
+    }
+    let beta = if variance > 0.0 { covariance / variance } else { 0.0 };
+    let active: Vec<f64> = strategy_returns.iter().zip(benchmark_returns.iter()).map(|(s, b)| s - b).collect();
+    let mean_active = mean(&active);
+    let active_deviation = (active.iter().map(|a| (a - mean_active).powi(2)).sum::<f64>() / active.len().max(1) as f64).sqrt();
+    let tracking_error = active_deviation * periods_per_year.sqrt();
+    let benchmark_return = match (aligned.first(), aligned.last()) {
+        (Some((_, first)), Some((_, last))) if *first != 0.0 => last / first - 1.0,
+        _ => 0.0,
+    };
+    BenchmarkMetrics {
+        benchmark_return,
+        alpha: (mean_strategy - beta * mean_benchmark) * periods_per_year,
+        beta,
+        tracking_error,
+        information_ratio: if tracking_error > 0.0 { mean_active * periods_per_year / tracking_error } else { 0.0 },
+    }
+}
+
+/// The results of a run as a single JSON object, as printed by `--headless`.
+pub fn json_summary(recap: &SessionRecap, config: &Config, verdict: &Verdict) -> serde_json::Value {
+    let benchmark = config
+        .benchmark
+        .as_ref()
+        .map(|_| benchmark_metrics(&recap.equity_curve, &recap.benchmark_curve, annualization(config)));
+    serde_json::json!({
+        "symbol": config.symbol,
+        "timeframe": config.timeframe,
+        "base_funds": config.base_funds,
+        "fingerprints": recap.fingerprints,
+        "fast": config.fast.then_some(&config.fast_sampling),
+        "metrics": recap.metrics,
+        "balances": recap.balances,
+        "execution": execution_quality(&recap.trades),
+        "pips": config.fx.as_ref().map(|fx| pip_summary(&recap.trades, fx.pip(&config.symbol))),
+        "reporting": recap.reporting,
+        "benchmark": benchmark,
+        "sweep": recap.sweep.as_ref().map(|sweep| serde_json::json!({
+            "variants": sweep.variants.len(),
+            "best": sweep.best,
+            "reality_check_p_value": sweep.reality_check,
+            "out_of_sample": sweep.cross_validation.as_ref().map(|cross_validation| mean(&cross_validation.out_of_sample)),
+            "sensitivity": sweep.sensitivity.iter().map(|sensitivity| serde_json::json!({
+                "parameter": sensitivity.parameter,
+                "swing": sensitivity.swing(),
+                "safe": [&sensitivity.response[sensitivity.safe.0].0, &sensitivity.response[sensitivity.safe.1].0],
+            })).collect::<Vec<_>>(),
+        })),
+        "profit_concentration": profit_concentration(recap, config.concentration_weeks),
+        "regimes": recap.regime_breakdown,
+        "outages": recap.outage_impact,
+        "tax": config.tax.as_ref().map(|tax| {
+            let report = crate::tax::report(recap, tax);
+            serde_json::json!({
+                "method": report.method,
+                "years": report.years,
+                "open_lots": report.open_lots,
+            })
+        }),
+        "exit_analysis": recap.exit_analysis,
+        "forward_returns": recap.forward_returns,
+        "signal_quality": recap.signal_quality,
+        "snapshots": recap.snapshots,
+        "monthly_restart": recap.monthly_restart,
+        "monte_carlo": recap.monte_carlo.as_ref().map(|result| serde_json::json!({
+            "simulations": result.simulations,
+            "percentiles": crate::montecarlo::PERCENTILES,
+            "final_equity": result.final_equity,
+            "max_drawdown_pct": result.max_drawdown_pct,
+        })),
+        "acceptance": verdict,
+    })
+}
+
+/// Average return some candles after the entries, in the direction of the trades.
+#[derive(Debug, Serialize)]
+pub struct ForwardReturn {
+    /// Candles after the entry candle.
+    pub horizon: usize,
+    /// Entries with enough data after them.
+    pub entries: usize,
+    /// Mean return from the close of the entry candle, in percentage, positive when the price moved the way of the trade.
+    pub mean_return: f64,
+    /// 95% confidence interval of the mean, from its standard error.
+    pub low: f64,
+    pub high: f64,
+    /// Share of the entries with a positive return, in percentage.
+    pub positive_share: f64,
+}
+
+/// Measures the returns `horizons` candles after the entry of every trade, open ones included, whatever its exit, to
+/// judge the entries apart from the exits. Horizons past the end of the data leave the entry out.
+pub fn forward_returns(trades: &[Trade], klines: &[Kline], horizons: &[usize]) -> Vec<ForwardReturn> {
+    let entries: Vec<(usize, f64)> = trades
+        .iter()
+        .filter_map(|trade| {
+            let index = klines.partition_point(|kline| kline.timestamp <= trade.entry_date).checked_sub(1)?;
+            Some((index, if trade.direction == Direction::Short { -1.0 } else { 1.0 }))
+        })
+        .collect();
+    horizons
+        .iter()
+        .map(|horizon| {
+            let values: Vec<f64> = entries
+                .iter()
+                .filter_map(|(index, side)| {
+                    let (entry, later) = (klines[*index].close, klines.get(index + horizon)?.close);
+                    Some(side * (later / entry - 1.0) * 100.0)
+                })
+                .collect();
+            let mean_return = mean(&values);
+            let deviation = if values.len() > 1 { (values.iter().map(|value| (value - mean_return).powi(2)).sum::<f64>() / (values.len() - 1) as f64).sqrt() } else { 0.0 };
+            let margin = if values.is_empty() { 0.0 } else { 1.96 * deviation / (values.len() as f64).sqrt() };
+            ForwardReturn {
+                horizon: *horizon,
+                entries: values.len(),
+                mean_return,
+                low: mean_return - margin,
+                high: mean_return + margin,
+                positive_share: if values.is_empty() { 0.0 } else { values.iter().filter(|value| **value > 0.0).count() as f64 / values.len() as f64 * 100.0 },
+            }
+        })
+        .collect()
+}
+
+/// Positions open at the close of a candle and their value, see `exposure_timeline`.
+#[derive(Debug, Serialize)]
+pub struct ExposurePoint {
+    pub date: NaiveDateTime,
+    pub open_positions: usize,
+    /// Value of the long and of the short positions at the close of the candle, in quote currency.
+    pub long: f64,
+    pub short: f64,
+}
+
+/// Positions open at the close of every candle, from the candle of their entry to the one before their exit, with
+/// their value at its close. The trades of every strategy and every lot count, open ones until the end of the data.
+pub fn exposure_timeline(trades: &[Trade], klines: &[Kline]) -> Vec<ExposurePoint> {
+    // changes of the number of positions and of the long and short quantities, applied from their candle on
+    let mut changes = vec![(0i64, 0.0, 0.0); klines.len() + 1];
+    for trade in trades.iter() {
+        let start = klines.partition_point(|kline| kline.timestamp < trade.entry_date);
+        let end = trade.exit_date.map(|date| klines.partition_point(|kline| kline.timestamp < date)).unwrap_or(klines.len()).max(start);
+        let (long, short) = if trade.direction == Direction::Short { (0.0, -trade.quantity()) } else { (trade.quantity(), 0.0) };
+        changes[start] = (changes[start].0 + 1, changes[start].1 + long, changes[start].2 + short);
+        changes[end] = (changes[end].0 - 1, changes[end].1 - long, changes[end].2 - short);
+    }
+    let (mut open_positions, mut long, mut short) = (0i64, 0.0, 0.0);
+    klines
+        .iter()
+        .zip(changes.iter())
+        .map(|(kline, change)| {
+            open_positions += change.0;
+            long += change.1;
+            short += change.2;
+            ExposurePoint {
+                date: kline.timestamp,
+                open_positions: open_positions.max(0) as usize,
+                long: long.max(0.0) * kline.close,
+                short: short.max(0.0) * kline.close,
+            }
+        })
+        .collect()
+}
+
+/// Share of the entries of a strategy followed by a favorable move of `multiple` ATRs within `horizon` candles.
+#[derive(Debug, Serialize)]
+pub struct SignalHitRate {
+    pub multiple: f64,
+    pub horizon: usize,
+    /// Entries with an ATR and enough data after them.
+    pub entries: usize,
+    /// In percentage.
+    pub hit_rate: f64,
+}
+
+/// Signal quality of one strategy, see `signal_quality`.
+#[derive(Debug, Serialize)]
+pub struct SignalQuality {
+    pub strategy: String,
+    pub hit_rates: Vec<SignalHitRate>,
+}
+
+/// Measures, for every trade, whether the high (the low for a short) of the candles following its entry candle went
+/// `multiples` ATRs past the close of the entry candle within `horizons` candles, with the ATR at the entry candle.
+/// The sizes, stops and exits of the trades play no part: only the entries are judged. Entries during the warm-up of
+/// the ATR or too close to the end of the data for a horizon are left out of it.
+pub fn signal_quality(strategy: String, trades: &[Trade], klines: &[Kline], config: &SignalQualityConfig) -> SignalQuality {
+    let mut atr = Atr::new(config.atr_window.max(1));
+    let atrs: Vec<Option<f64>> = klines
+        .iter()
+        .map(|kline| {
+            atr.update(kline);
+            atr.value()
+        })
+        .collect();
+    // index of the entry candle, ATR and direction of each entry
+    let entries: Vec<(usize, f64, bool)> = trades
+        .iter()
+        .filter_map(|trade| {
+            let index = klines.partition_point(|kline| kline.timestamp <= trade.entry_date).checked_sub(1)?;
+            Some((index, atrs[index]?, trade.direction != Direction::Short))
+        })
+        .collect();
+    let mut hit_rates = Vec::new();
+    for multiple in config.multiples.iter() {
+        for horizon in config.horizons.iter() {
+            let (mut counted, mut hits) = (0, 0);
+            for (index, atr, long) in entries.iter() {
+                let Some(window) = klines.get(index + 1..index + 1 + horizon) else {
+                    continue;
+                };
+                let entry = klines[*index].close;
+                let favorable = window.iter().map(|kline| if *long { kline.high - entry } else { entry - kline.low }).fold(f64::MIN, f64::max);
+                counted += 1;
+                if favorable >= multiple * atr {
+                    hits += 1;
+                }
+            }
+            hit_rates.push(SignalHitRate {
+                multiple: *multiple,
+                horizon: *horizon,
+                entries: counted,
+                hit_rate: if counted > 0 { hits as f64 / counted as f64 * 100.0 } else { 0.0 },
+            });
+        }
+    }
+    SignalQuality { strategy, hit_rates }
+}
+
+/// Returns of the candles falling in one bucket of a calendar split, like an hour of the day.
+#[derive(Debug, Serialize)]
+pub struct SeasonalBucket {
+    pub label: String,
+    pub candles: usize,
+    /// Mean close-to-close return of the candles, in percentage.
+    pub mean_return: f64,
+    /// Share of the candles closing above the previous close, in percentage.
+    pub up_share: f64,
+    /// Mean over its standard error: beyond about 2, the mean is unlikely to be noise.
+    pub t_stat: f64,
+}
+
+/// Average returns of the candles of a dataset by hour of the day, day of the week and month, whatever the strategy.
+#[derive(Debug, Serialize)]
+pub struct Seasonality {
+    pub by_hour: Vec<SeasonalBucket>,
+    pub by_weekday: Vec<SeasonalBucket>,
+    pub by_month: Vec<SeasonalBucket>,
+}
+
+/// Splits the returns of `klines` by calendar bucket. Each return goes to the bucket of the candle it was made in,
+/// buckets without candles are left out.
+pub fn seasonality(klines: &[Kline]) -> Seasonality {
+    let returns: Vec<(NaiveDateTime, f64)> = klines.windows(2).filter(|pair| pair[0].close != 0.0).map(|pair| (pair[1].timestamp, (pair[1].close / pair[0].close - 1.0) * 100.0)).collect();
+    let split = |labels: &[String], bucket: &dyn Fn(NaiveDateTime) -> usize| -> Vec<SeasonalBucket> {
+        labels
+            .iter()
+            .enumerate()
+            .filter_map(|(index, label)| {
+                let values: Vec<f64> = returns.iter().filter(|(date, _)| bucket(*date) == index).map(|(_, value)| *value).collect();
+                if values.is_empty() {
+                    return None;
+                }
+                let mean_return = mean(&values);
+                let deviation = if values.len() > 1 { (values.iter().map(|value| (value - mean_return).powi(2)).sum::<f64>() / (values.len() - 1) as f64).sqrt() } else { 0.0 };
+                Some(SeasonalBucket {
+                    label: label.clone(),
+                    candles: values.len(),
+                    mean_return,
+                    up_share: values.iter().filter(|value| **value > 0.0).count() as f64 / values.len() as f64 * 100.0,
+                    t_stat: if deviation > 0.0 { mean_return / (deviation / (values.len() as f64).sqrt()) } else { 0.0 },
+                })
+            })
+            .collect()
+    };
+    let hours: Vec<String> = (0..24).map(|hour| format!("{:02}:00", hour)).collect();
+    let weekdays: Vec<String> = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"].iter().map(|day| day.to_string()).collect();
+    let months: Vec<String> = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"].iter().map(|month| month.to_string()).collect();
+    Seasonality {
+        by_hour: split(&hours, &|date| date.hour() as usize),
+        by_weekday: split(&weekdays, &|date| date.weekday().num_days_from_monday() as usize),
+        by_month: split(&months, &|date| date.month0() as usize),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, NaiveDate};
+
+    fn hour(hours: i64) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap() + Duration::hours(hours)
+    }
+
+    /// A long trade entered and exited at the given hours, `None` while still open.
+    fn trade(entry: i64, exit: Option<i64>, profit: f64) -> Trade {
+        serde_json::from_value(serde_json::json!({
+            "entry_date": hour(entry),
+            "exit_date": exit.map(hour),
+            "entry_price": 100.0,
+            "exit_price": exit.map(|_| 100.0),
+            "direction": "Long",
+            "allocated": 100.0,
+            "profit": exit.map(|_| profit),
+            "commission": 0.0,
+            "ambiguous_exit": false,
+        }))
+        .unwrap()
+    }
+
+    /// A recap of `trades` over an hourly equity curve from hour 0 to `end`.
+    fn recap(trades: Vec<Trade>, open_trades: Vec<Trade>, end: i64) -> SessionRecap {
+        let equity_curve = (0..=end).map(|hours| (hour(hours), 1000.0)).collect();
+        SessionRecap::new(trades, open_trades, Vec::new(), equity_curve, Metrics::new())
+    }
+
+    #[test]
+    fn timeframes_give_their_candles_per_year_or_an_error() {
+        assert_eq!(periods_per_year("1h"), Ok(8760.0));
+        assert_eq!(periods_per_year("d"), Ok(365.0));
+        assert_eq!(periods_per_year("3M"), Ok(4.0));
+        assert!(periods_per_year("1H").is_err());
+        assert!(periods_per_year("0h").is_err());
+        assert!(periods_per_year("").is_err());
+    }
+
+    #[test]
+    fn overlapping_trades_are_merged_into_one_position() {
+        // the second trade opens and closes inside the first, the market is flat from 10 to 20 only
+        let concentration = profit_concentration(&recap(vec![trade(2, Some(10), 1.0), trade(4, Some(6), 1.0), trade(20, Some(22), 1.0)], Vec::new(), 24), 3);
+        assert_eq!(concentration.longest_flat_period, Some((hour(10), 10 * 3600)));
+        assert_eq!(concentration.entry_gaps, Some((16 * 3600, 2 * 3600, 16 * 3600)));
+    }
+
+    #[test]
+    fn flat_periods_include_the_start_and_end_of_the_data() {
+        let concentration = profit_concentration(&recap(vec![trade(2, Some(4), 1.0), trade(5, Some(6), 1.0)], Vec::new(), 24), 3);
+        assert_eq!(concentration.longest_flat_period, Some((hour(6), 18 * 3600)));
+        let concentration = profit_concentration(&recap(vec![trade(12, Some(14), 1.0)], Vec::new(), 16), 3);
+        assert_eq!(concentration.longest_flat_period, Some((hour(0), 12 * 3600)));
+    }
+
+    #[test]
+    fn an_open_trade_is_a_position_until_the_end() {
+        let concentration = profit_concentration(&recap(vec![trade(1, Some(2), 1.0)], vec![trade(3, None, 0.0)], 24), 3);
+        assert_eq!(concentration.longest_flat_period, Some((hour(0), 3600)));
+    }
+}
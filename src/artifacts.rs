@@ -0,0 +1,88 @@
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+use crate::acceptance::Verdict;
+use crate::config::Config;
+use crate::testing::{Metrics, SessionRecap, StrategyFingerprint};
+
+/// Describes a run, written as `manifest.json` next to its other artifacts.
+#[derive(Serialize)]
+struct Manifest<'a> {
+    run_id: &'a str,
+    started_at: String,
+    /// The config as written, see `Config::as_written`.
+    config: serde_json::Value,
+    /// Revision of the code of the strategies run.
+    fingerprints: &'a [StrategyFingerprint],
+    artifacts: Vec<String>,
+    metrics: &'a Metrics,
+    acceptance: &'a Verdict,
+}
+
+/// The directory holding every file written by a run, under `output_dir`.
+pub struct RunDirectory {
+    pub run_id: String,
+    pub path: PathBuf,
+    started_at: chrono::DateTime<chrono::Local>,
+}
+
+impl RunDirectory {
+    /// Creates the directory of the run and moves every output file of the config into it, keeping their file names.
+    /// Returns `None` when no `output_dir` is configured, in which case the paths are left as they are.
+    pub fn create(config: &mut Config) -> std::io::Result<Option<Self>> {
+        let Some(root) = &config.output_dir else {
+            return Ok(None);
+        };
+        let started_at = chrono::Local::now();
+        let base_id = config.run_id.clone().unwrap_or_else(|| started_at.format("%Y%m%d-%H%M%S").to_string());
+        // two runs started in the same second, or reusing a run id, get a suffix rather than sharing a directory
+        let mut run_id = base_id.clone();
+        let mut attempt = 1;
+        let path = loop {
+            let path = Path::new(root).join(&run_id);
+            match std::fs::create_dir_all(Path::new(root)).and_then(|_| std::fs::create_dir(&path)) {
+                Ok(()) => break path,
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    attempt += 1;
+                    run_id = format!("{}-{}", base_id, attempt);
+                }
+                Err(e) => return Err(e),
+            }
+        };
+        let relocate = |file: &str| path.join(Path::new(file).file_name().unwrap_or(file.as_ref())).to_string_lossy().into_owned();
+        config.log_file = relocate(&config.log_file);
+        config.log_graph_file = relocate(&config.log_graph_file);
+        config.correlation_file = config.correlation_file.as_deref().map(relocate);
+        config.summary_file = config.summary_file.as_deref().map(relocate);
+        config.indicators_file = config.indicators_file.as_deref().map(relocate);
+        config.trades_file = config.trades_file.as_deref().map(relocate);
+        config.trade_book_file = config.trade_book_file.as_deref().map(relocate);
+        if let Some(animation) = config.animation.as_mut() {
+            animation.file = relocate(&animation.file);
+        }
+        if let Some(tax) = config.tax.as_mut() {
+            tax.report_file = tax.report_file.as_deref().map(relocate);
+        }
+        config.run_id = Some(run_id.clone());
+        Ok(Some(Self { run_id, path, started_at }))
+    }
+
+    /// Lists the files of the run and writes the manifest.
+    pub fn write_manifest(&self, config: &Config, recap: &SessionRecap, verdict: &Verdict) -> Result<(), Box<dyn std::error::Error>> {
+        let mut artifacts: Vec<String> = std::fs::read_dir(&self.path)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.file_name().to_string_lossy().into_owned()))
+            .filter(|name| name != "manifest.json")
+            .collect();
+        artifacts.sort();
+        let manifest = Manifest {
+            run_id: &self.run_id,
+            started_at: self.started_at.to_rfc3339(),
+            config: config.as_written(),
+            fingerprints: &recap.fingerprints,
+            artifacts,
+            metrics: &recap.metrics,
+            acceptance: verdict,
+        };
+        std::fs::write(self.path.join("manifest.json"), serde_json::to_string_pretty(&manifest)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,55 @@
+//! Scheduled blackouts, like central bank announcements or exchange maintenance: no entry is allowed inside the
+//! windows, and positions can be closed when one starts. Times are the ones of the timestamps of the data.
+
+use chrono::{Datelike, NaiveDateTime, NaiveTime, Weekday};
+use crate::config::{parse_date_range, BlackoutConfig, RecurringWindow};
+
+struct Recurring {
+    /// Every day when `None`.
+    weekday: Option<Weekday>,
+    start: NaiveTime,
+    /// Excluded. A window ending before it starts runs past midnight.
+    end: NaiveTime,
+}
+
+impl Recurring {
+    fn parse(window: &RecurringWindow) -> Result<Self, String> {
+        let time = |text: &str| NaiveTime::parse_from_str(text, "%H:%M").map_err(|_| format!("invalid time `{}`, expected HH:MM", text));
+        Ok(Self {
+            weekday: window.weekday.as_deref().map(|day| day.parse::<Weekday>().map_err(|_| format!("invalid weekday `{}`", day))).transpose()?,
+            start: time(&window.start)?,
+            end: time(&window.end)?,
+        })
+    }
+
+    fn contains(&self, date: NaiveDateTime) -> bool {
+        let time = date.time();
+        let on = |weekday: Weekday| self.weekday.is_none_or(|day| day == weekday);
+        if self.start < self.end {
+            on(date.weekday()) && self.start <= time && time < self.end
+        } else {
+            (on(date.weekday()) && time >= self.start) || (on(date.weekday().pred()) && time < self.end)
+        }
+    }
+}
+
+pub struct Blackouts {
+    windows: Vec<(NaiveDateTime, NaiveDateTime)>,
+    recurring: Vec<Recurring>,
+    /// Close every position at the open of the first candle of a window.
+    pub flatten: bool,
+}
+
+impl Blackouts {
+    pub fn new(config: &BlackoutConfig) -> Result<Self, String> {
+        Ok(Self {
+            windows: config.windows.iter().map(|window| parse_date_range(window)).collect::<Result<_, _>>().map_err(|e| format!("blackouts.windows: {}", e))?,
+            recurring: config.recurring.iter().map(Recurring::parse).collect::<Result<_, _>>().map_err(|e| format!("blackouts.recurring: {}", e))?,
+            flatten: config.flatten,
+        })
+    }
+
+    pub fn contains(&self, date: NaiveDateTime) -> bool {
+        self.windows.iter().any(|(start, end)| *start <= date && date < *end) || self.recurring.iter().any(|window| window.contains(date))
+    }
+}
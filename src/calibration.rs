@@ -0,0 +1,139 @@
+//! Calibration of the slippage from order book snapshots: market orders of several sizes are walked through the
+//! levels of each snapshot, and the average price they would have paid is compared to the middle of the book.
+
+use chrono::NaiveDateTime;
+use csv::Reader;
+#[cfg(feature = "files")]
+use std::fs::File;
+use std::collections::HashMap;
+use std::io::Read;
+use serde::Serialize;
+use crate::config::{parse_date, SlippageModel, SlippagePoint};
+use crate::montecarlo::percentile;
+
+/// Order sizes measured, as fractions of the notional given to the calibration.
+pub const SIZE_FRACTIONS: [f64; 5] = [0.1, 0.25, 0.5, 1.0, 2.0];
+
+/// The levels of the book at one time, best price first.
+pub struct BookSnapshot {
+    pub timestamp: NaiveDateTime,
+    /// Price and quantity of the base asset of each level.
+    pub bids: Vec<(f64, f64)>,
+    pub asks: Vec<(f64, f64)>,
+}
+
+impl BookSnapshot {
+    fn mid(&self) -> Option<f64> {
+        Some((self.bids.first()?.0 + self.asks.first()?.0) / 2.0)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SlippageEstimate {
+    /// Size of the orders, in quote currency.
+    pub notional: f64,
+    /// Buys and sells measured, two per snapshot deep enough.
+    pub orders: usize,
+    /// Snapshots without enough depth on one side to fill the order.
+    pub too_thin: usize,
+    /// Average slippage of the orders, as a fraction of the middle price like `slippage`.
+    pub mean: f64,
+    /// 95th percentile of the slippage of the orders.
+    pub p95: f64,
+}
+
+/// Reads the snapshots of a CSV file, see `parse_book`.
+#[cfg(feature = "files")]
+pub fn read_book(file_path: &str) -> Result<Vec<BookSnapshot>, String> {
+    let file = File::open(file_path).map_err(|e| format!("cannot read {}: {}", file_path, e))?;
+    parse_book(file)
+}
+
+/// Parses an order book from CSV rows of `timestamp`, `side` (`bid` or `ask`), `price` and `quantity`, one row per
+/// level, the rows of a snapshot sharing their timestamp.
+pub fn parse_book(reader: impl Read) -> Result<Vec<BookSnapshot>, String> {
+    let mut rdr = Reader::from_reader(reader);
+    let mut snapshots: Vec<BookSnapshot> = Vec::new();
+    for (line, result) in rdr.deserialize().enumerate() {
+        let record: HashMap<String, String> = result.map_err(|e| e.to_string())?;
+        let field = |name: &str| record.get(name).map(|value| value.trim()).ok_or(format!("row {}: missing `{}` column", line + 1, name));
+        let number = |name: &str| field(name)?.parse::<f64>().map_err(|e| format!("row {}: {}: {}", line + 1, name, e));
+        let timestamp = parse_date(field("timestamp")?)?;
+        let level = (number("price")?, number("quantity")?);
+        if snapshots.last().is_none_or(|snapshot| snapshot.timestamp != timestamp) {
+            snapshots.push(BookSnapshot { timestamp, bids: Vec::new(), asks: Vec::new() });
+        }
+        let snapshot = snapshots.last_mut().unwrap();
+        match field("side")?.to_lowercase().as_str() {
+            "bid" => snapshot.bids.push(level),
+            "ask" => snapshot.asks.push(level),
+            side => return Err(format!("row {}: unknown side `{}`, expected bid or ask", line + 1, side)),
+        }
+    }
+    for snapshot in snapshots.iter_mut() {
+        snapshot.bids.sort_by(|a, b| b.0.total_cmp(&a.0));
+        snapshot.asks.sort_by(|a, b| a.0.total_cmp(&b.0));
+    }
+    Ok(snapshots)
+}
+
+/// Average price paid by a market order of `notional` walking `levels`, `None` when they are not deep enough.
+fn average_price(levels: &[(f64, f64)], notional: f64) -> Option<f64> {
+    let (mut remaining, mut quantity) = (notional, 0.0);
+    for (price, available) in levels.iter() {
+        let taken = remaining.min(price * available);
+        quantity += taken / price;
+        remaining -= taken;
+        if remaining <= 0.0 {
+            return Some(notional / quantity);
+        }
+    }
+    None
+}
+
+/// Measures the slippage of buys and sells of each size in `SIZE_FRACTIONS` of `notional` on every snapshot.
+pub fn calibrate(snapshots: &[BookSnapshot], notional: f64) -> Vec<SlippageEstimate> {
+    SIZE_FRACTIONS
+        .iter()
+        .map(|fraction| {
+            let size = notional * fraction;
+            let mut slippages = Vec::new();
+            let mut too_thin = 0;
+            for snapshot in snapshots.iter() {
+                let Some(mid) = snapshot.mid() else {
+                    too_thin += 1;
+                    continue;
+                };
+                match (average_price(&snapshot.asks, size), average_price(&snapshot.bids, size)) {
+                    (Some(buy), Some(sell)) => {
+                        slippages.push(buy / mid - 1.0);
+                        slippages.push(1.0 - sell / mid);
+                    }
+                    _ => too_thin += 1,
+                }
+            }
+            slippages.sort_by(|a, b| a.total_cmp(b));
+            SlippageEstimate {
+                notional: size,
+                orders: slippages.len(),
+                too_thin,
+                mean: crate::analysis::mean(&slippages),
+                p95: if slippages.is_empty() { 0.0 } else { percentile(&slippages, 95.0) },
+            }
+        })
+        .collect()
+}
+
+/// The slippage model of the estimates measured on at least one order, at their average slippage.
+pub fn model(estimates: &[SlippageEstimate]) -> SlippageModel {
+    SlippageModel {
+        points: estimates
+            .iter()
+            .filter(|estimate| estimate.orders > 0)
+            .map(|estimate| SlippagePoint {
+                notional: estimate.notional,
+                slippage: estimate.mean,
+            })
+            .collect(),
+    }
+}
@@ -0,0 +1,121 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use crate::config::StrategyConfig;
+use crate::regime::Regime;
+use crate::strategy::Signal;
+use crate::testing::{Metrics, PortfolioState};
+
+/// Snapshot of a simulation, enough to resume it without replaying the bars already processed.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub bars_processed: usize,
+    pub last_timestamp: NaiveDateTime,
+    pub portfolio: PortfolioState,
+    pub strategy: serde_json::Value,
+    /// State of the regime classifier, null without `regime`.
+    #[serde(default)]
+    pub regime: serde_json::Value,
+    #[serde(default)]
+    pub regimes: Vec<(NaiveDateTime, Regime)>,
+    /// State of the rejection model, null without `rejections`.
+    #[serde(default)]
+    pub rejections: serde_json::Value,
+    #[serde(default)]
+    pub requotes: usize,
+    /// State of each risk manager, in the order of `risk_managers`.
+    #[serde(default)]
+    pub risk_managers: Vec<serde_json::Value>,
+    /// Signal of the last candle and its close, to be filled at the open of the next one with `NextOpen`.
+    #[serde(default)]
+    pub pending_signal: Option<(Signal, f64)>,
+    /// Average true range of the `Atr` trailing stops, null in checkpoints written before it was saved.
+    #[serde(default)]
+    pub trailing_atr: serde_json::Value,
+    /// State of the position sizer, null without `VolatilityTarget` sizing.
+    #[serde(default)]
+    pub sizer: serde_json::Value,
+}
+
+#[cfg(feature = "files")]
+pub fn save_checkpoint(file_path: &str, checkpoint: &Checkpoint) -> Result<(), Box<dyn std::error::Error>> {
+    let json = serde_json::to_string(checkpoint)?;
+    // write to a temporary file first so an interrupted save never leaves a truncated checkpoint behind
+    let tmp_path = format!("{}.tmp", file_path);
+    std::fs::write(&tmp_path, json)?;
+    std::fs::rename(&tmp_path, file_path)?;
+    Ok(())
+}
+
+#[cfg(feature = "files")]
+pub fn load_checkpoint(file_path: &str) -> Result<Checkpoint, Box<dyn std::error::Error>> {
+    let json = std::fs::read_to_string(file_path)?;
+    let checkpoint = serde_json::from_str(&json)?;
+    Ok(checkpoint)
+}
+
+/// A finished variant of a sweep, with what is needed to rank it again without running it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SweepEntry {
+    pub parameters: StrategyConfig,
+    pub metrics: Metrics,
+    pub equity_curve: Vec<(NaiveDateTime, f64)>,
+    /// Metrics on each cross-validation fold, empty without cross-validation.
+    pub fold_metrics: Vec<Metrics>,
+}
+
+/// Reads the variants already run by a sweep. The first line of the file holds the settings the results depend on,
+/// and each following line a variant, so an interrupted sweep loses at most the line it was writing. The file is
+/// created when missing, and rewritten without the truncated line otherwise.
+#[cfg(feature = "files")]
+pub fn load_sweep_progress(file_path: &str, settings: &serde_json::Value) -> Result<Vec<SweepEntry>, Box<dyn std::error::Error>> {
+    let text = match std::fs::read_to_string(file_path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let mut lines = text.lines();
+    if let Some(header) = lines.next() {
+        if header != serde_json::to_string(settings)? {
+            return Err(format!("{} was made with other settings or data, remove it to start the sweep over", file_path).into());
+        }
+    }
+    let entries: Vec<SweepEntry> = lines.map_while(|line| serde_json::from_str(line).ok()).collect();
+    let mut json = serde_json::to_string(settings)? + "\n";
+    for entry in entries.iter() {
+        json += &(serde_json::to_string(entry)? + "\n");
+    }
+    let tmp_path = format!("{}.tmp", file_path);
+    std::fs::write(&tmp_path, json)?;
+    std::fs::rename(&tmp_path, file_path)?;
+    Ok(entries)
+}
+
+/// Adds a finished variant to the progress file of a sweep, in a single write.
+#[cfg(feature = "files")]
+pub fn append_sweep_entry(file_path: &str, entry: &SweepEntry) -> Result<(), Box<dyn std::error::Error>> {
+    use std::io::Write;
+    let json = serde_json::to_string(entry)? + "\n";
+    std::fs::OpenOptions::new().append(true).open(file_path)?.write_all(json.as_bytes())?;
+    Ok(())
+}
+
+/// Checkpoints live in files, so they are unavailable without the `files` feature.
+#[cfg(not(feature = "files"))]
+pub fn save_checkpoint(_file_path: &str, _checkpoint: &Checkpoint) -> Result<(), Box<dyn std::error::Error>> {
+    Err("checkpoints need the `files` feature".into())
+}
+
+#[cfg(not(feature = "files"))]
+pub fn load_checkpoint(_file_path: &str) -> Result<Checkpoint, Box<dyn std::error::Error>> {
+    Err("checkpoints need the `files` feature".into())
+}
+
+#[cfg(not(feature = "files"))]
+pub fn load_sweep_progress(_file_path: &str, _settings: &serde_json::Value) -> Result<Vec<SweepEntry>, Box<dyn std::error::Error>> {
+    Err("sweep progress files need the `files` feature".into())
+}
+
+#[cfg(not(feature = "files"))]
+pub fn append_sweep_entry(_file_path: &str, _entry: &SweepEntry) -> Result<(), Box<dyn std::error::Error>> {
+    Err("sweep progress files need the `files` feature".into())
+}
@@ -0,0 +1,138 @@
+/// What the program was asked to do.
+#[derive(Clone)]
+pub enum Command {
+    Backtest,
+    /// Check a config file without running anything.
+    ConfigValidate,
+    /// Print the JSON Schema of the config.
+    ConfigSchema,
+    /// Create a starter project in a directory.
+    Init { directory: String },
+    /// Write the skeleton of a strategy module with this name.
+    NewStrategy { name: String },
+    /// Run the variants of the sweep of a coordinator listening at this address.
+    Worker { address: String },
+    /// Report the average returns of the data by hour, weekday and month, without any strategy.
+    AnalyzeSeasonality,
+    /// Measure the slippage of orders up to `notional` on order book snapshots and write the model to `output`.
+    CalibrateSlippage { book_path: String, notional: f64, output: String },
+}
+
+pub struct Args {
+    pub command: Command,
+    pub config_path: String,
+    pub profile: Option<String>,
+    /// `--set key=value` overrides, applied on top of the config file and profile.
+    pub overrides: Vec<(String, String)>,
+    /// `--dashboard`, only given on the command line.
+    pub dashboard: bool,
+}
+
+fn usage() -> ! {
+    eprintln!("Usage:");
+    eprintln!("  retroval [backtest] [--config <path>] [--profile <name>] [--set <key>=<value>]... [--headless] [--fast] [--dashboard] [--chart-range <start>..<end>]");
+    eprintln!("  retroval config validate [<path>] [--profile <name>] [--set <key>=<value>]...");
+    eprintln!("  retroval config schema");
+    eprintln!("  retroval init [<directory>]");
+    eprintln!("  retroval new-strategy <name>");
+    eprintln!("  retroval worker <address> [--config <path>] [--profile <name>] [--set <key>=<value>]...");
+    eprintln!("  retroval analyze seasonality [--config <path>] [--profile <name>] [--set <key>=<value>]... [--headless]");
+    eprintln!("  retroval calibrate slippage <order book> --notional <amount> [--output <path>]");
+    std::process::exit(2);
+}
+
+/// Parses the command line, without the program name.
+pub fn parse_args(args: impl Iterator<Item = String>) -> Args {
+    let mut parsed = Args {
+        command: Command::Backtest,
+        config_path: "config.json".to_string(),
+        profile: None,
+        overrides: Vec::new(),
+        dashboard: false,
+    };
+    let mut args = args.peekable();
+    if let Some(command) = args.peek() {
+        if !command.starts_with("--") {
+            parsed.command = match command.as_str() {
+                "backtest" => Command::Backtest,
+                "init" => Command::Init { directory: ".".to_string() },
+                "worker" => Command::Worker { address: String::new() },
+                "new-strategy" => Command::NewStrategy { name: String::new() },
+                "config" => {
+                    args.next();
+                    match args.peek().map(|arg| arg.as_str()) {
+                        Some("validate") => Command::ConfigValidate,
+                        Some("schema") => Command::ConfigSchema,
+                        _ => usage(),
+                    }
+                }
+                "calibrate" => {
+                    args.next();
+                    match args.peek().map(|arg| arg.as_str()) {
+                        Some("slippage") => Command::CalibrateSlippage {
+                            book_path: String::new(),
+                            notional: 0.0,
+                            output: "slippage_model.json".to_string(),
+                        },
+                        _ => usage(),
+                    }
+                }
+                "analyze" => {
+                    args.next();
+                    match args.peek().map(|arg| arg.as_str()) {
+                        Some("seasonality") => Command::AnalyzeSeasonality,
+                        _ => usage(),
+                    }
+                }
+                _ => usage(),
+            };
+            args.next();
+        }
+    }
+    // these commands take an optional positional path, the worker the address of the coordinator, new-strategy a name
+    // and the calibration its order book
+    if let Some(path) = args.next_if(|arg| !arg.starts_with("--")) {
+        match &mut parsed.command {
+            Command::ConfigValidate => parsed.config_path = path,
+            Command::Init { directory } => *directory = path,
+            Command::Worker { address } => *address = path,
+            Command::NewStrategy { name } => *name = path,
+            Command::CalibrateSlippage { book_path, .. } => *book_path = path,
+            _ => usage(),
+        }
+    }
+    if matches!(&parsed.command, Command::Worker { address: value } | Command::NewStrategy { name: value } | Command::CalibrateSlippage { book_path: value, .. } if value.is_empty()) {
+        usage();
+    }
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => parsed.config_path = args.next().unwrap_or_else(|| usage()),
+            "--profile" => parsed.profile = Some(args.next().unwrap_or_else(|| usage())),
+            "--headless" => parsed.overrides.push(("headless".to_string(), "true".to_string())),
+            "--fast" => parsed.overrides.push(("fast".to_string(), "true".to_string())),
+            "--dashboard" => parsed.dashboard = true,
+            "--chart-range" => {
+                let range = args.next().unwrap_or_else(|| usage());
+                parsed.overrides.push(("chart_range".to_string(), range));
+            }
+            "--notional" | "--output" => {
+                let value = args.next().unwrap_or_else(|| usage());
+                match (&mut parsed.command, arg.as_str()) {
+                    (Command::CalibrateSlippage { notional, .. }, "--notional") => *notional = value.parse().unwrap_or_else(|_| usage()),
+                    (Command::CalibrateSlippage { output, .. }, _) => *output = value,
+                    _ => usage(),
+                }
+            }
+            "--set" => {
+                let assignment = args.next().unwrap_or_else(|| usage());
+                let (key, value) = assignment.split_once('=').unwrap_or_else(|| usage());
+                parsed.overrides.push((key.to_string(), value.to_string()));
+            }
+            _ => usage(),
+        }
+    }
+    if matches!(&parsed.command, Command::CalibrateSlippage { notional, .. } if *notional <= 0.0) {
+        usage();
+    }
+    parsed
+}
@@ -1,32 +1,1187 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use chrono::NaiveDateTime;
 use serde_json::{self, Map};
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize};
+use crate::expression::{Constraint, Expression, Parsed};
+use crate::symbol::Symbol;
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 pub enum LogLevel {
     All,
+    /// What `Info` logs, plus every signal of the strategy with the indicator values behind it.
+    Signals,
     Info,
     None,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// What to do with a trade that is still open when the data runs out.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, JsonSchema)]
+pub enum EndOfData {
+    /// Close it at the last candle and count it like any other trade.
+    #[default]
+    ForceClose,
+    /// Leave it open and report its value at the last candle as unrealized profit.
+    MarkToMarket,
+    /// Close it at the last candle, but leave it out of the metrics.
+    Exclude,
+}
+
+/// The metric runs are ranked by in the summary table, best first.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, JsonSchema)]
+pub enum Objective {
+    #[default]
+    TotalProfit,
+    SharpeRatio,
+    ProfitFactor,
+    WinRate,
+    /// The shallowest drawdown ranks first.
+    MaxDrawdown,
+    /// Arithmetic over the metrics, like `sharpe - 0.5 * max_drawdown_pct`. Runs breaking one of the constraints,
+    /// like `max_drawdown_pct < 15`, rank last.
+    Expression {
+        expression: Parsed<Expression>,
+        #[serde(default)]
+        constraints: Vec<Parsed<Constraint>>,
+    },
+}
+
+impl std::fmt::Display for Objective {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Objective::Expression { expression, constraints } if constraints.is_empty() => write!(f, "{}", expression),
+            Objective::Expression { expression, constraints } => {
+                let constraints: Vec<&str> = constraints.iter().map(|constraint| constraint.text.as_str()).collect();
+                write!(f, "{} with {}", expression, constraints.join(", "))
+            }
+            objective => write!(f, "{:?}", objective),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, JsonSchema)]
+pub enum Theme {
+    #[default]
+    Light,
+    Dark,
+}
+
+/// Look of the graph. Colors are hex strings (`"#26a69a"`), and default to the ones of the theme.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields, default)]
+pub struct ChartStyle {
+    pub theme: Theme,
+    pub up_color: Option<String>,
+    pub down_color: Option<String>,
+    pub font: String,
+    /// Width of a candle in pixels at 96 DPI, the width of the graph follows the number of candles.
+    pub candle_width: u32,
+    /// Width of the graphs in pixels at 96 DPI, the candles being sized to fill it. When not set, the main graph
+    /// follows `candle_width` and the others are 1024 pixels wide (1280 for comparisons).
+    pub width: Option<u32>,
+    /// Height of the graph in pixels at 96 DPI.
+    pub height: u32,
+    /// Scales every size of the graph, 192 renders it at twice the resolution.
+    pub dpi: u32,
+    /// Candles drawn at most, longer data being merged into fewer candles. `0` draws them all.
+    pub max_candles: usize,
+    pub panels: PanelRatios,
+}
+
+/// Heights of the panels of the main graph relative to each other. The price and equity panels share `height`, the
+/// rolling metric panel is added under them.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields, default)]
+pub struct PanelRatios {
+    pub price: f64,
+    pub equity: f64,
+    pub rolling: f64,
+}
+
+impl Default for PanelRatios {
+    fn default() -> Self {
+        Self {
+            price: 2.0,
+            equity: 1.0,
+            rolling: 0.75,
+        }
+    }
+}
+
+impl Default for ChartStyle {
+    fn default() -> Self {
+        Self {
+            theme: Theme::Light,
+            up_color: None,
+            down_color: None,
+            font: "sans-serif".to_string(),
+            candle_width: 5,
+            width: None,
+            height: 768,
+            dpi: 96,
+            max_candles: 5000,
+            panels: PanelRatios::default(),
+        }
+    }
+}
+
+/// Metric of the rolling panel drawn under the equity curve.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, JsonSchema)]
+pub enum RollingMetricKind {
+    /// Mean over standard deviation of the returns of the trades, not annualized.
+    Sharpe,
+    /// Percentage of winning trades.
+    WinRate,
+}
+
+/// A panel of the main graph, written to a file of its own with `graph_panels`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, JsonSchema)]
+pub enum GraphPanel {
+    Price,
+    Equity,
+    /// Distance of the equity from its highest point so far, in percentage. Only drawn in a file of its own.
+    Drawdown,
+    /// The `rolling_metric` panel.
+    Rolling,
+}
+
+impl GraphPanel {
+    /// Suffix of the file of the panel, `graph.png` giving `graph_equity.png`.
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            GraphPanel::Price => "price",
+            GraphPanel::Equity => "equity",
+            GraphPanel::Drawdown => "drawdown",
+            GraphPanel::Rolling => "rolling",
+        }
+    }
+}
+
+/// Animated GIF of the run: the price and equity panels drawn again every `every` candles, filled up to that time.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields, default)]
+pub struct Animation {
+    pub file: String,
+    /// Candles between two frames, so that the animation has about 100 frames when not set.
+    pub every: Option<usize>,
+    /// Time each frame is shown, in milliseconds.
+    pub frame_delay: u32,
+}
+
+impl Default for Animation {
+    fn default() -> Self {
+        Self {
+            file: "animation.gif".to_string(),
+            every: None,
+            frame_delay: 100,
+        }
+    }
+}
+
+/// A metric computed over the last `window` closed trades, after each trade.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RollingMetric {
+    pub metric: RollingMetricKind,
+    pub window: usize,
+}
+
+/// How pivot levels are derived from the high, low and close of the previous session.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, JsonSchema)]
+pub enum PivotKind {
+    /// Floor trader pivots: the supports and resistances step away from the pivot by multiples of the range.
+    Classic,
+    /// The supports and resistances are 38.2%, 61.8% and 100% of the range away from the pivot.
+    Fibonacci,
+}
+
+/// Support and resistance levels drawn on the candlestick chart.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ChartLevels {
+    /// Swing highs and lows confirmed over this many candles on each side, drawn until a close crosses them.
+    #[serde(default)]
+    pub swing_window: Option<usize>,
+    /// Pivot levels of each day, from the previous one.
+    #[serde(default)]
+    pub pivots: Option<PivotKind>,
+}
+
+/// Which level is assumed to be hit first when a candle reaches both the stop-loss and the take-profit.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, JsonSchema)]
+pub enum IntrabarAssumption {
+    /// The stop-loss is always hit first.
+    #[default]
+    WorstCase,
+    /// The take-profit is always hit first.
+    BestCase,
+    /// The price goes from the open to the closest extreme first, then to the other one.
+    OhlcPath,
+}
+
+/// Distance of a trailing stop behind the best price since the entry, see `trailing_stop`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, JsonSchema)]
+pub enum TrailingStop {
+    /// A percentage of the best price.
+    Percent(f64),
+    /// A multiple of the average true range over `trailing_atr_window` candles.
+    Atr(f64),
+    /// No trailing stop, for a strategy turning off the one of the config for a trade, see `strategy::ExitLevels`.
+    None,
+}
+
+/// Price the orders of the signals are filled at.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, JsonSchema)]
+pub enum SignalFill {
+    /// The close of the candle the signal was given on.
+    #[default]
+    Close,
+    /// The open of the next candle, as an order placed after the close of a session.
+    NextOpen,
+}
+
+/// Sides traded by the engine, whatever the strategy signals.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default, JsonSchema)]
+pub enum DirectionFilter {
+    /// Buy signals open long trades and sell signals close them.
+    #[default]
+    LongOnly,
+    /// Sell signals open short trades and buy signals close them.
+    ShortOnly,
+    /// Buy and sell signals open trades on their side, closing the trade on the other side first.
+    Both,
+}
+
+/// How the portfolio accounts for positions and commissions.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default, JsonSchema)]
+pub enum Accounting {
+    /// Positions are worth their allocated notional moved by the price, both commissions being paid on exit.
+    #[default]
+    Cfd,
+    /// Buying converts the quote currency into base asset held in the wallet, commissions being taken on each fill.
+    Spot,
+}
+
+/// Conventions of the numbers of the console, HTML and CSV reports, see `locale.rs`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, JsonSchema)]
+pub enum Locale {
+    /// `1,234.56`, `$1,234.56`.
+    #[serde(rename = "en-US")]
+    EnUs,
+    /// `1,234.56`, `£1,234.56`.
+    #[serde(rename = "en-GB")]
+    EnGb,
+    /// `1.234,56`, `1.234,56 €`.
+    #[serde(rename = "de-DE")]
+    DeDe,
+    /// `1 234,56`, `1 234,56 €`.
+    #[serde(rename = "fr-FR")]
+    FrFr,
+    /// `1.234,56`, `1.234,56 €`.
+    #[serde(rename = "es-ES")]
+    EsEs,
+    /// `1.234,56`, `1.234,56 €`.
+    #[serde(rename = "it-IT")]
+    ItIt,
+    /// `1.234,56`, `€ 1.234,56`.
+    #[serde(rename = "nl-NL")]
+    NlNl,
+    /// `1'234.56`, `€ 1'234.56`.
+    #[serde(rename = "de-CH")]
+    DeCh,
+}
+
+/// A risk manager reviewing entry orders, see `risk.rs`.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub enum RiskManagerConfig {
+    /// Caps each position to `max_fraction` of the current equity.
+    MaxExposure { max_fraction: f64 },
+    /// Blocks entries taking the value of all the positions of the session above `max_fraction` of its equity.
+    MaxGrossExposure { max_fraction: f64 },
+    /// Blocks entries taking the long minus the short positions of the session beyond `max_fraction` of its equity.
+    MaxNetExposure { max_fraction: f64 },
+    /// Scales positions down when the realized volatility over `window` candles exceeds `target_volatility` (in percentage).
+    VolatilityScaled { window: usize, target_volatility: f64 },
+    /// Multiplies entries by `size_factor`, or blocks them when it is 0, while the realized volatility over `window`
+    /// candles is at or above `percentile` of the ones of the last `lookback` candles.
+    VolatilityThrottle {
+        window: usize,
+        percentile: f64,
+        #[serde(default)]
+        size_factor: f64,
+        #[serde(default = "default_volatility_lookback")]
+        lookback: usize,
+    },
+}
+
+/// How much to allocate to each new trade, see `sizing.rs`.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub enum Sizing {
+    /// A fixed fraction of the available cash, so position sizes compound with the results.
+    Fraction { fraction: f64 },
+    /// The same amount of quote currency for every trade, removing compounding effects.
+    FixedNotional { amount: f64 },
+    /// Sized so that a move of `atr_multiplier` ATRs against the position loses `risk_fraction` of the equity.
+    /// `fallback_fraction` of the cash is used until `atr_window` candles have been seen.
+    VolatilityTarget { risk_fraction: f64, atr_window: usize, atr_multiplier: f64, fallback_fraction: f64 },
+    /// A number of forex lots of the base currency for every trade.
+    Lots { lots: f64, size: LotSize },
+}
+
+/// Standard sizes of a forex lot.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, JsonSchema)]
+pub enum LotSize {
+    /// 100 000 units of the base currency.
+    Standard,
+    /// 10 000 units.
+    Mini,
+    /// 1 000 units.
+    Micro,
+}
+
+impl LotSize {
+    pub fn units(&self) -> f64 {
+        match self {
+            LotSize::Standard => 100_000.0,
+            LotSize::Mini => 10_000.0,
+            LotSize::Micro => 1_000.0,
+        }
+    }
+}
+
+/// Forex conventions: price moves counted in pips, and a spread between the bid and the ask.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct FxConfig {
+    /// Price move of one pip, defaults to 0.01 when the quote is JPY and 0.0001 otherwise.
+    #[serde(default)]
+    pub pip_size: Option<f64>,
+    /// Distance between the bid and the ask in pips, the prices of the data being the middle of it.
+    #[serde(default)]
+    pub spread_pips: f64,
+}
+
+impl FxConfig {
+    pub fn pip(&self, symbol: &Symbol) -> f64 {
+        match self.pip_size {
+            Some(pip_size) => pip_size,
+            None if symbol.quote == "JPY" => 0.01,
+            None => 0.0001,
+        }
+    }
+}
+
+impl Default for Sizing {
+    fn default() -> Self {
+        Sizing::Fraction { fraction: 0.1 }
+    }
+}
+
+/// A strategy picked by name, with its parameters passed as-is, see `strategy::build_strategy`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+pub struct StrategyConfig {
+    pub name: String,
+    #[serde(default)]
+    pub params: serde_json::Value,
+}
+
+impl StrategyConfig {
+    /// Name of the strategy followed by its parameters, to tell apart several instances of the same strategy.
+    pub fn label(&self) -> String {
+        match &self.params {
+            serde_json::Value::Null => self.name.clone(),
+            params => format!("{} {}", self.name, params),
+        }
+    }
+}
+
+/// Runs the strategy once per combination of parameters, each run with the whole capital, and keeps the best one
+/// according to the objective.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Sweep {
+    pub name: String,
+    /// Parameters of the strategy, arrays holding the values to try and anything else being used as-is.
+    pub params: serde_json::Map<String, serde_json::Value>,
+    /// Number of bootstrap samples of the reality check, 0 to skip it.
+    #[serde(default = "default_reality_check_samples")]
+    pub reality_check_samples: usize,
+    /// Average length in candles of the blocks resampled by the reality check.
+    #[serde(default = "default_block_length")]
+    pub block_length: usize,
+    #[serde(default)]
+    pub seed: u64,
+    /// Selects the parameters by cross-validation over this many contiguous folds of the data instead of
+    /// on the whole data.
+    #[serde(default)]
+    pub folds: Option<usize>,
+    /// Number of candles dropped at both ends of each fold.
+    #[serde(default)]
+    pub purge: usize,
+    /// Saves every finished variant to this file, and skips the variants it already lists.
+    #[serde(default)]
+    pub progress_file: Option<String>,
+    /// Address to listen on for workers (`retroval worker <address>`), which then run the variants instead of this process.
+    #[serde(default)]
+    pub workers: Option<String>,
+    /// Seconds a worker has to return a variant before it is considered lost and the variant is sent to another one.
+    #[serde(default = "default_worker_timeout")]
+    pub worker_timeout: u64,
+    /// Secret the workers must present before receiving anything, required with `workers`. Give it through an
+    /// environment variable (`"${RETROVAL_WORKER_TOKEN}"`) so it is not written with the results.
+    #[serde(default)]
+    pub worker_token: Option<String>,
+    /// Parameters searched by the optimizer, by name, on top of the fixed ones of `params`.
+    #[serde(default)]
+    pub ranges: BTreeMap<String, ParameterRange>,
+    /// Searches `ranges` instead of trying every combination of `params`.
+    #[serde(default)]
+    pub optimizer: Option<Optimizer>,
+    /// Number of values of each range tried by the sensitivity report.
+    #[serde(default = "default_sensitivity_points")]
+    pub sensitivity_points: usize,
+}
+
+/// Bounds of a parameter searched by the optimizer. The bounds and the type default to the ones the strategy declares.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ParameterRange {
+    #[serde(default)]
+    pub min: Option<f64>,
+    #[serde(default)]
+    pub max: Option<f64>,
+    /// Only try whole numbers.
+    #[serde(default)]
+    pub integer: Option<bool>,
+    /// Where the good values are expected to be, the whole range being equally likely when not set.
+    #[serde(default)]
+    pub prior: Option<Prior>,
+}
+
+/// A normal distribution, cut at the bounds of the range.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Prior {
+    pub mean: f64,
+    pub std: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub enum Optimizer {
+    /// Bayesian optimization with a tree-structured Parzen estimator: after `initial_trials` random ones, every
+    /// trial tries the values most likely among the best trials so far compared to the others.
+    Tpe {
+        trials: usize,
+        #[serde(default = "default_initial_trials")]
+        initial_trials: usize,
+    },
+}
+
+fn default_reality_check_samples() -> usize {
+    1000
+}
+
+fn default_block_length() -> usize {
+    24
+}
+
+fn default_worker_timeout() -> u64 {
+    600
+}
+
+fn default_initial_trials() -> usize {
+    10
+}
+
+fn default_sensitivity_points() -> usize {
+    9
+}
+
+impl Sweep {
+    /// Every combination of the parameters, the last parameter changing first.
+    pub fn variants(&self) -> Vec<StrategyConfig> {
+        let mut variants = vec![serde_json::Map::new()];
+        for (name, values) in self.params.iter() {
+            let values = match values {
+                serde_json::Value::Array(values) => values.clone(),
+                value => vec![value.clone()],
+            };
+            variants = variants
+                .into_iter()
+                .flat_map(|variant| {
+                    values.iter().map(move |value| {
+                        let mut variant = variant.clone();
+                        variant.insert(name.clone(), value.clone());
+                        variant
+                    })
+                })
+                .collect();
+        }
+        variants
+            .into_iter()
+            .map(|params| StrategyConfig {
+                name: self.name.clone(),
+                params: serde_json::Value::Object(params),
+            })
+            .collect()
+    }
+}
+
+/// A strategy trading a share of the capital in a multi-strategy session.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct StrategyAllocation {
+    #[serde(flatten)]
+    pub strategy: StrategyConfig,
+    pub weight: f64,
+}
+
+/// Periodically moves idle cash between strategies based on their trailing performance.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Reallocation {
+    /// Number of candles between two reallocations.
+    pub interval: usize,
+    /// Number of candles the trailing return is measured over.
+    pub lookback: usize,
+}
+
+/// An external series the results are compared to.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct BenchmarkConfig {
+    pub path: String,
+    pub timestamp_header: String,
+    pub value_header: String,
+}
+
+/// A currency the results are also reported in, see `reporting.rs`.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ReportingCurrency {
+    pub currency: String,
+    /// Series of the price of one unit of the quote currency in `currency`.
+    pub path: String,
+    pub timestamp_header: String,
+    pub value_header: String,
+    /// The series is the price of one unit of `currency` in the quote currency instead.
+    #[serde(default)]
+    pub inverted: bool,
+}
+
+/// Resampling of the closed trades, see `montecarlo.rs`.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct MonteCarlo {
+    #[serde(default = "default_simulations")]
+    pub simulations: usize,
+    #[serde(default)]
+    pub seed: u64,
+}
+
+fn default_simulations() -> usize {
+    1000
+}
+
+/// Alternative exits replayed on the closed trades, see `exits.rs`.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ExitAnalysisConfig {
+    /// Take-profit targets in multiples of the risk of the trade (R), with a stop-loss one risk away.
+    #[serde(default = "default_r_targets")]
+    pub r_targets: Vec<f64>,
+    /// Trailing stops, in percentage below the highest price since the entry (above the lowest for shorts).
+    #[serde(default = "default_trailing_stops")]
+    pub trailing_stops: Vec<f64>,
+    /// Risk of the trades without a stop-loss, in percentage of the entry price.
+    #[serde(default = "default_exit_risk")]
+    pub risk: f64,
+}
+
+fn default_r_targets() -> Vec<f64> {
+    vec![1.0, 2.0, 3.0]
+}
+
+fn default_trailing_stops() -> Vec<f64> {
+    vec![0.5, 1.0, 2.0, 5.0]
+}
+
+fn default_exit_risk() -> f64 {
+    1.0
+}
+
+/// Returns following the entries of the trades, see `analysis::forward_returns`.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ForwardReturnsConfig {
+    /// Candles after the entry the returns are measured at.
+    #[serde(default = "default_horizons")]
+    pub horizons: Vec<usize>,
+}
+
+fn default_horizons() -> Vec<usize> {
+    vec![1, 2, 5, 10, 20, 50, 100]
+}
+
+/// Share of the entries followed by a favorable move of several ATRs, see `analysis::signal_quality`.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SignalQualityConfig {
+    /// Candles of the average true range the moves are measured in.
+    #[serde(default = "default_atr_window")]
+    pub atr_window: usize,
+    /// Moves counted as hits, in ATRs.
+    #[serde(default = "default_atr_multiples")]
+    pub multiples: Vec<f64>,
+    /// Candles after the entry the move has to happen within.
+    #[serde(default = "default_hit_horizons")]
+    pub horizons: Vec<usize>,
+}
+
+fn default_atr_window() -> usize {
+    14
+}
+
+fn default_atr_multiples() -> Vec<f64> {
+    vec![0.5, 1.0, 2.0]
+}
+
+fn default_hit_horizons() -> Vec<usize> {
+    vec![5, 10, 20]
+}
+
+/// Classification of every candle in a market regime, see `regime.rs`.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RegimeConfig {
+    #[serde(default = "default_adx_window")]
+    pub adx_window: usize,
+    /// ADX above which the market is trending.
+    #[serde(default = "default_adx_threshold")]
+    pub adx_threshold: f64,
+    #[serde(default = "default_volatility_window")]
+    pub volatility_window: usize,
+    /// Percentile of the realized volatility of the last `volatility_lookback` candles from which the market is in
+    /// high volatility, whatever the ADX.
+    #[serde(default = "default_volatility_percentile")]
+    pub volatility_percentile: f64,
+    #[serde(default = "default_volatility_lookback")]
+    pub volatility_lookback: usize,
+}
+
+fn default_adx_window() -> usize {
+    14
+}
+
+fn default_adx_threshold() -> f64 {
+    25.0
+}
+
+fn default_volatility_window() -> usize {
+    24
+}
+
+fn default_volatility_percentile() -> f64 {
+    80.0
+}
+
+fn default_volatility_lookback() -> usize {
+    2000
+}
+
+/// Entry orders rejected or requoted by the exchange, see `events::RejectionModel`.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RejectionConfig {
+    /// Probability that an entry order is rejected, in percentage.
+    #[serde(default)]
+    pub rejection_rate: f64,
+    /// Probability that a market entry is requoted, in percentage.
+    #[serde(default)]
+    pub requote_rate: f64,
+    /// Move of the price against a requoted entry, in percentage.
+    #[serde(default)]
+    pub requote_slippage: f64,
+    #[serde(default)]
+    pub seed: u64,
+}
+
+/// Slippage growing with the size of the orders, as measured by `retroval calibrate slippage`.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SlippageModel {
+    /// Slippage at given order sizes, interpolated in between and kept flat beyond the smallest and the largest.
+    /// Sorted by notional when the config is read.
+    #[serde(deserialize_with = "sorted_slippage_points")]
+    pub points: Vec<SlippagePoint>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SlippagePoint {
+    /// Size of the order, in quote currency.
+    pub notional: f64,
+    /// Slippage of the order, in the unit of `slippage`.
+    pub slippage: f64,
+}
+
+/// Reads the points of a slippage model, sorted by notional, rejecting the ones that cannot be interpolated.
+fn sorted_slippage_points<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<SlippagePoint>, D::Error> {
+    let mut points = Vec::<SlippagePoint>::deserialize(deserializer)?;
+    if let Some(point) = points.iter().find(|point| !(point.notional.is_finite() && point.notional > 0.0)) {
+        return Err(serde::de::Error::custom(format!("the notional of a slippage point must be positive, got {}", point.notional)));
+    }
+    if let Some(point) = points.iter().find(|point| !(point.slippage.is_finite() && point.slippage >= 0.0)) {
+        return Err(serde::de::Error::custom(format!("the slippage of a slippage point cannot be negative, got {}", point.slippage)));
+    }
+    points.sort_by(|a, b| a.notional.total_cmp(&b.notional));
+    if let Some(pair) = points.windows(2).find(|pair| pair[0].notional == pair[1].notional) {
+        return Err(serde::de::Error::custom(format!("two slippage points have the notional {}", pair[0].notional)));
+    }
+    Ok(points)
+}
+
+impl SlippageModel {
+    /// Slippage of an order of `notional`, see `points`. Zero without any point.
+    pub fn slippage(&self, notional: f64) -> f64 {
+        let points = &self.points;
+        let (Some(first), Some(last)) = (points.first(), points.last()) else {
+            return 0.0;
+        };
+        if notional <= first.notional {
+            return first.slippage;
+        }
+        if notional >= last.notional {
+            return last.slippage;
+        }
+        let above = points.partition_point(|point| point.notional < notional);
+        let (low, high) = (&points[above - 1], &points[above]);
+        low.slippage + (high.slippage - low.slippage) * (notional - low.notional) / (high.notional - low.notional)
+    }
+}
+
+/// Windows in which the exchange is down, see `outage.rs`.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct OutageConfig {
+    /// Fixed windows, `start..end` like `chart_range`.
+    #[serde(default)]
+    pub windows: Vec<String>,
+    /// Probability that a random outage starts on a candle, in percentage.
+    #[serde(default)]
+    pub rate: f64,
+    /// Length of the random outages, in candles.
+    #[serde(default = "default_outage_duration")]
+    pub duration: usize,
+    #[serde(default)]
+    pub seed: u64,
+}
+
+fn default_outage_duration() -> usize {
+    6
+}
+
+/// How often a cash flow comes back, or a snapshot of the metrics is taken.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, JsonSchema)]
+pub enum Recurrence {
+    Daily,
+    Weekly,
+    /// On the same day of the month as the first one, or the last day of shorter months.
+    Monthly,
+}
+
+/// Candles kept by `--fast`, see `historical::thin`.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct FastSampling {
+    /// Candles merged into one.
+    #[serde(default = "default_stride")]
+    pub stride: usize,
+    /// Share of the candles kept at random instead, between 0 and 1.
+    #[serde(default)]
+    pub sample: Option<f64>,
+    #[serde(default)]
+    pub seed: u64,
+}
+
+impl Default for FastSampling {
+    fn default() -> Self {
+        Self {
+            stride: default_stride(),
+            sample: None,
+            seed: 0,
+        }
+    }
+}
+
+fn default_stride() -> usize {
+    10
+}
+
+/// Metrics taken during the run, see `testing::MetricSnapshot`. At least one of `bars` and `every` is needed.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct MetricSnapshots {
+    /// Takes a snapshot every that many candles.
+    #[serde(default)]
+    pub bars: Option<usize>,
+    /// Takes a snapshot on the last candle of every day, week or month.
+    #[serde(default)]
+    pub every: Option<Recurrence>,
+    /// JSON Lines file the snapshots are appended to as they are taken.
+    #[serde(default)]
+    pub file: Option<String>,
+}
+
+/// An external deposit or withdrawal of the quote currency, like a monthly contribution.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CashFlow {
+    /// Date of the first or only transfer, see `parse_date`.
+    pub date: String,
+    /// Deposited when positive, withdrawn when negative.
+    pub amount: f64,
+    #[serde(default)]
+    pub every: Option<Recurrence>,
+    /// Last date of a recurring transfer, included. The end of the data when not set.
+    #[serde(default)]
+    pub until: Option<String>,
+}
+
+/// Side of the position held at the start, see `InitialPosition`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, JsonSchema)]
+pub enum PositionSide {
+    Long,
+    Short,
+}
+
+/// A position already open when the backtest starts, managed like a trade of the strategy.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct InitialPosition {
+    pub direction: PositionSide,
+    /// Base asset held, or owed for a short, positive either way.
+    pub quantity: f64,
+    pub entry_price: f64,
+    /// See `parse_date`. The first candle when not set.
+    #[serde(default)]
+    pub entry_date: Option<String>,
+    /// Prices of the exits, computed from `stop_loss` and `take_profit` of the config when not set.
+    #[serde(default)]
+    pub stop_loss: Option<f64>,
+    #[serde(default)]
+    pub take_profit: Option<f64>,
+}
+
+/// State of the account at the start, on top of `base_funds` of free cash.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct InitialPortfolio {
+    #[serde(default)]
+    pub position: Option<InitialPosition>,
+    /// Amounts of other assets held by currency, outside of any position.
+    #[serde(default)]
+    pub holdings: BTreeMap<String, f64>,
+}
+
+/// Order in which the sales are matched against the lots held, see `tax.rs`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default, JsonSchema)]
+pub enum LotMethod {
+    /// First in, first out: the oldest lot is sold first.
+    #[default]
+    Fifo,
+    /// Last in, first out: the newest lot is sold first.
+    Lifo,
+    /// Highest in, first out: the lot with the highest cost is sold first.
+    Hifo,
+}
+
+/// Realized gains report, see `tax.rs`.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TaxConfig {
+    #[serde(default)]
+    pub method: LotMethod,
+    /// Gains of lots held more than this many days are long-term.
+    #[serde(default = "default_long_term_days")]
+    pub long_term_days: i64,
+    /// Export every disposal to this CSV file.
+    #[serde(default)]
+    pub report_file: Option<String>,
+}
+
+fn default_long_term_days() -> i64 {
+    365
+}
+
+/// A window coming back every day, or every week on `weekday`, like `{"weekday": "Wed", "start": "18:00", "end": "20:00"}`.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RecurringWindow {
+    #[serde(default)]
+    pub weekday: Option<String>,
+    pub start: String,
+    pub end: String,
+}
+
+/// Windows without new entries, see `blackout.rs`.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct BlackoutConfig {
+    /// Fixed windows, `start..end` like `chart_range`.
+    #[serde(default)]
+    pub windows: Vec<String>,
+    #[serde(default)]
+    pub recurring: Vec<RecurringWindow>,
+    /// Close the positions when a window starts.
+    #[serde(default)]
+    pub flatten: bool,
+}
+
+/// Conditions on which the run pauses to dump its state, see `debugger.rs`. Without any, it pauses on the first candle.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DebugConfig {
+    /// Pause on the first candle at or after this date, `YYYY-MM-DD` or `YYYY-MM-DD HH:MM:SS`.
+    #[serde(default)]
+    pub break_at: Option<String>,
+    /// Pause on every candle where a position is opened.
+    #[serde(default)]
+    pub break_on_trade: bool,
+    /// Pause when the drawdown of the equity from its peak goes above this percentage.
+    #[serde(default)]
+    pub break_on_drawdown: Option<f64>,
+}
+
+/// Thresholds the results have to meet, checked after the run. Criteria left out are not checked.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Acceptance {
+    pub min_sharpe: Option<f64>,
+    pub max_drawdown_pct: Option<f64>,
+    pub min_profit_factor: Option<f64>,
+    pub min_trades: Option<usize>,
+}
+
+impl Acceptance {
+    pub fn is_empty(&self) -> bool {
+        self.min_sharpe.is_none() && self.max_drawdown_pct.is_none() && self.min_profit_factor.is_none() && self.min_trades.is_none()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     pub data_path: String,
     pub headers: Map<String, serde_json::Value>,
     pub base_funds: f64,
     pub transaction_fee: f64,
     pub slippage: f64,
-    pub pair: String,
+    /// Replaces `slippage` with one depending on the size of each order.
+    #[serde(default)]
+    pub slippage_model: Option<SlippageModel>,
+    #[serde(default)]
+    pub min_notional: f64,
+    #[serde(default)]
+    pub cooldown_bars: usize,
+    /// The traded instrument, like `BTC/USDT`.
+    pub symbol: Symbol,
     pub timeframe: String,
-    pub base_currency: String,
-    pub quote_currency: String,
+    /// Aggregate the candles of the data to `timeframe` before the run.
+    #[serde(default)]
+    pub resample: bool,
+    /// Trading days in a year, annualizing the daily candles of markets closed on weekends and holidays.
+    #[serde(default)]
+    pub trading_days_per_year: Option<f64>,
     pub log_level: LogLevel,
     pub log_file: String,
+    /// Keep the log of the previous runs, prefixing every line with the run id.
+    #[serde(default)]
+    pub log_append: bool,
+    /// Rotate the log file once it reaches this size, in bytes.
+    #[serde(default)]
+    pub log_max_bytes: Option<u64>,
+    /// Number of rotated log files kept, `logs.log.1` being the most recent.
+    #[serde(default = "default_log_max_files")]
+    pub log_max_files: usize,
     pub log_graph: bool,
     pub log_graph_file: String,
+    #[serde(default)]
+    pub chart_style: ChartStyle,
+    /// Panels also written to files of their own next to the graph.
+    #[serde(default)]
+    pub graph_panels: Vec<GraphPanel>,
+    #[serde(default)]
+    pub animation: Option<Animation>,
+    #[serde(default)]
+    pub chart_range: Option<String>,
+    #[serde(default)]
+    pub trade_chart_context: Option<usize>,
+    /// Write the closed trades to this HTML file, one page each, see `tradebook.rs`.
+    #[serde(default)]
+    pub trade_book_file: Option<String>,
+    /// Format the numbers of the reports for this locale. The JSON outputs and the log keep the plain format.
+    #[serde(default)]
+    pub locale: Option<Locale>,
+    #[serde(default)]
+    pub chart_levels: Option<ChartLevels>,
+    #[serde(default)]
+    pub chart_volume_profile: Option<usize>,
+    /// Draws the number of positions open and their value over time, see `analysis::exposure_timeline`.
+    #[serde(default)]
+    pub chart_exposure: bool,
+    #[serde(default)]
+    pub rolling_metric: Option<RollingMetric>,
+    #[serde(default)]
+    pub end_of_data: EndOfData,
+    #[serde(default)]
+    pub stop_loss: Option<f64>,
+    #[serde(default)]
+    pub take_profit: Option<f64>,
+    /// Stop-loss following the price in the direction of the trade, never moving back.
+    #[serde(default)]
+    pub trailing_stop: Option<TrailingStop>,
+    #[serde(default = "default_atr_window")]
+    pub trailing_atr_window: usize,
+    #[serde(default)]
+    pub intrabar_assumption: IntrabarAssumption,
+    #[serde(default)]
+    pub signal_fill: SignalFill,
+    #[serde(default)]
+    pub fx: Option<FxConfig>,
+    #[serde(default)]
+    pub direction_filter: DirectionFilter,
+    #[serde(default)]
+    pub accounting: Accounting,
+    /// Annual interest rate on borrowed assets, in percentage.
+    #[serde(default)]
+    pub borrow_rate: f64,
+    #[serde(default)]
+    pub cash_flows: Vec<CashFlow>,
+    #[serde(default)]
+    pub initial_portfolio: Option<InitialPortfolio>,
+    #[serde(default)]
+    pub metric_snapshots: Option<MetricSnapshots>,
+    /// Also runs the strategy restarting flat with `base_funds` every calendar month, see `testing::monthly_restart`.
+    #[serde(default)]
+    pub monthly_restart: bool,
+    #[serde(default)]
+    pub rejections: Option<RejectionConfig>,
+    #[serde(default)]
+    pub outages: Option<OutageConfig>,
+    #[serde(default)]
+    pub blackouts: Option<BlackoutConfig>,
+    #[serde(default)]
+    pub checkpoint_file: Option<String>,
+    #[serde(default)]
+    pub checkpoint_interval: Option<usize>,
+    #[serde(default)]
+    pub resume_from: Option<String>,
+    #[serde(default)]
+    pub risk_managers: Vec<RiskManagerConfig>,
+    #[serde(default)]
+    pub sizing: Sizing,
+    /// Strategy of single-strategy runs, the built-in `simple` strategy when not set.
+    #[serde(default)]
+    pub strategy: Option<StrategyConfig>,
+    #[serde(default)]
+    pub strategies: Vec<StrategyAllocation>,
+    #[serde(default)]
+    pub sweep: Option<Sweep>,
+    #[serde(default)]
+    pub reallocation: Option<Reallocation>,
+    #[serde(default)]
+    pub plugins_dir: Option<String>,
+    #[serde(default)]
+    pub correlation_file: Option<String>,
+    #[serde(default = "default_correlation_threshold")]
+    pub correlation_threshold: f64,
+    #[serde(default)]
+    pub objective: Objective,
+    #[serde(default)]
+    pub summary_file: Option<String>,
+    /// Number of best weeks whose share of the profit is reported.
+    #[serde(default = "default_concentration_weeks")]
+    pub concentration_weeks: usize,
+    /// Keep the values of `Strategy::indicators` on every candle in the recap.
+    #[serde(default)]
+    pub record_indicators: bool,
+    /// Export the recorded indicators to this CSV file, recording them even without `record_indicators`.
+    #[serde(default)]
+    pub indicators_file: Option<String>,
+    /// Export every trade, with its id and the ids of its orders, to this CSV file.
+    #[serde(default)]
+    pub trades_file: Option<String>,
+    #[serde(default)]
+    pub tax: Option<TaxConfig>,
+    #[serde(default)]
+    pub benchmark: Option<BenchmarkConfig>,
+    #[serde(default)]
+    pub reporting_currency: Option<ReportingCurrency>,
+    #[serde(default)]
+    pub monte_carlo: Option<MonteCarlo>,
+    #[serde(default)]
+    pub exit_analysis: Option<ExitAnalysisConfig>,
+    #[serde(default)]
+    pub forward_returns: Option<ForwardReturnsConfig>,
+    #[serde(default)]
+    pub signal_quality: Option<SignalQualityConfig>,
+    #[serde(default)]
+    pub regime: Option<RegimeConfig>,
+    #[serde(default)]
+    pub debug: Option<DebugConfig>,
+    #[serde(default)]
+    pub acceptance: Acceptance,
+    #[serde(default)]
+    pub output_dir: Option<String>,
+    #[serde(default)]
+    pub run_id: Option<String>,
+    /// Set by `--headless`: no graph and a single JSON object on stdout.
+    #[serde(default)]
+    pub headless: bool,
+    /// Set by `--fast`: a rough run on part of the candles, see `fast_sampling`.
+    #[serde(default)]
+    pub fast: bool,
+    #[serde(default)]
+    pub fast_sampling: FastSampling,
+    /// Set by `--dashboard`, never read from the config: the progress of the run is published for the local web
+    /// dashboard, see `testing::live_status`.
+    #[serde(skip)]
+    pub dashboard: bool,
+    /// Address the dashboard is served on.
+    #[serde(default = "default_dashboard_address")]
+    pub dashboard_address: String,
+    /// Names and values of the environment variables expanded in the config, see `as_written`.
+    #[serde(skip)]
+    pub env_values: Vec<(String, String)>,
+}
+
+fn default_concentration_weeks() -> usize {
+    3
+}
+
+fn default_log_max_files() -> usize {
+    5
+}
+
+fn default_dashboard_address() -> String {
+    "127.0.0.1:8080".to_string()
+}
+
+fn default_correlation_threshold() -> f64 {
+    0.7
 }
 
 impl Config {
+    /// Decimals of the prices in the log: the `price_precision` of the symbol, one more than the pip with `fx`, 2
+    /// otherwise.
+    pub fn price_decimals(&self) -> usize {
+        match (self.symbol.price_precision, &self.fx) {
+            (Some(decimals), _) => decimals as usize,
+            (None, Some(fx)) => (-fx.pip(&self.symbol).log10()).round().max(0.0) as usize + 1,
+            (None, None) => 2,
+        }
+    }
+
     pub fn get_headers(&self) -> HashMap<String, String> {
         let mut headers = HashMap::new();
         for (header, value) in &self.headers {
@@ -34,10 +1189,413 @@ impl Config {
         }
         headers
     }
+
+    /// Checks the values the types cannot, like intervals that must not be zero, so a bad config is reported when it
+    /// is loaded rather than in the middle of a run.
+    pub fn validate(&self) -> Result<(), String> {
+        crate::analysis::periods_per_year(&self.timeframe).map_err(|e| format!("timeframe: {}", e))?;
+        if self.trading_days_per_year.is_some_and(|days| !(days.is_finite() && days > 0.0)) {
+            return Err("trading_days_per_year: must be positive".to_string());
+        }
+        if self.resample {
+            crate::historical::period_start(chrono::DateTime::UNIX_EPOCH.naive_utc(), &self.timeframe).map_err(|e| format!("timeframe: {}", e))?;
+        }
+        if self.checkpoint_interval == Some(0) {
+            return Err("checkpoint_interval: must be at least 1".to_string());
+        }
+        if self.reallocation.as_ref().is_some_and(|rule| rule.interval == 0) {
+            return Err("reallocation.interval: must be at least 1".to_string());
+        }
+        for (index, manager) in self.risk_managers.iter().enumerate() {
+            match manager {
+                RiskManagerConfig::VolatilityScaled { window: 0, .. } | RiskManagerConfig::VolatilityThrottle { window: 0, .. } => {
+                    return Err(format!("risk_managers.{}.window: must be at least 1", index));
+                }
+                RiskManagerConfig::VolatilityThrottle { lookback: 0, .. } => return Err(format!("risk_managers.{}.lookback: must be at least 1", index)),
+                RiskManagerConfig::VolatilityThrottle { percentile, .. } if !(0.0..=100.0).contains(percentile) => {
+                    return Err(format!("risk_managers.{}.percentile: must be between 0 and 100", index));
+                }
+                _ => {}
+            }
+        }
+        if let Sizing::VolatilityTarget { atr_window: 0, .. } = self.sizing {
+            return Err("sizing.atr_window: must be at least 1".to_string());
+        }
+        if !self.strategies.is_empty() {
+            let single_strategy = [
+                ("debug", self.debug.is_some()),
+                ("cash_flows", !self.cash_flows.is_empty()),
+                ("initial_portfolio", self.initial_portfolio.is_some()),
+                ("metric_snapshots", self.metric_snapshots.is_some()),
+                ("dashboard", self.dashboard),
+                ("monthly_restart", self.monthly_restart),
+            ];
+            if let Some((field, _)) = single_strategy.iter().find(|(_, set)| *set) {
+                return Err(format!("{}: only works with a single strategy, not with strategies", field));
+            }
+        }
+        for (index, flow) in self.cash_flows.iter().enumerate() {
+            let parse = |date: &str| parse_date(date).map_err(|e| format!("cash_flows.{}: {}", index, e));
+            let first = parse(&flow.date)?;
+            if flow.until.as_deref().map(parse).transpose()?.is_some_and(|until| until < first) {
+                return Err(format!("cash_flows.{}.until: is before date", index));
+            }
+        }
+        if self.outages.as_ref().is_some_and(|outages| outages.duration == 0) {
+            return Err("outages.duration: must be at least 1".to_string());
+        }
+        if let Some(blackouts) = &self.blackouts {
+            crate::blackout::Blackouts::new(blackouts)?;
+        }
+        if let Some(rule) = &self.metric_snapshots {
+            if (rule.bars.is_none() && rule.every.is_none()) || rule.bars == Some(0) {
+                return Err("metric_snapshots: needs a positive number of bars or a period in every".to_string());
+            }
+        }
+        match self.trailing_stop {
+            Some(TrailingStop::Percent(distance) | TrailingStop::Atr(distance)) if !(distance.is_finite() && distance > 0.0) => {
+                return Err("trailing_stop: the distance must be positive".to_string());
+            }
+            Some(TrailingStop::None) => return Err("trailing_stop: leave it out for no trailing stop".to_string()),
+            _ => {}
+        }
+        if self.accounting == Accounting::Spot && self.direction_filter != DirectionFilter::LongOnly {
+            return Err("direction_filter: Spot accounting cannot sell short, must be LongOnly".to_string());
+        }
+        if let Some(initial) = &self.initial_portfolio {
+            if initial.holdings.contains_key(&self.symbol.quote) {
+                return Err(format!("initial_portfolio.holdings: the {} held is base_funds", self.symbol.quote));
+            }
+            if let Some(position) = &initial.position {
+                if !(position.quantity > 0.0 && position.entry_price > 0.0) {
+                    return Err("initial_portfolio.position: quantity and entry_price must be positive".to_string());
+                }
+                if position.direction == PositionSide::Short && self.accounting == Accounting::Spot {
+                    return Err("initial_portfolio.position: Spot accounting cannot hold a short position".to_string());
+                }
+                if let Some(entry_date) = &position.entry_date {
+                    parse_date(entry_date).map_err(|e| format!("initial_portfolio.position.entry_date: {}", e))?;
+                }
+            }
+        }
+        if self.signal_quality.as_ref().is_some_and(|quality| quality.atr_window == 0) {
+            return Err("signal_quality.atr_window: must be at least 1".to_string());
+        }
+        if let Some(regime) = &self.regime {
+            for (field, value) in [("adx_window", regime.adx_window), ("volatility_window", regime.volatility_window), ("volatility_lookback", regime.volatility_lookback)] {
+                if value == 0 {
+                    return Err(format!("regime.{}: must be at least 1", field));
+                }
+            }
+            if !(0.0..=100.0).contains(&regime.volatility_percentile) {
+                return Err("regime.volatility_percentile: must be between 0 and 100".to_string());
+            }
+        }
+        if self.monte_carlo.as_ref().is_some_and(|monte_carlo| monte_carlo.simulations == 0) {
+            return Err("monte_carlo.simulations: must be at least 1".to_string());
+        }
+        if let Some(sweep) = &self.sweep {
+            if sweep.block_length == 0 {
+                return Err("sweep.block_length: must be at least 1".to_string());
+            }
+            if sweep.folds.is_some_and(|folds| folds < 2) {
+                return Err("sweep.folds: must be at least 2".to_string());
+            }
+            if sweep.workers.is_some() && sweep.worker_token.as_deref().unwrap_or("").is_empty() {
+                return Err("sweep.worker_token: required with workers".to_string());
+            }
+            if sweep.workers.is_some() && sweep.optimizer.is_some() {
+                return Err("sweep.workers: cannot be used with an optimizer, its trials depend on each other".to_string());
+            }
+        }
+        if self.fast_sampling.stride == 0 {
+            return Err("fast_sampling.stride: must be at least 1".to_string());
+        }
+        if self.fast_sampling.sample.is_some_and(|share| !(share > 0.0 && share <= 1.0)) {
+            return Err("fast_sampling.sample: must be above 0 and at most 1".to_string());
+        }
+        if self.animation.as_ref().is_some_and(|animation| animation.every == Some(0)) {
+            return Err("animation.every: must be at least 1".to_string());
+        }
+        if self.chart_volume_profile == Some(0) {
+            return Err("chart_volume_profile: must be at least 1".to_string());
+        }
+        if self.rolling_metric.as_ref().is_some_and(|rolling| rolling.window == 0) {
+            return Err("rolling_metric.window: must be at least 1".to_string());
+        }
+        if self.chart_style.dpi == 0 {
+            return Err("chart_style.dpi: must be at least 1".to_string());
+        }
+        let panels = &self.chart_style.panels;
+        for (field, ratio) in [("price", panels.price), ("equity", panels.equity), ("rolling", panels.rolling)] {
+            if !(ratio.is_finite() && ratio > 0.0) {
+                return Err(format!("chart_style.panels.{}: must be positive", field));
+            }
+        }
+        if let Some(range) = &self.chart_range {
+            parse_date_range(range).map_err(|e| format!("chart_range: {}", e))?;
+        }
+        crate::analysis::check_objective(&self.objective).map_err(|e| format!("objective: {}", e))?;
+        for (field, color) in [("chart_style.up_color", &self.chart_style.up_color), ("chart_style.down_color", &self.chart_style.down_color)] {
+            if let Some(color) = color {
+                parse_color(color).map_err(|e| format!("{}: {}", field, e))?;
+            }
+        }
+        self.check_features()
+    }
+
+    /// Rejects the settings that need a feature the library was built without, rather than ignoring them.
+    fn check_features(&self) -> Result<(), String> {
+        #[cfg(not(feature = "files"))]
+        {
+            let files = [
+                ("log_level", !matches!(self.log_level, LogLevel::None)),
+                ("benchmark", self.benchmark.is_some()),
+                ("reporting_currency", self.reporting_currency.is_some()),
+                ("checkpoint_file", self.checkpoint_file.is_some()),
+                ("resume_from", self.resume_from.is_some()),
+                ("metric_snapshots.file", self.metric_snapshots.as_ref().is_some_and(|rule| rule.file.is_some())),
+                ("sweep.progress_file", self.sweep.as_ref().is_some_and(|sweep| sweep.progress_file.is_some())),
+            ];
+            if let Some((field, _)) = files.iter().find(|(_, set)| *set) {
+                return Err(format!("{}: needs the `files` feature to read or write files", field));
+            }
+        }
+        #[cfg(not(feature = "plugins"))]
+        if self.plugins_dir.is_some() {
+            return Err("plugins_dir: needs the `plugins` feature".to_string());
+        }
+        Ok(())
+    }
+
+    /// The config with the values of the environment variables put back as `${NAME}`, for the files written by a
+    /// run, so secrets given through the environment are not written in plain text.
+    pub fn as_written(&self) -> serde_json::Value {
+        let mut value = serde_json::to_value(self).expect("Error while serializing config");
+        let mut env_values: Vec<&(String, String)> = self.env_values.iter().filter(|(_, value)| !value.is_empty()).collect();
+        // a value containing another one is restored first
+        env_values.sort_by_key(|(_, value)| std::cmp::Reverse(value.len()));
+        restore_env_values(&mut value, &env_values);
+        value
+    }
+}
+
+fn restore_env_values(value: &mut serde_json::Value, env_values: &[&(String, String)]) {
+    match value {
+        serde_json::Value::String(text) => {
+            for (name, env_value) in env_values {
+                *text = text.replace(env_value.as_str(), &format!("${{{}}}", name));
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(|value| restore_env_values(value, env_values)),
+        serde_json::Value::Object(values) => values.values_mut().for_each(|value| restore_env_values(value, env_values)),
+        _ => {}
+    }
+}
+
+/// Recursively overwrites the fields of `base` with the ones of `overrides`. Objects are merged, everything else is replaced.
+#[cfg(feature = "files")]
+fn merge(base: &mut serde_json::Value, overrides: serde_json::Value) {
+    match (base, overrides) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                merge(base.entry(key).or_insert(serde_json::Value::Null), value);
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+/// Reads a config file, on top of the file named by its `extends` field if it has one (relative to the file).
+#[cfg(feature = "files")]
+fn read_config_value(file_path: &str) -> Result<serde_json::Value, String> {
+    let json = std::fs::read_to_string(file_path).map_err(|e| format!("cannot read {}: {}", file_path, e))?;
+    let mut value: serde_json::Value = serde_json::from_str(&json).map_err(|e| format!("{}: {}", file_path, e))?;
+    let parent = value.as_object_mut().and_then(|object| object.remove("extends"));
+    match parent {
+        Some(serde_json::Value::String(parent)) => {
+            let parent_path = std::path::Path::new(file_path).parent().unwrap_or(std::path::Path::new("")).join(parent);
+            let mut base = read_config_value(&parent_path.to_string_lossy())?;
+            merge(&mut base, value);
+            Ok(base)
+        }
+        Some(_) => Err(format!("{}: `extends` must be a path", file_path)),
+        None => Ok(value),
+    }
+}
+
+/// Returns the overrides of a profile, on top of the ones of the profile it `extends` if any.
+#[cfg(feature = "files")]
+fn resolve_profile(profiles: &Map<String, serde_json::Value>, name: &str, depth: usize) -> Result<serde_json::Value, String> {
+    if depth > profiles.len() {
+        return Err(format!("profile `{}` extends itself", name));
+    }
+    let mut profile = profiles.get(name).ok_or(format!("unknown profile: {}", name))?.clone();
+    let parent = profile.as_object_mut().and_then(|object| object.remove("extends"));
+    match parent {
+        Some(serde_json::Value::String(parent)) => {
+            let mut base = resolve_profile(profiles, &parent, depth + 1)?;
+            merge(&mut base, profile);
+            Ok(base)
+        }
+        Some(_) => Err(format!("profile `{}`: `extends` must be a profile name", name)),
+        None => Ok(profile),
+    }
+}
+
+/// Replaces every `${NAME}` in a string with the value of the environment variable `NAME`, recording it in `expanded_values`.
+fn expand_env(text: &str, expanded_values: &mut Vec<(String, String)>) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        let end = rest[start..].find('}').map(|end| start + end).ok_or(format!("unclosed `${{` in config value: {}", text))?;
+        let name = &rest[start + 2..end];
+        let value = std::env::var(name).map_err(|_| format!("environment variable `{}` used in config is not set", name))?;
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&value);
+        if !expanded_values.iter().any(|(known, _)| known == name) {
+            expanded_values.push((name.to_string(), value));
+        }
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Expands environment variables in every string of the config, so secrets and machine-specific paths stay out of the file.
+fn expand_env_values(value: &mut serde_json::Value, expanded: &mut Vec<(String, String)>) -> Result<(), String> {
+    match value {
+        serde_json::Value::String(text) => *text = expand_env(text, expanded)?,
+        serde_json::Value::Array(values) => values.iter_mut().try_for_each(|value| expand_env_values(value, expanded))?,
+        serde_json::Value::Object(values) => values.values_mut().try_for_each(|value| expand_env_values(value, expanded))?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Sets the field at a dotted path (`sizing.Fraction.fraction`, `strategies.0.weight`), creating missing objects.
+/// The value is parsed as JSON when possible, and used as a string otherwise.
+#[cfg(feature = "files")]
+fn set_path(root: &mut serde_json::Value, path: &str, raw_value: &str) -> Result<(), String> {
+    let value = serde_json::from_str(raw_value).unwrap_or(serde_json::Value::String(raw_value.to_string()));
+    let mut current = root;
+    for key in path.split('.') {
+        current = match current {
+            serde_json::Value::Array(items) => {
+                let index: usize = key.parse().map_err(|_| format!("--set {}: `{}` is not an index", path, key))?;
+                items.get_mut(index).ok_or(format!("--set {}: index {} is out of bounds", path, index))?
+            }
+            serde_json::Value::Null => {
+                *current = serde_json::Value::Object(Map::new());
+                current.as_object_mut().unwrap().entry(key).or_insert(serde_json::Value::Null)
+            }
+            serde_json::Value::Object(object) => object.entry(key).or_insert(serde_json::Value::Null),
+            _ => return Err(format!("--set {}: `{}` is not an object", path, key)),
+        };
+    }
+    *current = value;
+    Ok(())
+}
+
+/// Reads a config file with its base files, profile and command line overrides applied, reporting unknown fields and type errors.
+#[cfg(feature = "files")]
+pub fn load_config(file_path: &str, profile: Option<&str>, overrides: &[(String, String)]) -> Result<Config, String> {
+    let mut value = read_config_value(file_path)?;
+    let profiles = value.as_object_mut().and_then(|object| object.remove("profiles"));
+    if let Some(name) = profile {
+        let profiles = match profiles {
+            Some(serde_json::Value::Object(profiles)) => profiles,
+            _ => return Err(format!("unknown profile: {}", name)),
+        };
+        merge(&mut value, resolve_profile(&profiles, name, 0)?);
+    }
+    for (path, raw_value) in overrides {
+        set_path(&mut value, path, raw_value)?;
+    }
+    let mut env_values = Vec::new();
+    expand_env_values(&mut value, &mut env_values)?;
+    migrate_symbol(&mut value)?;
+    // the path of the offending field is part of the error, e.g. `sizing.Fraction.fraction: invalid type`
+    let mut config: Config = serde_path_to_error::deserialize(value).map_err(|e| e.to_string())?;
+    config.env_values = env_values;
+    crate::strategy::check_config(&config)?;
+    config.validate()?;
+    Ok(config)
+}
+
+/// Parses a config given as a JSON string rather than a file. Environment variables are expanded, but
+/// `extends` and `profiles` are not supported since they need files to be resolved against.
+pub fn parse_config(text: &str) -> Result<Config, String> {
+    let mut value: serde_json::Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    let mut env_values = Vec::new();
+    expand_env_values(&mut value, &mut env_values)?;
+    migrate_symbol(&mut value)?;
+    let mut config: Config = serde_path_to_error::deserialize(value).map_err(|e| e.to_string())?;
+    config.env_values = env_values;
+    crate::strategy::check_config(&config)?;
+    config.validate()?;
+    Ok(config)
+}
+
+/// Turns the `pair`, `base_currency` and `quote_currency` of configs written before `symbol` into a symbol.
+fn migrate_symbol(value: &mut serde_json::Value) -> Result<(), String> {
+    let Some(object) = value.as_object_mut() else {
+        return Ok(());
+    };
+    let legacy = ["pair", "base_currency", "quote_currency"].map(|key| object.remove(key));
+    if legacy.iter().all(Option::is_none) {
+        return Ok(());
+    }
+    if object.contains_key("symbol") {
+        return Err("`pair`, `base_currency` and `quote_currency` were replaced by `symbol`, set only `symbol`".to_string());
+    }
+    match &legacy[1..] {
+        [Some(serde_json::Value::String(base)), Some(serde_json::Value::String(quote))] => {
+            object.insert("symbol".to_string(), serde_json::Value::String(format!("{}/{}", base, quote)));
+            Ok(())
+        }
+        _ => Err("`pair`, `base_currency` and `quote_currency` were replaced by `symbol`, like \"symbol\": \"BTC/USDT\"".to_string()),
+    }
+}
+
+#[cfg(feature = "files")]
+pub fn read_config(file_path: &str, profile: Option<&str>, overrides: &[(String, String)]) -> Config {
+    match load_config(file_path, profile, overrides) {
+        Ok(config) => config,
+        Err(e) => panic!("Error while reading config: {}", e),
+    }
+}
+
+/// Reads a `#rrggbb` color into its red, green and blue channels.
+pub fn parse_color(hex: &str) -> Result<(u8, u8, u8), String> {
+    let digits = hex.trim_start_matches('#');
+    let channel = |i: usize| digits.get(i..i + 2).and_then(|channel| u8::from_str_radix(channel, 16).ok());
+    match (digits.len(), channel(0), channel(2), channel(4)) {
+        (6, Some(r), Some(g), Some(b)) => Ok((r, g, b)),
+        _ => Err(format!("invalid color `{}`, expected #rrggbb", hex)),
+    }
+}
+
+/// Reads a date (`2023-03-01`) or a date and time (`2023-03-01 12:00:00`).
+pub fn parse_date(date: &str) -> Result<NaiveDateTime, String> {
+    let date = date.trim();
+    NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").map(|date| date.and_time(chrono::NaiveTime::MIN)))
+        .map_err(|_| format!("invalid date `{}`, expected YYYY-MM-DD or YYYY-MM-DD HH:MM:SS", date))
+}
+
+/// Reads a `start..end` window of dates, see `parse_date`. The start is included and the end excluded.
+pub fn parse_date_range(range: &str) -> Result<(NaiveDateTime, NaiveDateTime), String> {
+    let (start, end) = range.split_once("..").ok_or(format!("invalid range `{}`, expected start..end", range))?;
+    let (start, end) = (parse_date(start)?, parse_date(end)?);
+    if start >= end {
+        return Err(format!("range `{}` is empty", range));
+    }
+    Ok((start, end))
 }
 
-pub fn read_config(file_path: &str) -> Config {
-    let json = std::fs::read_to_string(file_path).expect("file not found");
-    let config: Config = serde_json::from_str(&json).expect("error while parsing JSON");
-    config
+/// JSON Schema of the config file, once base files and profiles are applied.
+pub fn config_schema() -> String {
+    let schema = schemars::schema_for!(Config);
+    serde_json::to_string_pretty(&schema).unwrap()
 }
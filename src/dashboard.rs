@@ -0,0 +1,159 @@
+//! The local web dashboard of `--dashboard`: a page following the run in progress, served from a background thread.
+//! The page polls `/status` for the status published by the engine (see `testing::live_status`) and the end of the log.
+
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+use retroval::config::Config;
+use retroval::testing;
+
+/// Lines of the log sent with each status.
+const LOG_TAIL: usize = 30;
+/// Bytes read from the end of the log for them, far more than `LOG_TAIL` lines take.
+const LOG_TAIL_BYTES: u64 = 64 * 1024;
+/// How long a connection may take to send its request before it is dropped, the connections being answered one at a
+/// time.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+const PAGE: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>retroval</title>
+<style>
+body { font-family: sans-serif; margin: 1.5em; color: #222; }
+h1 { font-size: 1.3em; }
+h2 { font-size: 1.05em; margin-top: 1.5em; }
+#progress { width: 100%; height: 0.6em; background: #eee; }
+#bar { height: 100%; width: 0; background: #2962ff; }
+canvas { width: 100%; height: 260px; border: 1px solid #ddd; }
+table { border-collapse: collapse; font-size: 0.9em; }
+td, th { padding: 0.2em 1em 0.2em 0; text-align: left; }
+pre { font-size: 0.8em; background: #f6f6f6; padding: 0.5em; white-space: pre-wrap; }
+.up { color: #26a69a; } .down { color: #ef5350; }
+</style>
+</head>
+<body>
+<h1 id="title">Waiting for the run to start...</h1>
+<div id="progress"><div id="bar"></div></div>
+<p id="summary"></p>
+<canvas id="equity"></canvas>
+<h2>Open positions</h2>
+<table id="open"></table>
+<h2>Recent trades</h2>
+<table id="recent"></table>
+<h2>Log</h2>
+<pre id="log"></pre>
+<script>
+const money = (value) => value.toFixed(2);
+const signed = (value) => `<span class="${value >= 0 ? "up" : "down"}">${money(value)}</span>`;
+const escape = (text) => text.replace(/&/g, "&amp;").replace(/</g, "&lt;").replace(/>/g, "&gt;");
+
+function drawEquity(curve) {
+  const canvas = document.getElementById("equity");
+  const width = canvas.width = canvas.clientWidth * devicePixelRatio;
+  const height = canvas.height = canvas.clientHeight * devicePixelRatio;
+  const context = canvas.getContext("2d");
+  context.clearRect(0, 0, width, height);
+  if (curve.length < 2) return;
+  const values = curve.map((point) => point[1]);
+  const min = Math.min(...values), max = Math.max(...values);
+  const span = max - min || 1;
+  context.strokeStyle = "#2962ff";
+  context.lineWidth = devicePixelRatio;
+  context.beginPath();
+  values.forEach((value, index) => {
+    const x = index / (values.length - 1) * width;
+    const y = height - (value - min) / span * (height - 20 * devicePixelRatio) - 10 * devicePixelRatio;
+    index ? context.lineTo(x, y) : context.moveTo(x, y);
+  });
+  context.stroke();
+  context.fillStyle = "#555";
+  context.font = `${12 * devicePixelRatio}px sans-serif`;
+  context.fillText(money(max), 4, 14 * devicePixelRatio);
+  context.fillText(money(min), 4, height - 4);
+}
+
+function trades(id, rows, header, row) {
+  document.getElementById(id).innerHTML = rows.length ? `<tr>${header.map((name) => `<th>${name}</th>`).join("")}</tr>` + rows.map(row).join("") : "<tr><td>none</td></tr>";
+}
+
+async function refresh() {
+  try {
+    const response = await fetch("/status");
+    const { status, log } = await response.json();
+    document.getElementById("log").innerHTML = escape(log.join("\n"));
+    if (status) {
+      document.getElementById("title").textContent = `${status.finished ? "Finished" : "Running"}: ${status.date}`;
+      document.getElementById("bar").style.width = `${status.bars / status.total_bars * 100}%`;
+      document.getElementById("summary").innerHTML = `${status.bars} of ${status.total_bars} candles, equity ${money(status.equity)}, cash ${money(status.cash)}, ${status.closed_trades} closed trades, profit ${signed(status.total_profit)}`;
+      drawEquity(status.equity_curve);
+      trades("open", status.open_trades, ["Trade", "Direction", "Entry", "Price", "Allocated", "Stop-loss", "Take-profit"], (trade) =>
+        `<tr><td>${trade.id}</td><td>${trade.direction}</td><td>${trade.entry_date}</td><td>${money(trade.entry_price)}</td><td>${money(trade.allocated)}</td><td>${trade.stop_loss ? money(trade.stop_loss) : "none"}</td><td>${trade.take_profit ? money(trade.take_profit) : "none"}</td></tr>`);
+      trades("recent", status.recent_trades, ["Trade", "Direction", "Entry", "Exit", "Reason", "Profit"], (trade) =>
+        `<tr><td>${trade.id}</td><td>${trade.direction}</td><td>${trade.entry_date}</td><td>${trade.exit_date}</td><td>${trade.exit_reason ?? ""}</td><td>${signed(trade.profit ?? 0)}</td></tr>`);
+    }
+  } catch (e) {
+    document.getElementById("title").textContent = "The run is over, the dashboard stopped with it.";
+    return;
+  }
+  setTimeout(refresh, 1000);
+}
+refresh();
+</script>
+</body>
+</html>
+"##;
+
+/// The last `LOG_TAIL` lines of the log of the run, empty while there is none. Only the end of the file is read, the
+/// log of a long run growing large.
+fn log_tail(config: &Config) -> Vec<String> {
+    let Ok(mut file) = std::fs::File::open(&config.log_file) else {
+        return Vec::new();
+    };
+    let start = file.metadata().map(|metadata| metadata.len().saturating_sub(LOG_TAIL_BYTES)).unwrap_or(0);
+    let mut bytes = Vec::new();
+    if file.seek(SeekFrom::Start(start)).and_then(|_| file.read_to_end(&mut bytes)).is_err() {
+        return Vec::new();
+    }
+    let text = String::from_utf8_lossy(&bytes);
+    // the first line is cut unless the whole file was read
+    let lines: Vec<&str> = text.lines().skip(if start > 0 { 1 } else { 0 }).collect();
+    lines[lines.len().saturating_sub(LOG_TAIL)..].iter().map(|line| line.to_string()).collect()
+}
+
+/// Answers one request: the page on `/`, the status on `/status`.
+fn respond(mut stream: TcpStream, config: &Config) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut request = String::new();
+    BufReader::new(&stream).read_line(&mut request)?;
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+    let (status, content_type, body) = match path {
+        "/" => ("200 OK", "text/html; charset=utf-8", PAGE.to_string()),
+        "/status" => {
+            let body = serde_json::json!({ "status": testing::live_status(), "log": log_tail(config) });
+            ("200 OK", "application/json", body.to_string())
+        }
+        _ => ("404 Not Found", "text/plain", "not found".to_string()),
+    };
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}", status, content_type, body.len(), body)
+}
+
+/// Serves the dashboard on `dashboard_address` from a background thread, for as long as the program runs.
+pub fn serve(config: &Config) -> std::io::Result<()> {
+    let listener = TcpListener::bind(&config.dashboard_address)?;
+    let config = config.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = respond(stream, &config) {
+                        eprintln!("Error while answering the dashboard: {}", e);
+                    }
+                }
+                Err(e) => eprintln!("Error while accepting a dashboard connection: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
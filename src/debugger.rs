@@ -0,0 +1,191 @@
+//! Bar-by-bar debugger of single-strategy runs: the run pauses after the candles that hit a breakpoint, dumps the state
+//! of the strategy and the portfolio, and reads commands from the terminal to step through the next candles.
+
+use std::io::{BufRead, Write};
+use chrono::NaiveDateTime;
+use crate::config::{parse_date, DebugConfig};
+use crate::historical::Kline;
+use crate::testing::{Direction, PortfolioState};
+
+/// Everything dumped when the run pauses.
+pub struct Snapshot<'a> {
+    pub kline: &'a Kline,
+    pub equity: f64,
+    pub portfolio: PortfolioState,
+    pub strategy: serde_json::Value,
+    pub indicators: Vec<(String, f64)>,
+}
+
+pub struct Debugger {
+    break_at: Option<NaiveDateTime>,
+    break_on_trade: bool,
+    break_on_drawdown: Option<f64>,
+    /// Pause on the next candle whatever the breakpoints, after `next`, and from the first candle when there are no breakpoints.
+    stepping: bool,
+    /// Cleared by `quit`, the run then goes to the end without pausing.
+    enabled: bool,
+    peak: f64,
+    in_drawdown: bool,
+    entries: usize,
+}
+
+impl Debugger {
+    /// `entries` is the number of positions opened before the first candle, when resuming from a checkpoint.
+    pub fn new(config: &DebugConfig, entries: usize) -> Result<Self, String> {
+        let break_at = config.break_at.as_deref().map(parse_date).transpose().map_err(|e| format!("debug.break_at: {}", e))?;
+        Ok(Self {
+            stepping: break_at.is_none() && !config.break_on_trade && config.break_on_drawdown.is_none(),
+            break_at,
+            break_on_trade: config.break_on_trade,
+            break_on_drawdown: config.break_on_drawdown,
+            enabled: true,
+            peak: 0.0,
+            in_drawdown: false,
+            entries,
+        })
+    }
+
+    /// Why the run has to pause after a candle, given the equity, the deposits (positive) and withdrawals (negative)
+    /// made on the candle and the number of positions opened so far. The transfers move the peak along with the
+    /// equity, so a withdrawal is not a drawdown. The drawdown breakpoint only triggers again once the drawdown went
+    /// back under the threshold.
+    pub fn check(&mut self, timestamp: NaiveDateTime, equity: f64, transferred: f64, entries: usize) -> Option<String> {
+        if self.peak > 0.0 {
+            self.peak += transferred;
+        }
+        self.peak = self.peak.max(equity);
+        let drawdown = if self.peak > 0.0 { (self.peak - equity) / self.peak * 100.0 } else { 0.0 };
+        let opened = entries.saturating_sub(self.entries);
+        self.entries = entries;
+        let mut reasons = Vec::new();
+        if let Some(threshold) = self.break_on_drawdown {
+            if drawdown > threshold && !self.in_drawdown {
+                reasons.push(format!("drawdown {:.2}% above {:.2}%", drawdown, threshold));
+            }
+            self.in_drawdown = drawdown > threshold;
+        }
+        if self.break_at.is_some_and(|date| timestamp >= date) {
+            self.break_at = None;
+            reasons.push(format!("reached {}", timestamp));
+        }
+        if self.break_on_trade && opened > 0 {
+            reasons.push(format!("{} position(s) opened", opened));
+        }
+        if !self.enabled || (reasons.is_empty() && !self.stepping) {
+            return None;
+        }
+        Some(if reasons.is_empty() { "step".to_string() } else { reasons.join(", ") })
+    }
+
+    /// Dumps the state and reads commands until the run is resumed.
+    pub fn pause(&mut self, reason: &str, snapshot: &Snapshot) {
+        print_snapshot(reason, snapshot, self.peak);
+        let stdin = std::io::stdin();
+        loop {
+            print!("(debug) ");
+            std::io::stdout().flush().expect("error while writing to stdout");
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line).expect("error while reading stdin") == 0 {
+                // end of input, nobody is left to step
+                self.enabled = false;
+                return;
+            }
+            match line.trim() {
+                "" | "n" | "next" => {
+                    self.stepping = true;
+                    return;
+                }
+                "c" | "continue" => {
+                    self.stepping = false;
+                    return;
+                }
+                "q" | "quit" => {
+                    self.enabled = false;
+                    return;
+                }
+                "p" | "portfolio" => println!("{}", serde_json::to_string_pretty(&snapshot.portfolio).expect("error while serializing portfolio")),
+                "s" | "state" => print_snapshot(reason, snapshot, self.peak),
+                _ => {
+                    println!("  n, next (or Enter)  run the next candle");
+                    println!("  c, continue         run until the next breakpoint");
+                    println!("  s, state            print the state again");
+                    println!("  p, portfolio        print the whole portfolio, closed trades included");
+                    println!("  q, quit             run to the end without pausing");
+                }
+            }
+        }
+    }
+}
+
+fn print_snapshot(reason: &str, snapshot: &Snapshot, peak: f64) {
+    let kline = snapshot.kline;
+    let portfolio = &snapshot.portfolio;
+    println!("== {} ({})", kline.timestamp, reason);
+    println!("Candle: open {:.2}, high {:.2}, low {:.2}, close {:.2}, volume {:.2}", kline.open, kline.high, kline.low, kline.close, kline.volume);
+    let drawdown = if peak > 0.0 { (peak - snapshot.equity) / peak * 100.0 } else { 0.0 };
+    println!("Equity: {:.2} (cash {:.2}, drawdown {:.2}% from {:.2}), {} closed trades", snapshot.equity, portfolio.cash, drawdown, peak, portfolio.closed_trades.len());
+    let balances: Vec<String> = portfolio.balances.iter().map(|(currency, balance)| format!("{:.8} {}", balance, currency)).collect();
+    println!("Balances: {}", balances.join(", "));
+    for trade in portfolio.open_trade.iter().chain(portfolio.lots.values()) {
+        let change = match trade.direction {
+            Direction::Long => kline.close / trade.entry_price - 1.0,
+            Direction::Short => trade.entry_price / kline.close - 1.0,
+            Direction::Flat => 0.0,
+        };
+        println!(
+            "Open position {}: {:?} since {} at {:.2}, {:.2} allocated, {:+.2}% since entry",
+            trade.id,
+            trade.direction,
+            trade.entry_date,
+            trade.entry_price,
+            trade.allocated,
+            change * 100.0
+        );
+    }
+    for order in portfolio.limit_orders.iter() {
+        println!("Limit order {}: {:?} {:.2} at {:.2}", order.id, order.side, order.size, order.price);
+    }
+    if let Some(last) = portfolio.closed_trades.last() {
+        println!("Last closed trade {}: {} to {}, profit {:.2}", last.id, last.entry_date, last.exit_date.unwrap_or(last.entry_date), last.profit.unwrap_or(0.0));
+    }
+    if !snapshot.indicators.is_empty() {
+        let indicators: Vec<String> = snapshot.indicators.iter().map(|(name, value)| format!("{}={:.2}", name, value)).collect();
+        println!("Indicators: {}", indicators.join(", "));
+    }
+    println!("Strategy state: {}", snapshot.strategy);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, NaiveDate};
+
+    fn hour(hours: i64) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap() + Duration::hours(hours)
+    }
+
+    fn debugger() -> Debugger {
+        let config = DebugConfig {
+            break_at: None,
+            break_on_trade: false,
+            break_on_drawdown: Some(10.0),
+        };
+        Debugger::new(&config, 0).unwrap()
+    }
+
+    #[test]
+    fn withdrawals_are_not_drawdowns() {
+        let mut debugger = debugger();
+        assert_eq!(debugger.check(hour(0), 1000.0, 0.0, 0), None);
+        assert_eq!(debugger.check(hour(1), 500.0, -500.0, 0), None);
+        assert!(debugger.check(hour(2), 440.0, 0.0, 0).is_some());
+    }
+
+    #[test]
+    fn the_drawdown_breakpoint_triggers_once_per_drawdown() {
+        let mut debugger = debugger();
+        let equities = [1000.0, 850.0, 800.0, 950.0, 850.0];
+        let paused: Vec<bool> = equities.iter().enumerate().map(|(index, equity)| debugger.check(hour(index as i64), *equity, 0.0, 0).is_some()).collect();
+        assert_eq!(paused, [false, true, false, false, true]);
+    }
+}
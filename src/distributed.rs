@@ -0,0 +1,215 @@
+//! Runs the variants of a sweep on other machines. The coordinator (the process running the sweep) listens on
+//! `sweep.workers`, and every `retroval worker <address>` connecting to it receives variants one at a time.
+//! Messages are JSON objects, one per line. A worker first presents `sweep.worker_token`, and is dropped unless it
+//! matches. A worker that disconnects, does not answer within `worker_timeout` seconds or returns another variant
+//! than the one it was sent is dropped, and its variant goes back to the queue for the other workers.
+
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Mutex;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::checkpoint::SweepEntry;
+use crate::config::{self, Config, StrategyConfig, Sweep};
+use crate::historical::Kline;
+use crate::testing::SweepRunner;
+
+/// Longest message read before the worker presented its token.
+const MAX_JOIN_BYTES: u64 = 4 * 1024;
+/// Longest message read afterwards, a result holding the equity curve of a variant.
+const MAX_MESSAGE_BYTES: u64 = 256 * 1024 * 1024;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Message {
+    /// First message of the worker, with the `worker_token` of its config.
+    Join { token: String },
+    /// Answer of the coordinator to a valid `Join`. The worker checks it has the same data and settings, see `testing::sweep_settings`.
+    Hello { settings: serde_json::Value, sweep: Sweep },
+    /// A variant to run, answered with `Result`.
+    Run(StrategyConfig),
+    Result(SweepEntry),
+    /// Sent by a worker whose data or settings differ from the coordinator's.
+    Refused(String),
+    /// Every variant is done, the worker can exit.
+    Done,
+}
+
+fn send(stream: &mut TcpStream, message: &Message) -> Result<(), Box<dyn std::error::Error>> {
+    let json = serde_json::to_string(message)? + "\n";
+    stream.write_all(json.as_bytes())?;
+    Ok(())
+}
+
+/// Reads a message of at most `limit` bytes, newline included.
+fn receive(reader: &mut BufReader<TcpStream>, limit: u64) -> Result<Message, Box<dyn std::error::Error>> {
+    let mut line = Vec::new();
+    reader.by_ref().take(limit).read_until(b'\n', &mut line)?;
+    match line.last() {
+        None => Err("connection closed".into()),
+        Some(b'\n') => Ok(serde_json::from_slice(&line)?),
+        Some(_) if line.len() as u64 == limit => Err(format!("message longer than {} bytes", limit).into()),
+        Some(_) => Err("connection closed in the middle of a message".into()),
+    }
+}
+
+/// Compares the tokens in a time that does not depend on where they differ.
+fn same_token(given: &str, expected: &str) -> bool {
+    given.len() == expected.len() && given.bytes().zip(expected.bytes()).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+/// Checks the token of a worker, then hands variants to it until the queue is empty and every variant is done. The
+/// variant being run is left in `current`, so it can be queued again if the worker fails.
+#[allow(clippy::too_many_arguments)]
+fn serve(
+    mut stream: TcpStream,
+    timeout: Duration,
+    token: &str,
+    hello: &Message,
+    queue: &Mutex<VecDeque<(usize, StrategyConfig)>>,
+    finished: &AtomicBool,
+    results: &Sender<(usize, SweepEntry)>,
+    current: &mut Option<(usize, StrategyConfig)>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(timeout))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    match receive(&mut reader, MAX_JOIN_BYTES)? {
+        Message::Join { token: given } if same_token(&given, token) => {}
+        Message::Join { .. } => {
+            send(&mut stream, &Message::Refused("invalid worker token".to_string()))?;
+            return Err("invalid worker token".into());
+        }
+        message => return Err(format!("unexpected message {:?}", message).into()),
+    }
+    send(&mut stream, hello)?;
+    loop {
+        let next = queue.lock().unwrap().pop_front();
+        let Some((index, variant)) = next else {
+            if finished.load(Ordering::SeqCst) {
+                return send(&mut stream, &Message::Done);
+            }
+            // the other workers may still fail and give their variant back
+            std::thread::sleep(Duration::from_millis(100));
+            continue;
+        };
+        send(&mut stream, &Message::Run(variant.clone()))?;
+        *current = Some((index, variant));
+        match receive(&mut reader, MAX_MESSAGE_BYTES)? {
+            Message::Result(entry) if Some(&entry.parameters) != current.as_ref().map(|(_, variant)| variant) => {
+                return Err(format!("returned variant {} instead of the one it was sent", entry.parameters.label()).into());
+            }
+            Message::Result(entry) => {
+                *current = None;
+                // the coordinator only stops listening once it has every result
+                let _ = results.send((index, entry));
+            }
+            Message::Refused(reason) => return Err(reason.into()),
+            message => return Err(format!("unexpected message {:?}", message).into()),
+        }
+    }
+}
+
+/// Listens on `address` and farms the `pending` variants (with their index in the sweep) out to the workers that
+/// connect, calling `on_entry` with each result as it arrives. Returns once every variant is done, waiting for new
+/// workers as long as needed if they all fail. `settings` are the ones of `testing::sweep_settings`.
+pub fn coordinate(
+    address: &str,
+    sweep: &Sweep,
+    settings: serde_json::Value,
+    pending: Vec<(usize, StrategyConfig)>,
+    on_entry: &mut dyn FnMut(usize, SweepEntry),
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let token = sweep.worker_token.as_deref().ok_or("sweep.worker_token is required with workers")?;
+    let listener = TcpListener::bind(address)?;
+    // polled, so the listener notices when the sweep is done
+    listener.set_nonblocking(true)?;
+    // the token is not needed once the worker has joined
+    let hello = Message::Hello {
+        settings,
+        sweep: config::Sweep { worker_token: None, ..sweep.clone() },
+    };
+    let timeout = Duration::from_secs(sweep.worker_timeout);
+    let total = pending.len();
+    let queue = Mutex::new(VecDeque::from(pending));
+    let finished = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel();
+    let log = |text: String| match config.log_level {
+        config::LogLevel::None => {}
+        _ => println!("{}", text),
+    };
+    log(format!("Waiting for workers on {} to run {} variants", address, total));
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            while !finished.load(Ordering::SeqCst) {
+                let (stream, peer) = match listener.accept() {
+                    Ok(connection) => connection,
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                        std::thread::sleep(Duration::from_millis(100));
+                        continue;
+                    }
+                    Err(e) => {
+                        eprintln!("Error while accepting a worker: {}", e);
+                        continue;
+                    }
+                };
+                log(format!("Worker {} connected", peer));
+                let (hello, queue, finished, sender) = (&hello, &queue, &finished, sender.clone());
+                scope.spawn(move || {
+                    let mut current = None;
+                    if let Err(e) = serve(stream, timeout, token, hello, queue, finished, &sender, &mut current) {
+                        eprintln!("Worker {} failed: {}", peer, e);
+                        if let Some(variant) = current {
+                            queue.lock().unwrap().push_back(variant);
+                        }
+                    }
+                });
+            }
+        });
+        for done in 1..=total {
+            let (index, entry) = receiver.recv().expect("the listener keeps the channel open");
+            log(format!("Variant {}/{} done: {}", done, total, entry.parameters.label()));
+            on_entry(index, entry);
+        }
+        finished.store(true, Ordering::SeqCst);
+    });
+    Ok(())
+}
+
+/// Connects to a coordinator and runs the variants it sends until it has no more, with the data and settings of
+/// `config`. Returns the number of variants run.
+pub fn run_worker(address: &str, config: &Config, klines: &[Kline]) -> Result<usize, Box<dyn std::error::Error>> {
+    let token = config.sweep.as_ref().and_then(|sweep| sweep.worker_token.clone()).ok_or("the config has no sweep.worker_token to present to the coordinator")?;
+    let mut stream = TcpStream::connect(address)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    send(&mut stream, &Message::Join { token })?;
+    let runner = match receive(&mut reader, MAX_MESSAGE_BYTES)? {
+        Message::Hello { settings, sweep } => {
+            let runner = SweepRunner::new(config, klines, &sweep)?;
+            // compared through JSON text, like the coordinator's settings were
+            let local: serde_json::Value = serde_json::from_str(&serde_json::to_string(&runner.settings(config, &sweep))?)?;
+            if local != settings {
+                let reason = "the data or settings of the worker differ from the coordinator's".to_string();
+                send(&mut stream, &Message::Refused(reason.clone()))?;
+                return Err(reason.into());
+            }
+            runner
+        }
+        Message::Refused(reason) => return Err(format!("refused by the coordinator: {}", reason).into()),
+        _ => return Err("the coordinator did not say hello".into()),
+    };
+    let mut count = 0;
+    loop {
+        match receive(&mut reader, MAX_MESSAGE_BYTES)? {
+            Message::Run(variant) => {
+                send(&mut stream, &Message::Result(runner.run(&variant)))?;
+                count += 1;
+            }
+            Message::Done => return Ok(count),
+            message => return Err(format!("unexpected message {:?}", message).into()),
+        }
+    }
+}
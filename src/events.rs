@@ -0,0 +1,153 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use crate::config::RejectionConfig;
+use crate::historical::Kline;
+use crate::montecarlo::Random;
+use crate::strategy::Signal;
+use crate::testing::{Direction, ExitReason};
+
+/// A new candle entering the simulation.
+pub struct BarEvent<'a> {
+    pub kline: &'a Kline,
+}
+
+/// What the strategy returned for a candle.
+pub struct SignalEvent {
+    pub date: NaiveDateTime,
+    pub price: f64,
+    /// Close the strategy saw when it signalled, `price` unless the order waits for the next open.
+    pub signal_price: f64,
+    pub signal: Signal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+/// An order resting at a price until a candle reaches it, placed by strategies through `Strategy::limit_orders`.
+/// A buy opens a lot under its `id`, and a sell with the same `id` closes that lot.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LimitOrder {
+    pub id: usize,
+    pub side: Side,
+    pub price: f64,
+    /// Amount to allocate in quote currency, only used by buys.
+    pub size: f64,
+}
+
+impl LimitOrder {
+    /// Price the order fills at if `kline` reaches it. A candle opening past the price fills at the open.
+    pub fn fill_price(&self, kline: &Kline) -> Option<f64> {
+        match self.side {
+            Side::Buy if kline.low <= self.price => Some(kline.open.min(self.price)),
+            Side::Sell if kline.high >= self.price => Some(kline.open.max(self.price)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OrderAction {
+    Enter(Direction),
+    Exit(ExitReason),
+    /// A limit order of the strategy reached by the candle.
+    Limit(LimitOrder),
+}
+
+/// A request to open or close a position at a given price.
+pub struct OrderEvent {
+    /// `O1` for the first order of the portfolio, referenced by the log and the trades it opens or closes.
+    pub id: String,
+    pub date: NaiveDateTime,
+    pub price: f64,
+    /// Price the order was decided at: the close of the signal, the limit price or the stop-loss or take-profit level.
+    pub signal_price: f64,
+    pub action: OrderAction,
+    /// Amount to allocate in quote currency, only used by entries.
+    pub size: f64,
+}
+
+/// An order that went through the execution model. Slippage and commission are applied by the portfolio.
+pub struct FillEvent {
+    pub order_id: String,
+    pub date: NaiveDateTime,
+    pub price: f64,
+    pub signal_price: f64,
+    pub action: OrderAction,
+    pub size: f64,
+}
+
+pub enum Event<'a> {
+    Bar(BarEvent<'a>),
+    Signal(SignalEvent),
+    Order(OrderEvent),
+    Fill(FillEvent),
+}
+
+/// Turns orders into fills. Implementations decide if, when and at what price an order gets executed.
+pub trait ExecutionHandler {
+    fn execute(&mut self, order: &OrderEvent) -> Option<FillEvent>;
+}
+
+/// Fills every order immediately at the requested price.
+pub struct MarketExecution;
+
+impl ExecutionHandler for MarketExecution {
+    fn execute(&mut self, order: &OrderEvent) -> Option<FillEvent> {
+        Some(FillEvent {
+            order_id: order.id.clone(),
+            date: order.date,
+            price: order.price,
+            signal_price: order.signal_price,
+            action: order.action,
+            size: order.size,
+        })
+    }
+}
+
+/// Fills orders like `MarketExecution`, except that a share of the entries is rejected, and a share of the market
+/// entries is requoted at a worse price, the way an exchange treats orders sent at the same time as everyone else's.
+/// Exits always go through.
+#[derive(Serialize, Deserialize)]
+pub struct RejectionModel {
+    rejection_rate: f64,
+    requote_rate: f64,
+    requote_slippage: f64,
+    random: Random,
+}
+
+impl RejectionModel {
+    pub fn new(config: &RejectionConfig, seed: u64) -> Self {
+        Self {
+            rejection_rate: config.rejection_rate,
+            requote_rate: config.requote_rate,
+            requote_slippage: config.requote_slippage,
+            random: Random::new(seed),
+        }
+    }
+}
+
+impl ExecutionHandler for RejectionModel {
+    fn execute(&mut self, order: &OrderEvent) -> Option<FillEvent> {
+        let mut fill = MarketExecution.execute(order)?;
+        let market_entry = match order.action {
+            OrderAction::Enter(_) => true,
+            OrderAction::Limit(LimitOrder { side: Side::Buy, .. }) => false,
+            _ => return Some(fill),
+        };
+        // both draws are made for every entry, so one rate does not change which orders the other one hits
+        let (rejected, requoted) = (self.random.unit() * 100.0 < self.rejection_rate, self.random.unit() * 100.0 < self.requote_rate);
+        if rejected {
+            return None;
+        }
+        if market_entry && requoted {
+            fill.price = match order.action {
+                OrderAction::Enter(Direction::Short) => order.price * (1.0 - self.requote_slippage / 100.0),
+                _ => order.price * (1.0 + self.requote_slippage / 100.0),
+            };
+        }
+        Some(fill)
+    }
+}
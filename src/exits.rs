@@ -0,0 +1,154 @@
+//! What-if analysis of the exits: every closed trade is replayed on its candles with other exits, fixed targets in
+//! multiples of its risk and trailing stops, to see which kind of exit would have served the strategy better. The
+//! entries stay the same, and an alternative exit never holds a trade past its actual exit.
+
+use serde::Serialize;
+use crate::config::ExitAnalysisConfig;
+use crate::historical::Kline;
+use crate::testing::{Direction, Trade};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum ExitFamily {
+    /// A take-profit `parameter` risks away, with the stop-loss one risk away.
+    FixedR,
+    /// A stop `parameter` percent behind the best price since the entry.
+    Trailing,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExitAlternative {
+    pub family: ExitFamily,
+    pub parameter: f64,
+    /// Total profit of the trades with this exit, with their actual commissions and financing.
+    pub profit: f64,
+    pub win_rate: f64,
+}
+
+impl ExitAlternative {
+    pub fn label(&self) -> String {
+        match self.family {
+            ExitFamily::FixedR => format!("{}R target", self.parameter),
+            ExitFamily::Trailing => format!("{}% trailing stop", self.parameter),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExitAnalysis {
+    /// Closed trades replayed.
+    pub trades: usize,
+    /// Average maximum favorable excursion of the trades, the furthest the price went their way, in risks (R).
+    pub mean_mfe_r: f64,
+    /// Average maximum adverse excursion, the furthest the price went against them, in risks.
+    pub mean_mae_r: f64,
+    /// Share of the favorable excursions kept by the actual exits.
+    pub captured: f64,
+    pub actual_profit: f64,
+    pub actual_win_rate: f64,
+    pub alternatives: Vec<ExitAlternative>,
+}
+
+impl ExitAnalysis {
+    /// The alternative of `family` with the highest profit.
+    pub fn best(&self, family: ExitFamily) -> Option<&ExitAlternative> {
+        self.alternatives.iter().filter(|alternative| alternative.family == family).max_by(|a, b| a.profit.total_cmp(&b.profit))
+    }
+}
+
+/// Price the trade would have left at with a take-profit `target` and a stop-loss `risk` away from the entry, both
+/// relative to the entry price. A candle reaching both is assumed to hit the stop first.
+fn fixed_r_exit(trade: &Trade, path: &[Kline], risk: f64, target: f64, exit_price: f64) -> f64 {
+    let long = trade.direction == Direction::Long;
+    let side = if long { 1.0 } else { -1.0 };
+    let stop = trade.entry_price * (1.0 - side * risk);
+    let take = trade.entry_price * (1.0 + side * target * risk);
+    for kline in path.iter() {
+        let (adverse, favorable) = if long { (kline.low, kline.high) } else { (kline.high, kline.low) };
+        if side * (adverse - stop) <= 0.0 {
+            return stop;
+        }
+        if side * (favorable - take) >= 0.0 {
+            return take;
+        }
+    }
+    exit_price
+}
+
+/// Price the trade would have left at with a stop `width` behind the best price since the entry. The stop only
+/// moves with the candles before the one it is checked on.
+fn trailing_exit(trade: &Trade, path: &[Kline], width: f64, exit_price: f64) -> f64 {
+    let long = trade.direction == Direction::Long;
+    let mut best = trade.entry_price;
+    for kline in path.iter() {
+        let stop = if long { best * (1.0 - width) } else { best * (1.0 + width) };
+        if (long && kline.low <= stop) || (!long && kline.high >= stop) {
+            return stop;
+        }
+        best = if long { best.max(kline.high) } else { best.min(kline.low) };
+    }
+    exit_price
+}
+
+/// Replays the closed trades on `klines` with the exits of `config`. The candles of a trade are the ones after its
+/// entry candle, up to its exit candle included.
+pub fn analyze(trades: &[Trade], klines: &[Kline], config: &ExitAnalysisConfig) -> ExitAnalysis {
+    let mut alternatives: Vec<ExitAlternative> = config
+        .r_targets
+        .iter()
+        .map(|target| (ExitFamily::FixedR, *target))
+        .chain(config.trailing_stops.iter().map(|width| (ExitFamily::Trailing, *width)))
+        .map(|(family, parameter)| ExitAlternative { family, parameter, profit: 0.0, win_rate: 0.0 })
+        .collect();
+    let mut wins = vec![0usize; alternatives.len()];
+    let mut actual_wins = 0;
+    let (mut count, mut mfe_sum, mut mae_sum, mut kept, mut available, mut actual_profit) = (0, 0.0, 0.0, 0.0, 0.0, 0.0);
+    for trade in trades.iter() {
+        let (Some(exit_date), Some(exit_price), Some(profit)) = (trade.exit_date, trade.exit_price, trade.profit) else {
+            continue;
+        };
+        let side = if trade.direction == Direction::Short { -1.0 } else { 1.0 };
+        let start = klines.partition_point(|kline| kline.timestamp <= trade.entry_date);
+        let end = klines.partition_point(|kline| kline.timestamp <= exit_date).max(start);
+        let path = &klines[start..end];
+        let risk = match trade.stop_loss.map(|stop| (trade.entry_price - stop).abs() / trade.entry_price) {
+            Some(risk) if risk > 0.0 => risk,
+            _ => config.risk / 100.0,
+        };
+        // excursions relative to the entry price, positive in the direction of the trade
+        let favorable = path.iter().map(|kline| side * (if side > 0.0 { kline.high } else { kline.low } / trade.entry_price - 1.0)).fold(0.0, f64::max);
+        let adverse = path.iter().map(|kline| -side * (if side > 0.0 { kline.low } else { kline.high } / trade.entry_price - 1.0)).fold(0.0, f64::max);
+        count += 1;
+        mfe_sum += favorable / risk;
+        mae_sum += adverse / risk;
+        kept += side * (exit_price / trade.entry_price - 1.0);
+        available += favorable;
+        actual_profit += profit;
+        if profit > 0.0 {
+            actual_wins += 1;
+        }
+        let costs = trade.commission + trade.financing;
+        for (alternative, wins) in alternatives.iter_mut().zip(wins.iter_mut()) {
+            let price = match alternative.family {
+                ExitFamily::FixedR => fixed_r_exit(trade, path, risk, alternative.parameter, exit_price),
+                ExitFamily::Trailing => trailing_exit(trade, path, alternative.parameter / 100.0, exit_price),
+            };
+            let profit = trade.allocated * side * (price / trade.entry_price - 1.0) - costs;
+            alternative.profit += profit;
+            if profit > 0.0 {
+                *wins += 1;
+            }
+        }
+    }
+    for (alternative, wins) in alternatives.iter_mut().zip(wins.iter()) {
+        alternative.win_rate = if count > 0 { *wins as f64 / count as f64 } else { 0.0 };
+    }
+    ExitAnalysis {
+        trades: count,
+        mean_mfe_r: if count > 0 { mfe_sum / count as f64 } else { 0.0 },
+        mean_mae_r: if count > 0 { mae_sum / count as f64 } else { 0.0 },
+        captured: if available > 0.0 { kept / available } else { 0.0 },
+        actual_profit,
+        actual_win_rate: if count > 0 { actual_wins as f64 / count as f64 } else { 0.0 },
+        alternatives,
+    }
+}
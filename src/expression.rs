@@ -0,0 +1,286 @@
+//! Arithmetic over named values, used by objective expressions like `sharpe - 0.5 * max_drawdown_pct`,
+//! and comparisons between two of them, like `max_drawdown_pct < 15`.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Number(f64),
+    Variable(String),
+    Negate(Box<Expression>),
+    Binary(char, Box<Expression>, Box<Expression>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Operator(char),
+    Comparison(&'static str),
+    Open,
+    Close,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            ' ' | '\t' => {}
+            '+' | '-' | '*' | '/' => tokens.push(Token::Operator(c)),
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '<' | '>' => {
+                let comparison = match (c, chars.next_if(|(_, next)| *next == '=').is_some()) {
+                    ('<', true) => "<=",
+                    ('<', false) => "<",
+                    ('>', true) => ">=",
+                    _ => ">",
+                };
+                tokens.push(Token::Comparison(comparison));
+            }
+            c if c.is_ascii_digit() || c == '.' => {
+                let mut end = start + 1;
+                while let Some((index, _)) = chars.next_if(|(_, next)| next.is_ascii_digit() || *next == '.') {
+                    end = index + 1;
+                }
+                let number = text[start..end].parse().map_err(|_| format!("invalid number `{}`", &text[start..end]))?;
+                tokens.push(Token::Number(number));
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut end = start + 1;
+                while let Some((index, _)) = chars.next_if(|(_, next)| next.is_ascii_alphanumeric() || *next == '_') {
+                    end = index + 1;
+                }
+                tokens.push(Token::Name(text[start..end].to_string()));
+            }
+            c => return Err(format!("unexpected character `{}`", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over the tokens: sums of products of (possibly negated) atoms.
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    /// Parses all the tokens as a single expression.
+    fn parse(tokens: &[Token]) -> Result<Expression, String> {
+        let mut parser = Parser { tokens, position: 0 };
+        let expression = parser.sum()?;
+        match parser.peek() {
+            None => Ok(expression),
+            Some(token) => Err(format!("unexpected {:?}", token)),
+        }
+    }
+
+    fn sum(&mut self) -> Result<Expression, String> {
+        let mut left = self.product()?;
+        while let Some(&Token::Operator(operator @ ('+' | '-'))) = self.peek() {
+            self.position += 1;
+            left = Expression::Binary(operator, Box::new(left), Box::new(self.product()?));
+        }
+        Ok(left)
+    }
+
+    fn product(&mut self) -> Result<Expression, String> {
+        let mut left = self.atom()?;
+        while let Some(&Token::Operator(operator @ ('*' | '/'))) = self.peek() {
+            self.position += 1;
+            left = Expression::Binary(operator, Box::new(left), Box::new(self.atom()?));
+        }
+        Ok(left)
+    }
+
+    fn atom(&mut self) -> Result<Expression, String> {
+        let token = self.peek().cloned().ok_or("unexpected end of expression")?;
+        self.position += 1;
+        match token {
+            Token::Number(number) => Ok(Expression::Number(number)),
+            Token::Name(name) => Ok(Expression::Variable(name)),
+            Token::Operator('-') => Ok(Expression::Negate(Box::new(self.atom()?))),
+            Token::Open => {
+                let inner = self.sum()?;
+                match self.peek() {
+                    Some(Token::Close) => {
+                        self.position += 1;
+                        Ok(inner)
+                    }
+                    _ => Err("missing `)`".to_string()),
+                }
+            }
+            token => Err(format!("unexpected {:?}", token)),
+        }
+    }
+}
+
+impl Expression {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        Parser::parse(&tokenize(text)?).map_err(|e| format!("{} in `{}`", e, text))
+    }
+
+    /// Computes the expression, looking the variables up with `variable`.
+    pub fn evaluate(&self, variable: &dyn Fn(&str) -> Option<f64>) -> Result<f64, String> {
+        Ok(match self {
+            Expression::Number(number) => *number,
+            Expression::Variable(name) => variable(name).ok_or(format!("unknown variable `{}`", name))?,
+            Expression::Negate(inner) => -inner.evaluate(variable)?,
+            Expression::Binary(operator, left, right) => {
+                let (left, right) = (left.evaluate(variable)?, right.evaluate(variable)?);
+                match operator {
+                    '+' => left + right,
+                    '-' => left - right,
+                    '*' => left * right,
+                    _ => left / right,
+                }
+            }
+        })
+    }
+}
+
+/// Two expressions compared with `<`, `<=`, `>` or `>=`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Constraint {
+    pub left: Expression,
+    pub comparison: &'static str,
+    pub right: Expression,
+}
+
+impl Constraint {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let tokens = tokenize(text)?;
+        let split = tokens.iter().position(|token| matches!(token, Token::Comparison(_))).ok_or(format!("no comparison in `{}`", text))?;
+        let Token::Comparison(comparison) = tokens[split] else {
+            unreachable!()
+        };
+        let side = |tokens: &[Token]| Parser::parse(tokens).map_err(|e| format!("{} in `{}`", e, text));
+        Ok(Self {
+            left: side(&tokens[..split])?,
+            comparison,
+            right: side(&tokens[split + 1..])?,
+        })
+    }
+
+    pub fn holds(&self, variable: &dyn Fn(&str) -> Option<f64>) -> Result<bool, String> {
+        let (left, right) = (self.left.evaluate(variable)?, self.right.evaluate(variable)?);
+        Ok(match self.comparison {
+            "<" => left < right,
+            "<=" => left <= right,
+            ">" => left > right,
+            _ => left >= right,
+        })
+    }
+}
+
+impl std::str::FromStr for Expression {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        Self::parse(text)
+    }
+}
+
+impl std::str::FromStr for Constraint {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        Self::parse(text)
+    }
+}
+
+/// An expression or a constraint of the config, parsed once when the config is read and written back as it was given.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parsed<T> {
+    pub text: String,
+    pub value: T,
+}
+
+impl<T> std::fmt::Display for Parsed<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl<'de, T: std::str::FromStr<Err = String>> Deserialize<'de> for Parsed<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        let value = text.parse().map_err(serde::de::Error::custom)?;
+        Ok(Self { text, value })
+    }
+}
+
+impl<T> Serialize for Parsed<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.text.serialize(serializer)
+    }
+}
+
+impl<T> schemars::JsonSchema for Parsed<T> {
+    fn schema_name() -> String {
+        "Expression".to_string()
+    }
+
+    fn json_schema(generator: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        String::json_schema(generator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluate(text: &str) -> Result<f64, String> {
+        Expression::parse(text)?.evaluate(&|name| match name {
+            "sharpe" => Some(2.0),
+            "max_drawdown_pct" => Some(10.0),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn products_bind_tighter_than_sums() {
+        assert_eq!(evaluate("1 + 2 * 3"), Ok(7.0));
+        assert_eq!(evaluate("(1 + 2) * 3"), Ok(9.0));
+        assert_eq!(evaluate("8 / 4 / 2"), Ok(1.0));
+        assert_eq!(evaluate("10 - 4 - 3"), Ok(3.0));
+    }
+
+    #[test]
+    fn variables_and_negation() {
+        assert_eq!(evaluate("sharpe - 0.5 * max_drawdown_pct"), Ok(-3.0));
+        assert_eq!(evaluate("-sharpe * -2"), Ok(4.0));
+        assert!(evaluate("sortino").unwrap_err().contains("unknown variable `sortino`"));
+    }
+
+    #[test]
+    fn malformed_expressions_are_rejected() {
+        for text in ["", "1 +", "(1 + 2", "1 2", "sharpe $ 2", "1..2", "sharpe < 2"] {
+            assert!(Expression::parse(text).is_err(), "{} was accepted", text);
+        }
+    }
+
+    #[test]
+    fn constraints_compare_both_sides() {
+        let variable = |name: &str| (name == "max_drawdown_pct").then_some(10.0);
+        assert_eq!(Constraint::parse("max_drawdown_pct < 15").unwrap().holds(&variable), Ok(true));
+        assert_eq!(Constraint::parse("max_drawdown_pct >= 2 * 6").unwrap().holds(&variable), Ok(false));
+        assert_eq!(Constraint::parse("max_drawdown_pct <= 10").unwrap().comparison, "<=");
+        assert!(Constraint::parse("max_drawdown_pct").is_err());
+        assert!(Constraint::parse("< 15").is_err());
+    }
+
+    #[test]
+    fn parsed_expressions_keep_their_text() {
+        let parsed: Parsed<Expression> = serde_json::from_str("\"sharpe  -  1\"").unwrap();
+        assert_eq!(parsed.value, Expression::parse("sharpe - 1").unwrap());
+        assert_eq!(serde_json::to_string(&parsed).unwrap(), "\"sharpe  -  1\"");
+        assert!(serde_json::from_str::<Parsed<Expression>>("\"sharpe -\"").is_err());
+    }
+}
@@ -0,0 +1,77 @@
+//! C API of the engine, see `include/retroval.h`. Every string crossing the boundary is UTF-8 JSON.
+
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use crate::{acceptance, analysis, config, historical, testing};
+
+/// Called with every trade as soon as it is closed. The string is only valid during the call.
+pub type TradeCallback = extern "C" fn(trade_json: *const c_char, user_data: *mut c_void);
+
+fn run(config_json: &str, on_trade: &mut dyn FnMut(&testing::Trade)) -> Result<serde_json::Value, String> {
+    let config = config::parse_config(config_json)?;
+    let klines = historical::read_data(&config).map_err(|e| format!("Error while reading klines: {}", e))?;
+    let recap = testing::run_backtest(&config, &klines, on_trade).map_err(|e| e.to_string())?;
+    let verdict = acceptance::evaluate(&recap.metrics, &config);
+    let mut summary = analysis::json_summary(&recap, &config, &verdict);
+    summary["trades"] = serde_json::json!(recap.trades);
+    Ok(summary)
+}
+
+/// Runs `run` without letting a panic unwind into the caller, errors become `{"error": "..."}`.
+unsafe fn run_to_c(config_json: *const c_char, on_trade: &mut dyn FnMut(&testing::Trade)) -> *mut c_char {
+    let result = if config_json.is_null() {
+        Err("config is null".to_string())
+    } else {
+        match CStr::from_ptr(config_json).to_str() {
+            Ok(config_json) => panic::catch_unwind(AssertUnwindSafe(|| run(config_json, on_trade))).unwrap_or_else(|payload| {
+                let message = payload
+                    .downcast_ref::<String>()
+                    .cloned()
+                    .or_else(|| payload.downcast_ref::<&str>().map(|message| message.to_string()))
+                    .unwrap_or_else(|| "unknown error".to_string());
+                Err(message)
+            }),
+            Err(e) => Err(format!("config is not valid UTF-8: {}", e)),
+        }
+    };
+    let output = match result {
+        Ok(summary) => summary,
+        Err(e) => serde_json::json!({ "error": e }),
+    };
+    CString::new(output.to_string()).unwrap().into_raw()
+}
+
+/// Runs a backtest from a config given as JSON, and returns the results as JSON.
+/// The returned string must be released with `retroval_free_string`.
+///
+/// # Safety
+/// `config_json` must be null or a valid nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn retroval_run(config_json: *const c_char) -> *mut c_char {
+    run_to_c(config_json, &mut |_| {})
+}
+
+/// Like `retroval_run`, and streams every closed trade to `on_trade` during the run, with `user_data` passed as-is.
+///
+/// # Safety
+/// `config_json` must be null or a valid nul-terminated string, `on_trade` must be safe to call with `user_data`.
+#[no_mangle]
+pub unsafe extern "C" fn retroval_run_with_callback(config_json: *const c_char, on_trade: Option<TradeCallback>, user_data: *mut c_void) -> *mut c_char {
+    run_to_c(config_json, &mut |trade| {
+        if let Some(on_trade) = on_trade {
+            let trade = CString::new(serde_json::to_string(trade).unwrap()).unwrap();
+            on_trade(trade.as_ptr(), user_data);
+        }
+    })
+}
+
+/// Releases a string returned by the engine.
+///
+/// # Safety
+/// `string` must be null or a pointer returned by `retroval_run` or `retroval_run_with_callback`, released only once.
+#[no_mangle]
+pub unsafe extern "C" fn retroval_free_string(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
@@ -1,21 +1,103 @@
 use chrono::NaiveDateTime;
 use plotters::prelude::*;
 use plotters::style::full_palette as palette;
-use plotters::coord::{Shift, types::{RangedCoordi64, RangedCoordf64}};
+use plotters::coord::{Shift, types::RangedCoordf64};
 
-use crate::historical::Kline;
-use crate::config::{self, Config};
-use crate::testing::{SessionRecap, Trade};
+use crate::historical::{self, Kline};
+use crate::analysis::{self, ParameterSurface};
+use crate::config::{self, Animation, ChartLevels, ChartStyle, Config, GraphPanel, RollingMetric, RollingMetricKind, Theme};
+use crate::indicators::{Pivots, SwingKind, Swings, VolumeProfile};
+use crate::montecarlo::{MonteCarloResult, PERCENTILES};
+use crate::regime::{Regime, REGIMES};
+use crate::testing::{Direction, ExitReason, Sensitivity, SessionRecap, SkipReason, SkippedSignal, Trade};
 
-fn get_timestamp_range(klines: &Vec<Kline>) -> (i64, i64) {
+/// Colors and sizes of the graph, resolved from the `chart_style` of the config.
+struct Style<'a> {
+    font: &'a str,
+    scale: f64,
+    background: RGBColor,
+    foreground: RGBColor,
+    grid: RGBColor,
+    up: RGBColor,
+    down: RGBColor,
+    entry: RGBColor,
+    exit: RGBColor,
+    forced_exit: RGBColor,
+    skipped: RGBColor,
+    equity: RGBColor,
+    benchmark: RGBColor,
+}
+
+/// The colors of `chart_style` are checked by `Config::validate`.
+fn parse_color(hex: &str) -> RGBColor {
+    let (r, g, b) = config::parse_color(hex).expect("invalid color in chart_style");
+    RGBColor(r, g, b)
+}
+
+impl<'a> Style<'a> {
+    fn new(chart_style: &'a ChartStyle) -> Self {
+        let (background, foreground, grid, up, down, entry, exit, forced_exit, skipped, equity) = match chart_style.theme {
+            Theme::Light => (WHITE, BLACK, palette::GREY_A200, GREEN, RED, palette::BLUE, palette::PINK, palette::GREY, palette::AMBER_700, BLUE),
+            Theme::Dark => (
+                RGBColor(0x16, 0x1a, 0x25),
+                palette::GREY_300,
+                RGBColor(0x2a, 0x2e, 0x39),
+                RGBColor(0x26, 0xa6, 0x9a),
+                RGBColor(0xef, 0x53, 0x50),
+                palette::LIGHTBLUE_300,
+                palette::PINK_300,
+                palette::GREY_600,
+                palette::AMBER_300,
+                palette::LIGHTBLUE_300,
+            ),
+        };
+        Self {
+            font: &chart_style.font,
+            scale: chart_style.dpi as f64 / 96.0,
+            background,
+            foreground,
+            grid,
+            up: chart_style.up_color.as_deref().map(parse_color).unwrap_or(up),
+            down: chart_style.down_color.as_deref().map(parse_color).unwrap_or(down),
+            entry,
+            exit,
+            forced_exit,
+            skipped,
+            equity,
+            benchmark: palette::GREY,
+        }
+    }
+
+    /// Scales a size given at 96 DPI.
+    fn px(&self, size: u32) -> u32 {
+        (size as f64 * self.scale).round().max(1.0) as u32
+    }
+
+    fn text(&self, size: u32) -> TextStyle<'a> {
+        (self.font, self.px(size) as f64).into_font().color(&self.foreground)
+    }
+}
+
+fn get_date_range(klines: &[Kline]) -> (NaiveDateTime, NaiveDateTime) {
     let x_min = klines.iter().map(|k| k.timestamp).min().unwrap();
     let x_max = klines.iter().map(|k| k.timestamp).max().unwrap();
-    let min_timestamp = x_min.and_utc().timestamp();
-    let max_timestamp = x_max.and_utc().timestamp();
-    (min_timestamp, max_timestamp)
+    (x_min, x_max)
+}
+
+/// Date format of the x-axis labels, precise enough to tell neighbouring labels apart.
+/// Labels are placed on round dates, so above half a day between two labels they fall on midnight.
+fn date_format(start: NaiveDateTime, end: NaiveDateTime, labels: usize) -> &'static str {
+    let seconds_per_label = (end - start).num_seconds() / labels.max(1) as i64;
+    if seconds_per_label > 43200 {
+        "%Y-%m-%d"
+    } else if seconds_per_label >= 60 {
+        "%m-%d %H:%M"
+    } else {
+        "%H:%M:%S"
+    }
 }
 
-fn get_price_range(klines: &Vec<Kline>) -> (f64, f64) {
+fn get_price_range(klines: &[Kline]) -> (f64, f64) {
     let y_min = klines.iter().map(|k| k.low).min_by(|a, b| a.partial_cmp(b).unwrap()).unwrap();
     let y_max = klines.iter().map(|k| k.high).max_by(|a, b| a.partial_cmp(b).unwrap()).unwrap();
     let min_price = y_min - 0.1 * y_min;
@@ -23,96 +105,1187 @@ fn get_price_range(klines: &Vec<Kline>) -> (f64, f64) {
     (min_price, max_price)
 }
 
-fn draw_trade_lines(trades: &Vec<Trade>, chart: &mut ChartContext<BitMapBackend, Cartesian2d<RangedCoordi64, RangedCoordf64>>, min_price: f64, max_price: f64) -> Result<(), Box<dyn std::error::Error>> {
+/// Width of the y-axis labels at 96 DPI, the same for both panels so their x-axes line up.
+const Y_LABEL_AREA: u32 = 60;
+
+/// The x-axis shared by both panels.
+struct TimeAxis {
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+    labels: usize,
+    format: &'static str,
+}
+
+impl TimeAxis {
+    fn contains(&self, date: NaiveDateTime) -> bool {
+        self.start <= date && date <= self.end
+    }
+}
+
+type PriceChart<'a, 'b> = ChartContext<'a, BitMapBackend<'b>, Cartesian2d<RangedDateTime<NaiveDateTime>, RangedCoordf64>>;
+
+fn draw_trade_lines<'a>(trades: impl Iterator<Item = &'a Trade>, chart: &mut PriceChart, style: &Style, axis: &TimeAxis, min_price: f64, max_price: f64) -> Result<(), Box<dyn std::error::Error>> {
     let entry_line_style = ShapeStyle {
-        color: palette::BLUE.to_rgba(),
+        color: style.entry.to_rgba(),
         filled: false,
-        stroke_width: 1,
+        stroke_width: style.px(1),
     };
     let exit_line_style = ShapeStyle {
-        color: palette::PINK.to_rgba(),
+        color: style.exit.to_rgba(),
         filled: false,
-        stroke_width: 1,
+        stroke_width: style.px(1),
     };
-    // draw lines for trades here
+    let forced_exit_line_style = ShapeStyle {
+        color: style.forced_exit.to_rgba(),
+        filled: false,
+        stroke_width: style.px(2),
+    };
+    // draw lines for trades here, leaving out the ones outside of the chart
     for trade in trades {
-        let entry_ts = trade.entry_date.and_utc().timestamp();
-        // Draw a vertical line for the trade entry (using a semi-transparent green)
-        chart.draw_series(std::iter::once(PathElement::new(
-            vec![(entry_ts, min_price), (entry_ts, max_price)],
-            entry_line_style,
-        )))?;
-
-        if let Some(exit_date) = trade.exit_date {
-            let exit_ts = exit_date.and_utc().timestamp();
-            // Draw a vertical line for the trade exit (using a semi-transparent red)
+        // Draw a vertical line for the trade entry
+        if axis.contains(trade.entry_date) {
             chart.draw_series(std::iter::once(PathElement::new(
-                vec![(exit_ts, min_price), (exit_ts, max_price)],
-                exit_line_style,
+                vec![(trade.entry_date, min_price), (trade.entry_date, max_price)],
+                entry_line_style,
+            )))?;
+        }
+
+        if let Some(exit_date) = trade.exit_date.filter(|date| axis.contains(*date)) {
+            // Draw a vertical line for the trade exit (thicker and grey when forced by the end of the data)
+            let line_style = match trade.exit_reason {
+                Some(ExitReason::EndOfData) => forced_exit_line_style,
+                _ => exit_line_style,
+            };
+            chart.draw_series(std::iter::once(PathElement::new(
+                vec![(exit_date, min_price), (exit_date, max_price)],
+                line_style,
             )))?;
         }
     }
     Ok(())
 }
 
-fn make_equity_chart(chart_element: &DrawingArea<BitMapBackend<'_>, Shift>, equity_curve: &Vec<(NaiveDateTime, f64)>, min_equity: f64, max_equity: f64) -> Result<(), Box<dyn std::error::Error>> {
-    let mut equity_chart = ChartBuilder::on(chart_element)
-        .x_label_area_size(40)
-        .y_label_area_size(40)
-        .caption("Equity curve", ("sans-serif", 15.0).into_font())
-        .build_cartesian_2d(0..equity_curve.len() as i64, min_equity..max_equity)?;
+/// Writes the id of each trade at the top of its entry line, to find it in the log and the trades file. Left out when
+/// the trades would not fit, more than two per date label of the axis.
+fn draw_trade_ids<'a>(trades: impl Iterator<Item = &'a Trade>, chart: &mut PriceChart, style: &Style, axis: &TimeAxis, max_price: f64) -> Result<(), Box<dyn std::error::Error>> {
+    let visible: Vec<&Trade> = trades.filter(|trade| axis.contains(trade.entry_date)).collect();
+    if visible.len() > axis.labels * 2 {
+        return Ok(());
+    }
+    let offset = style.px(3) as i32;
+    chart.draw_series(visible.iter().map(|trade| EmptyElement::at((trade.entry_date, max_price)) + Text::new(trade.id.clone(), (offset, offset), style.text(10).color(&style.entry))))?;
+    Ok(())
+}
 
-    equity_chart.configure_mesh().disable_x_mesh().draw()?;
-    equity_chart.draw_series(LineSeries::new(
-        equity_curve.iter().enumerate().map(|(i, (_, equity))| (i as i64, *equity)),
-        BLUE,
-    ))?;
+/// Draws the stop-loss and take-profit of each trade as a segment from its entry to its exit, or to the end of the chart
+/// while it is still open, clipped to the chart.
+fn draw_trade_levels<'a>(trades: impl Iterator<Item = &'a Trade>, chart: &mut PriceChart, style: &Style, axis: &TimeAxis) -> Result<(), Box<dyn std::error::Error>> {
+    for trade in trades {
+        let start = trade.entry_date.max(axis.start);
+        let end = trade.exit_date.unwrap_or(axis.end).min(axis.end);
+        if start > end {
+            continue;
+        }
+        let levels = [trade.stop_loss.map(|price| (price, style.down)), trade.take_profit.map(|price| (price, style.up))];
+        for (price, color) in levels.into_iter().flatten() {
+            chart.draw_series(std::iter::once(PathElement::new(vec![(start, price), (end, price)], color.stroke_width(style.px(2)))))?;
+        }
+    }
     Ok(())
 }
 
-pub fn plot_graph(config: &Config, klines: &Vec<Kline>, recap: SessionRecap) -> Result<(), Box<dyn std::error::Error>> {
-    let (min_timestamp, max_timestamp) = get_timestamp_range(klines);
-    let (min_price, max_price) = get_price_range(klines);
+/// Draws the support and resistance levels of `chart_levels`: each swing as a segment from the swing to the first close
+/// across it, and the pivot levels of each day over that day, resistances in the down color and supports in the up color.
+fn draw_levels(klines: &[Kline], levels: &ChartLevels, chart: &mut PriceChart, style: &Style) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(last) = klines.last() else {
+        return Ok(());
+    };
+    let width = style.px(1);
+    if let Some(window) = levels.swing_window {
+        let mut swings = Swings::new(window);
+        for kline in klines.iter() {
+            swings.update(kline);
+        }
+        for swing in swings.swings() {
+            let low = swing.kind == SwingKind::Low;
+            let broken = klines.iter().find(|kline| kline.timestamp > swing.timestamp && if low { kline.close < swing.price } else { kline.close > swing.price });
+            let end = broken.unwrap_or(last).timestamp;
+            let color = if low { style.up } else { style.down };
+            chart.draw_series(std::iter::once(PathElement::new(vec![(swing.timestamp, swing.price), (end, swing.price)], color.mix(0.7).stroke_width(width))))?;
+        }
+    }
+    if let Some(kind) = levels.pivots {
+        let mut pivots = Pivots::new(kind);
+        let mut segments: Vec<(NaiveDateTime, NaiveDateTime, [f64; 7])> = Vec::new();
+        for kline in klines.iter() {
+            pivots.update(kline);
+            let Some(levels) = pivots.value() else {
+                continue;
+            };
+            let prices = [levels.pivot, levels.supports[0], levels.supports[1], levels.supports[2], levels.resistances[0], levels.resistances[1], levels.resistances[2]];
+            match segments.last_mut() {
+                Some((_, end, last_prices)) if *last_prices == prices => *end = kline.timestamp,
+                _ => segments.push((kline.timestamp, kline.timestamp, prices)),
+            }
+        }
+        for (start, end, prices) in segments {
+            for (index, price) in prices.into_iter().enumerate() {
+                let color = match index {
+                    0 => style.foreground,
+                    1..=3 => style.up,
+                    _ => style.down,
+                };
+                chart.draw_series(std::iter::once(PathElement::new(vec![(start, price), (end, price)], color.mix(0.5).stroke_width(width))))?;
+            }
+        }
+    }
+    Ok(())
+}
 
-    let pair = &config.pair;
-    let title = format!("Backtesting results on {}", pair);
+/// Draws the volume profile of the candles as a horizontal histogram over the same prices as the candlesticks, the
+/// point of control in the exit color and the value area (70% of the volume) in the entry color.
+fn make_volume_profile_chart(chart_element: &DrawingArea<BitMapBackend<'_>, Shift>, style: &Style, profile: &VolumeProfile, min_price: f64, max_price: f64) -> Result<(), Box<dyn std::error::Error>> {
+    let max_volume = profile.volumes.iter().fold(0.0, |max: f64, volume| max.max(*volume));
+    let mut chart = ChartBuilder::on(chart_element)
+        .x_label_area_size(style.px(40))
+        .caption("Volume profile", style.text(15))
+        .build_cartesian_2d(0.0..max_volume * 1.05, min_price..max_price)?;
+    chart.configure_mesh().axis_style(style.foreground).disable_mesh().x_labels(0).y_labels(0).draw()?;
+    let poc = profile.point_of_control();
+    let (value_low, value_high) = profile.value_area(0.7);
+    chart.draw_series(profile.volumes.iter().enumerate().map(|(index, volume)| {
+        let price = profile.price(index);
+        let color = if price == poc {
+            style.exit
+        } else if (value_low..=value_high).contains(&price) {
+            style.entry
+        } else {
+            style.forced_exit
+        };
+        let half = profile.bucket_size / 2.0;
+        Rectangle::new([(0.0, price - half), (*volume, price + half)], color.mix(0.8).filled())
+    }))?;
+    Ok(())
+}
 
-    let candle_px_width = 5;
-    let chart_width = candle_px_width * klines.len() as u32;
+/// Shades the background of each run of candles of the same regime, with a legend: trending in the entry color,
+/// ranging in grey and high volatility in the exit color.
+fn draw_regimes<'a, 'b: 'a>(regimes: &[(NaiveDateTime, Regime)], chart: &mut PriceChart<'a, 'b>, style: &Style, axis: &TimeAxis, timeframe: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let (min_price, max_price) = (chart.y_range().start, chart.y_range().end);
+    let candle = chrono::Duration::seconds((365.0 * 24.0 * 3600.0 / analysis::periods_per_year(timeframe)?).round() as i64);
+    let mut runs: Vec<(NaiveDateTime, NaiveDateTime, Regime)> = Vec::new();
+    for (date, regime) in regimes.iter().filter(|(date, _)| axis.contains(*date)) {
+        match runs.last_mut() {
+            Some((_, end, last)) if last == regime => *end = *date,
+            _ => runs.push((*date, *date, *regime)),
+        }
+    }
+    for regime in REGIMES {
+        let color = match regime {
+            Regime::Trending => style.entry,
+            Regime::Ranging => style.forced_exit,
+            Regime::HighVolatility => style.exit,
+        };
+        let shades = runs.iter().filter(|(_, _, run)| *run == regime).map(|(start, end, _)| Rectangle::new([(*start, min_price), ((*end + candle).min(axis.end), max_price)], color.mix(0.12).filled()));
+        chart
+            .draw_series(shades)?
+            .label(regime.to_string())
+            .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 20, y + 5)], color.mix(0.4).filled()));
+    }
+    chart
+        .configure_series_labels()
+        .position(SeriesLabelPosition::UpperLeft)
+        .background_style(style.background.mix(0.8))
+        .border_style(style.foreground)
+        .label_font(style.text(12))
+        .draw()?;
+    Ok(())
+}
 
-    let root_area = BitMapBackend::new(&config.log_graph_file, (chart_width, 768)).into_drawing_area();
-    root_area.fill(&WHITE)?;
-    let root_area = root_area.titled(&title, ("sans-serif", 60))?;
+/// Shades the stretches where no trade could happen: data gaps (more than one candle missing) in grey, and runs of
+/// entries blocked by a risk manager or a cooldown in the down color, from the first blocked entry to the last one
+/// before the next trade.
+fn draw_no_trade_periods(klines: &[Kline], recap: &SessionRecap, chart: &mut PriceChart, style: &Style, axis: &TimeAxis, timeframe: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let (min_price, max_price) = (chart.y_range().start, chart.y_range().end);
+    let candle = chrono::Duration::seconds((365.0 * 24.0 * 3600.0 / analysis::periods_per_year(timeframe)?).round() as i64);
+    let gaps = klines.windows(2).filter(|pair| pair[1].timestamp - pair[0].timestamp > candle * 2).map(|pair| (pair[0].timestamp + candle, pair[1].timestamp));
+    chart.draw_series(gaps.map(|(start, end)| Rectangle::new([(start, min_price), (end, max_price)], style.forced_exit.mix(0.25).filled())))?;
 
-    let (main, bottom_elem) = root_area.split_vertically(512);
+    let mut entries: Vec<NaiveDateTime> = recap.trades.iter().chain(recap.open_trades.iter()).chain(recap.open_lots.iter()).map(|trade| trade.entry_date).collect();
+    entries.sort();
+    let mut blocked: Vec<(NaiveDateTime, NaiveDateTime)> = Vec::new();
+    for signal in recap.skipped_signals.iter().filter(|signal| matches!(signal.reason, SkipReason::RiskLimit | SkipReason::Cooldown)) {
+        match blocked.last_mut() {
+            // still blocked when no trade was entered since the previous blocked entry
+            Some((_, end)) if !entries.iter().any(|entry| *end < *entry && *entry <= signal.date) => *end = signal.date,
+            _ => blocked.push((signal.date, signal.date)),
+        }
+    }
+    chart.draw_series(
+        blocked
+            .into_iter()
+            .filter(|(start, end)| axis.contains(*start) || axis.contains(*end))
+            .map(|(start, end)| Rectangle::new([(start.max(axis.start), min_price), ((end + candle).min(axis.end), max_price)], style.down.mix(0.15).filled())),
+    )?;
+    Ok(())
+}
 
-    let mut cstick_chart = ChartBuilder::on(&main)
-        .x_label_area_size(40)
-        .y_label_area_size(40)
-        .caption("Candlestick data", ("sans-serif", 15.0).into_font())
-        .build_cartesian_2d(min_timestamp..max_timestamp, min_price..max_price)?;
+fn draw_skipped_signals(skipped: &[SkippedSignal], chart: &mut PriceChart, style: &Style, axis: &TimeAxis, min_price: f64, max_price: f64) -> Result<(), Box<dyn std::error::Error>> {
+    // skipped entries are marked along the bottom of the chart, pointing in the direction of the signal
+    let marker_price = min_price + 0.02 * (max_price - min_price);
+    let size = style.px(4) as i32;
+    chart.draw_series(skipped.iter().filter(|signal| axis.contains(signal.date)).map(|signal| {
+        let size = if signal.direction == Direction::Short { -size } else { size };
+        TriangleMarker::new((signal.date, marker_price), size, style.skipped.filled())
+    }))?;
+    Ok(())
+}
 
-    cstick_chart.configure_mesh().light_line_style(palette::GREY_A200).disable_x_mesh().draw()?;
+/// Draws the candlesticks of the price panel, with the trades, levels and regimes of the recap over them.
+#[allow(clippy::too_many_arguments)]
+fn make_price_chart(chart_element: &DrawingArea<BitMapBackend<'_>, Shift>, style: &Style, axis: &TimeAxis, config: &Config, klines: &[Kline], candles: &[Kline], candle_px_width: u32, recap: &SessionRecap, min_price: f64, max_price: f64) -> Result<(), Box<dyn std::error::Error>> {
+    let mut cstick_chart = ChartBuilder::on(chart_element)
+        .x_label_area_size(style.px(40))
+        .y_label_area_size(style.px(Y_LABEL_AREA))
+        .caption("Candlestick data", style.text(15))
+        .build_cartesian_2d(RangedDateTime::from(axis.start..axis.end), min_price..max_price)?;
 
+    cstick_chart
+        .configure_mesh()
+        .axis_style(style.foreground)
+        .bold_line_style(style.grid)
+        .light_line_style(style.grid)
+        .label_style(style.text(12))
+        .disable_x_mesh()
+        .x_labels(axis.labels)
+        .x_label_formatter(&|date| date.format(axis.format).to_string())
+        .draw()?;
+
+    if !recap.regimes.is_empty() {
+        draw_regimes(&recap.regimes, &mut cstick_chart, style, axis, &config.timeframe)?;
+    }
+    draw_no_trade_periods(klines, recap, &mut cstick_chart, style, axis, &config.timeframe)?;
     cstick_chart.draw_series(
-        klines.iter().map(|candle| {
-            CandleStick::new(candle.timestamp.and_utc().timestamp(), candle.open, candle.high, candle.low, candle.close, GREEN.filled(), RED, 5)
+        candles.iter().map(|candle| {
+            CandleStick::new(candle.timestamp, candle.open, candle.high, candle.low, candle.close, style.up.filled(), style.down, candle_px_width)
         }),
     )?;
-    draw_trade_lines(&recap.trades, &mut cstick_chart, min_price, max_price)?;
+    if let Some(levels) = &config.chart_levels {
+        draw_levels(klines, levels, &mut cstick_chart, style)?;
+    }
+    draw_trade_levels(recap.trades.iter().chain(recap.open_trades.iter()).chain(recap.open_lots.iter()), &mut cstick_chart, style, axis)?;
+    draw_trade_lines(recap.trades.iter().chain(recap.open_trades.iter()).chain(recap.open_lots.iter()), &mut cstick_chart, style, axis, min_price, max_price)?;
+    draw_trade_ids(recap.trades.iter().chain(recap.open_trades.iter()).chain(recap.open_lots.iter()), &mut cstick_chart, style, axis, max_price)?;
+    draw_skipped_signals(&recap.skipped_signals, &mut cstick_chart, style, axis, min_price, max_price)?;
+    Ok(())
+}
+
+/// Draws the equity curve on the same time axis as the candlesticks, so both panels line up.
+fn make_equity_chart(chart_element: &DrawingArea<BitMapBackend<'_>, Shift>, style: &Style, axis: &TimeAxis, equity_curve: &[(NaiveDateTime, f64)], benchmark_curve: &[(NaiveDateTime, f64)], min_equity: f64, max_equity: f64) -> Result<(), Box<dyn std::error::Error>> {
+    let mut equity_chart = ChartBuilder::on(chart_element)
+        .x_label_area_size(style.px(40))
+        .y_label_area_size(style.px(Y_LABEL_AREA))
+        .caption("Equity curve", style.text(15))
+        .build_cartesian_2d(RangedDateTime::from(axis.start..axis.end), min_equity..max_equity)?;
+
+    equity_chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .x_labels(axis.labels)
+        .x_label_formatter(&|date| date.format(axis.format).to_string())
+        .axis_style(style.foreground)
+        .bold_line_style(style.grid)
+        .light_line_style(style.grid)
+        .label_style(style.text(12))
+        .draw()?;
+    let line_style = style.equity.stroke_width(style.px(1));
+    equity_chart.draw_series(LineSeries::new(
+        equity_curve.iter().copied(),
+        line_style,
+    ))?
+    .label("Strategy")
+    .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], line_style));
+    if !benchmark_curve.is_empty() {
+        let line_style = style.benchmark.stroke_width(style.px(1));
+        equity_chart.draw_series(LineSeries::new(
+            benchmark_curve.iter().copied(),
+            line_style,
+        ))?
+        .label("Benchmark")
+        .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], line_style));
+        equity_chart
+            .configure_series_labels()
+            .background_style(style.background.mix(0.8))
+            .border_style(style.foreground)
+            .label_font(style.text(12))
+            .draw()?;
+    }
+    Ok(())
+}
+
+/// Draws how far below its highest point so far the equity is, in percentage.
+fn make_drawdown_chart(chart_element: &DrawingArea<BitMapBackend<'_>, Shift>, style: &Style, axis: &TimeAxis, drawdowns: &[(NaiveDateTime, f64)]) -> Result<(), Box<dyn std::error::Error>> {
+    let deepest = drawdowns.iter().fold(0.0_f64, |deepest, (_, drawdown)| deepest.min(*drawdown));
+    let mut drawdown_chart = ChartBuilder::on(chart_element)
+        .x_label_area_size(style.px(40))
+        .y_label_area_size(style.px(Y_LABEL_AREA))
+        .caption("Drawdown (%)", style.text(15))
+        .build_cartesian_2d(RangedDateTime::from(axis.start..axis.end), (deepest * 1.1).min(-1.0)..0.0)?;
+
+    drawdown_chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .x_labels(axis.labels)
+        .x_label_formatter(&|date| date.format(axis.format).to_string())
+        .axis_style(style.foreground)
+        .bold_line_style(style.grid)
+        .light_line_style(style.grid)
+        .label_style(style.text(12))
+        .draw()?;
+    drawdown_chart.draw_series(AreaSeries::new(drawdowns.iter().copied(), 0.0, style.down.mix(0.3)).border_style(style.down.stroke_width(style.px(1))))?;
+    Ok(())
+}
+
+fn make_rolling_chart(chart_element: &DrawingArea<BitMapBackend<'_>, Shift>, style: &Style, axis: &TimeAxis, rule: &RollingMetric, values: &[(NaiveDateTime, f64)]) -> Result<(), Box<dyn std::error::Error>> {
+    let (caption, min_value, max_value) = match rule.metric {
+        RollingMetricKind::WinRate => (format!("Win rate over the last {} trades (%)", rule.window), 0.0, 100.0),
+        RollingMetricKind::Sharpe => {
+            let (min, max) = values.iter().fold((0.0_f64, 0.0_f64), |(min, max), (_, value)| (min.min(*value), max.max(*value)));
+            let margin = (max - min).max(0.1) * 0.1;
+            (format!("Sharpe ratio over the last {} trades", rule.window), min - margin, max + margin)
+        }
+    };
+    let mut rolling_chart = ChartBuilder::on(chart_element)
+        .x_label_area_size(style.px(40))
+        .y_label_area_size(style.px(Y_LABEL_AREA))
+        .caption(caption, style.text(15))
+        .build_cartesian_2d(RangedDateTime::from(axis.start..axis.end), min_value..max_value)?;
+
+    rolling_chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .x_labels(axis.labels)
+        .x_label_formatter(&|date| date.format(axis.format).to_string())
+        .axis_style(style.foreground)
+        .bold_line_style(style.grid)
+        .light_line_style(style.grid)
+        .label_style(style.text(12))
+        .draw()?;
+    // the neutral level: no edge for the Sharpe ratio, a coin flip for the win rate
+    let neutral = match rule.metric {
+        RollingMetricKind::WinRate => 50.0,
+        RollingMetricKind::Sharpe => 0.0,
+    };
+    rolling_chart.draw_series(std::iter::once(PathElement::new(vec![(axis.start, neutral), (axis.end, neutral)], style.benchmark.stroke_width(style.px(1)))))?;
+    rolling_chart.draw_series(LineSeries::new(values.iter().copied(), style.equity.stroke_width(style.px(1))))?;
+    Ok(())
+}
+
+/// Largest-Triangle-Three-Buckets downsampling: keeps `threshold` points of the line, the first and the last ones and
+/// in each bucket between them the point making the largest triangle with the point kept before and the average of the
+/// next bucket, so the peaks and troughs survive. Shorter lines are kept whole.
+fn lttb(points: &[(NaiveDateTime, f64)], threshold: usize) -> Vec<(NaiveDateTime, f64)> {
+    if threshold < 3 || points.len() <= threshold {
+        return points.to_vec();
+    }
+    let x = |index: usize| points[index].0.and_utc().timestamp() as f64;
+    let every = (points.len() - 2) as f64 / (threshold - 2) as f64;
+    let bound = |bucket: usize| ((bucket as f64 * every) as usize + 1).min(points.len());
+    let mut sampled = vec![points[0]];
+    let mut kept = 0;
+    for bucket in 0..threshold - 2 {
+        let (start, end) = (bound(bucket), bound(bucket + 1));
+        // the last bucket looks ahead to the last point
+        let next = end..bound(bucket + 2);
+        let count = next.len().max(1) as f64;
+        let (next_x, next_y) = next.fold((0.0, 0.0), |(sum_x, sum_y), index| (sum_x + x(index) / count, sum_y + points[index].1 / count));
+        let (kept_x, kept_y) = (x(kept), points[kept].1);
+        let area = |index: usize| ((kept_x - next_x) * (points[index].1 - kept_y) - (kept_x - x(index)) * (next_y - kept_y)).abs();
+        kept = (start..end).max_by(|a, b| area(*a).total_cmp(&area(*b))).unwrap_or(start);
+        sampled.push(points[kept]);
+    }
+    sampled.push(points[points.len() - 1]);
+    sampled
+}
 
-    let curve = &recap.equity_curve;
-    let (min_equity, max_equity) = curve.iter().fold((f64::MAX, f64::MIN), |(min, max), (_, equity)| {
+/// With the `zoom` suffix, `graph.png` becomes `graph_zoom.png`, next to it.
+fn suffixed_path(graph_file: &str, suffix: &str) -> String {
+    let path = std::path::Path::new(graph_file);
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let file_name = match path.extension() {
+        Some(extension) => format!("{}_{}.{}", stem, suffix, extension.to_string_lossy()),
+        None => format!("{}_{}", stem, suffix),
+    };
+    path.with_file_name(file_name).to_string_lossy().into_owned()
+}
+
+/// Draws one small graph per closed trade into a `trades` directory next to the graph, with `context` candles
+/// before the entry and after the exit, and the entry, exit, stop-loss and take-profit levels.
+fn plot_trade_charts(config: &Config, klines: &[Kline], trades: &[Trade], context: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let style = Style::new(&config.chart_style);
+    let directory = std::path::Path::new(&config.log_graph_file).parent().unwrap_or(std::path::Path::new("")).join("trades");
+    std::fs::create_dir_all(&directory)?;
+    for trade in trades.iter() {
+        let (Some(exit_date), Some(exit_price)) = (trade.exit_date, trade.exit_price) else {
+            continue;
+        };
+        let entry_index = klines.iter().position(|kline| kline.timestamp >= trade.entry_date).unwrap_or(0);
+        let exit_index = klines.iter().position(|kline| kline.timestamp >= exit_date).unwrap_or(klines.len() - 1);
+        let window = &klines[entry_index.saturating_sub(context)..=(exit_index + context).min(klines.len() - 1)];
+        let (start, end) = get_date_range(window);
+        let levels: Vec<(f64, RGBColor)> = [
+            Some((trade.entry_price, style.entry)),
+            Some((exit_price, style.exit)),
+            trade.stop_loss.map(|price| (price, style.down)),
+            trade.take_profit.map(|price| (price, style.up)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        // the range covers the candles and every level, with a small margin
+        let (low, high) = window
+            .iter()
+            .map(|kline| (kline.low, kline.high))
+            .chain(levels.iter().map(|(price, _)| (*price, *price)))
+            .fold((f64::MAX, f64::MIN), |(min, max), (low, high)| (min.min(low), max.max(high)));
+        let margin = (high - low).max(high * 0.001) * 0.1;
+
+        let candle_px_width = style.px(config.chart_style.candle_width.max(8));
+        let width = (candle_px_width * window.len() as u32).max(style.px(400));
+        let height = style.px(300);
+        let labels = (width / style.px(75)).max(2) as usize;
+        let axis = TimeAxis {
+            start,
+            end,
+            labels,
+            format: date_format(start, end, labels),
+        };
+        let file_path = directory.join(format!("trade_{}.png", trade.id));
+        let root_area = BitMapBackend::new(&file_path, (width, height)).into_drawing_area();
+        root_area.fill(&style.background)?;
+        let caption = format!(
+            "Trade {} (orders {} to {}): {:?}, {:.2} {} ({:?})",
+            trade.id,
+            trade.entry_order,
+            trade.exit_order.as_deref().unwrap_or("end of data"),
+            trade.direction,
+            trade.profit.unwrap_or(0.0),
+            config.symbol.quote,
+            trade.exit_reason.unwrap_or(ExitReason::Signal)
+        );
+        let mut chart = ChartBuilder::on(&root_area)
+            .x_label_area_size(style.px(30))
+            .y_label_area_size(style.px(Y_LABEL_AREA))
+            .caption(caption, style.text(13))
+            .build_cartesian_2d(RangedDateTime::from(axis.start..axis.end), (low - margin)..(high + margin))?;
+        chart
+            .configure_mesh()
+            .axis_style(style.foreground)
+            .bold_line_style(style.grid)
+            .light_line_style(TRANSPARENT)
+            .label_style(style.text(10))
+            .disable_x_mesh()
+            .x_labels(axis.labels)
+            .x_label_formatter(&|date| date.format(axis.format).to_string())
+            .draw()?;
+        chart.draw_series(window.iter().map(|candle| {
+            CandleStick::new(candle.timestamp, candle.open, candle.high, candle.low, candle.close, style.up.filled(), style.down, candle_px_width * 3 / 4)
+        }))?;
+        for (price, color) in levels.iter() {
+            chart.draw_series(std::iter::once(PathElement::new(vec![(start, *price), (end, *price)], color.stroke_width(style.px(2)))))?;
+        }
+        draw_trade_lines(std::iter::once(trade), &mut chart, &style, &axis, low - margin, high + margin)?;
+    }
+    match config.log_level {
+        config::LogLevel::None => {}
+        _ => {
+            println!("Trade graphs saved to {}", directory.display());
+        }
+    }
+    Ok(())
+}
+
+/// Draws the equity after each trade of the run over the percentile bands of the Monte Carlo resamplings:
+/// 5th to 95th, 25th to 75th and the median.
+fn plot_monte_carlo(config: &Config, recap: &SessionRecap, result: &MonteCarloResult) -> Result<(), Box<dyn std::error::Error>> {
+    let style = Style::new(&config.chart_style);
+    let file_path = suffixed_path(&config.log_graph_file, "monte_carlo");
+    let steps = result.equity_bands[0].len();
+    let mut realized: Vec<(f64, f64)> = vec![(0.0, config.base_funds)];
+    let mut equity = config.base_funds;
+    for trade in recap.trades.iter() {
+        if let Some(profit) = trade.profit {
+            equity += profit;
+            realized.push((realized.len() as f64, equity));
+        }
+    }
+    let (min_equity, max_equity) = result.equity_bands.iter().flatten().chain(realized.iter().map(|(_, equity)| equity)).fold((f64::MAX, f64::MIN), |(min, max), equity| {
+        (min.min(*equity), max.max(*equity))
+    });
+    let margin = (max_equity - min_equity).max(1.0) * 0.05;
+
+    let size = (style.px(config.chart_style.width.unwrap_or(1024)), style.px(config.chart_style.height));
+    let root_area = BitMapBackend::new(&file_path, size).into_drawing_area();
+    root_area.fill(&style.background)?;
+    let title = format!("Monte Carlo on {} ({} resamplings of {} trades)", config.symbol, result.simulations, steps - 1);
+    let mut chart = ChartBuilder::on(&root_area)
+        .margin(style.px(10))
+        .margin_right(style.px(40))
+        .x_label_area_size(style.px(40))
+        .y_label_area_size(style.px(Y_LABEL_AREA))
+        .caption(title, style.text(24))
+        .build_cartesian_2d(0.0..(steps - 1).max(1) as f64, (min_equity - margin)..(max_equity + margin))?;
+    chart
+        .configure_mesh()
+        .axis_style(style.foreground)
+        .bold_line_style(style.grid)
+        .light_line_style(TRANSPARENT)
+        .label_style(style.text(12))
+        .x_label_formatter(&|trade| format!("{:.0}", trade))
+        .x_desc("Trades")
+        .y_desc(format!("Equity ({})", config.symbol.quote))
+        .draw()?;
+
+    // bands are drawn from the widest to the narrowest, PERCENTILES being symmetric around the median
+    let band = |index: usize| result.equity_bands[index].iter().enumerate().map(|(step, equity)| (step as f64, *equity));
+    let last = PERCENTILES.len() - 1;
+    for (low, opacity) in [(0, 0.2), (1, 0.35)] {
+        let high = last - low;
+        let area: Vec<(f64, f64)> = band(low).chain(band(high).collect::<Vec<_>>().into_iter().rev()).collect();
+        chart
+            .draw_series(std::iter::once(Polygon::new(area, style.equity.mix(opacity).filled())))?
+            .label(format!("{:.0}th to {:.0}th percentile", PERCENTILES[low], PERCENTILES[high]))
+            .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 20, y + 5)], style.equity.mix(opacity).filled()));
+    }
+    let median_style = style.equity.stroke_width(style.px(2));
+    chart
+        .draw_series(LineSeries::new(band(last / 2), median_style))?
+        .label("Median")
+        .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], median_style));
+    let realized_style = style.foreground.stroke_width(style.px(1));
+    chart
+        .draw_series(LineSeries::new(realized, realized_style))?
+        .label("This run")
+        .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], realized_style));
+    chart
+        .configure_series_labels()
+        .position(SeriesLabelPosition::UpperLeft)
+        .background_style(style.background.mix(0.8))
+        .border_style(style.foreground)
+        .label_font(style.text(12))
+        .draw()?;
+
+    match config.log_level {
+        config::LogLevel::None => {}
+        _ => {
+            println!("Monte Carlo graph saved to {}", file_path);
+        }
+    }
+    Ok(())
+}
+
+/// Draws the mean return after the entries against the candles since them, with its 95% confidence interval.
+fn plot_forward_returns(config: &Config, points: &[analysis::ForwardReturn]) -> Result<(), Box<dyn std::error::Error>> {
+    let style = Style::new(&config.chart_style);
+    let file_path = suffixed_path(&config.log_graph_file, "forward_returns");
+    let points: Vec<&analysis::ForwardReturn> = points.iter().filter(|point| point.entries > 0).collect();
+    if points.is_empty() {
+        return Ok(());
+    }
+    let max_horizon = points.iter().map(|point| point.horizon).max().unwrap_or(1).max(1) as f64;
+    let (min_return, max_return) = points.iter().fold((0.0f64, 0.0f64), |(min, max), point| (min.min(point.low), max.max(point.high)));
+    let margin = (max_return - min_return).max(0.01) * 0.05;
+
+    let size = (style.px(config.chart_style.width.unwrap_or(1024)), style.px(config.chart_style.height));
+    let root_area = BitMapBackend::new(&file_path, size).into_drawing_area();
+    root_area.fill(&style.background)?;
+    let title = format!("Returns after the entries on {} ({} entries)", config.symbol, points[0].entries);
+    let mut chart = ChartBuilder::on(&root_area)
+        .margin(style.px(10))
+        .margin_right(style.px(40))
+        .x_label_area_size(style.px(40))
+        .y_label_area_size(style.px(Y_LABEL_AREA))
+        .caption(title, style.text(24))
+        .build_cartesian_2d(0.0..max_horizon, (min_return - margin)..(max_return + margin))?;
+    chart
+        .configure_mesh()
+        .axis_style(style.foreground)
+        .bold_line_style(style.grid)
+        .light_line_style(TRANSPARENT)
+        .label_style(style.text(12))
+        .x_label_formatter(&|candles| format!("{:.0}", candles))
+        .y_label_formatter(&|value| format!("{:.2}%", value))
+        .x_desc("Candles after the entry")
+        .y_desc("Mean return")
+        .draw()?;
+
+    let area: Vec<(f64, f64)> = points.iter().map(|point| (point.horizon as f64, point.high)).chain(points.iter().rev().map(|point| (point.horizon as f64, point.low))).collect();
+    chart
+        .draw_series(std::iter::once(Polygon::new(area, style.equity.mix(0.25).filled())))?
+        .label("95% confidence interval")
+        .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 20, y + 5)], style.equity.mix(0.25).filled()));
+    chart.draw_series(LineSeries::new(vec![(0.0, 0.0), (max_horizon, 0.0)], style.foreground.stroke_width(style.px(1))))?;
+    let mean_style = style.equity.stroke_width(style.px(2));
+    chart
+        .draw_series(LineSeries::new(points.iter().map(|point| (point.horizon as f64, point.mean_return)), mean_style))?
+        .label("Mean return")
+        .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], mean_style));
+    chart
+        .configure_series_labels()
+        .position(SeriesLabelPosition::UpperLeft)
+        .background_style(style.background.mix(0.8))
+        .border_style(style.foreground)
+        .label_font(style.text(12))
+        .draw()?;
+
+    match config.log_level {
+        config::LogLevel::None => {}
+        _ => {
+            println!("Forward returns graph saved to {}", file_path);
+        }
+    }
+    Ok(())
+}
+
+/// Draws the number of positions open over time above the value of the long and short positions, to show when the
+/// session was concentrated.
+fn plot_exposure(config: &Config, timeline: &[analysis::ExposurePoint]) -> Result<(), Box<dyn std::error::Error>> {
+    let (Some(first), Some(last)) = (timeline.first(), timeline.last()) else {
+        return Ok(());
+    };
+    let style = Style::new(&config.chart_style);
+    let file_path = suffixed_path(&config.log_graph_file, "exposure");
+    let width = style.px(config.chart_style.width.unwrap_or(1024));
+    let labels = (width / style.px(75)).max(2) as usize;
+    let axis = TimeAxis {
+        start: first.date,
+        end: last.date,
+        labels,
+        format: date_format(first.date, last.date, labels),
+    };
+    let points = width as usize;
+    let positions = lttb(&timeline.iter().map(|point| (point.date, point.open_positions as f64)).collect::<Vec<_>>(), points);
+    let long = lttb(&timeline.iter().map(|point| (point.date, point.long)).collect::<Vec<_>>(), points);
+    let short = lttb(&timeline.iter().map(|point| (point.date, point.short)).collect::<Vec<_>>(), points);
+    let max_positions = timeline.iter().map(|point| point.open_positions).max().unwrap_or(0).max(1) as f64;
+    let max_value = timeline.iter().map(|point| point.long.max(point.short)).fold(0.0, f64::max).max(1.0);
+    // the first time the most positions were open
+    let peak = timeline.iter().rev().max_by_key(|point| point.open_positions).unwrap_or(first);
+
+    let root_area = BitMapBackend::new(&file_path, (width, style.px(config.chart_style.height))).into_drawing_area();
+    root_area.fill(&style.background)?;
+    let title = format!("Exposure on {}, at most {} positions open ({})", config.symbol, peak.open_positions, peak.date.format("%Y-%m-%d %H:%M"));
+    let title_size = style.px(24).min(width * 2 / (title.len() as u32 + 2));
+    let root_area = root_area.titled(&title, (style.font, title_size as f64).into_font().color(&style.foreground))?;
+    let (top, bottom) = root_area.split_vertically(root_area.dim_in_pixel().1 / 3);
+
+    let mut count_chart = ChartBuilder::on(&top)
+        .x_label_area_size(style.px(40))
+        .y_label_area_size(style.px(Y_LABEL_AREA))
+        .caption("Open positions", style.text(15))
+        .build_cartesian_2d(RangedDateTime::from(axis.start..axis.end), 0.0..max_positions * 1.1)?;
+    count_chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .x_labels(axis.labels)
+        .x_label_formatter(&|date| date.format(axis.format).to_string())
+        .y_labels(max_positions as usize + 2)
+        .y_label_formatter(&|count| format!("{:.0}", count))
+        .axis_style(style.foreground)
+        .bold_line_style(style.grid)
+        .light_line_style(TRANSPARENT)
+        .label_style(style.text(12))
+        .draw()?;
+    count_chart.draw_series(AreaSeries::new(positions.iter().copied(), 0.0, style.equity.mix(0.3)).border_style(style.equity.stroke_width(style.px(1))))?;
+
+    let mut value_chart = ChartBuilder::on(&bottom)
+        .x_label_area_size(style.px(40))
+        .y_label_area_size(style.px(Y_LABEL_AREA))
+        .caption(format!("Value of the open positions ({})", config.symbol.quote), style.text(15))
+        .build_cartesian_2d(RangedDateTime::from(axis.start..axis.end), 0.0..max_value * 1.1)?;
+    value_chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .x_labels(axis.labels)
+        .x_label_formatter(&|date| date.format(axis.format).to_string())
+        .axis_style(style.foreground)
+        .bold_line_style(style.grid)
+        .light_line_style(TRANSPARENT)
+        .label_style(style.text(12))
+        .draw()?;
+    for (values, color, label) in [(&long, style.up, "Long"), (&short, style.down, "Short")] {
+        value_chart
+            .draw_series(AreaSeries::new(values.iter().copied(), 0.0, color.mix(0.3)).border_style(color.stroke_width(style.px(1))))?
+            .label(label)
+            .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 20, y + 5)], color.mix(0.3).filled()));
+    }
+    value_chart
+        .configure_series_labels()
+        .position(SeriesLabelPosition::UpperLeft)
+        .background_style(style.background.mix(0.8))
+        .border_style(style.foreground)
+        .label_font(style.text(12))
+        .draw()?;
+
+    match config.log_level {
+        config::LogLevel::None => {}
+        _ => {
+            println!("Exposure graph saved to {}", file_path);
+        }
+    }
+    Ok(())
+}
+
+/// Draws the objective over the grid of a two-parameter sweep, from the down color (worst) to the up color (best),
+/// with the best variant outlined.
+pub fn plot_parameter_surface(file_path: &str, surface: &ParameterSurface, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let style = Style::new(&config.chart_style);
+    let (columns, rows) = (surface.x_values.len() as u32, surface.y_values.len() as u32);
+    let (min, max) = surface.values.iter().flatten().filter(|value| value.is_finite()).fold((f64::MAX, f64::MIN), |(min, max), value| (min.min(*value), max.max(*value)));
+    let color = |value: f64| {
+        let ratio = if max > min { ((value - min) / (max - min)).clamp(0.0, 1.0) } else { 1.0 };
+        let mix = |down: u8, up: u8| (down as f64 + (up as f64 - down as f64) * ratio).round() as u8;
+        RGBColor(mix(style.down.0, style.up.0), mix(style.down.1, style.up.1), mix(style.down.2, style.up.2))
+    };
+    let cell = style.px(80);
+    let size = (cell * columns + style.px(Y_LABEL_AREA + 60), cell * rows + style.px(140));
+    let root_area = BitMapBackend::new(file_path, size).into_drawing_area();
+    root_area.fill(&style.background)?;
+    let verdict = if surface.is_spike() { "narrow spike, likely overfit" } else { "broad plateau" };
+    let sample = if surface.cross_validated { "mean over the folds" } else { "whole data" };
+    let title = format!("{} by {} and {} ({}, {})", config.objective, surface.x_name, surface.y_name, sample, verdict);
+    let title_size = style.px(20).min(size.0 * 2 / (title.len() as u32 + 2));
+    let mut chart = ChartBuilder::on(&root_area)
+        .margin(style.px(10))
+        .x_label_area_size(style.px(50))
+        .y_label_area_size(style.px(Y_LABEL_AREA))
+        .caption(title, (style.font, title_size as f64).into_font().color(&style.foreground))
+        .build_cartesian_2d((0..columns - 1).into_segmented(), (0..rows - 1).into_segmented())?;
+    let label = |values: &[serde_json::Value], segment: &SegmentValue<u32>| match segment {
+        SegmentValue::CenterOf(index) | SegmentValue::Exact(index) => values.get(*index as usize).map(|value| value.to_string()).unwrap_or_default(),
+        SegmentValue::Last => String::new(),
+    };
+    chart
+        .configure_mesh()
+        .disable_mesh()
+        .axis_style(style.foreground)
+        .label_style(style.text(12))
+        .x_labels(columns as usize)
+        .y_labels(rows as usize)
+        .x_label_formatter(&|segment| label(&surface.x_values, segment))
+        .y_label_formatter(&|segment| label(&surface.y_values, segment))
+        .x_desc(&surface.x_name)
+        .y_desc(&surface.y_name)
+        .draw()?;
+    for (x, row) in surface.values.iter().enumerate() {
+        for (y, value) in row.iter().enumerate() {
+            let (x, y) = (x as u32, y as u32);
+            let corners = [(SegmentValue::Exact(x), SegmentValue::Exact(y)), (SegmentValue::Exact(x + 1), SegmentValue::Exact(y + 1))];
+            chart.draw_series(std::iter::once(Rectangle::new(corners.clone(), color(*value).filled())))?;
+            if (x as usize, y as usize) == surface.best {
+                chart.draw_series(std::iter::once(Rectangle::new(corners, style.foreground.stroke_width(style.px(3)))))?;
+            }
+            // the variants breaking a constraint of the objective have no value
+            let text = if value.is_finite() { format!("{:.2}", value) } else { "-".to_string() };
+            chart.draw_series(std::iter::once(Text::new(
+                text,
+                (SegmentValue::CenterOf(x), SegmentValue::CenterOf(y)),
+                style.text(11).color(&BLACK).pos(plotters::style::text_anchor::Pos::new(plotters::style::text_anchor::HPos::Center, plotters::style::text_anchor::VPos::Center)),
+            )))?;
+        }
+    }
+
+    match config.log_level {
+        config::LogLevel::None => {}
+        _ => {
+            println!("Parameter surface saved to {}", file_path);
+        }
+    }
+    Ok(())
+}
+
+/// Draws the objective against each swept parameter, the others at their best values, one panel per parameter.
+/// The values within the safe range are shaded, and the best one is circled.
+pub fn plot_sensitivity(file_path: &str, sensitivity: &[Sensitivity], config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let style = Style::new(&config.chart_style);
+    let panel_height = style.px(260);
+    let size = (style.px(config.chart_style.width.unwrap_or(1024)), panel_height * sensitivity.len() as u32 + style.px(40));
+    let root_area = BitMapBackend::new(file_path, size).into_drawing_area();
+    root_area.fill(&style.background)?;
+    let root_area = root_area.titled(&format!("Sensitivity of {} to each parameter", config.objective), style.text(20))?;
+    for (area, sensitivity) in root_area.split_evenly((sensitivity.len(), 1)).iter().zip(sensitivity.iter()) {
+        // values breaking a constraint of the objective are left out
+        let points: Vec<(f64, f64)> = sensitivity.response.iter().enumerate().filter(|(_, (_, score))| score.is_finite()).map(|(index, (_, score))| (index as f64, *score)).collect();
+        if points.is_empty() {
+            continue;
+        }
+        let (min, max) = points.iter().fold((f64::MAX, f64::MIN), |(min, max), (_, score)| (min.min(*score), max.max(*score)));
+        let margin = (max - min).max(1.0) * 0.1;
+        let count = sensitivity.response.len();
+        let mut chart = ChartBuilder::on(area)
+            .margin(style.px(10))
+            .margin_right(style.px(40))
+            .x_label_area_size(style.px(40))
+            .y_label_area_size(style.px(Y_LABEL_AREA))
+            .build_cartesian_2d(-0.5..count as f64 - 0.5, (min - margin)..(max + margin))?;
+        let label = |x: &f64| match sensitivity.response.get(x.round() as usize) {
+            Some((value, _)) if (x - x.round()).abs() < 1e-6 => value.to_string(),
+            _ => String::new(),
+        };
+        chart
+            .configure_mesh()
+            .axis_style(style.foreground)
+            .bold_line_style(style.grid)
+            .light_line_style(TRANSPARENT)
+            .label_style(style.text(12))
+            .x_labels(count)
+            .x_label_formatter(&label)
+            .x_desc(&sensitivity.parameter)
+            .y_desc(format!("{}", config.objective))
+            .draw()?;
+        let (first, last) = sensitivity.safe;
+        chart.draw_series(std::iter::once(Rectangle::new([(first as f64 - 0.3, min - margin), (last as f64 + 0.3, max + margin)], style.up.mix(0.15).filled())))?;
+        chart.draw_series(LineSeries::new(points.clone(), style.equity.stroke_width(style.px(2))))?;
+        chart.draw_series(points.iter().map(|point| Circle::new(*point, style.px(3), style.equity.filled())))?;
+        let best = (sensitivity.best as f64, sensitivity.response[sensitivity.best].1);
+        chart.draw_series(std::iter::once(Circle::new(best, style.px(7), style.up.stroke_width(style.px(2)))))?;
+    }
+
+    match config.log_level {
+        config::LogLevel::None => {}
+        _ => {
+            println!("Sensitivity graph saved to {}", file_path);
+        }
+    }
+    Ok(())
+}
+
+/// Overlays several equity curves rebased to 100 at their start, so runs with different capital can be compared.
+pub fn plot_comparison(file_path: &str, title: &str, curves: &[(String, Vec<(NaiveDateTime, f64)>)], config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let style = Style::new(&config.chart_style);
+    let normalized: Vec<(&str, Vec<(NaiveDateTime, f64)>)> = curves
+        .iter()
+        .filter_map(|(name, curve)| {
+            let start = curve.first().map(|(_, value)| *value).filter(|value| *value != 0.0)?;
+            Some((name.as_str(), curve.iter().map(|(date, value)| (*date, value / start * 100.0)).collect()))
+        })
+        .collect();
+    let points = || normalized.iter().flat_map(|(_, curve)| curve.iter());
+    let (Some(start), Some(end)) = (points().map(|(date, _)| *date).min(), points().map(|(date, _)| *date).max()) else {
+        return Err("no equity curve to compare".into());
+    };
+    let (min_value, max_value) = points().fold((f64::MAX, f64::MIN), |(min, max), (_, value)| (min.min(*value), max.max(*value)));
+    let margin = (max_value - min_value).max(1.0) * 0.05;
+
+    let width = style.px(config.chart_style.width.unwrap_or(1280));
+    let labels = (width / style.px(75)).max(2) as usize;
+    let axis = TimeAxis {
+        start,
+        end,
+        labels,
+        format: date_format(start, end, labels),
+    };
+    let root_area = BitMapBackend::new(file_path, (width, style.px(config.chart_style.height))).into_drawing_area();
+    root_area.fill(&style.background)?;
+    let mut chart = ChartBuilder::on(&root_area)
+        .margin(style.px(10))
+        .margin_right(style.px(40))
+        .x_label_area_size(style.px(40))
+        .y_label_area_size(style.px(Y_LABEL_AREA))
+        .caption(title, style.text(24))
+        .build_cartesian_2d(RangedDateTime::from(axis.start..axis.end), (min_value - margin)..(max_value + margin))?;
+    chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .x_labels(axis.labels)
+        .x_label_formatter(&|date| date.format(axis.format).to_string())
+        .axis_style(style.foreground)
+        .bold_line_style(style.grid)
+        .light_line_style(TRANSPARENT)
+        .label_style(style.text(12))
+        .y_desc("Equity (start = 100)")
+        .draw()?;
+    for (index, (name, curve)) in normalized.into_iter().enumerate() {
+        let line_style = Palette99::pick(index).stroke_width(style.px(1));
+        chart
+            .draw_series(LineSeries::new(curve, line_style))?
+            .label(name)
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], line_style));
+    }
+    chart
+        .configure_series_labels()
+        .position(SeriesLabelPosition::UpperLeft)
+        .background_style(style.background.mix(0.8))
+        .border_style(style.foreground)
+        .label_font(style.text(12))
+        .draw()?;
+
+    match config.log_level {
+        config::LogLevel::None => {}
+        _ => {
+            println!("Comparison graph saved to {}", file_path);
+        }
+    }
+    Ok(())
+}
+
+/// Draws the graph when `log_graph` is enabled, with the Monte Carlo graph if there is a result and the comparison
+/// of the strategies in multi-strategy sessions, the zoomed graph of `chart_range` when there is one, and the graph of each trade when `trade_chart_context` is set.
+pub fn plot_graph(config: &Config, klines: &[Kline], recap: &SessionRecap) -> Result<(), Box<dyn std::error::Error>> {
+    if config.log_graph {
+        let title = format!("Backtesting results on {}", config.symbol);
+        draw_chart(&config.log_graph_file, &title, config, klines, recap)?;
+        if let Some(result) = &recap.monte_carlo {
+            plot_monte_carlo(config, recap, result)?;
+        }
+        if !recap.forward_returns.is_empty() {
+            plot_forward_returns(config, &recap.forward_returns)?;
+        }
+        if config.chart_exposure {
+            let trades: Vec<Trade> = recap.trades.iter().chain(recap.open_trades.iter()).chain(recap.open_lots.iter()).cloned().collect();
+            plot_exposure(config, &analysis::exposure_timeline(&trades, klines))?;
+        }
+        if let (Some(sweep), Some(result)) = (&config.sweep, &recap.sweep) {
+            if let Some(surface) = analysis::parameter_surface(sweep, result, config) {
+                plot_parameter_surface(&suffixed_path(&config.log_graph_file, "sweep"), &surface, config)?;
+            }
+            if !result.sensitivity.is_empty() {
+                plot_sensitivity(&suffixed_path(&config.log_graph_file, "sensitivity"), &result.sensitivity, config)?;
+            }
+        }
+        // multi-strategy sessions also compare each strategy to the whole portfolio
+        if !recap.contributions.is_empty() {
+            let mut curves: Vec<(String, Vec<(NaiveDateTime, f64)>)> = recap.contributions.iter().map(|contribution| (contribution.name.clone(), contribution.recap.equity_curve.clone())).collect();
+            curves.push(("Portfolio".to_string(), recap.equity_curve.clone()));
+            if !recap.benchmark_curve.is_empty() {
+                curves.push(("Benchmark".to_string(), recap.benchmark_curve.clone()));
+            }
+            let title = format!("Strategies on {}", config.symbol);
+            plot_comparison(&suffixed_path(&config.log_graph_file, "comparison"), &title, &curves, config)?;
+        }
+    }
+    if let Some(range) = &config.chart_range {
+        let (start, end) = config::parse_date_range(range).map_err(|e| format!("chart_range: {}", e))?;
+        let window: Vec<Kline> = klines.iter().filter(|kline| start <= kline.timestamp && kline.timestamp < end).cloned().collect();
+        if window.is_empty() {
+            return Err(format!("no candle in chart_range `{}`", range).into());
+        }
+        let title = format!("Backtesting results on {} from {} to {}", config.symbol, start.format("%Y-%m-%d %H:%M"), end.format("%Y-%m-%d %H:%M"));
+        draw_chart(&suffixed_path(&config.log_graph_file, "zoom"), &title, config, &window, recap)?;
+    }
+    if let Some(context) = config.trade_chart_context {
+        plot_trade_charts(config, klines, &recap.trades, context)?;
+    }
+    if let Some(animation) = &config.animation {
+        plot_animation(config, klines, recap, animation)?;
+    }
+    Ok(())
+}
+
+/// Draws the price and equity panels of the whole session again and again into an animated GIF, each frame showing
+/// the candles, trades and equity up to a later candle. The axes stay the same from one frame to the next.
+fn plot_animation(config: &Config, klines: &[Kline], recap: &SessionRecap, animation: &Animation) -> Result<(), Box<dyn std::error::Error>> {
+    let style = Style::new(&config.chart_style);
+    let width = style.px(config.chart_style.width.unwrap_or(1024));
+    let height = style.px(config.chart_style.height);
+    let (start, end) = get_date_range(klines);
+    let (min_price, max_price) = get_price_range(klines);
+    // candles at least two pixels wide, merged when there are more
+    let stride = klines.len().div_ceil((width / style.px(2)) as usize).max(1);
+    let candles = if stride > 1 { historical::merge_candles(klines, stride) } else { klines.to_vec() };
+    let candle_px_width = (width / candles.len() as u32).max(1);
+    let labels = (width / style.px(75)).max(2) as usize;
+    let axis = TimeAxis {
+        start,
+        end,
+        labels,
+        format: date_format(start, end, labels),
+    };
+    let panels = &config.chart_style.panels;
+    let price_height = (height as f64 * panels.price / (panels.price + panels.equity)).round() as u32;
+
+    let points = width as usize;
+    let curve = lttb(&recap.equity_curve, points);
+    let benchmark_curve = lttb(&recap.benchmark_curve, points);
+    let (min_equity, max_equity) = curve.iter().chain(benchmark_curve.iter()).fold((f64::MAX, f64::MIN), |(min, max), (_, equity)| {
+        (min.min(*equity), max.max(*equity))
+    });
+    let min_equity = min_equity - 0.1 * min_equity;
+    let max_equity = max_equity + 0.1 * max_equity;
+
+    let every = animation.every.unwrap_or(klines.len().div_ceil(100)).max(1);
+    let mut frames: Vec<usize> = (every - 1..klines.len()).step_by(every).collect();
+    if frames.last() != Some(&(klines.len() - 1)) {
+        frames.push(klines.len() - 1);
+    }
+    let trades: Vec<&Trade> = recap.trades.iter().chain(recap.open_trades.iter()).chain(recap.open_lots.iter()).collect();
+    let root_area = BitMapBackend::gif(&animation.file, (width, height), animation.frame_delay)?.into_drawing_area();
+    for index in frames.iter() {
+        let date = klines[*index].timestamp;
+        // the trades opened so far, the ones closed later being still open on this frame
+        let visible: Vec<Trade> = trades
+            .iter()
+            .filter(|trade| trade.entry_date <= date)
+            .map(|trade| {
+                let mut trade = (*trade).clone();
+                if trade.exit_date.is_some_and(|exit_date| exit_date > date) {
+                    trade.exit_date = None;
+                    trade.exit_price = None;
+                    trade.exit_reason = None;
+                }
+                trade
+            })
+            .collect();
+        let so_far = TimeAxis { start, end: date, labels, format: axis.format };
+
+        root_area.fill(&style.background)?;
+        let title = format!("{} on {}", config.symbol, date.format("%Y-%m-%d %H:%M"));
+        let title_style = (style.font, style.px(30) as f64).into_font().color(&style.foreground);
+        let area = root_area.titled(&title, title_style)?;
+        let (main, bottom_elem) = area.split_vertically(price_height);
+        let mut cstick_chart = ChartBuilder::on(&main)
+            .x_label_area_size(style.px(40))
+            .y_label_area_size(style.px(Y_LABEL_AREA))
+            .caption("Candlestick data", style.text(15))
+            .build_cartesian_2d(RangedDateTime::from(axis.start..axis.end), min_price..max_price)?;
+        cstick_chart
+            .configure_mesh()
+            .axis_style(style.foreground)
+            .bold_line_style(style.grid)
+            .light_line_style(style.grid)
+            .label_style(style.text(12))
+            .disable_x_mesh()
+            .x_labels(axis.labels)
+            .x_label_formatter(&|date| date.format(axis.format).to_string())
+            .draw()?;
+        cstick_chart.draw_series(candles.iter().take_while(|candle| candle.timestamp <= date).map(|candle| {
+            CandleStick::new(candle.timestamp, candle.open, candle.high, candle.low, candle.close, style.up.filled(), style.down, candle_px_width)
+        }))?;
+        draw_trade_levels(visible.iter(), &mut cstick_chart, &style, &so_far)?;
+        draw_trade_lines(visible.iter(), &mut cstick_chart, &style, &so_far, min_price, max_price)?;
+
+        let curve: Vec<(NaiveDateTime, f64)> = curve.iter().take_while(|(point, _)| *point <= date).copied().collect();
+        let benchmark_curve: Vec<(NaiveDateTime, f64)> = benchmark_curve.iter().take_while(|(point, _)| *point <= date).copied().collect();
+        make_equity_chart(&bottom_elem, &style, &axis, &curve, &benchmark_curve, min_equity, max_equity)?;
+        root_area.present()?;
+    }
+
+    match config.log_level {
+        config::LogLevel::None => {}
+        _ => {
+            println!("Animation saved to {} ({} frames)", animation.file, frames.len());
+        }
+    }
+    Ok(())
+}
+
+/// Draws the candlesticks and equity curve of `klines`, with what happened in the recap during that time.
+fn draw_chart(file_path: &str, title: &str, config: &Config, klines: &[Kline], recap: &SessionRecap) -> Result<(), Box<dyn std::error::Error>> {
+    let style = Style::new(&config.chart_style);
+    let (start, end) = get_date_range(klines);
+    let (min_price, max_price) = get_price_range(klines);
+    // the graph grows with the candles, so long data is drawn with merged ones
+    let max_candles = config.chart_style.max_candles;
+    let merged = if max_candles > 0 && klines.len() > max_candles { Some(historical::merge_candles(klines, klines.len().div_ceil(max_candles))) } else { None };
+    let candles = merged.as_deref().unwrap_or(klines);
+
+    // a fixed width narrows or widens the candles to fill it
+    let (candle_px_width, chart_width) = match config.chart_style.width {
+        Some(width) => ((style.px(width) / candles.len() as u32).max(1), style.px(width)),
+        None => (style.px(config.chart_style.candle_width), style.px(config.chart_style.candle_width) * candles.len() as u32),
+    };
+    let chart_height = style.px(config.chart_style.height);
+    let panels = &config.chart_style.panels;
+    if panels.price <= 0.0 || panels.equity <= 0.0 || panels.rolling <= 0.0 {
+        return Err("chart_style.panels: the ratios must be positive".into());
+    }
+    let panel_height = |ratio: f64| (chart_height as f64 * ratio / (panels.price + panels.equity)).round() as u32;
+
+    // at most one label every 75 pixels, the dates are rounded so there are usually fewer
+    let labels = (chart_width / style.px(75)).max(2) as usize;
+    let axis = TimeAxis {
+        start,
+        end,
+        labels,
+        format: date_format(start, end, labels),
+    };
+
+    // the rolling metric gets a panel of its own under the equity curve, on top of `height`
+    let rolling_height = if config.rolling_metric.is_some() { panel_height(panels.rolling) } else { 0 };
+    // the volume profile gets a column of its own on the right, so the time axes of the panels still line up
+    let profile = config.chart_volume_profile.and_then(|buckets| VolumeProfile::from_klines(klines, buckets));
+    let profile_width = if profile.is_some() { style.px(200) } else { 0 };
+    let root_area = BitMapBackend::new(file_path, (chart_width + profile_width, chart_height + rolling_height)).into_drawing_area();
+    root_area.fill(&style.background)?;
+    // the title shrinks to fit narrow graphs, a character being about half as wide as the font size
+    let title_size = style.px(60).min(chart_width * 2 / (title.len() as u32 + 2));
+    let title_style = (style.font, title_size as f64).into_font().color(&style.foreground);
+    let root_area = root_area.titled(title, title_style)?;
+    let (root_area, profile_elem) = root_area.split_horizontally(chart_width);
+    let (root_area, rolling_elem) = root_area.split_vertically(root_area.dim_in_pixel().1 - rolling_height);
+
+    // the candlesticks and the equity curve share the height, two thirds and one third by default
+    let (main, bottom_elem) = root_area.split_vertically(panel_height(panels.price));
+
+    make_price_chart(&main, &style, &axis, config, klines, candles, candle_px_width, recap, min_price, max_price)?;
+    if let Some(profile) = &profile {
+        let (profile_main, _) = profile_elem.split_vertically(panel_height(panels.price));
+        make_volume_profile_chart(&profile_main, &style, profile, min_price, max_price)?;
+    }
+
+    // more points than pixels only slow the drawing down
+    let points = chart_width as usize;
+    let curve = lttb(&recap.equity_curve.iter().filter(|(date, _)| axis.contains(*date)).copied().collect::<Vec<_>>(), points);
+    let benchmark_curve = lttb(&recap.benchmark_curve.iter().filter(|(date, _)| axis.contains(*date)).copied().collect::<Vec<_>>(), points);
+    let (min_equity, max_equity) = curve.iter().chain(benchmark_curve.iter()).fold((f64::MAX, f64::MIN), |(min, max), (_, equity)| {
         (min.min(*equity), max.max(*equity))
     });
     let min_equity = min_equity - 0.1 * min_equity;
     let max_equity = max_equity + 0.1 * max_equity;
-    make_equity_chart(&bottom_elem, curve, min_equity, max_equity)?;
+    make_equity_chart(&bottom_elem, &style, &axis, &curve, &benchmark_curve, min_equity, max_equity)?;
+    let rolling_values = config.rolling_metric.as_ref().map(|rule| lttb(&analysis::rolling_metric(&recap.trades, rule).into_iter().filter(|(date, _)| axis.contains(*date)).collect::<Vec<_>>(), points));
+    if let (Some(rule), Some(values)) = (&config.rolling_metric, &rolling_values) {
+        make_rolling_chart(&rolling_elem, &style, &axis, rule, values)?;
+    }
 
     match config.log_level {
         config::LogLevel::None => {}
         _ => {
-            println!("Graph saved to {}", config.log_graph_file);
+            println!("Graph saved to {}", file_path);
+        }
+    }
+
+    // each requested panel again, alone and as tall as the whole graph
+    for panel in config.graph_panels.iter() {
+        let panel_path = suffixed_path(file_path, panel.suffix());
+        let area = BitMapBackend::new(&panel_path, (chart_width, chart_height)).into_drawing_area();
+        area.fill(&style.background)?;
+        match panel {
+            GraphPanel::Price => make_price_chart(&area, &style, &axis, config, klines, candles, candle_px_width, recap, min_price, max_price)?,
+            GraphPanel::Equity => make_equity_chart(&area, &style, &axis, &curve, &benchmark_curve, min_equity, max_equity)?,
+            GraphPanel::Drawdown => {
+                let mut peak = f64::MIN;
+                let drawdowns: Vec<(NaiveDateTime, f64)> = recap
+                    .equity_curve
+                    .iter()
+                    .filter(|(date, _)| axis.contains(*date))
+                    .map(|(date, equity)| {
+                        peak = peak.max(*equity);
+                        (*date, if peak > 0.0 { (equity / peak - 1.0) * 100.0 } else { 0.0 })
+                    })
+                    .collect();
+                make_drawdown_chart(&area, &style, &axis, &lttb(&drawdowns, points))?;
+            }
+            GraphPanel::Rolling => match (&config.rolling_metric, &rolling_values) {
+                (Some(rule), Some(values)) => make_rolling_chart(&area, &style, &axis, rule, values)?,
+                _ => return Err("graph_panels: the Rolling panel needs rolling_metric".into()),
+            },
+        }
+        match config.log_level {
+            config::LogLevel::None => {}
+            _ => {
+                println!("Graph saved to {}", panel_path);
+            }
         }
     }
 
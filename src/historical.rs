@@ -1,8 +1,14 @@
-use chrono::NaiveDateTime;
+use chrono::{Datelike, NaiveDateTime};
 use csv::Reader;
+#[cfg(feature = "files")]
 use std::fs::File;
 use std::collections::HashMap;
+use std::io::Read;
 use serde::Deserialize;
+use crate::config::{parse_date, FastSampling};
+use crate::montecarlo::Random;
+#[cfg(feature = "files")]
+use crate::config::Config;
 
 #[derive(Debug, Deserialize)]
 pub struct RawKline {
@@ -14,7 +20,7 @@ pub struct RawKline {
     pub volume: f64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Kline {
     pub timestamp: NaiveDateTime,
     pub open: f64,
@@ -24,11 +30,28 @@ pub struct Kline {
     pub volume: f64,
 }
 
+/// FNV-1a hash of `bytes`, stable from one build to the next unlike the hasher of the standard library.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Hash of the timestamps and values of every candle, telling apart data that differ anywhere, not only at the ends.
+pub fn fingerprint(klines: &[Kline]) -> String {
+    let mut bytes = Vec::with_capacity(klines.len() * 48);
+    for kline in klines {
+        bytes.extend(kline.timestamp.and_utc().timestamp().to_le_bytes());
+        for value in [kline.open, kline.high, kline.low, kline.close, kline.volume] {
+            bytes.extend(value.to_bits().to_le_bytes());
+        }
+    }
+    format!("{:016x}", fnv1a(&bytes))
+}
+
 fn to_klines(raw_klines: Vec<RawKline>) -> Vec<Kline> {
     raw_klines
         .iter()
         .map(|raw_kline| Kline {
-            timestamp: NaiveDateTime::parse_from_str(&raw_kline.timestamp, "%Y-%m-%d %H:%M:%S").unwrap(),
+            timestamp: parse_date(&raw_kline.timestamp).unwrap_or_else(|e| panic!("{}", e)),
             open: raw_kline.open,
             high: raw_kline.high,
             low: raw_kline.low,
@@ -38,10 +61,109 @@ fn to_klines(raw_klines: Vec<RawKline>) -> Vec<Kline> {
         .collect()
 }
 
+#[cfg(feature = "files")]
 pub fn read_klines(file_path: &str, headers: HashMap<String, String>) -> Result<Vec<Kline>, csv::Error> {
-    let mut raw_klines = Vec::new();
     let csvfile = File::open(file_path).expect("CSV file not found");
-    let mut rdr = Reader::from_reader(csvfile);
+    parse_klines(csvfile, headers)
+}
+
+/// Reads the klines of `data_path`, aggregated to the timeframe when `resample` is set.
+#[cfg(feature = "files")]
+pub fn read_data(config: &Config) -> Result<Vec<Kline>, Box<dyn std::error::Error>> {
+    let klines = read_klines(&config.data_path, config.get_headers())?;
+    Ok(match config.resample {
+        true => resample(&klines, &config.timeframe)?,
+        false => klines,
+    })
+}
+
+/// Start of the period of `timeframe` holding `date`: periods of minutes, hours and days are counted from the epoch,
+/// weeks start on Monday and months on the first day, counted from the year 0.
+pub(crate) fn period_start(date: NaiveDateTime, timeframe: &str) -> Result<NaiveDateTime, String> {
+    let (amount, unit) = timeframe.split_at(timeframe.len().saturating_sub(1));
+    let amount: i64 = match amount {
+        "" => 1,
+        amount => amount.parse().ok().filter(|amount| *amount > 0).ok_or_else(|| format!("{} is not a whole number of periods to resample to", amount))?,
+    };
+    let day = date.date();
+    Ok(match unit {
+        "m" | "h" | "d" => {
+            let seconds = amount * match unit {
+                "m" => 60,
+                "h" => 3600,
+                _ => 86400,
+            };
+            let timestamp = date.and_utc().timestamp();
+            chrono::DateTime::from_timestamp(timestamp - timestamp.rem_euclid(seconds), 0).unwrap().naive_utc()
+        }
+        "w" => {
+            let monday = day - chrono::Duration::days(day.weekday().num_days_from_monday() as i64);
+            let weeks = (monday - chrono::NaiveDate::from_ymd_opt(1970, 1, 5).unwrap()).num_weeks();
+            (monday - chrono::Duration::weeks(weeks.rem_euclid(amount))).and_time(chrono::NaiveTime::MIN)
+        }
+        "M" => {
+            let months = day.year() as i64 * 12 + day.month0() as i64;
+            let first = months - months.rem_euclid(amount);
+            chrono::NaiveDate::from_ymd_opt((first / 12) as i32, (first % 12) as u32 + 1, 1).unwrap().and_time(chrono::NaiveTime::MIN)
+        }
+        _ => return Err(format!("unknown timeframe {}, expected a number followed by m, h, d, w or M", timeframe)),
+    })
+}
+
+/// Aggregates klines sorted by date into candles of `timeframe` (like `1w` or `1M` from daily candles): the open of
+/// the first candle of each period, the highest high, the lowest low, the close of the last one and the total volume.
+/// Each candle is dated by the first candle of its period, like the first trading day of a week.
+pub fn resample(klines: &[Kline], timeframe: &str) -> Result<Vec<Kline>, String> {
+    let mut resampled: Vec<Kline> = Vec::new();
+    let mut current_period = None;
+    for kline in klines.iter() {
+        let period = period_start(kline.timestamp, timeframe)?;
+        match resampled.last_mut() {
+            Some(candle) if current_period == Some(period) => {
+                candle.high = candle.high.max(kline.high);
+                candle.low = candle.low.min(kline.low);
+                candle.close = kline.close;
+                candle.volume += kline.volume;
+            }
+            _ => {
+                resampled.push(kline.clone());
+                current_period = Some(period);
+            }
+        }
+    }
+    Ok(resampled)
+}
+
+/// The candles of a `--fast` run: `stride` consecutive candles merged into one, or with `sample`,
+/// that share of the candles drawn at random, in order, with the others dropped.
+pub fn thin(klines: &[Kline], sampling: &FastSampling) -> Vec<Kline> {
+    if let Some(share) = sampling.sample {
+        let mut random = Random::new(sampling.seed);
+        return klines.iter().filter(|_| random.unit() < share).cloned().collect();
+    }
+    merge_candles(klines, sampling.stride)
+}
+
+/// Merges every `stride` consecutive candles into one: the open of the first, the highest high, the lowest low, the
+/// close of the last and the total volume, dated by the first.
+pub fn merge_candles(klines: &[Kline], stride: usize) -> Vec<Kline> {
+    klines
+        .chunks(stride.max(1))
+        .map(|chunk| {
+            let mut candle = chunk[0].clone();
+            candle.high = chunk.iter().map(|kline| kline.high).fold(f64::MIN, f64::max);
+            candle.low = chunk.iter().map(|kline| kline.low).fold(f64::MAX, f64::min);
+            candle.close = chunk[chunk.len() - 1].close;
+            candle.volume = chunk.iter().map(|kline| kline.volume).sum();
+            candle
+        })
+        .collect()
+}
+
+/// Parses klines from csv data that is already in memory or comes from any other source than a file.
+pub fn parse_klines(reader: impl Read, headers: HashMap<String, String>) -> Result<Vec<Kline>, csv::Error> {
+    let mut raw_klines = Vec::new();
+    let mut rdr = Reader::from_reader(reader);
 
     // read the csv file and fill the klines vector based on the provided headers
     for result in rdr.deserialize() {
@@ -58,3 +180,90 @@ pub fn read_klines(file_path: &str, headers: HashMap<String, String>) -> Result<
     }
     Ok(to_klines(raw_klines))
 }
+
+/// Reads a single timestamped series (e.g. a benchmark index) from two columns of a csv file.
+#[cfg(feature = "files")]
+pub fn read_series(file_path: &str, timestamp_header: &str, value_header: &str) -> Result<Vec<(NaiveDateTime, f64)>, String> {
+    let csvfile = File::open(file_path).map_err(|e| format!("cannot read {}: {}", file_path, e))?;
+    parse_series(csvfile, timestamp_header, value_header)
+}
+
+/// Parses a series like `read_series`. Its values are prices or rates, which the results are divided by, so they
+/// must be positive.
+pub fn parse_series(reader: impl Read, timestamp_header: &str, value_header: &str) -> Result<Vec<(NaiveDateTime, f64)>, String> {
+    let mut rdr = Reader::from_reader(reader);
+    let mut series = Vec::new();
+    for (line, result) in rdr.deserialize().enumerate() {
+        let record: HashMap<String, String> = result.map_err(|e| e.to_string())?;
+        let field = |name: &str| record.get(name).map(|value| value.trim()).ok_or(format!("row {}: missing `{}` column", line + 1, name));
+        let timestamp = parse_date(field(timestamp_header)?).map_err(|e| format!("row {}: {}", line + 1, e))?;
+        let value = field(value_header)?.parse::<f64>().map_err(|e| format!("row {}: {}: {}", line + 1, value_header, e))?;
+        if value <= 0.0 || !value.is_finite() {
+            return Err(format!("row {}: {} must be positive, got {}", line + 1, value_header, value));
+        }
+        series.push((timestamp, value));
+    }
+    series.sort_by_key(|(timestamp, _)| *timestamp);
+    Ok(series)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, NaiveDate};
+
+    /// Candles every `step` from `start`, the i-th opening at `100 + i` with a range of one around its open and a volume of one.
+    fn candles(start: NaiveDateTime, step: Duration, count: usize) -> Vec<Kline> {
+        (0..count)
+            .map(|i| {
+                let open = 100.0 + i as f64;
+                Kline { timestamp: start + step * i as i32, open, high: open + 1.0, low: open - 1.0, close: open + 0.5, volume: 1.0 }
+            })
+            .collect()
+    }
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap().and_hms_opt(0, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn resampling_needs_a_whole_number_of_known_periods() {
+        let klines = candles(date(2024, 1, 1), Duration::hours(1), 2);
+        assert!(resample(&klines, "1.5h").is_err());
+        assert!(resample(&klines, "1y").is_err());
+    }
+
+    #[test]
+    fn daily_candles_make_weeks_dated_by_their_first_candle() {
+        // Wednesday 2024-01-03 to Sunday 2024-01-14
+        let weeks = resample(&candles(date(2024, 1, 3), Duration::days(1), 12), "1w").unwrap();
+        assert_eq!(weeks.len(), 2);
+        let first = &weeks[0];
+        assert_eq!(first.timestamp, date(2024, 1, 3));
+        assert_eq!((first.open, first.high, first.low, first.close, first.volume), (100.0, 105.0, 99.0, 104.5, 5.0));
+        let second = &weeks[1];
+        assert_eq!(second.timestamp, date(2024, 1, 8));
+        assert_eq!((second.open, second.high, second.low, second.close, second.volume), (105.0, 112.0, 104.0, 111.5, 7.0));
+    }
+
+    #[test]
+    fn daily_candles_make_calendar_months() {
+        let months = resample(&candles(date(2024, 1, 30), Duration::days(1), 32), "1M").unwrap();
+        assert_eq!(months.iter().map(|month| (month.timestamp, month.volume)).collect::<Vec<_>>(), vec![
+            (date(2024, 1, 30), 2.0),
+            (date(2024, 2, 1), 29.0),
+            (date(2024, 3, 1), 1.0),
+        ]);
+    }
+
+    #[test]
+    fn hourly_candles_make_four_hour_candles_aligned_on_midnight() {
+        let candles = resample(&candles(date(2024, 1, 1) + Duration::hours(2), Duration::hours(1), 8), "4h").unwrap();
+        assert_eq!(candles.iter().map(|candle| (candle.timestamp.time().format("%H").to_string(), candle.volume)).collect::<Vec<_>>(), vec![
+            ("02".to_string(), 2.0),
+            ("04".to_string(), 4.0),
+            ("08".to_string(), 2.0),
+        ]);
+        assert_eq!((candles[1].open, candles[1].close), (102.0, 105.5));
+    }
+}
@@ -0,0 +1,618 @@
+use std::collections::VecDeque;
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use crate::config::PivotKind;
+use crate::historical::Kline;
+
+/// Average true range over the last `window` candles, updated one candle at a time.
+#[derive(Serialize, Deserialize)]
+pub struct Atr {
+    window: usize,
+    previous_close: Option<f64>,
+    true_ranges: VecDeque<f64>,
+}
+
+impl Atr {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window,
+            previous_close: None,
+            true_ranges: VecDeque::new(),
+        }
+    }
+
+    pub fn update(&mut self, kline: &Kline) {
+        let true_range = match self.previous_close {
+            Some(close) => (kline.high - kline.low)
+                .max((kline.high - close).abs())
+                .max((kline.low - close).abs()),
+            None => kline.high - kline.low,
+        };
+        self.previous_close = Some(kline.close);
+        self.true_ranges.push_back(true_range);
+        if self.true_ranges.len() > self.window {
+            self.true_ranges.pop_front();
+        }
+    }
+
+    /// `None` until `window` candles have been seen.
+    pub fn value(&self) -> Option<f64> {
+        if self.true_ranges.len() < self.window {
+            None
+        } else {
+            Some(self.true_ranges.iter().sum::<f64>() / self.window as f64)
+        }
+    }
+}
+
+/// Typical price of a candle, the price its volume is assumed to have traded at.
+fn typical_price(kline: &Kline) -> f64 {
+    (kline.high + kline.low + kline.close) / 3.0
+}
+
+/// Volume-weighted average price of the current session, a calendar day of the data, reset at midnight.
+#[derive(Serialize, Deserialize)]
+pub struct Vwap {
+    session: Option<NaiveDate>,
+    price_volume: f64,
+    volume: f64,
+}
+
+impl Vwap {
+    pub fn new() -> Self {
+        Self {
+            session: None,
+            price_volume: 0.0,
+            volume: 0.0,
+        }
+    }
+
+    pub fn update(&mut self, kline: &Kline) {
+        let session = kline.timestamp.date();
+        if self.session != Some(session) {
+            self.session = Some(session);
+            self.price_volume = 0.0;
+            self.volume = 0.0;
+        }
+        self.price_volume += typical_price(kline) * kline.volume;
+        self.volume += kline.volume;
+    }
+
+    /// `None` until some volume was traded in the session.
+    pub fn value(&self) -> Option<f64> {
+        (self.volume > 0.0).then(|| self.price_volume / self.volume)
+    }
+}
+
+impl Default for Vwap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Where an `AnchoredVwap` starts.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Anchor {
+    /// The first candle at or after the date.
+    Date(NaiveDateTime),
+    /// The last swing low: a candle whose low is below the lows of the `n` candles on each side. A swing is only
+    /// known `n` candles later, the VWAP then being recomputed from it.
+    SwingLow(usize),
+    /// The last swing high, like `SwingLow`.
+    SwingHigh(usize),
+}
+
+/// Volume-weighted average price since an anchor, never reset otherwise.
+#[derive(Serialize, Deserialize)]
+pub struct AnchoredVwap {
+    anchor: Anchor,
+    /// Typical price times volume, volume, low and high of the last candles, enough to spot swings.
+    recent: VecDeque<(f64, f64, f64, f64)>,
+    price_volume: f64,
+    volume: f64,
+    anchored: bool,
+}
+
+impl AnchoredVwap {
+    pub fn new(anchor: Anchor) -> Self {
+        Self {
+            anchor,
+            recent: VecDeque::new(),
+            price_volume: 0.0,
+            volume: 0.0,
+            anchored: false,
+        }
+    }
+
+    pub fn update(&mut self, kline: &Kline) {
+        let candle = (typical_price(kline) * kline.volume, kline.volume, kline.low, kline.high);
+        let window = match self.anchor {
+            Anchor::Date(date) => {
+                self.anchored |= kline.timestamp >= date;
+                if self.anchored {
+                    self.price_volume += candle.0;
+                    self.volume += candle.1;
+                }
+                return;
+            }
+            Anchor::SwingLow(window) | Anchor::SwingHigh(window) => window,
+        };
+        self.recent.push_back(candle);
+        if self.recent.len() > 2 * window + 1 {
+            self.recent.pop_front();
+        }
+        if self.anchored {
+            self.price_volume += candle.0;
+            self.volume += candle.1;
+        }
+        if self.recent.len() == 2 * window + 1 && self.is_swing(window) {
+            self.anchored = true;
+            self.price_volume = self.recent.iter().skip(window).map(|candle| candle.0).sum();
+            self.volume = self.recent.iter().skip(window).map(|candle| candle.1).sum();
+        }
+    }
+
+    /// Whether the middle candle of `recent` is a swing of the kind of the anchor.
+    fn is_swing(&self, window: usize) -> bool {
+        let (_, _, low, high) = self.recent[window];
+        let mut others = self.recent.iter().enumerate().filter(|(index, _)| *index != window);
+        match self.anchor {
+            Anchor::SwingLow(_) => others.all(|(_, candle)| candle.2 > low),
+            _ => others.all(|(_, candle)| candle.3 < high),
+        }
+    }
+
+    /// `None` before the anchor, or while no volume was traded since it.
+    pub fn value(&self) -> Option<f64> {
+        (self.anchored && self.volume > 0.0).then(|| self.price_volume / self.volume)
+    }
+}
+
+/// Trailing band `multiplier` ATRs away from the middle of the candles, below the price in an uptrend and above it
+/// in a downtrend. The band only moves in the direction of the trend, and the trend flips when the close crosses it.
+#[derive(Serialize, Deserialize)]
+pub struct SuperTrend {
+    multiplier: f64,
+    atr: Atr,
+    previous_close: Option<f64>,
+    /// Upper and lower band, the level being the lower one in an uptrend.
+    bands: Option<(f64, f64)>,
+    uptrend: bool,
+}
+
+impl SuperTrend {
+    pub fn new(window: usize, multiplier: f64) -> Self {
+        Self {
+            multiplier,
+            atr: Atr::new(window),
+            previous_close: None,
+            bands: None,
+            uptrend: true,
+        }
+    }
+
+    pub fn update(&mut self, kline: &Kline) {
+        self.atr.update(kline);
+        let previous_close = self.previous_close.replace(kline.close);
+        let (Some(atr), Some(previous_close)) = (self.atr.value(), previous_close) else {
+            return;
+        };
+        let middle = (kline.high + kline.low) / 2.0;
+        let (upper, lower) = (middle + self.multiplier * atr, middle - self.multiplier * atr);
+        let (upper, lower) = match self.bands {
+            Some((previous_upper, previous_lower)) => (
+                if upper < previous_upper || previous_close > previous_upper { upper } else { previous_upper },
+                if lower > previous_lower || previous_close < previous_lower { lower } else { previous_lower },
+            ),
+            None => (upper, lower),
+        };
+        self.bands = Some((upper, lower));
+        if self.uptrend && kline.close < lower {
+            self.uptrend = false;
+        } else if !self.uptrend && kline.close > upper {
+            self.uptrend = true;
+        }
+    }
+
+    /// Level of the band, `None` until the ATR is known.
+    pub fn value(&self) -> Option<f64> {
+        self.bands.map(|(upper, lower)| if self.uptrend { lower } else { upper })
+    }
+
+    pub fn uptrend(&self) -> bool {
+        self.uptrend
+    }
+}
+
+/// Lines of the Ichimoku cloud on a candle.
+#[derive(Debug, Clone, Copy)]
+pub struct IchimokuLines {
+    /// Conversion line, middle of the range of the last `tenkan` candles.
+    pub tenkan: f64,
+    /// Base line, middle of the range of the last `kijun` candles.
+    pub kijun: f64,
+    /// Leading spans of the cloud over the candle, computed `kijun` candles earlier.
+    pub span_a: f64,
+    pub span_b: f64,
+    /// Close `kijun` candles earlier, the lagging span of this candle being compared to it.
+    pub lagging_close: f64,
+}
+
+impl IchimokuLines {
+    pub fn cloud_top(&self) -> f64 {
+        self.span_a.max(self.span_b)
+    }
+
+    pub fn cloud_bottom(&self) -> f64 {
+        self.span_a.min(self.span_b)
+    }
+}
+
+/// Ichimoku cloud, with the leading spans shifted forward by `kijun` candles like on charts.
+#[derive(Serialize, Deserialize)]
+pub struct Ichimoku {
+    tenkan: usize,
+    kijun: usize,
+    span_b: usize,
+    candles: VecDeque<(f64, f64, f64)>,
+    /// Leading spans computed on the last candles, the front one being the cloud over the current candle.
+    spans: VecDeque<(f64, f64)>,
+}
+
+impl Ichimoku {
+    pub fn new(tenkan: usize, kijun: usize, span_b: usize) -> Self {
+        Self {
+            tenkan,
+            kijun,
+            span_b,
+            candles: VecDeque::new(),
+            spans: VecDeque::new(),
+        }
+    }
+
+    /// Middle of the range of the last `window` candles.
+    fn midpoint(&self, window: usize) -> Option<f64> {
+        let start = self.candles.len().checked_sub(window)?;
+        let candles = self.candles.range(start..);
+        let high = candles.clone().map(|candle| candle.0).fold(f64::NEG_INFINITY, f64::max);
+        let low = candles.map(|candle| candle.1).fold(f64::INFINITY, f64::min);
+        Some((high + low) / 2.0)
+    }
+
+    pub fn update(&mut self, kline: &Kline) {
+        self.candles.push_back((kline.high, kline.low, kline.close));
+        // the lagging close needs `kijun` candles before the current one
+        if self.candles.len() > self.span_b.max(self.tenkan).max(self.kijun + 1) {
+            self.candles.pop_front();
+        }
+        if let (Some(tenkan), Some(kijun), Some(span_b)) = (self.midpoint(self.tenkan), self.midpoint(self.kijun), self.midpoint(self.span_b)) {
+            self.spans.push_back(((tenkan + kijun) / 2.0, span_b));
+            if self.spans.len() > self.kijun + 1 {
+                self.spans.pop_front();
+            }
+        }
+    }
+
+    /// `None` until the cloud over the current candle is known, `span_b + kijun` candles in.
+    pub fn value(&self) -> Option<IchimokuLines> {
+        if self.spans.len() <= self.kijun {
+            return None;
+        }
+        let (span_a, span_b) = self.spans[0];
+        Some(IchimokuLines {
+            tenkan: self.midpoint(self.tenkan)?,
+            kijun: self.midpoint(self.kijun)?,
+            span_a,
+            span_b,
+            lagging_close: self.candles[self.candles.len() - 1 - self.kijun].2,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SwingKind {
+    High,
+    Low,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Swing {
+    pub timestamp: NaiveDateTime,
+    pub price: f64,
+    pub kind: SwingKind,
+}
+
+/// Swing highs and lows: candles whose high is above (or low below) the ones of the `window` candles on each side.
+/// A swing is only known `window` candles after it. Their prices make the support and resistance levels.
+#[derive(Serialize, Deserialize)]
+pub struct Swings {
+    window: usize,
+    recent: VecDeque<(NaiveDateTime, f64, f64)>,
+    swings: Vec<Swing>,
+}
+
+impl Swings {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window,
+            recent: VecDeque::new(),
+            swings: Vec::new(),
+        }
+    }
+
+    pub fn update(&mut self, kline: &Kline) {
+        self.recent.push_back((kline.timestamp, kline.high, kline.low));
+        if self.recent.len() > 2 * self.window + 1 {
+            self.recent.pop_front();
+        }
+        if self.recent.len() < 2 * self.window + 1 {
+            return;
+        }
+        let (timestamp, high, low) = self.recent[self.window];
+        let others = || self.recent.iter().enumerate().filter(|(index, _)| *index != self.window).map(|(_, candle)| candle);
+        if others().all(|candle| candle.1 < high) {
+            self.swings.push(Swing { timestamp, price: high, kind: SwingKind::High });
+        }
+        if others().all(|candle| candle.2 > low) {
+            self.swings.push(Swing { timestamp, price: low, kind: SwingKind::Low });
+        }
+    }
+
+    /// Every swing confirmed so far, oldest first.
+    pub fn swings(&self) -> &[Swing] {
+        &self.swings
+    }
+
+    pub fn last(&self, kind: SwingKind) -> Option<&Swing> {
+        self.swings.iter().rev().find(|swing| swing.kind == kind)
+    }
+
+    /// Closest swing price below `price`, high or low: a broken resistance is a support.
+    pub fn support(&self, price: f64) -> Option<f64> {
+        self.swings.iter().map(|swing| swing.price).filter(|level| *level < price).fold(None, |best, level| Some(best.map_or(level, |best: f64| best.max(level))))
+    }
+
+    /// Closest swing price above `price`, high or low.
+    pub fn resistance(&self, price: f64) -> Option<f64> {
+        self.swings.iter().map(|swing| swing.price).filter(|level| *level > price).fold(None, |best, level| Some(best.map_or(level, |best: f64| best.min(level))))
+    }
+}
+
+/// Pivot, supports and resistances of a session, closest first.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct PivotLevels {
+    pub pivot: f64,
+    pub supports: [f64; 3],
+    pub resistances: [f64; 3],
+}
+
+impl PivotLevels {
+    /// Levels derived from the high, low and close of the previous session.
+    pub fn new(kind: PivotKind, high: f64, low: f64, close: f64) -> Self {
+        let pivot = (high + low + close) / 3.0;
+        let range = high - low;
+        match kind {
+            PivotKind::Classic => Self {
+                pivot,
+                supports: [2.0 * pivot - high, pivot - range, low - 2.0 * (high - pivot)],
+                resistances: [2.0 * pivot - low, pivot + range, high + 2.0 * (pivot - low)],
+            },
+            PivotKind::Fibonacci => Self {
+                pivot,
+                supports: [pivot - 0.382 * range, pivot - 0.618 * range, pivot - range],
+                resistances: [pivot + 0.382 * range, pivot + 0.618 * range, pivot + range],
+            },
+        }
+    }
+}
+
+/// Pivot levels of the current session, a calendar day of the data, from the previous one.
+#[derive(Serialize, Deserialize)]
+pub struct Pivots {
+    kind: PivotKind,
+    session: Option<NaiveDate>,
+    /// High, low and close of the current session so far.
+    range: Option<(f64, f64, f64)>,
+    levels: Option<PivotLevels>,
+}
+
+impl Pivots {
+    pub fn new(kind: PivotKind) -> Self {
+        Self {
+            kind,
+            session: None,
+            range: None,
+            levels: None,
+        }
+    }
+
+    pub fn update(&mut self, kline: &Kline) {
+        let session = kline.timestamp.date();
+        if self.session != Some(session) {
+            if let Some((high, low, close)) = self.range.take() {
+                self.levels = Some(PivotLevels::new(self.kind, high, low, close));
+            }
+            self.session = Some(session);
+        }
+        self.range = Some(match self.range {
+            Some((high, low, _)) => (high.max(kline.high), low.min(kline.low), kline.close),
+            None => (kline.high, kline.low, kline.close),
+        });
+    }
+
+    /// `None` during the first session.
+    pub fn value(&self) -> Option<PivotLevels> {
+        self.levels
+    }
+}
+
+/// Volume traded at each price, in buckets of equal size between the lowest and the highest price of the candles.
+/// The volume of a candle is spread evenly over its range.
+#[derive(Debug, Clone)]
+pub struct VolumeProfile {
+    pub low: f64,
+    pub bucket_size: f64,
+    pub volumes: Vec<f64>,
+}
+
+impl VolumeProfile {
+    /// Profile of candles given as their low, high and volume. `None` without candles or without any range.
+    pub fn build(candles: impl Iterator<Item = (f64, f64, f64)> + Clone, buckets: usize) -> Option<Self> {
+        let low = candles.clone().map(|candle| candle.0).fold(f64::INFINITY, f64::min);
+        let high = candles.clone().map(|candle| candle.1).fold(f64::NEG_INFINITY, f64::max);
+        if buckets == 0 || high <= low {
+            return None;
+        }
+        let bucket_size = (high - low) / buckets as f64;
+        let mut volumes = vec![0.0; buckets];
+        let bucket = |price: f64| (((price - low) / bucket_size) as usize).min(buckets - 1);
+        for (candle_low, candle_high, volume) in candles {
+            let (first, last) = (bucket(candle_low), bucket(candle_high));
+            for (index, bucket_volume) in volumes.iter_mut().enumerate().take(last + 1).skip(first) {
+                // the share of the range of the candle inside the bucket, the whole volume for a candle without range
+                let overlap = (candle_high.min(low + (index + 1) as f64 * bucket_size) - candle_low.max(low + index as f64 * bucket_size)).max(0.0);
+                *bucket_volume += if candle_high > candle_low { volume * overlap / (candle_high - candle_low) } else { volume };
+            }
+        }
+        Some(Self { low, bucket_size, volumes })
+    }
+
+    pub fn from_klines(klines: &[Kline], buckets: usize) -> Option<Self> {
+        Self::build(klines.iter().map(|kline| (kline.low, kline.high, kline.volume)), buckets)
+    }
+
+    /// Middle price of a bucket.
+    pub fn price(&self, index: usize) -> f64 {
+        self.low + (index as f64 + 0.5) * self.bucket_size
+    }
+
+    fn poc_index(&self) -> usize {
+        self.volumes.iter().enumerate().fold(0, |best, (index, volume)| if *volume > self.volumes[best] { index } else { best })
+    }
+
+    /// Point of control, the middle of the bucket with the most volume.
+    pub fn point_of_control(&self) -> f64 {
+        self.price(self.poc_index())
+    }
+
+    /// Buckets of the value area, the smallest range around the point of control holding `share` (like 0.7) of the
+    /// volume, grown one bucket at a time on the side with the most volume.
+    fn value_area_indices(&self, share: f64) -> (usize, usize) {
+        let target = self.volumes.iter().sum::<f64>() * share;
+        let (mut first, mut last) = (self.poc_index(), self.poc_index());
+        let mut volume = self.volumes[first];
+        while volume < target && (first > 0 || last + 1 < self.volumes.len()) {
+            let below = if first > 0 { self.volumes[first - 1] } else { f64::NEG_INFINITY };
+            let above = if last + 1 < self.volumes.len() { self.volumes[last + 1] } else { f64::NEG_INFINITY };
+            if above >= below {
+                last += 1;
+                volume += above;
+            } else {
+                first -= 1;
+                volume += below;
+            }
+        }
+        (first, last)
+    }
+
+    /// Lowest and highest price of the value area, see `value_area_indices`.
+    pub fn value_area(&self, share: f64) -> (f64, f64) {
+        let (first, last) = self.value_area_indices(share);
+        (self.low + first as f64 * self.bucket_size, self.low + (last + 1) as f64 * self.bucket_size)
+    }
+}
+
+/// Volume profile of the last `window` candles.
+#[derive(Serialize, Deserialize)]
+pub struct VolumeProfiler {
+    window: usize,
+    buckets: usize,
+    candles: VecDeque<(f64, f64, f64)>,
+}
+
+impl VolumeProfiler {
+    pub fn new(window: usize, buckets: usize) -> Self {
+        Self {
+            window,
+            buckets,
+            candles: VecDeque::new(),
+        }
+    }
+
+    pub fn update(&mut self, kline: &Kline) {
+        self.candles.push_back((kline.low, kline.high, kline.volume));
+        if self.candles.len() > self.window {
+            self.candles.pop_front();
+        }
+    }
+
+    /// `None` until `window` candles have been seen.
+    pub fn value(&self) -> Option<VolumeProfile> {
+        if self.candles.len() < self.window {
+            return None;
+        }
+        VolumeProfile::build(self.candles.iter().copied(), self.buckets)
+    }
+}
+
+/// Average directional index over `window` candles, with Wilder's smoothing: the strength of the trend from 0 to 100,
+/// whatever its direction.
+#[derive(Serialize, Deserialize)]
+pub struct Adx {
+    window: usize,
+    /// High, low and close of the previous candle.
+    previous: Option<(f64, f64, f64)>,
+    /// Number of moves since the first candle.
+    moves: usize,
+    /// Smoothed true range, upward and downward movement.
+    smoothed: (f64, f64, f64),
+    /// Number of directional indexes averaged so far.
+    indexes: usize,
+    adx: f64,
+}
+
+impl Adx {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window,
+            previous: None,
+            moves: 0,
+            smoothed: (0.0, 0.0, 0.0),
+            indexes: 0,
+            adx: 0.0,
+        }
+    }
+
+    pub fn update(&mut self, kline: &Kline) {
+        let Some((high, low, close)) = self.previous.replace((kline.high, kline.low, kline.close)) else {
+            return;
+        };
+        let (up, down) = (kline.high - high, low - kline.low);
+        let plus = if up > down && up > 0.0 { up } else { 0.0 };
+        let minus = if down > up && down > 0.0 { down } else { 0.0 };
+        let true_range = (kline.high - kline.low).max((kline.high - close).abs()).max((kline.low - close).abs());
+        let window = self.window as f64;
+        self.moves += 1;
+        let (range, plus, minus) = if self.moves <= self.window {
+            (self.smoothed.0 + true_range, self.smoothed.1 + plus, self.smoothed.2 + minus)
+        } else {
+            let (range, smoothed_plus, smoothed_minus) = self.smoothed;
+            (range - range / window + true_range, smoothed_plus - smoothed_plus / window + plus, smoothed_minus - smoothed_minus / window + minus)
+        };
+        self.smoothed = (range, plus, minus);
+        if self.moves < self.window || range <= 0.0 {
+            return;
+        }
+        let (plus_di, minus_di) = (plus / range, minus / range);
+        let dx = if plus_di + minus_di > 0.0 { 100.0 * (plus_di - minus_di).abs() / (plus_di + minus_di) } else { 0.0 };
+        // a plain average of the first `window` indexes, then Wilder's smoothing
+        self.indexes += 1;
+        let weight = self.indexes.min(self.window) as f64;
+        self.adx += (dx - self.adx) / weight;
+    }
+
+    /// `None` until `2 * window` candles have been seen.
+    pub fn value(&self) -> Option<f64> {
+        (self.indexes >= self.window).then_some(self.adx)
+    }
+}
@@ -0,0 +1,301 @@
+use std::io::{BufRead, Write};
+use std::path::Path;
+use chrono::{Duration, NaiveDate};
+use retroval::symbol::Symbol;
+
+const STRATEGY_TEMPLATE: &str = r#"// Starter strategy generated by `retroval init`.
+//
+// To use it, copy this file to `src/`, add `mod my_strategy;` to `main.rs`,
+// and register it in `strategy::build_strategy`:
+//
+//     "my_strategy" => Box::new(my_strategy::MyStrategy::new()),
+//
+// If it takes parameters, declare them in `strategy::builtin_parameters`, for example
+// `ParameterSpec::integer("window", 20, Some(1.0), None)`, and read them with `params.integer("window")`.
+//
+// then pick it in the config with `"strategies": [{"name": "my_strategy", "weight": 1}]`.
+
+use crate::historical::Kline;
+use crate::strategy::{Signal, Strategy};
+
+pub struct MyStrategy {
+    previous_close: Option<f64>,
+    in_position: bool,
+}
+
+impl MyStrategy {
+    pub fn new() -> Self {
+        Self {
+            previous_close: None,
+            in_position: false,
+        }
+    }
+}
+
+impl Strategy for MyStrategy {
+    fn on_tick(&mut self, kline: &Kline) -> Option<Signal> {
+        // Buy after a green candle, sell after a red one.
+        let signal = match self.previous_close {
+            Some(previous) if kline.close > previous && !self.in_position => {
+                self.in_position = true;
+                Signal::Buy
+            }
+            Some(previous) if kline.close < previous && self.in_position => {
+                self.in_position = false;
+                Signal::Sell
+            }
+            _ => Signal::Hold,
+        };
+        self.previous_close = Some(kline.close);
+        Some(signal)
+    }
+}
+"#;
+
+const NEW_STRATEGY_TEMPLATE: &str = r#"// Strategy skeleton generated by `retroval new-strategy {name}`.
+//
+// To use it, move this file to `src/`, add `pub mod {name};` to `lib.rs`, then register it in `strategy.rs`:
+// in `builtin_parameters`
+//
+//     "{name}" => Some(crate::{name}::parameters()),
+//
+// and in `try_build_strategy`
+//
+//     "{name}" => Box::new(crate::{name}::{type}::from_parameters(&params)),
+//
+// then pick it in the config with `"strategy": {"name": "{name}", "params": {"window": 20}}`.
+// Run its tests with `cargo test {name}`.
+
+use serde::{Deserialize, Serialize};
+use crate::historical::Kline;
+use crate::strategy::{ParameterSpec, Parameters, Signal, Strategy};
+
+/// Parameters of the strategy, with their defaults and bounds, checked before it is built.
+pub fn parameters() -> Vec<ParameterSpec> {
+    vec![ParameterSpec::integer("window", 20, Some(1.0), Some(1000.0))]
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct {type} {
+    window: usize,
+    closes: Vec<f64>,
+    in_position: bool,
+}
+
+impl {type} {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window,
+            closes: Vec::new(),
+            in_position: false,
+        }
+    }
+
+    pub fn from_parameters(params: &Parameters) -> Self {
+        Self::new(params.integer("window") as usize)
+    }
+
+    /// Highest close of the `window` candles before the last one.
+    fn previous_high(&self) -> Option<f64> {
+        if self.closes.len() <= self.window {
+            return None;
+        }
+        let previous = &self.closes[self.closes.len() - 1 - self.window..self.closes.len() - 1];
+        previous.iter().copied().reduce(f64::max)
+    }
+}
+
+impl Strategy for {type} {
+    fn on_tick(&mut self, kline: &Kline) -> Option<Signal> {
+        self.closes.push(kline.close);
+        // Buy when the close breaks above the previous highs, sell when it falls back below them.
+        let signal = match self.previous_high() {
+            Some(high) if kline.close > high && !self.in_position => {
+                self.in_position = true;
+                Signal::Buy
+            }
+            Some(high) if kline.close < high && self.in_position => {
+                self.in_position = false;
+                Signal::Sell
+            }
+            _ => Signal::Hold,
+        };
+        Some(signal)
+    }
+
+    fn indicators(&self) -> Vec<(String, f64)> {
+        self.previous_high().map(|high| ("previous_high".to_string(), high)).into_iter().collect()
+    }
+
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("error while serializing strategy state")
+    }
+
+    fn load_state(&mut self, state: serde_json::Value) {
+        *self = serde_json::from_value(state).expect("error while restoring strategy state");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, NaiveDate};
+
+    /// Hourly candles closing at `closes`.
+    fn klines(closes: &[f64]) -> Vec<Kline> {
+        let start = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+        closes
+            .iter()
+            .enumerate()
+            .map(|(i, close)| Kline {
+                timestamp: start + Duration::hours(i as i64),
+                open: *close,
+                high: *close,
+                low: *close,
+                close: *close,
+                volume: 1.0,
+            })
+            .collect()
+    }
+
+    fn signals(strategy: &mut {type}, closes: &[f64]) -> Vec<String> {
+        klines(closes).iter().map(|kline| format!("{:?}", strategy.on_tick(kline).unwrap())).collect()
+    }
+
+    #[test]
+    fn parameters_take_their_defaults() {
+        let params = Parameters::check(&parameters(), &serde_json::json!({})).unwrap();
+        assert_eq!({type}::from_parameters(&params).window, 20);
+        assert!(Parameters::check(&parameters(), &serde_json::json!({"window": 0})).is_err());
+    }
+
+    #[test]
+    fn buys_the_breakout_and_sells_the_fall() {
+        let mut strategy = {type}::new(2);
+        assert_eq!(signals(&mut strategy, &[10.0, 11.0, 12.0, 9.0, 8.0]), ["Hold", "Hold", "Buy", "Sell", "Hold"]);
+    }
+
+    #[test]
+    fn state_survives_a_checkpoint() {
+        let mut strategy = {type}::new(2);
+        signals(&mut strategy, &[10.0, 11.0, 12.0]);
+        let mut restored = {type}::new(2);
+        restored.load_state(strategy.save_state());
+        assert_eq!(signals(&mut restored, &[9.0]), ["Sell"]);
+    }
+}
+"#;
+
+/// Asks a question on the terminal, returning `default` when the answer is empty.
+fn prompt(question: &str, default: &str) -> String {
+    print!("{} [{}]: ", question, default);
+    std::io::stdout().flush().unwrap();
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer).unwrap();
+    match answer.trim() {
+        "" => default.to_string(),
+        answer => answer.to_string(),
+    }
+}
+
+/// A deterministic random walk, enough to check that everything runs.
+fn sample_data(candles: usize) -> String {
+    let mut csv = String::from("Open Time,Open,High,Low,Close,Volume\n");
+    let start = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+    let mut seed: u64 = 42;
+    let mut next = || {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (seed >> 33) as f64 / (1u64 << 31) as f64
+    };
+    let mut close = 100.0;
+    for i in 0..candles {
+        let open = close;
+        close = open * (1.0 + (next() - 0.5) * 0.02);
+        let high = open.max(close) * (1.0 + next() * 0.005);
+        let low = open.min(close) * (1.0 - next() * 0.005);
+        let volume = 1000.0 + next() * 500.0;
+        let timestamp = start + Duration::hours(i as i64);
+        csv.push_str(&format!("{},{:.2},{:.2},{:.2},{:.2},{:.2}\n", timestamp.format("%Y-%m-%d %H:%M:%S"), open, high, low, close, volume));
+    }
+    csv
+}
+
+fn sample_config(symbol: &str, base_funds: f64) -> String {
+    let config = serde_json::json!({
+        "data_path": "sample_data.csv",
+        "headers": {
+            "timestamp": "Open Time",
+            "open": "Open",
+            "high": "High",
+            "low": "Low",
+            "close": "Close",
+            "volume": "Volume"
+        },
+        "base_funds": base_funds,
+        "transaction_fee": 0.1,
+        "slippage": 0.001,
+        "symbol": symbol,
+        "timeframe": "1h",
+        "log_level": "All",
+        "log_file": "logs.log",
+        "log_graph": true,
+        "log_graph_file": "graph.png"
+    });
+    serde_json::to_string_pretty(&config).unwrap()
+}
+
+/// Creates a starter config, sample strategy and sample dataset in `directory`, asking a few questions first.
+/// Existing files are never overwritten.
+pub fn init_project(directory: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let directory = Path::new(directory);
+    let files = ["config.json", "sample_data.csv", "my_strategy.rs"];
+    for file in files.iter() {
+        if directory.join(file).exists() {
+            return Err(format!("{} already exists, not overwriting it", directory.join(file).display()).into());
+        }
+    }
+    let symbol = prompt("Symbol", "BTC/USDT");
+    Symbol::parse(&symbol)?;
+    let base_funds: f64 = prompt("Starting funds", "1000").parse()?;
+
+    std::fs::create_dir_all(directory)?;
+    std::fs::write(directory.join("config.json"), sample_config(&symbol, base_funds))?;
+    std::fs::write(directory.join("sample_data.csv"), sample_data(500))?;
+    std::fs::write(directory.join("my_strategy.rs"), STRATEGY_TEMPLATE)?;
+    for file in files.iter() {
+        println!("Created {}", directory.join(file).display());
+    }
+    // paths in the config are relative to the working directory
+    println!("Run `retroval backtest` from {} to try it.", directory.display());
+    Ok(())
+}
+
+/// `snake_case` name to the `CamelCase` type of its strategy, like `my_breakout` to `MyBreakoutStrategy`.
+fn strategy_type(name: &str) -> String {
+    let mut type_name = String::new();
+    for word in name.split('_').filter(|word| !word.is_empty()) {
+        let mut chars = word.chars();
+        if let Some(first) = chars.next() {
+            type_name.push(first.to_ascii_uppercase());
+            type_name.extend(chars);
+        }
+    }
+    type_name + "Strategy"
+}
+
+/// Writes the skeleton of a strategy named `name` to `<name>.rs` in the working directory: its parameters, its
+/// `Strategy` implementation, its tests and how to register it. An existing file is never overwritten.
+pub fn new_strategy(name: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let valid = name.starts_with(|c: char| c.is_ascii_lowercase()) && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    if !valid {
+        return Err(format!("invalid strategy name `{}`, expected snake_case like my_strategy", name).into());
+    }
+    let path = Path::new(name).with_extension("rs");
+    if path.exists() {
+        return Err(format!("{} already exists, not overwriting it", path.display()).into());
+    }
+    std::fs::write(&path, NEW_STRATEGY_TEMPLATE.replace("{name}", name).replace("{type}", &strategy_type(name)))?;
+    println!("Created {}", path.display());
+    println!("Follow the comment at its top to register it.");
+    Ok(())
+}
@@ -0,0 +1,37 @@
+pub mod acceptance;
+#[cfg(feature = "files")]
+pub mod artifacts;
+pub mod analysis;
+pub mod checkpoint;
+pub mod blackout;
+pub mod calibration;
+pub mod config;
+pub mod debugger;
+pub mod distributed;
+pub mod events;
+pub mod exits;
+pub mod expression;
+#[cfg(feature = "files")]
+pub mod ffi;
+pub mod historical;
+pub mod indicators;
+pub mod locale;
+#[cfg(feature = "files")]
+pub mod logging;
+pub mod montecarlo;
+pub mod optimizer;
+pub mod outage;
+pub mod regime;
+pub mod reporting;
+#[cfg(feature = "plugins")]
+pub mod plugin;
+pub mod risk;
+pub mod sizing;
+pub mod strategy;
+pub mod symbol;
+pub mod tax;
+pub mod testing;
+#[cfg(feature = "files")]
+pub mod tradebook;
+#[cfg(feature = "plotting")]
+pub mod graphing;
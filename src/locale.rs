@@ -0,0 +1,111 @@
+//! Number formatting of the reports for the configured locale: decimal separator, thousands separator and currency
+//! symbols. Without a locale, numbers keep the plain format of the JSON outputs and of the log.
+
+use std::fs::File;
+use crate::config::Locale;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Formatter {
+    locale: Option<Locale>,
+}
+
+impl Formatter {
+    pub fn new(locale: Option<Locale>) -> Self {
+        Self { locale }
+    }
+
+    fn decimal(&self) -> char {
+        match self.locale {
+            Some(Locale::DeDe | Locale::FrFr | Locale::EsEs | Locale::ItIt | Locale::NlNl) => ',',
+            _ => '.',
+        }
+    }
+
+    fn group(&self) -> Option<char> {
+        match self.locale {
+            None => None,
+            Some(Locale::EnUs | Locale::EnGb) => Some(','),
+            Some(Locale::DeDe | Locale::EsEs | Locale::ItIt | Locale::NlNl) => Some('.'),
+            Some(Locale::FrFr) => Some('\u{a0}'),
+            Some(Locale::DeCh) => Some('\''),
+        }
+    }
+
+    /// `value` with `decimals` digits after the separator and its thousands grouped.
+    pub fn number(&self, value: f64, decimals: usize) -> String {
+        let text = format!("{:.*}", decimals, value);
+        let (sign, digits) = match text.strip_prefix('-') {
+            Some(digits) => ("-", digits),
+            None => ("", text.as_str()),
+        };
+        let (integer, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        let mut result = sign.to_string();
+        for (index, digit) in integer.chars().enumerate() {
+            if let Some(group) = self.group() {
+                if index > 0 && (integer.len() - index) % 3 == 0 {
+                    result.push(group);
+                }
+            }
+            result.push(digit);
+        }
+        if !fraction.is_empty() {
+            result.push(self.decimal());
+            result.push_str(fraction);
+        }
+        result
+    }
+
+    /// `value` in percentage, with `decimals` digits.
+    pub fn percent(&self, value: f64, decimals: usize) -> String {
+        match self.locale {
+            Some(Locale::FrFr) => format!("{}\u{a0}%", self.number(value, decimals)),
+            _ => format!("{}%", self.number(value, decimals)),
+        }
+    }
+
+    /// An amount with two decimals and its currency: the symbol where the locale places it for the currencies having
+    /// one, the code after the amount otherwise.
+    pub fn money(&self, value: f64, currency: &str) -> String {
+        let amount = self.number(value, 2);
+        let symbol = match currency {
+            "USD" => "$",
+            "EUR" => "€",
+            "GBP" => "£",
+            "JPY" => "¥",
+            _ => "",
+        };
+        if symbol.is_empty() || self.locale.is_none() {
+            return format!("{} {}", amount, currency);
+        }
+        match self.locale {
+            Some(Locale::EnUs | Locale::EnGb) => match amount.strip_prefix('-') {
+                Some(amount) => format!("-{}{}", symbol, amount),
+                None => format!("{}{}", symbol, amount),
+            },
+            Some(Locale::NlNl | Locale::DeCh) => format!("{} {}", symbol, amount),
+            _ => format!("{}\u{a0}{}", amount, symbol),
+        }
+    }
+
+    /// `items` separated by commas, or by semicolons when the decimal separator is a comma.
+    pub fn list(&self, items: &[String]) -> String {
+        items.join(if self.decimal() == ',' { "; " } else { ", " })
+    }
+
+    /// `value` in full precision for a CSV cell, with the decimal separator of the locale and no grouping, as
+    /// spreadsheets read it.
+    pub fn csv_number(&self, value: f64) -> String {
+        value.to_string().replace('.', &self.decimal().to_string())
+    }
+
+    /// `value` with `decimals` digits for a CSV cell, see `csv_number`.
+    pub fn csv_fixed(&self, value: f64, decimals: usize) -> String {
+        format!("{:.*}", decimals, value).replace('.', &self.decimal().to_string())
+    }
+
+    /// A CSV writer to `file_path`, separating the cells with a semicolon when the decimal separator is a comma.
+    pub fn csv_writer(&self, file_path: &str) -> csv::Result<csv::Writer<File>> {
+        let delimiter = if self.decimal() == ',' { b';' } else { b',' };
+        csv::WriterBuilder::new().delimiter(delimiter).from_path(file_path)
+    }
+}
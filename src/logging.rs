@@ -0,0 +1,73 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use crate::config::Config;
+use crate::testing::MetricSnapshot;
+
+/// `logs.log` becomes `logs.log.1`, `logs.log.2`...
+fn rotated_path(log_file: &str, index: usize) -> String {
+    format!("{}.{}", log_file, index)
+}
+
+/// Shifts `log_file` to `log_file.1`, `.1` to `.2` and so on, dropping the files past `max_files`.
+fn rotate(log_file: &str, max_files: usize) -> std::io::Result<()> {
+    if max_files == 0 {
+        return match std::fs::remove_file(log_file) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+    }
+    for index in (1..max_files).rev() {
+        let from = rotated_path(log_file, index);
+        if Path::new(&from).exists() {
+            std::fs::rename(&from, rotated_path(log_file, index + 1))?;
+        }
+    }
+    // another run may have rotated the file in the meantime, it is not an error
+    match std::fs::rename(log_file, rotated_path(log_file, 1)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Prepares the log file for a new run: it is removed, unless `log_append` is set. The file of the metric snapshots
+/// is removed too, unless the run resumes from a checkpoint and carries on with it.
+pub fn start_run(config: &Config) -> std::io::Result<()> {
+    if !config.log_append && Path::new(&config.log_file).exists() {
+        std::fs::remove_file(&config.log_file)?;
+    }
+    if let (Some(file), None) = (config.metric_snapshots.as_ref().and_then(|snapshots| snapshots.file.as_ref()), &config.resume_from) {
+        if Path::new(file).exists() {
+            std::fs::remove_file(file)?;
+        }
+    }
+    Ok(())
+}
+
+/// Appends `snapshot` to `file_path` as one line of JSON.
+pub fn append_snapshot(file_path: &str, snapshot: &MetricSnapshot) -> std::io::Result<()> {
+    let line = serde_json::to_string(snapshot)?;
+    OpenOptions::new().create(true).append(true).open(file_path)?.write_all(format!("{}\n", line).as_bytes())
+}
+
+/// Appends lines to the log file, rotating it first when it is over `log_max_bytes`. With `log_append`, every line
+/// is prefixed with the run id, and the lines are written in a single call so concurrent runs do not interleave them.
+pub fn append(config: &Config, lines: &[String]) -> std::io::Result<()> {
+    if let Some(max_bytes) = config.log_max_bytes {
+        let size = std::fs::metadata(&config.log_file).map(|metadata| metadata.len()).unwrap_or(0);
+        if size >= max_bytes {
+            rotate(&config.log_file, config.log_max_files)?;
+        }
+    }
+    let prefix = match (&config.run_id, config.log_append) {
+        (Some(run_id), true) => format!("[{}] ", run_id),
+        _ => String::new(),
+    };
+    let mut text = String::new();
+    for line in lines {
+        text.push_str(&prefix);
+        text.push_str(line);
+        text.push('\n');
+    }
+    OpenOptions::new().create(true).append(true).open(&config.log_file)?.write_all(text.as_bytes())
+}
@@ -1,38 +1,754 @@
-mod config;
-mod historical;
-mod strategy;
-mod testing;
-mod graphing;
-
-fn print_metrics(metrics: &testing::Metrics, config: &config::Config) {
-    let profit_percentage = metrics.total_profit / config.base_funds * 100.0;
-    let max_drawdown_percentage = metrics.max_drawdown / config.base_funds * 100.0;
-    println!("Backtest results on {}:", config.pair);
+mod cli;
+mod dashboard;
+mod init;
+
+use retroval::{acceptance, analysis, artifacts, calibration, config, distributed, exits, graphing, historical, locale, montecarlo, risk, tax, testing, tradebook};
+
+fn print_extension(recap: &testing::SessionRecap, config: &config::Config) {
+    let Some(resumed_at) = recap.resumed_at else {
+        return;
+    };
+    let new_trades: Vec<&testing::Trade> = recap.trades.iter().filter(|trade| trade.exit_date.is_some_and(|date| date > resumed_at)).collect();
+    let new_profit: f64 = new_trades.iter().filter_map(|trade| trade.profit).sum();
+    let format = locale::Formatter::new(config.locale);
+    println!("Since checkpoint ({}): {} trades closed, profit {}", resumed_at, new_trades.len(), format.money(new_profit, &config.symbol.quote));
+}
+
+fn print_balances(recap: &testing::SessionRecap, config: &config::Config) {
+    if recap.balances.is_empty() {
+        return;
+    }
+    let format = locale::Formatter::new(config.locale);
+    let balances: Vec<String> = recap.balances.iter().map(|(currency, balance)| format!("{} {}", format.number(*balance, 8), currency)).collect();
+    println!("Final balances: {}", format.list(&balances));
+}
+
+fn print_risk_events(recap: &testing::SessionRecap, config: &config::Config) {
+    if config.risk_managers.is_empty() {
+        return;
+    }
+    println!("Risk managers: {} orders resized, {} vetoed", recap.metrics.risk_resizes, recap.metrics.risk_vetoes);
+    let mut names: Vec<&str> = recap.risk_events.iter().map(|event| event.manager.as_str()).collect();
+    names.sort();
+    names.dedup();
+    for name in names {
+        let events = recap.risk_events.iter().filter(|event| event.manager == name);
+        let (resized, vetoed) = events.fold((0, 0), |(resized, vetoed), event| match event.decision {
+            risk::RiskDecision::Resize(_) => (resized + 1, vetoed),
+            risk::RiskDecision::Veto(_) => (resized, vetoed + 1),
+            risk::RiskDecision::Accept => (resized, vetoed),
+        });
+        println!("  {}: {} resized, {} vetoed", name, resized, vetoed);
+    }
+}
+
+fn print_contributions(recap: &testing::SessionRecap, config: &config::Config) {
+    if recap.contributions.is_empty() {
+        return;
+    }
+    let rows = analysis::summary_table(&recap.contributions, config);
+    let format = locale::Formatter::new(config.locale);
+    println!("Strategies (ranked by {}):", config.objective);
+    println!(
+        "  {:>4} {:<24} {:>13} {:>7} {:>12} {:>9} {:>9} {:>8} {:>8} {:>12}",
+        "rank", "strategy", "weight", "trades", "profit", "return %", "win %", "pf", "sharpe", "drawdown"
+    );
+    for row in rows.iter() {
+        println!(
+            "  {:>4} {:<24} {:>5} -> {:<4} {:>7} {:>12} {:>9} {:>9} {:>8} {:>8} {:>12}",
+            row.rank,
+            row.name,
+            format.number(row.initial_weight, 2),
+            format.number(row.final_weight, 2),
+            row.trades,
+            format.number(row.profit, 2),
+            format.number(row.return_pct, 2),
+            format.number(row.win_rate, 2),
+            format.number(row.profit_factor, 2),
+            format.number(row.sharpe_ratio, 2),
+            format.number(row.max_drawdown, 2)
+        );
+    }
+    if let Some(path) = &config.summary_file {
+        analysis::write_summary_csv(&rows, path, &format).expect("error while writing summary table");
+    }
+}
+
+fn print_sweep(recap: &testing::SessionRecap, config: &config::Config) {
+    let Some(sweep) = &recap.sweep else {
+        return;
+    };
+    let rows = analysis::summary_table(&sweep.variants, config);
+    let format = locale::Formatter::new(config.locale);
+    println!("Sweep of {} variants (ranked by {}):", rows.len(), config.objective);
+    println!("  {:>4} {:<32} {:>7} {:>12} {:>9} {:>9} {:>8} {:>8} {:>12}", "rank", "parameters", "trades", "profit", "return %", "win %", "pf", "sharpe", "drawdown");
+    for row in rows.iter() {
+        println!(
+            "  {:>4} {:<32} {:>7} {:>12} {:>9} {:>9} {:>8} {:>8} {:>12}",
+            row.rank,
+            row.name,
+            row.trades,
+            format.number(row.profit, 2),
+            format.number(row.return_pct, 2),
+            format.number(row.win_rate, 2),
+            format.number(row.profit_factor, 2),
+            format.number(row.sharpe_ratio, 2),
+            format.number(row.max_drawdown, 2)
+        );
+    }
+    if let Some(path) = &config.summary_file {
+        analysis::write_summary_csv(&rows, path, &format).expect("error while writing summary table");
+    }
+    if let Some(cross_validation) = &sweep.cross_validation {
+        println!("Cross-validation over {} folds ({} of each variant on each fold):", cross_validation.folds.len(), config.objective);
+        for (fold, (start, end)) in cross_validation.folds.iter().enumerate() {
+            println!("  fold {}: {} to {}", fold + 1, start, end);
+        }
+        let folds: Vec<String> = (1..=cross_validation.folds.len()).map(|fold| format!("{:>10}", format!("fold {}", fold))).collect();
+        println!("  {:<32} {} {:>10}", "parameters", folds.join(" "), "mean");
+        for (variant, scores) in sweep.variants.iter().zip(cross_validation.scores.iter()) {
+            let row: Vec<String> = scores.iter().map(|score| format!("{:>10}", format.number(*score, 2))).collect();
+            println!("  {:<32} {} {:>10}", variant.name, row.join(" "), format.number(analysis::mean(scores), 2));
+        }
+        println!("Out-of-sample results of the selection (parameters picked on the other folds):");
+        for (fold, (variant, score)) in cross_validation.selected.iter().zip(cross_validation.out_of_sample.iter()).enumerate() {
+            println!("  fold {}: {} -> {}", fold + 1, sweep.variants[*variant].name, format.number(*score, 2));
+        }
+        println!("  mean: {}", format.number(analysis::mean(&cross_validation.out_of_sample), 2));
+    }
+    println!("Best parameters: {}", sweep.best.label());
+    if !sweep.sensitivity.is_empty() {
+        println!("Sensitivity of the objective to each parameter, the others at their best values (* best, | within 10% of the spread of the sweep):");
+        for sensitivity in sweep.sensitivity.iter() {
+            let (first, last) = sensitivity.safe;
+            println!(
+                "  {}: moves the objective by {}, safe from {} to {}",
+                sensitivity.parameter,
+                format.number(sensitivity.swing(), 2), sensitivity.response[first].0, sensitivity.response[last].0
+            );
+            for (index, (value, score)) in sensitivity.response.iter().enumerate() {
+                let mark = if index == sensitivity.best { "*" } else if (first..=last).contains(&index) { "|" } else { " " };
+                println!("    {} {:>10} {:>12}", mark, value.to_string(), format.number(*score, 2));
+            }
+        }
+    }
+    if let Some(surface) = config.sweep.as_ref().and_then(|sweep_config| analysis::parameter_surface(sweep_config, sweep, config)) {
+        let shape = if surface.is_spike() { "a narrow spike, the best parameters are likely overfit" } else { "a broad plateau" };
+        println!("The optimum sits on {} (its neighbors are {} of the range below it)", shape, format.percent(surface.neighbor_drop * 100.0, 0));
+    }
+    if let Some(p_value) = sweep.reality_check {
+        println!("Probability that the best result is luck (reality check): {}", format.percent(p_value * 100.0, 1));
+        if p_value > 0.05 {
+            println!("Warning: the best variant does not beat not trading with 95% confidence, its edge may come from trying many variants");
+        }
+    }
+    println!("Results of the best parameters:");
+}
+
+fn print_correlations(recap: &testing::SessionRecap, config: &config::Config) {
+    if recap.contributions.len() < 2 {
+        return;
+    }
+    let matrix = analysis::strategy_correlations(&recap.contributions);
+    let format = locale::Formatter::new(config.locale);
+    println!("Correlation of per-bar returns:");
+    for (label, row) in matrix.labels.iter().zip(matrix.values.iter()) {
+        let row: Vec<String> = row.iter().map(|value| format!("{:>6}", format.number(*value, 2))).collect();
+        println!("  {} | {}", row.join(" "), label);
+    }
+    for (a, b, value) in matrix.correlated_pairs(config.correlation_threshold) {
+        println!("  Highly correlated: {} and {} ({})", a, b, format.number(value, 2));
+    }
+    if let Some(path) = &config.correlation_file {
+        matrix.write_csv(path, &format).expect("error while writing correlation matrix");
+    }
+}
+
+fn print_benchmark(recap: &testing::SessionRecap, config: &config::Config) {
+    let metrics = analysis::benchmark_metrics(&recap.equity_curve, &recap.benchmark_curve, analysis::annualization(config));
+    let format = locale::Formatter::new(config.locale);
+    println!("Benchmark return: {}", format.percent(metrics.benchmark_return * 100.0, 2));
+    println!("Alpha: {}, beta: {} (annualized alpha)", format.number(metrics.alpha, 4), format.number(metrics.beta, 4));
+    println!("Tracking error: {}, information ratio: {}", format.number(metrics.tracking_error, 4), format.number(metrics.information_ratio, 4));
+}
+
+/// Everything `--headless` reports, as a single JSON object on stdout.
+fn print_json_summary(recap: &testing::SessionRecap, config: &config::Config, verdict: &acceptance::Verdict) {
+    println!("{}", analysis::json_summary(recap, config, verdict));
+}
+
+fn print_execution(recap: &testing::SessionRecap, config: &config::Config) {
+    let execution = analysis::execution_quality(&recap.trades);
+    if execution.fills == 0 {
+        return;
+    }
+    let format = locale::Formatter::new(config.locale);
+    let quote = config.symbol.quote.as_str();
+    println!(
+        "Execution ({} fills): implementation shortfall {} bps on average, {} median, {} at the 95th percentile",
+        execution.fills,
+        format.number(execution.mean_shortfall_bps, 2),
+        format.number(execution.median_shortfall_bps, 2),
+        format.number(execution.p95_shortfall_bps, 2)
+    );
+    let financing = match config.borrow_rate > 0.0 {
+        true => format!(", financing {}", format.money(-execution.financing, quote)),
+        false => String::new(),
+    };
+    println!(
+        "  Profit at signal prices {}, execution drag {}, commission {}{}",
+        format.money(execution.signal_profit, quote),
+        format.money(-execution.execution_drag, quote),
+        format.money(-execution.commission, quote),
+        financing
+    );
+}
+
+fn print_exit_analysis(recap: &testing::SessionRecap, config: &config::Config) {
+    let Some(analysis) = &recap.exit_analysis else {
+        return;
+    };
+    let format = locale::Formatter::new(config.locale);
+    let quote = config.symbol.quote.as_str();
+    println!(
+        "Exit analysis ({} trades): favorable excursion {}R and adverse excursion {}R on average, {} of the favorable excursion kept",
+        analysis.trades,
+        format.number(analysis.mean_mfe_r, 2),
+        format.number(analysis.mean_mae_r, 2),
+        format.percent(analysis.captured * 100.0, 2)
+    );
+    println!("  {:<20} {:>14} {:>9}", "Actual exits", format.money(analysis.actual_profit, quote), format.percent(analysis.actual_win_rate * 100.0, 2));
+    for alternative in analysis.alternatives.iter() {
+        println!("  {:<20} {:>14} {:>9}", alternative.label(), format.money(alternative.profit, quote), format.percent(alternative.win_rate * 100.0, 2));
+    }
+    for family in [exits::ExitFamily::FixedR, exits::ExitFamily::Trailing] {
+        if let Some(best) = analysis.best(family).filter(|best| best.profit > analysis.actual_profit) {
+            println!("  The {} would have improved the profit by {}", best.label(), format.money(best.profit - analysis.actual_profit, quote));
+        }
+    }
+}
+
+fn print_forward_returns(recap: &testing::SessionRecap, config: &config::Config) {
+    if recap.forward_returns.is_empty() {
+        return;
+    }
+    let format = locale::Formatter::new(config.locale);
+    println!("Returns after the entries, in the direction of the trades:");
+    println!("  {:>8} {:>8} {:>10} {:>22} {:>9}", "candles", "entries", "mean", "95% interval", "positive");
+    for point in recap.forward_returns.iter() {
+        println!(
+            "  {:>8} {:>8} {:>10} {:>22} {:>9}",
+            point.horizon,
+            point.entries,
+            format.percent(point.mean_return, 3),
+            format!("{} to {}", format.percent(point.low, 3), format.percent(point.high, 3)),
+            format.percent(point.positive_share, 1)
+        );
+    }
+}
+
+fn print_signal_quality(recap: &testing::SessionRecap, config: &config::Config) {
+    let format = locale::Formatter::new(config.locale);
+    for quality in recap.signal_quality.iter() {
+        println!("Signal quality of {} (entries followed by a favorable move):", quality.strategy);
+        println!("  {:>6} {:>8} {:>8} {:>9}", "ATRs", "candles", "entries", "hit rate");
+        for hit_rate in quality.hit_rates.iter() {
+            println!(
+                "  {:>6} {:>8} {:>8} {:>9}",
+                format.number(hit_rate.multiple, 2),
+                hit_rate.horizon,
+                hit_rate.entries,
+                format.percent(hit_rate.hit_rate, 1)
+            );
+        }
+    }
+}
+
+fn print_monte_carlo(recap: &testing::SessionRecap, config: &config::Config) {
+    let Some(result) = &recap.monte_carlo else {
+        return;
+    };
+    let format = locale::Formatter::new(config.locale);
+    println!("Monte Carlo ({} resamplings of the trades):", result.simulations);
+    for (i, pct) in montecarlo::PERCENTILES.iter().enumerate() {
+        println!(
+            "  {:>2}th percentile: final equity {}, max drawdown {}",
+            pct,
+            format.money(result.final_equity[i], &config.symbol.quote),
+            format.percent(result.max_drawdown_pct[i], 2)
+        );
+    }
+}
+
+fn print_acceptance(verdict: &acceptance::Verdict) {
+    if verdict.passed {
+        println!("Acceptance criteria: passed");
+        return;
+    }
+    println!("Acceptance criteria: failed");
+    for failure in verdict.failures.iter() {
+        println!("  {}", failure);
+    }
+}
+
+fn print_end_of_data(recap: &testing::SessionRecap, config: &config::Config) {
+    let format = locale::Formatter::new(config.locale);
+    for trade in recap.open_trades.iter() {
+        println!("Open trade at end of data (entered {}), unrealized profit: {}", trade.entry_date, format.money(trade.profit.unwrap_or(0.0), &config.symbol.quote));
+    }
+    if let Some(oldest) = recap.open_lots.iter().map(|lot| lot.entry_date).min() {
+        let unrealized: f64 = recap.open_lots.iter().filter_map(|lot| lot.profit).sum();
+        println!("{} lots open at end of data (oldest entered {}), unrealized profit: {}", recap.open_lots.len(), oldest, format.money(unrealized, &config.symbol.quote));
+    }
+    let forced = recap.trades.iter().find(|trade| trade.exit_reason == Some(testing::ExitReason::EndOfData));
+    if let Some(trade) = forced {
+        let note = match config.end_of_data {
+            config::EndOfData::Exclude => "excluded from metrics",
+            _ => "included in metrics",
+        };
+        println!("Trade entered {} was force-closed at end of data ({})", trade.entry_date, note);
+    }
+}
+
+fn print_monthly_restart(recap: &testing::SessionRecap, config: &config::Config) {
+    let Some(restart) = &recap.monthly_restart else {
+        return;
+    };
+    let format = locale::Formatter::new(config.locale);
+    let quote = config.symbol.quote.as_str();
+    println!("Monthly restart (each month starting flat with {}):", format.money(config.base_funds, quote));
+    println!("  {:<8} {:>7} {:>14} {:>9} {:>9}", "month", "trades", "profit", "return", "drawdown");
+    for month in restart.months.iter() {
+        println!(
+            "  {:<8} {:>7} {:>14} {:>9} {:>9}",
+            month.month,
+            month.trades,
+            format.money(month.profit, quote),
+            format.percent(month.return_pct, 2),
+            format.percent(month.max_drawdown, 2)
+        );
+    }
+    println!(
+        "  Monthly return: mean {}, median {}, deviation {}, best {}, worst {}",
+        format.percent(restart.mean_return, 2),
+        format.percent(restart.median_return, 2),
+        format.percent(restart.deviation, 2),
+        format.percent(restart.best_return, 2),
+        format.percent(restart.worst_return, 2)
+    );
+    println!(
+        "  Profitable months: {} of {} ({}), worst drawdown within a month: {}",
+        restart.months.iter().filter(|month| month.profit > 0.0).count(),
+        restart.months.len(),
+        format.percent(restart.positive_share, 1),
+        format.percent(restart.worst_drawdown, 2)
+    );
+}
+
+fn print_outages(recap: &testing::SessionRecap, config: &config::Config) {
+    let Some(impact) = &recap.outage_impact else {
+        return;
+    };
+    println!("Outages: {} windows, {} candles without data or fills", impact.windows.len(), impact.candles);
+    for (start, end) in impact.windows.iter() {
+        println!("  {} to {}", start, end);
+    }
+    let format = locale::Formatter::new(config.locale);
+    println!(
+        "Without the outages: {} trades, profit {}, max drawdown {}. Impact of the outages on the profit: {}{}",
+        impact.without.total_trades,
+        format.money(impact.without.total_profit, &config.symbol.quote),
+        format.money(impact.without.max_drawdown, &config.symbol.quote),
+        if impact.profit_impact >= 0.0 { "+" } else { "" },
+        format.money(impact.profit_impact, &config.symbol.quote)
+    );
+}
+
+fn print_tax(recap: &testing::SessionRecap, config: &config::Config) {
+    let Some(tax_config) = &config.tax else {
+        return;
+    };
+    let report = tax::report(recap, tax_config);
+    let format = locale::Formatter::new(config.locale);
+    println!("Realized gains ({:?}, long-term after {} days):", report.method, tax_config.long_term_days);
+    println!("  {:>4} {:>9} {:>14} {:>14} {:>12} {:>12}", "year", "disposals", "proceeds", "cost", "short-term", "long-term");
+    for year in report.years.iter() {
+        println!(
+            "  {:>4} {:>9} {:>14} {:>14} {:>12} {:>12}",
+            year.year,
+            year.disposals,
+            format.number(year.proceeds, 2),
+            format.number(year.cost, 2),
+            format.number(year.short_term_gain, 2),
+            format.number(year.long_term_gain, 2)
+        );
+    }
+    if report.open_lots > 0 {
+        println!("  {} lots still held, their gains are not realized", report.open_lots);
+    }
+}
+
+/// Formats a duration in seconds as days and hours, like `3d 4h`.
+fn format_duration(seconds: i64) -> String {
+    format!("{}d {}h", seconds / 86400, seconds % 86400 / 3600)
+}
+
+fn print_concentration(recap: &testing::SessionRecap, config: &config::Config) {
+    let concentration = analysis::profit_concentration(recap, config.concentration_weeks);
+    if concentration.best_weeks.is_empty() {
+        return;
+    }
+    let format = locale::Formatter::new(config.locale);
+    let weeks: Vec<String> = concentration.best_weeks.iter().map(|(week, profit)| format!("{}: {}", week, format.number(*profit, 2))).collect();
+    println!("Best {} weeks: {} {}", concentration.best_weeks.len(), format.list(&weeks), config.symbol.quote);
+    if recap.metrics.total_profit > 0.0 {
+        println!("Share of the profit earned in the best {} weeks: {}", concentration.best_weeks.len(), format.percent(concentration.best_weeks_share * 100.0, 2));
+    }
+    if let Some((start, duration)) = concentration.longest_flat_period {
+        println!("Longest period without a position: {} from {}", format_duration(duration), start);
+    }
+    if let Some((median, p90, longest)) = concentration.entry_gaps {
+        println!("Time between entries: median {}, 90th percentile {}, longest {}", format_duration(median), format_duration(p90), format_duration(longest));
+    }
+    if concentration.single_event {
+        println!("Warning: more than half of the profit was earned in a single week, the edge may be a single event");
+    }
+}
+
+fn print_regimes(recap: &testing::SessionRecap, config: &config::Config) {
+    if recap.regime_breakdown.is_empty() {
+        return;
+    }
+    let candles: usize = recap.regime_breakdown.iter().map(|breakdown| breakdown.candles).sum();
+    let format = locale::Formatter::new(config.locale);
+    println!("Results by regime:");
+    println!("  {:<16} {:>8} {:>7} {:>12} {:>9} {:>8} {:>14} {:>7} {:>13} {:>14}", "regime", "candles", "trades", "profit", "win rate", "PF", "max drawdown", "Sharpe", "equity change", "drawdown share");
+    for breakdown in recap.regime_breakdown.iter() {
+        let metrics = &breakdown.metrics;
+        println!(
+            "  {:<16} {:>8} {:>7} {:>12} {:>9} {:>8} {:>14} {:>7} {:>13} {:>14}",
+            breakdown.regime.to_string(),
+            format.percent(breakdown.candles as f64 / candles as f64 * 100.0, 1),
+            metrics.total_trades,
+            format.number(metrics.total_profit, 2),
+            format.percent(metrics.win_rate * 100.0, 2),
+            format.number(metrics.profit_factor, 2),
+            format.number(metrics.max_drawdown, 2),
+            format.number(metrics.sharpe_ratio, 2),
+            format.number(breakdown.equity_change, 2),
+            format.percent(breakdown.drawdown_share * 100.0, 1)
+        );
+    }
+    println!("  (in {}, trades counted in the regime of their entry, equity change and drawdown share over the candles of the regime)", config.symbol.quote);
+}
+
+fn print_reporting(recap: &testing::SessionRecap, config: &config::Config) {
+    let Some(reporting) = &recap.reporting else {
+        return;
+    };
+    let format = locale::Formatter::new(config.locale);
+    let currency = reporting.currency.as_str();
+    let metrics = &reporting.metrics;
+    println!("Results in {}:", currency);
+    println!(
+        "Initial capital: {}, final equity: {} ({}, the {} moved {} against the {})",
+        format.money(reporting.initial_capital, currency),
+        format.money(reporting.final_equity, currency),
+        format.percent(reporting.total_return, 2),
+        config.symbol.quote,
+        format.percent(reporting.currency_return, 2),
+        currency
+    );
+    println!("Total profit: {}", format.money(metrics.total_profit, currency));
+    println!("Total commission: {}", format.money(metrics.total_commission, currency));
+    println!("Max drawdown: {} ({})", format.money(metrics.max_drawdown, currency), format.percent(metrics.percent_of_initial.max_drawdown, 2));
+    println!("Sharpe ratio: {}", format.number(metrics.sharpe_ratio, 2));
+    println!("Profit factor: {}", format.number(metrics.profit_factor, 2));
+}
+
+fn print_metrics(recap: &testing::SessionRecap, config: &config::Config) {
+    let metrics = &recap.metrics;
+    let format = locale::Formatter::new(config.locale);
+    let quote = config.symbol.quote.as_str();
+    // an amount followed by its percentages of the initial and of the peak equity
+    let amount = |value: f64, of_initial: f64, of_peak: f64| {
+        format!("{} ({} of initial equity, {} of peak equity)", format.money(value, quote), format.percent(of_initial, 2), format.percent(of_peak, 2))
+    };
+    let (initial, peak) = (&metrics.percent_of_initial, &metrics.percent_of_peak);
+    println!("Backtest results on {}:", config.symbol);
+    for fingerprint in recap.fingerprints.iter() {
+        println!("Strategy: {}, {}", fingerprint.strategy, fingerprint.fingerprint);
+    }
     println!("Total trades: {}", metrics.total_trades);
-    println!("Total profit: {:.2} {} ({:.2}%)", metrics.total_profit, config.quote_currency, profit_percentage);
-    println!("Total commission: {:.2} {}", metrics.total_commission, config.quote_currency);
-    println!("Win rate: {:.2}%", metrics.win_rate * 100.0);
-    println!("Average profit: {:.2} {}", metrics.avg_profit, config.quote_currency);
-    println!("Average loss: {:.2} {}", metrics.avg_loss, config.quote_currency);
-    println!("Max drawdown: {:.2} {} ({:.2}%)", metrics.max_drawdown, config.quote_currency, max_drawdown_percentage);
+    println!("Total profit: {}", amount(metrics.total_profit, initial.total_profit, peak.total_profit));
+    if let Some(fx) = &config.fx {
+        let pips = analysis::pip_summary(&recap.trades, fx.pip(&config.symbol));
+        println!(
+            "Total pips: {} (average {} per trade, best {}, worst {})",
+            format.number(pips.total, 1),
+            format.number(pips.average, 1),
+            format.number(pips.best, 1),
+            format.number(pips.worst, 1)
+        );
+    }
+    println!("Total commission: {}", amount(metrics.total_commission, initial.total_commission, peak.total_commission));
+    if config.borrow_rate > 0.0 {
+        println!("Total financing: {}", amount(metrics.total_financing, initial.total_financing, peak.total_financing));
+    }
+    println!("Win rate: {}", format.percent(metrics.win_rate * 100.0, 2));
+    println!("Average profit: {}", amount(metrics.avg_profit, initial.avg_profit, peak.avg_profit));
+    println!("Average loss: {}", amount(metrics.avg_loss, initial.avg_loss, peak.avg_loss));
+    println!("Max drawdown: {}", amount(metrics.max_drawdown, initial.max_drawdown, peak.max_drawdown));
     println!("Max drawdown duration: {} ({} timeframe)", metrics.max_drawdown_duration, config.timeframe);
+    println!("Sharpe ratio: {}", format.number(metrics.sharpe_ratio, 2));
+    if !config.cash_flows.is_empty() {
+        println!("Net deposits: {}", amount(metrics.net_deposits, initial.net_deposits, peak.net_deposits));
+        println!("Time-weighted return: {}", format.percent(metrics.time_weighted_return, 2));
+        println!("Money-weighted return: {} a year", format.percent(metrics.money_weighted_return, 2));
+    }
+    println!("Profit factor: {}", format.number(metrics.profit_factor, 2));
+    if metrics.ambiguous_exits > 0 {
+        println!("Ambiguous stop-loss/take-profit exits: {} ({:?} assumption)", metrics.ambiguous_exits, config.intrabar_assumption);
+    }
+    if metrics.requotes > 0 {
+        println!("Requoted entries: {}", metrics.requotes);
+    }
+    let reasons: Vec<String> = metrics.skipped_by_reason.iter().map(|(reason, count)| format!("{}: {}", reason, count)).collect();
+    if reasons.is_empty() {
+        println!("Skipped signals: 0");
+    } else {
+        println!("Skipped signals: {} ({})", metrics.skipped_signals, reasons.join(", "));
+    }
 }
 
 fn main() {
-    let config = config::read_config("config.json");
-    let klines = match historical::read_klines(&config.data_path, config.get_headers()) {
+    let args = cli::parse_args(std::env::args().skip(1));
+    match args.command.clone() {
+        cli::Command::Backtest => backtest(&args),
+        cli::Command::ConfigValidate => validate_config(&args),
+        cli::Command::ConfigSchema => println!("{}", config::config_schema()),
+        cli::Command::Init { directory } => {
+            if let Err(e) = init::init_project(&directory) {
+                eprintln!("Error while creating project: {}", e);
+                std::process::exit(1);
+            }
+        }
+        cli::Command::NewStrategy { name } => {
+            if let Err(e) = init::new_strategy(&name) {
+                eprintln!("Error while creating strategy: {}", e);
+                std::process::exit(1);
+            }
+        }
+        cli::Command::Worker { address } => worker(&args, &address),
+        cli::Command::AnalyzeSeasonality => analyze_seasonality(&args),
+        cli::Command::CalibrateSlippage { book_path, notional, output } => calibrate_slippage(&book_path, notional, &output),
+    }
+}
+
+fn print_seasonal_buckets(title: &str, buckets: &[analysis::SeasonalBucket], format: &locale::Formatter) {
+    println!("{}:", title);
+    println!("  {:<6} {:>8} {:>12} {:>8} {:>7}", "", "candles", "mean return", "up", "t-stat");
+    for bucket in buckets.iter() {
+        println!(
+            "  {:<6} {:>8} {:>12} {:>8} {:>7}",
+            bucket.label,
+            bucket.candles,
+            format.percent(bucket.mean_return, 4),
+            format.percent(bucket.up_share, 2),
+            format.number(bucket.t_stat, 2)
+        );
+    }
+}
+
+fn analyze_seasonality(args: &cli::Args) {
+    let config = config::read_config(&args.config_path, args.profile.as_deref(), &args.overrides);
+    let klines = match historical::read_data(&config) {
         Ok(klines) => klines,
         Err(e) => panic!("Error while reading klines: {:?}", e),
+    };
+    let seasonality = analysis::seasonality(&klines);
+    if config.headless {
+        println!("{}", serde_json::to_string_pretty(&seasonality).expect("error while serializing seasonality"));
+        return;
+    }
+    println!("Seasonality of {} ({}, {} candles), close-to-close returns of the candles:", config.symbol, config.timeframe, klines.len());
+    let format = locale::Formatter::new(config.locale);
+    print_seasonal_buckets("By hour", &seasonality.by_hour, &format);
+    print_seasonal_buckets("By weekday", &seasonality.by_weekday, &format);
+    print_seasonal_buckets("By month", &seasonality.by_month, &format);
+}
+
+fn calibrate_slippage(book_path: &str, notional: f64, output: &str) {
+    let snapshots = match calibration::read_book(book_path) {
+        Ok(snapshots) => snapshots,
+        Err(e) => {
+            eprintln!("Error while reading order book: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let estimates = calibration::calibrate(&snapshots, notional);
+    println!("Slippage of market orders on {} order book snapshots, from the middle of the book:", snapshots.len());
+    println!("  {:>14} {:>8} {:>10} {:>10} {:>10}", "notional", "orders", "too thin", "mean", "95th pct");
+    for estimate in estimates.iter() {
+        println!(
+            "  {:>14.2} {:>8} {:>10} {:>9.4}% {:>9.4}%",
+            estimate.notional,
+            estimate.orders,
+            estimate.too_thin,
+            estimate.mean * 100.0,
+            estimate.p95 * 100.0
+        );
+    }
+    let model = calibration::model(&estimates);
+    if model.points.is_empty() {
+        eprintln!("No snapshot is deep enough to fill the orders, no model written");
+        std::process::exit(1);
+    }
+    let json = serde_json::json!({ "slippage_model": model });
+    std::fs::write(output, serde_json::to_string_pretty(&json).unwrap()).expect("Error while writing slippage model");
+    println!("Wrote the slippage model to {}, add it to the config or `extends` it.", output);
+}
 
+fn worker(args: &cli::Args, address: &str) {
+    let config = config::read_config(&args.config_path, args.profile.as_deref(), &args.overrides);
+    let klines = match historical::read_data(&config) {
+        Ok(klines) => klines,
+        Err(e) => panic!("Error while reading klines: {:?}", e),
     };
-    // check if log file already exists
-    if std::path::Path::new(&config.log_file).exists() {
-        std::fs::remove_file(&config.log_file).expect("Error while removing log file");
+    match distributed::run_worker(address, &config, &klines) {
+        Ok(count) => println!("Ran {} variants for {}", count, address),
+        Err(e) => {
+            eprintln!("Worker stopped: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn validate_config(args: &cli::Args) {
+    match config::load_config(&args.config_path, args.profile.as_deref(), &args.overrides) {
+        Ok(_) => println!("{} is valid", args.config_path),
+        Err(e) => {
+            eprintln!("{} is invalid: {}", args.config_path, e);
+            std::process::exit(1);
+        }
     }
-    let recap = testing::run_simulation(&config, &klines);
-    print_metrics(&recap.metrics, &config);
-    match config.log_graph {
-        true => graphing::plot_graph(&config, &klines, recap).unwrap(),
-        false => (),
+}
+
+/// Thins the candles for `--fast`, the merged candles of a stride making the timeframe that many times longer.
+fn fast_klines(config: &mut config::Config, klines: Vec<historical::Kline>) -> Vec<historical::Kline> {
+    let sampling = &config.fast_sampling;
+    let thinned = historical::thin(&klines, sampling);
+    let description = match sampling.sample {
+        Some(share) => format!("{} of the {} candles drawn at random ({}%, seed {})", thinned.len(), klines.len(), share * 100.0, sampling.seed),
+        None => {
+            let (amount, unit) = config.timeframe.split_at(config.timeframe.len().saturating_sub(1));
+            let timeframe = format!("{}{}", amount.parse().unwrap_or(1) * sampling.stride, unit);
+            let description = format!("the {} candles merged {} by {} into {} candles of {}", klines.len(), sampling.stride, sampling.stride, thinned.len(), timeframe);
+            config.timeframe = timeframe;
+            description
+        }
     };
+    match (&config.log_level, config.headless) {
+        (config::LogLevel::None, _) | (_, true) => {}
+        _ => {
+            println!("FAST MODE: running on {}.", description);
+            println!("The results are only a rough read: the strategy sees different candles, stops are checked on fewer of them and the trades differ. Run without --fast before relying on them.");
+        }
+    }
+    thinned
+}
+
+fn backtest(args: &cli::Args) {
+    let mut config = config::read_config(&args.config_path, args.profile.as_deref(), &args.overrides);
+    // checked again with the options of the command line
+    config.dashboard = args.dashboard;
+    config.validate().unwrap_or_else(|e| panic!("Error while reading config: {}", e));
+    let run_directory = artifacts::RunDirectory::create(&mut config).expect("Error while creating run directory");
+    if config.log_append && config.run_id.is_none() {
+        // the process id tells apart runs started in the same second
+        config.run_id = Some(format!("{}-{}", chrono::Local::now().format("%Y%m%d-%H%M%S"), std::process::id()));
+    }
+    let klines = match historical::read_data(&config) {
+        Ok(klines) => klines,
+        Err(e) => panic!("Error while reading klines: {:?}", e),
+
+    };
+    let klines = if config.fast { fast_klines(&mut config, klines) } else { klines };
+    if config.dashboard {
+        dashboard::serve(&config).unwrap_or_else(|e| panic!("Error while starting the dashboard on {}: {}", config.dashboard_address, e));
+        if !config.headless {
+            println!("Dashboard of the run on http://{}", config.dashboard_address);
+        }
+    }
+    let recap = testing::run_backtest(&config, &klines, &mut |_| {}).unwrap_or_else(|e| panic!("Error while running backtest: {}", e));
+    let verdict = acceptance::evaluate(&recap.metrics, &config);
+    let format = locale::Formatter::new(config.locale);
+    if let Some(path) = &config.indicators_file {
+        analysis::write_indicators_csv(&recap, &klines, path, &format).expect("Error while writing indicators file");
+    }
+    if let Some(path) = &config.trades_file {
+        analysis::write_trades_csv(&recap, path, &format).expect("Error while writing trades file");
+    }
+    if let Some(tax_config) = &config.tax {
+        if let Some(path) = &tax_config.report_file {
+            tax::write_disposals_csv(&tax::report(&recap, tax_config), path, &format).expect("Error while writing tax report");
+        }
+    }
+    if config.headless {
+        print_json_summary(&recap, &config, &verdict);
+        // no graph is drawn in headless runs
+        if let Some(path) = &config.trade_book_file {
+            tradebook::write_trade_book(&config, &recap, path, false).expect("Error while writing trade book");
+        }
+        if let Some(run_directory) = &run_directory {
+            run_directory.write_manifest(&config, &recap, &verdict).expect("Error while writing run manifest");
+        }
+        if !config.acceptance.is_empty() && !verdict.passed {
+            std::process::exit(1);
+        }
+        return;
+    }
+    print_sweep(&recap, &config);
+    print_metrics(&recap, &config);
+    print_reporting(&recap, &config);
+    print_balances(&recap, &config);
+    print_execution(&recap, &config);
+    print_concentration(&recap, &config);
+    print_regimes(&recap, &config);
+    print_contributions(&recap, &config);
+    if config.benchmark.is_some() {
+        print_benchmark(&recap, &config);
+    }
+    print_correlations(&recap, &config);
+    print_risk_events(&recap, &config);
+    print_end_of_data(&recap, &config);
+    print_outages(&recap, &config);
+    print_monthly_restart(&recap, &config);
+    print_tax(&recap, &config);
+    print_extension(&recap, &config);
+    print_exit_analysis(&recap, &config);
+    print_forward_returns(&recap, &config);
+    print_signal_quality(&recap, &config);
+    print_monte_carlo(&recap, &config);
+    if !config.acceptance.is_empty() {
+        print_acceptance(&verdict);
+    }
+    if config.log_graph || config.chart_range.is_some() || config.trade_chart_context.is_some() || config.animation.is_some() {
+        if let Err(e) = graphing::plot_graph(&config, &klines, &recap) {
+            eprintln!("Error while drawing graphs: {}", e);
+            std::process::exit(1);
+        }
+    }
+    // after the graphs, whose trade graphs it shows
+    if let Some(path) = &config.trade_book_file {
+        tradebook::write_trade_book(&config, &recap, path, config.trade_chart_context.is_some()).expect("Error while writing trade book");
+        println!("Trade book saved to {}", path);
+    }
+    if let Some(run_directory) = &run_directory {
+        run_directory.write_manifest(&config, &recap, &verdict).expect("Error while writing run manifest");
+        println!("Run artifacts saved to {}", run_directory.path.display());
+    }
+    if !config.acceptance.is_empty() && !verdict.passed {
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,136 @@
+use serde::{Deserialize, Serialize};
+use crate::testing::Trade;
+
+/// Percentiles reported for every step of the resampled equity paths.
+pub const PERCENTILES: [f64; 5] = [5.0, 25.0, 50.0, 75.0, 95.0];
+
+/// Spread of the results when the closed trades happen in another order, or some of them not at all.
+#[derive(Debug, Serialize)]
+pub struct MonteCarloResult {
+    pub simulations: usize,
+    /// One curve per entry of `PERCENTILES`, with the equity after each trade, starting with the initial funds.
+    pub equity_bands: Vec<Vec<f64>>,
+    /// Final equity at each entry of `PERCENTILES`.
+    pub final_equity: Vec<f64>,
+    /// Max drawdown in percentage of the peak equity at each entry of `PERCENTILES`, the worst ones last.
+    pub max_drawdown_pct: Vec<f64>,
+}
+
+/// A small linear congruential generator, so results only depend on the seed.
+#[derive(Serialize, Deserialize)]
+pub struct Random(u64);
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        self.0 >> 33
+    }
+
+    /// A number between 0 and `len` excluded.
+    pub fn index(&mut self, len: usize) -> usize {
+        self.next() as usize % len
+    }
+
+    /// A number between 0 and 1.
+    pub fn unit(&mut self) -> f64 {
+        self.next() as f64 / (1u64 << 31) as f64
+    }
+
+    /// A number drawn from the standard normal distribution (Box-Muller).
+    pub fn normal(&mut self) -> f64 {
+        // 1 - unit is never 0, so its logarithm is finite
+        (-2.0 * (1.0 - self.unit()).ln()).sqrt() * (2.0 * std::f64::consts::PI * self.unit()).cos()
+    }
+}
+
+pub fn percentile(sorted: &[f64], pct: f64) -> f64 {
+    let index = (pct / 100.0 * (sorted.len() - 1) as f64).round() as usize;
+    sorted[index]
+}
+
+/// Bootstraps the trades: every simulation draws as many trades as were closed, with replacement, and compounds
+/// their returns on the initial funds. The same seed always gives the same result.
+pub fn simulate(trades: &[Trade], base_funds: f64, simulations: usize, seed: u64) -> Option<MonteCarloResult> {
+    // returns on the allocated amount, so resampled trades keep the sizing of the run
+    let returns: Vec<(f64, f64)> = trades
+        .iter()
+        .filter_map(|trade| trade.profit.map(|profit| (profit / trade.allocated, trade.allocated / base_funds)))
+        .collect();
+    if returns.is_empty() || simulations == 0 {
+        return None;
+    }
+    let mut random = Random(seed);
+    let mut paths: Vec<Vec<f64>> = Vec::with_capacity(simulations);
+    let mut drawdowns: Vec<f64> = Vec::with_capacity(simulations);
+    for _ in 0..simulations {
+        let mut equity = base_funds;
+        let mut peak = base_funds;
+        let mut max_drawdown: f64 = 0.0;
+        let mut path = Vec::with_capacity(returns.len() + 1);
+        path.push(equity);
+        for _ in 0..returns.len() {
+            let (trade_return, exposure) = returns[random.index(returns.len())];
+            equity += equity * exposure * trade_return;
+            peak = peak.max(equity);
+            max_drawdown = max_drawdown.max((peak - equity) / peak * 100.0);
+            path.push(equity);
+        }
+        paths.push(path);
+        drawdowns.push(max_drawdown);
+    }
+    let equity_bands: Vec<Vec<f64>> = PERCENTILES
+        .iter()
+        .map(|pct| {
+            (0..=returns.len())
+                .map(|step| {
+                    let mut values: Vec<f64> = paths.iter().map(|path| path[step]).collect();
+                    values.sort_by(|a, b| a.total_cmp(b));
+                    percentile(&values, *pct)
+                })
+                .collect()
+        })
+        .collect();
+    drawdowns.sort_by(|a, b| a.total_cmp(b));
+    Some(MonteCarloResult {
+        simulations,
+        final_equity: equity_bands.iter().map(|band| *band.last().unwrap()).collect(),
+        max_drawdown_pct: PERCENTILES.iter().map(|pct| percentile(&drawdowns, *pct)).collect(),
+        equity_bands,
+    })
+}
+
+/// White's reality check: the probability that the best of several variants only beats not trading by luck.
+/// Takes the per-candle returns of each variant (over the same candles), and resamples them with the stationary
+/// bootstrap, in blocks of `block_length` candles on average so volatility clusters are kept.
+pub fn reality_check(returns: &[Vec<f64>], samples: usize, block_length: usize, seed: u64) -> Option<f64> {
+    let length = returns.iter().map(|series| series.len()).min()?;
+    if length == 0 || samples == 0 {
+        return None;
+    }
+    let means: Vec<f64> = returns.iter().map(|series| series[..length].iter().sum::<f64>() / length as f64).collect();
+    let scale = (length as f64).sqrt();
+    let best = means.iter().fold(f64::MIN, |best, mean| best.max(mean * scale));
+    let mut random = Random(seed);
+    let restart = 1.0 / block_length.max(1) as f64;
+    let mut exceeded = 0;
+    for _ in 0..samples {
+        let mut sums = vec![0.0; returns.len()];
+        let mut index = random.index(length);
+        for _ in 0..length {
+            for (sum, series) in sums.iter_mut().zip(returns.iter()) {
+                *sum += series[index];
+            }
+            index = if random.unit() < restart { random.index(length) } else { (index + 1) % length };
+        }
+        // the resampled means are centered on the observed ones, as if no variant had an edge
+        let resampled_best = sums.iter().zip(means.iter()).fold(f64::MIN, |best, (sum, mean)| best.max((sum / length as f64 - mean) * scale));
+        if resampled_best >= best {
+            exceeded += 1;
+        }
+    }
+    Some(exceeded as f64 / samples as f64)
+}
@@ -0,0 +1,184 @@
+//! Bayesian search of the parameters of a sweep with a tree-structured Parzen estimator (TPE). The trials are split
+//! between the best quarter and the others, and each new trial tries the values that are the most likely among the
+//! best ones compared to the others. Every parameter is modelled on its own.
+
+use crate::checkpoint::SweepEntry;
+use crate::config::{Prior, StrategyConfig, Sweep};
+use crate::montecarlo::Random;
+use crate::strategy::{ParameterKind, ParameterSpec};
+
+/// Share of the trials considered good.
+const GAMMA: f64 = 0.25;
+/// Number of candidates drawn around the good trials for each new trial, the most promising one being tried.
+const CANDIDATES: usize = 24;
+
+/// A range of `Sweep::ranges`, with the bounds and the type declared by the strategy filled in.
+#[derive(Debug, Clone)]
+pub struct ParameterRange {
+    pub min: f64,
+    pub max: f64,
+    pub integer: bool,
+    pub prior: Option<Prior>,
+}
+
+/// Fills the ranges of the sweep in from the parameters the strategy declares, if it does. Ranges of undeclared
+/// parameters, or reaching out of the declared bounds, are refused.
+pub fn resolve_ranges(sweep: &Sweep, specs: Option<&[ParameterSpec]>) -> Result<Vec<(String, ParameterRange)>, String> {
+    sweep
+        .ranges
+        .iter()
+        .map(|(name, range)| {
+            let spec = match specs {
+                Some(specs) => Some(specs.iter().find(|spec| &spec.name == name).ok_or(format!("{}: not a parameter of {}", name, sweep.name))?),
+                None => None,
+            };
+            if spec.is_some_and(|spec| spec.kind == ParameterKind::Boolean) {
+                return Err(format!("{}: booleans cannot be searched in a range, list their values in `params`", name));
+            }
+            let bound = |value: Option<f64>, declared: Option<f64>, which: &str| {
+                value.or(declared).ok_or(format!("{}: `{}` is needed, the strategy does not declare it", name, which))
+            };
+            let resolved = ParameterRange {
+                min: bound(range.min, spec.and_then(|spec| spec.min), "min")?,
+                max: bound(range.max, spec.and_then(|spec| spec.max), "max")?,
+                integer: range.integer.unwrap_or(spec.is_some_and(|spec| spec.kind == ParameterKind::Integer)),
+                prior: range.prior.clone(),
+            };
+            if resolved.min >= resolved.max {
+                return Err(format!("{}: `min` has to be below `max`", name));
+            }
+            if let Some(spec) = spec {
+                let (min, max) = (spec.min.unwrap_or(f64::NEG_INFINITY), spec.max.unwrap_or(f64::INFINITY));
+                if resolved.min < min || resolved.max > max {
+                    return Err(format!("{}: the strategy only accepts values from {} to {}", name, min, max));
+                }
+                if spec.kind == ParameterKind::Integer && !resolved.integer {
+                    return Err(format!("{}: the strategy only accepts integers", name));
+                }
+            }
+            Ok((name.clone(), resolved))
+        })
+        .collect()
+}
+
+fn normal_density(x: f64, mean: f64, std: f64) -> f64 {
+    (-0.5 * ((x - mean) / std).powi(2)).exp() / (std * (2.0 * std::f64::consts::PI).sqrt())
+}
+
+/// Clamps a value to the range, rounding it for integer parameters.
+fn fit(range: &ParameterRange, x: f64) -> f64 {
+    let x = x.clamp(range.min, range.max);
+    if range.integer {
+        x.round()
+    } else {
+        x
+    }
+}
+
+fn prior_density(range: &ParameterRange, x: f64) -> f64 {
+    match &range.prior {
+        Some(prior) => normal_density(x, prior.mean, prior.std),
+        None => 1.0 / (range.max - range.min),
+    }
+}
+
+fn sample_prior(range: &ParameterRange, random: &mut Random) -> f64 {
+    match &range.prior {
+        Some(prior) => fit(range, prior.mean + prior.std * random.normal()),
+        None => fit(range, range.min + random.unit() * (range.max - range.min)),
+    }
+}
+
+/// Width of the bumps around the observed values, narrower as they get more numerous.
+fn bandwidth(range: &ParameterRange, count: usize) -> f64 {
+    0.2 * (range.max - range.min) * (count.max(1) as f64).powf(-0.2)
+}
+
+/// Density of a parameter over a set of trials: a bump around each of their values, and the prior.
+fn density(range: &ParameterRange, values: &[f64], x: f64) -> f64 {
+    let width = bandwidth(range, values.len());
+    let bumps: f64 = values.iter().map(|value| normal_density(x, *value, width)).sum();
+    // never 0, so the logarithm of the ratio is finite
+    (bumps + prior_density(range, x)) / (values.len() + 1) as f64 + f64::MIN_POSITIVE
+}
+
+/// Proposes the values of the next trial, in the order of `ranges`, from the values and the scores (higher is
+/// better) of the trials so far. The first `initial_trials` are drawn from the priors.
+pub fn propose(ranges: &[&ParameterRange], trials: &[(Vec<f64>, f64)], initial_trials: usize, random: &mut Random) -> Vec<f64> {
+    if trials.len() < initial_trials.max(2) {
+        return ranges.iter().map(|range| sample_prior(range, random)).collect();
+    }
+    let mut sorted: Vec<&(Vec<f64>, f64)> = trials.iter().collect();
+    sorted.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    let (good, bad) = sorted.split_at(((trials.len() as f64 * GAMMA).ceil() as usize).max(1));
+    let column = |trials: &[&(Vec<f64>, f64)], index: usize| -> Vec<f64> { trials.iter().map(|(values, _)| values[index]).collect() };
+    let mut best = (f64::NEG_INFINITY, Vec::new());
+    for _ in 0..CANDIDATES {
+        let mut candidate = Vec::new();
+        let mut score = 0.0;
+        for (index, range) in ranges.iter().enumerate() {
+            let (good, bad) = (column(good, index), column(bad, index));
+            // drawn from the density of the good trials: around one of their values, or from the prior
+            let pick = random.index(good.len() + 1);
+            let x = match good.get(pick) {
+                Some(value) => fit(range, value + bandwidth(range, good.len()) * random.normal()),
+                None => sample_prior(range, random),
+            };
+            score += density(range, &good, x).ln() - density(range, &bad, x).ln();
+            candidate.push(x);
+        }
+        if score > best.0 {
+            best = (score, candidate);
+        }
+    }
+    best.1
+}
+
+/// Runs `trials` new trials over the `ranges` of the sweep (see `resolve_ranges`), calling `run` for each of them,
+/// and returns them after the `previous` ones they were informed by (the entries of the progress file, or the
+/// trials of an earlier run). Previous entries of another strategy, with other fixed parameters or outside of the
+/// ranges are left out. A proposal that was already tried counts as a trial, but is not run again.
+pub fn search(
+    sweep: &Sweep,
+    ranges: &[(String, ParameterRange)],
+    trials: usize,
+    initial_trials: usize,
+    previous: Vec<SweepEntry>,
+    run: &mut dyn FnMut(StrategyConfig) -> SweepEntry,
+    score: &dyn Fn(&SweepEntry) -> f64,
+) -> Vec<SweepEntry> {
+    // both checked by `strategy::check_config`
+    let [base] = &sweep.variants()[..] else {
+        panic!("with an optimizer, the parameters to search go in `ranges`, and `params` cannot hold lists of values");
+    };
+    if ranges.is_empty() {
+        panic!("the optimizer needs at least one parameter in `ranges`");
+    }
+    let (names, ranges): (Vec<&String>, Vec<&ParameterRange>) = ranges.iter().map(|(name, range)| (name, range)).unzip();
+    let values = |parameters: &StrategyConfig| -> Option<Vec<f64>> {
+        if parameters.name != base.name {
+            return None;
+        }
+        let fixed = base.params.as_object()?.iter().all(|(name, value)| parameters.params.get(name) == Some(value));
+        let values: Option<Vec<f64>> = names.iter().map(|name| parameters.params.get(name.as_str()).and_then(|value| value.as_f64())).collect();
+        values.filter(|values| fixed && values.iter().zip(ranges.iter()).all(|(value, range)| (range.min..=range.max).contains(value)))
+    };
+    let mut entries: Vec<SweepEntry> = previous.into_iter().filter(|entry| values(&entry.parameters).is_some()).collect();
+    let mut observed: Vec<(Vec<f64>, f64)> = entries.iter().map(|entry| (values(&entry.parameters).unwrap(), score(entry))).collect();
+    let mut random = Random::new(sweep.seed);
+    for _ in 0..trials {
+        let proposal = propose(&ranges, &observed, initial_trials, &mut random);
+        let mut parameters = base.clone();
+        for ((name, range), value) in names.iter().zip(ranges.iter()).zip(proposal.iter()) {
+            let value = if range.integer { serde_json::json!(*value as i64) } else { serde_json::json!(value) };
+            parameters.params[name.as_str()] = value;
+        }
+        if entries.iter().any(|entry| entry.parameters.label() == parameters.label()) {
+            continue;
+        }
+        let entry = run(parameters);
+        observed.push((proposal, score(&entry)));
+        entries.push(entry);
+    }
+    entries
+}
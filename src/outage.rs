@@ -0,0 +1,64 @@
+//! Exchange outages: windows of candles in which the strategy gets no data and no order can be filled, while the
+//! market keeps moving. Open positions stay exposed, stop-losses included, until the exchange is back.
+
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use crate::config::{parse_date_range, OutageConfig};
+use crate::historical::Kline;
+use crate::montecarlo::Random;
+use crate::testing::Metrics;
+
+/// First and last candle of the outages, the fixed ones of the config and the random ones, in order. Windows
+/// without any candle are left out.
+pub fn windows(config: &OutageConfig, klines: &[Kline]) -> Result<Vec<(NaiveDateTime, NaiveDateTime)>, String> {
+    let mut down = vec![false; klines.len()];
+    for window in config.windows.iter() {
+        let (start, end) = parse_date_range(window).map_err(|e| format!("outages.windows: {}", e))?;
+        for (index, kline) in klines.iter().enumerate() {
+            down[index] |= start <= kline.timestamp && kline.timestamp < end;
+        }
+    }
+    let mut random = Random::new(config.seed);
+    let mut remaining = 0;
+    for is_down in down.iter_mut() {
+        // a draw is made for every candle, so the fixed windows do not move the random ones
+        let starts = random.unit() * 100.0 < config.rate;
+        if remaining == 0 && starts && !*is_down {
+            remaining = config.duration;
+        }
+        if remaining > 0 {
+            *is_down = true;
+            remaining -= 1;
+        }
+    }
+    let mut windows: Vec<(NaiveDateTime, NaiveDateTime)> = Vec::new();
+    for (index, kline) in klines.iter().enumerate().filter(|(index, _)| down[*index]) {
+        match windows.last_mut() {
+            Some((_, last)) if index > 0 && down[index - 1] => *last = kline.timestamp,
+            _ => windows.push((kline.timestamp, kline.timestamp)),
+        }
+    }
+    Ok(windows)
+}
+
+/// What the outages cost, compared to the same run with the exchange always up.
+#[derive(Debug, Serialize)]
+pub struct OutageImpact {
+    pub windows: Vec<(NaiveDateTime, NaiveDateTime)>,
+    pub candles: usize,
+    /// Total profit with the outages minus the total profit without them.
+    pub profit_impact: f64,
+    pub without: Metrics,
+}
+
+impl OutageImpact {
+    pub fn new(windows: Vec<(NaiveDateTime, NaiveDateTime)>, klines: &[Kline], with: &Metrics, without: Metrics) -> Self {
+        let candles = klines.iter().filter(|kline| windows.iter().any(|(start, end)| *start <= kline.timestamp && kline.timestamp <= *end)).count();
+        Self {
+            windows,
+            candles,
+            profit_impact: with.total_profit - without.total_profit,
+            without,
+        }
+    }
+}
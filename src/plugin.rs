@@ -0,0 +1,156 @@
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::rc::Rc;
+use libloading::Library;
+use crate::historical::{fnv1a, Kline};
+use crate::strategy::{Signal, Strategy, StrategyFactory};
+
+/// Bumped whenever the functions below or `PluginKline` change. Plugins built for another version are rejected.
+pub const PLUGIN_ABI_VERSION: u32 = 1;
+
+/// A candle as passed to plugins. The timestamp is in seconds since the epoch, UTC.
+#[repr(C)]
+pub struct PluginKline {
+    pub timestamp: i64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64,
+}
+
+type AbiVersionFn = unsafe extern "C" fn() -> u32;
+type NameFn = unsafe extern "C" fn() -> *const c_char;
+type VersionFn = unsafe extern "C" fn() -> *const c_char;
+type CreateFn = unsafe extern "C" fn(params: *const c_char) -> *mut c_void;
+type OnTickFn = unsafe extern "C" fn(strategy: *mut c_void, kline: *const PluginKline) -> i32;
+type DestroyFn = unsafe extern "C" fn(strategy: *mut c_void);
+
+/// A strategy library found in the plugins directory.
+pub struct Plugin {
+    pub name: String,
+    pub path: String,
+    /// The version exported by the plugin if any, and a hash of the library file.
+    pub version: String,
+    library: Rc<Library>,
+}
+
+impl Plugin {
+    /// Loads the library and checks it exports the plugin functions for the current ABI version.
+    fn load(path: &std::path::Path) -> Result<Self, String> {
+        // SAFETY: loading a library runs its initializers, plugins are trusted like the executable itself
+        let library = unsafe { Library::new(path) }.map_err(|e| e.to_string())?;
+        unsafe {
+            let version = library.get::<AbiVersionFn>(b"retroval_plugin_abi_version").map_err(|e| e.to_string())?;
+            let version = version();
+            if version != PLUGIN_ABI_VERSION {
+                return Err(format!("built for plugin ABI version {}, expected {}", version, PLUGIN_ABI_VERSION));
+            }
+            for symbol in [&b"retroval_create_strategy"[..], b"retroval_on_tick", b"retroval_destroy_strategy"] {
+                library.get::<*const c_void>(symbol).map_err(|e| e.to_string())?;
+            }
+            let name = library.get::<NameFn>(b"retroval_plugin_name").map_err(|e| e.to_string())?;
+            let name = name();
+            if name.is_null() {
+                return Err("retroval_plugin_name returned a null pointer".to_string());
+            }
+            let name = CStr::from_ptr(name).to_string_lossy().into_owned();
+            // the version is optional, the hash tells apart builds that forgot to bump it
+            let hash = std::fs::read(path).map(|bytes| format!("library {:016x}", fnv1a(&bytes))).map_err(|e| e.to_string())?;
+            let version = match library.get::<VersionFn>(b"retroval_plugin_version").map(|version| version()) {
+                Ok(version) if !version.is_null() => format!("{} ({})", CStr::from_ptr(version).to_string_lossy(), hash),
+                _ => hash,
+            };
+            Ok(Self {
+                name,
+                path: path.display().to_string(),
+                version,
+                library: Rc::new(library),
+            })
+        }
+    }
+}
+
+impl StrategyFactory for Plugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn version(&self) -> Option<String> {
+        Some(self.version.clone())
+    }
+
+    /// Creates a strategy instance, the parameters are passed to the plugin as a JSON string.
+    fn create(&self, params: &serde_json::Value) -> Box<dyn Strategy> {
+        let params = CString::new(params.to_string()).expect("strategy parameters contain a nul byte");
+        unsafe {
+            let create = self.library.get::<CreateFn>(b"retroval_create_strategy").unwrap();
+            let instance = create(params.as_ptr());
+            if instance.is_null() {
+                panic!("plugin {} refused parameters {}", self.name, params.to_string_lossy());
+            }
+            Box::new(PluginStrategy {
+                on_tick: *self.library.get::<OnTickFn>(b"retroval_on_tick").unwrap(),
+                destroy: *self.library.get::<DestroyFn>(b"retroval_destroy_strategy").unwrap(),
+                instance,
+                _library: self.library.clone(),
+            })
+        }
+    }
+}
+
+/// A strategy living in a plugin. The library is kept loaded as long as one of its instances exists.
+struct PluginStrategy {
+    on_tick: OnTickFn,
+    destroy: DestroyFn,
+    instance: *mut c_void,
+    _library: Rc<Library>,
+}
+
+impl Strategy for PluginStrategy {
+    fn on_tick(&mut self, kline: &Kline) -> Option<Signal> {
+        let kline = PluginKline {
+            timestamp: kline.timestamp.and_utc().timestamp(),
+            open: kline.open,
+            high: kline.high,
+            low: kline.low,
+            close: kline.close,
+            volume: kline.volume,
+        };
+        match unsafe { (self.on_tick)(self.instance, &kline) } {
+            0 => Some(Signal::Hold),
+            1 => Some(Signal::Buy),
+            2 => Some(Signal::Sell),
+            _ => None,
+        }
+    }
+}
+
+impl Drop for PluginStrategy {
+    fn drop(&mut self) {
+        unsafe { (self.destroy)(self.instance) }
+    }
+}
+
+/// Loads every shared library of the directory. Files that are not valid plugins are reported and skipped.
+pub fn load_plugins(directory: &str) -> Vec<Plugin> {
+    let entries = std::fs::read_dir(directory).unwrap_or_else(|e| panic!("Error while reading plugins directory {}: {}", directory, e));
+    let mut paths: Vec<std::path::PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == std::env::consts::DLL_EXTENSION))
+        .collect();
+    paths.sort();
+    let mut plugins: Vec<Plugin> = Vec::new();
+    for path in paths {
+        match Plugin::load(&path) {
+            Ok(plugin) => {
+                if let Some(other) = plugins.iter().find(|other| other.name == plugin.name) {
+                    eprintln!("Skipping plugin {}: strategy {} is already provided by {}", plugin.path, plugin.name, other.path);
+                } else {
+                    plugins.push(plugin);
+                }
+            }
+            Err(e) => eprintln!("Skipping plugin {}: {}", path.display(), e),
+        }
+    }
+    plugins
+}
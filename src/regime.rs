@@ -0,0 +1,223 @@
+//! Market regimes: every candle is classified as trending, ranging or in high volatility, so the results of a strategy
+//! can be broken down by the kind of market they were made in. The volatility is compared to its own history up to
+//! the candle, never to what comes after.
+
+use std::collections::VecDeque;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use crate::config::RegimeConfig;
+use crate::historical::Kline;
+use crate::indicators::Adx;
+use crate::testing::{Metrics, Trade};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Regime {
+    Trending,
+    Ranging,
+    HighVolatility,
+}
+
+impl std::fmt::Display for Regime {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Regime::Trending => write!(f, "trending"),
+            Regime::Ranging => write!(f, "ranging"),
+            Regime::HighVolatility => write!(f, "high_volatility"),
+        }
+    }
+}
+
+pub const REGIMES: [Regime; 3] = [Regime::Trending, Regime::Ranging, Regime::HighVolatility];
+
+/// Classifies candles one at a time: high volatility when the realized volatility is at or above the configured
+/// percentile of the ones of the last `volatility_lookback` candles, otherwise trending when the ADX is above its
+/// threshold, and ranging below.
+#[derive(Serialize, Deserialize)]
+pub struct RegimeClassifier {
+    adx_threshold: f64,
+    volatility_window: usize,
+    volatility_percentile: f64,
+    #[serde(default = "default_volatility_lookback")]
+    volatility_lookback: usize,
+    adx: Adx,
+    closes: VecDeque<f64>,
+    volatilities: VecDeque<f64>,
+}
+
+/// The lookback of checkpoints saved before it was configurable.
+fn default_volatility_lookback() -> usize {
+    2000
+}
+
+impl RegimeClassifier {
+    pub fn new(config: &RegimeConfig) -> Self {
+        Self {
+            adx_threshold: config.adx_threshold,
+            volatility_window: config.volatility_window,
+            volatility_percentile: config.volatility_percentile,
+            volatility_lookback: config.volatility_lookback,
+            adx: Adx::new(config.adx_window),
+            closes: VecDeque::new(),
+            volatilities: VecDeque::new(),
+        }
+    }
+
+    /// Standard deviation of the close-to-close returns over the window.
+    fn realized_volatility(&self) -> Option<f64> {
+        if self.closes.len() < self.volatility_window + 1 {
+            return None;
+        }
+        let returns: Vec<f64> = self.closes.iter().zip(self.closes.iter().skip(1)).map(|(previous, close)| close / previous - 1.0).collect();
+        let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+        Some((returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / returns.len() as f64).sqrt())
+    }
+
+    /// Regime of the candle, `None` until the ADX and the volatility are known.
+    pub fn update(&mut self, kline: &Kline) -> Option<Regime> {
+        self.adx.update(kline);
+        self.closes.push_back(kline.close);
+        if self.closes.len() > self.volatility_window + 1 {
+            self.closes.pop_front();
+        }
+        let volatility = self.realized_volatility()?;
+        self.volatilities.push_back(volatility);
+        while self.volatilities.len() > self.volatility_lookback {
+            self.volatilities.pop_front();
+        }
+        let adx = self.adx.value()?;
+        let below = self.volatilities.iter().filter(|value| **value < volatility).count();
+        if below as f64 / self.volatilities.len() as f64 * 100.0 >= self.volatility_percentile {
+            Some(Regime::HighVolatility)
+        } else if adx > self.adx_threshold {
+            Some(Regime::Trending)
+        } else {
+            Some(Regime::Ranging)
+        }
+    }
+}
+
+/// Results of a run over the candles of one regime.
+#[derive(Debug, Serialize)]
+pub struct RegimeBreakdown {
+    pub regime: Regime,
+    pub candles: usize,
+    /// Metrics of the trades entered in the regime, and Sharpe ratio of the candles of the regime.
+    pub metrics: Metrics,
+    /// Sum of the changes of the equity over the candles of the regime, open positions included.
+    pub equity_change: f64,
+    /// Share of the loss of the max drawdown of the equity curve that happened in candles of the regime. Negative
+    /// when the equity rose in the regime during the drawdown.
+    pub drawdown_share: f64,
+}
+
+/// Indexes of the peak and the trough of the deepest drawdown of the equity curve, `None` when it never falls.
+fn max_drawdown_span(equity_curve: &[(NaiveDateTime, f64)]) -> Option<(usize, usize)> {
+    let mut peak = 0;
+    let mut deepest: Option<(usize, usize)> = None;
+    for (index, (_, equity)) in equity_curve.iter().enumerate() {
+        if *equity > equity_curve[peak].1 {
+            peak = index;
+        }
+        let depth = equity_curve[peak].1 - equity;
+        if depth > 0.0 && deepest.is_none_or(|(start, end)| depth > equity_curve[start].1 - equity_curve[end].1) {
+            deepest = Some((peak, index));
+        }
+    }
+    deepest
+}
+
+/// Breaks the trades and the equity curve down by the regime of each candle. Trades count in the regime of their
+/// entry, trades entered before the first classified candle are left out. Regimes without candles are left out.
+pub fn breakdown(trades: &[Trade], equity_curve: &[(NaiveDateTime, f64)], regimes: &[(NaiveDateTime, Regime)], periods_per_year: f64) -> Vec<RegimeBreakdown> {
+    let regime_at = |date: NaiveDateTime| match regimes.binary_search_by_key(&date, |(timestamp, _)| *timestamp) {
+        Ok(index) => Some(regimes[index].1),
+        Err(0) => None,
+        Err(index) => Some(regimes[index - 1].1),
+    };
+    let drawdown = max_drawdown_span(equity_curve);
+    REGIMES
+        .iter()
+        .filter_map(|regime| {
+            let candles = regimes.iter().filter(|(_, tagged)| tagged == regime).count();
+            if candles == 0 {
+                return None;
+            }
+            let in_regime: Vec<Trade> = trades.iter().filter(|trade| regime_at(trade.entry_date) == Some(*regime)).cloned().collect();
+            let mut metrics = Metrics::new();
+            metrics.compute(&in_regime, &[]);
+            // the returns of the candles of the regime, chained into a curve of their own
+            let mut curve = vec![(equity_curve.first()?.0, 1.0)];
+            for pair in equity_curve.windows(2) {
+                if regime_at(pair[1].0) == Some(*regime) && pair[0].1 != 0.0 {
+                    let last = curve.last().unwrap().1;
+                    curve.push((pair[1].0, last * pair[1].1 / pair[0].1));
+                }
+            }
+            metrics.compute_returns(&curve, &[], periods_per_year);
+            metrics.compute_percentages(equity_curve.first()?.1, equity_curve);
+            // change of the equity over each candle of the regime, by index of the candle
+            let changes = || equity_curve.windows(2).enumerate().filter(|(_, pair)| regime_at(pair[1].0) == Some(*regime)).map(|(index, pair)| (index + 1, pair[1].1 - pair[0].1));
+            let equity_change = changes().map(|(_, change)| change).sum();
+            let drawdown_share = match drawdown {
+                Some((peak, trough)) => {
+                    let loss: f64 = changes().filter(|(index, _)| (peak + 1..=trough).contains(index)).map(|(_, change)| change).sum();
+                    -loss / (equity_curve[peak].1 - equity_curve[trough].1)
+                }
+                None => 0.0,
+            };
+            Some(RegimeBreakdown { regime: *regime, candles, metrics, equity_change, drawdown_share })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, NaiveDate};
+
+    fn classifier(lookback: usize) -> RegimeClassifier {
+        RegimeClassifier::new(&RegimeConfig {
+            adx_window: 2,
+            adx_threshold: 25.0,
+            volatility_window: 3,
+            volatility_percentile: 80.0,
+            volatility_lookback: lookback,
+        })
+    }
+
+    /// Hourly candles alternating between two moves, `calm` for the first `calm_candles` and wider afterwards.
+    fn klines(count: usize, calm_candles: usize) -> Vec<Kline> {
+        let start = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+        let mut close = 100.0;
+        (0..count)
+            .map(|i| {
+                let step = if i < calm_candles { 0.1 } else { 2.0 };
+                close += if i % 2 == 0 { step } else { -step * 0.5 };
+                Kline {
+                    timestamp: start + Duration::hours(i as i64),
+                    open: close,
+                    high: close + step,
+                    low: close - step,
+                    close,
+                    volume: 1.0,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn volatilities_are_kept_for_the_lookback_only() {
+        let mut classifier = classifier(10);
+        for kline in klines(100, 100).iter() {
+            classifier.update(kline);
+        }
+        assert_eq!(classifier.volatilities.len(), 10);
+    }
+
+    #[test]
+    fn a_volatility_jump_is_high_volatility() {
+        let mut classifier = classifier(50);
+        let regimes: Vec<Option<Regime>> = klines(60, 40).iter().map(|kline| classifier.update(kline)).collect();
+        assert_eq!(regimes[41], Some(Regime::HighVolatility));
+    }
+}
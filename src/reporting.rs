@@ -0,0 +1,84 @@
+//! Results restated in a reporting currency other than the quote currency, so runs on different quotes can be
+//! compared. Amounts are converted at the last rate known on their date: the equity and the cash flows on their
+//! candle, the allocation of a trade on its entry, and its profit, commission and financing on its exit.
+
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use crate::analysis;
+use crate::config::{Config, EndOfData};
+use crate::testing::{ExitReason, Metrics, SessionRecap, Trade};
+
+#[derive(Debug, Serialize)]
+pub struct ReportingResult {
+    pub currency: String,
+    pub initial_capital: f64,
+    pub final_equity: f64,
+    /// Change of the equity over the run in percentage, the moves of the exchange rate included.
+    pub total_return: f64,
+    /// Change of the value of the quote currency in the reporting currency over the run, in percentage.
+    pub currency_return: f64,
+    pub metrics: Metrics,
+    #[serde(skip)]
+    pub equity_curve: Vec<(NaiveDateTime, f64)>,
+}
+
+/// Price of one unit of the quote currency at `date`: the last rate at or before it, the first one before the
+/// series starts.
+fn rate_at(rates: &[(NaiveDateTime, f64)], date: NaiveDateTime) -> f64 {
+    let index = rates.partition_point(|(rate_date, _)| *rate_date <= date);
+    rates[index.saturating_sub(1)].1
+}
+
+/// Converts the recap of a run with `rates`, the price of one unit of the quote currency in the reporting currency
+/// (or its inverse when `inverted` is set), sorted by date.
+pub fn convert(recap: &SessionRecap, rates: &[(NaiveDateTime, f64)], config: &Config) -> ReportingResult {
+    let reporting_currency = config.reporting_currency.as_ref().expect("no reporting currency configured");
+    if rates.is_empty() {
+        panic!("reporting_currency: the exchange rate series is empty");
+    }
+    let rates: Vec<(NaiveDateTime, f64)> = match reporting_currency.inverted {
+        true => rates.iter().map(|(date, rate)| (*date, 1.0 / rate)).collect(),
+        false => rates.to_vec(),
+    };
+    let convert_at = |date: NaiveDateTime, amount: f64| amount * rate_at(&rates, date);
+    let excluded = |trade: &Trade| matches!(config.end_of_data, EndOfData::Exclude) && trade.exit_reason == Some(ExitReason::EndOfData);
+    let trades: Vec<Trade> = recap
+        .trades
+        .iter()
+        .filter(|trade| !excluded(trade))
+        .map(|trade| {
+            let exit_date = trade.exit_date.unwrap_or(trade.entry_date);
+            let mut converted = trade.clone();
+            converted.allocated = convert_at(trade.entry_date, trade.allocated);
+            converted.profit = trade.profit.map(|profit| convert_at(exit_date, profit));
+            converted.commission = convert_at(exit_date, trade.commission);
+            converted.financing = convert_at(exit_date, trade.financing);
+            converted
+        })
+        .collect();
+    let equity_curve: Vec<(NaiveDateTime, f64)> = recap.equity_curve.iter().map(|(date, equity)| (*date, convert_at(*date, *equity))).collect();
+    let cash_flows: Vec<(NaiveDateTime, f64)> = recap.cash_flows.iter().map(|(date, amount)| (*date, convert_at(*date, *amount))).collect();
+    let (first, last) = match (recap.equity_curve.first(), recap.equity_curve.last()) {
+        (Some((first, _)), Some((last, _))) => (*first, *last),
+        _ => (rates[0].0, rates[0].0),
+    };
+    let mut metrics = Metrics::new();
+    metrics.compute(&trades, &recap.skipped_signals);
+    metrics.compute_returns(&equity_curve, &cash_flows, analysis::annualization(config));
+    metrics.unrealized_profit = convert_at(last, recap.metrics.unrealized_profit);
+    metrics.risk_resizes = recap.metrics.risk_resizes;
+    metrics.risk_vetoes = recap.metrics.risk_vetoes;
+    metrics.requotes = recap.metrics.requotes;
+    let initial_capital = convert_at(first, config.base_funds);
+    metrics.compute_percentages(initial_capital, &equity_curve);
+    let final_equity = equity_curve.last().map_or(initial_capital, |(_, equity)| *equity);
+    ReportingResult {
+        currency: reporting_currency.currency.clone(),
+        initial_capital,
+        final_equity,
+        total_return: (final_equity / initial_capital - 1.0) * 100.0,
+        currency_return: (rate_at(&rates, last) / rate_at(&rates, first) - 1.0) * 100.0,
+        metrics,
+        equity_curve,
+    }
+}
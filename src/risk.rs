@@ -0,0 +1,375 @@
+use std::collections::VecDeque;
+use crate::config::RiskManagerConfig;
+use crate::events::{LimitOrder, OrderAction, OrderEvent, Side};
+use crate::historical::Kline;
+use crate::testing::Direction;
+
+/// Portfolio figures a risk manager can base its decisions on.
+pub struct RiskContext {
+    /// Equity of the strategy placing the order.
+    pub equity: f64,
+    /// Equity of the whole session, every strategy of a multi-strategy session included.
+    pub total_equity: f64,
+    /// Current value of the long and short positions of the whole session, in quote currency.
+    pub long_exposure: f64,
+    pub short_exposure: f64,
+}
+
+/// Equity and value of the long and short positions of a portfolio, in quote currency.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Exposure {
+    pub equity: f64,
+    pub long: f64,
+    pub short: f64,
+}
+
+impl std::ops::Add for Exposure {
+    type Output = Exposure;
+
+    fn add(self, other: Exposure) -> Exposure {
+        Exposure {
+            equity: self.equity + other.equity,
+            long: self.long + other.long,
+            short: self.short + other.short,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RiskDecision {
+    Accept,
+    /// Replace the order size (in quote currency) with this one.
+    Resize(f64),
+    /// Drop the order, with a human-readable explanation.
+    Veto(String),
+}
+
+/// A decision that changed an order, kept for the session recap.
+#[derive(Debug, Clone)]
+pub struct RiskEvent {
+    pub manager: String,
+    pub decision: RiskDecision,
+}
+
+/// Sits between the strategy and the execution model, and reviews every entry order.
+/// Exit orders are never reviewed, a risk manager cannot keep a position open.
+pub trait RiskManager {
+    fn name(&self) -> &str;
+
+    /// Called on every candle, before the strategy.
+    fn on_bar(&mut self, _kline: &Kline) {}
+
+    fn review(&mut self, order: &OrderEvent, context: &RiskContext) -> RiskDecision;
+
+    /// Returns what the manager learned from the candles so far, saved in checkpoints. Managers that only look at
+    /// the order and the portfolio keep the default implementation.
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::Value::Null
+    }
+
+    /// Restores a state previously returned by `save_state`.
+    fn load_state(&mut self, _state: serde_json::Value) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Caps the size of a position to a fraction of the current equity.
+pub struct MaxExposure {
+    max_fraction: f64,
+}
+
+impl RiskManager for MaxExposure {
+    fn name(&self) -> &str {
+        "max_exposure"
+    }
+
+    fn review(&mut self, order: &OrderEvent, context: &RiskContext) -> RiskDecision {
+        let cap = context.equity * self.max_fraction;
+        if cap <= 0.0 {
+            RiskDecision::Veto(format!("no exposure allowed with {:.2} equity", context.equity))
+        } else if order.size > cap {
+            RiskDecision::Resize(cap)
+        } else {
+            RiskDecision::Accept
+        }
+    }
+}
+
+/// Blocks the entries that would bring the value of all the positions of the session, long and short, above a
+/// fraction of its equity. Every position of a session is on `pair`, so this is also the limit per symbol.
+pub struct MaxGrossExposure {
+    max_fraction: f64,
+}
+
+impl RiskManager for MaxGrossExposure {
+    fn name(&self) -> &str {
+        "max_gross_exposure"
+    }
+
+    fn review(&mut self, order: &OrderEvent, context: &RiskContext) -> RiskDecision {
+        let cap = context.total_equity * self.max_fraction;
+        let gross = context.long_exposure + context.short_exposure + order.size;
+        if gross > cap {
+            RiskDecision::Veto(format!("gross exposure would reach {:.2}, above the limit of {:.2}", gross, cap))
+        } else {
+            RiskDecision::Accept
+        }
+    }
+}
+
+/// Blocks the entries that would bring the long minus the short positions of the session above a fraction of its
+/// equity, either way. Entries bringing the net exposure back toward zero always go through.
+pub struct MaxNetExposure {
+    max_fraction: f64,
+}
+
+impl RiskManager for MaxNetExposure {
+    fn name(&self) -> &str {
+        "max_net_exposure"
+    }
+
+    fn review(&mut self, order: &OrderEvent, context: &RiskContext) -> RiskDecision {
+        let cap = context.total_equity * self.max_fraction;
+        let net = context.long_exposure - context.short_exposure;
+        let after = match order.action {
+            OrderAction::Enter(Direction::Short) => net - order.size,
+            OrderAction::Enter(Direction::Long) | OrderAction::Limit(LimitOrder { side: Side::Buy, .. }) => net + order.size,
+            _ => net,
+        };
+        if after.abs() > cap && after.abs() > net.abs() {
+            RiskDecision::Veto(format!("net exposure would reach {:.2}, beyond the limit of {:.2}", after, cap))
+        } else {
+            RiskDecision::Accept
+        }
+    }
+}
+
+/// Scales positions down when the realized volatility of the last `window` candles is above the target.
+pub struct VolatilityScaled {
+    window: usize,
+    target_volatility: f64,
+    closes: Vec<f64>,
+}
+
+/// Standard deviation of the close-to-close returns of the last `window` candles, in percentage, `None` until
+/// `window + 1` closes were seen.
+fn realized_volatility(closes: &[f64], window: usize) -> Option<f64> {
+    if closes.len() < window + 1 {
+        return None;
+    }
+    let returns: Vec<f64> = closes.windows(2).map(|w| (w[1] - w[0]) / w[0] * 100.0).collect();
+    let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+    let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / returns.len() as f64;
+    Some(variance.sqrt())
+}
+
+/// Keeps the last `window + 1` closes.
+fn push_close(closes: &mut Vec<f64>, close: f64, window: usize) {
+    closes.push(close);
+    if closes.len() > window + 1 {
+        closes.remove(0);
+    }
+}
+
+impl RiskManager for VolatilityScaled {
+    fn name(&self) -> &str {
+        "volatility_scaled"
+    }
+
+    fn on_bar(&mut self, kline: &Kline) {
+        push_close(&mut self.closes, kline.close, self.window);
+    }
+
+    fn review(&mut self, order: &OrderEvent, _context: &RiskContext) -> RiskDecision {
+        match realized_volatility(&self.closes, self.window) {
+            Some(volatility) if volatility > self.target_volatility => {
+                RiskDecision::Resize(order.size * self.target_volatility / volatility)
+            }
+            _ => RiskDecision::Accept,
+        }
+    }
+
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::json!(self.closes)
+    }
+
+    fn load_state(&mut self, state: serde_json::Value) -> Result<(), String> {
+        self.closes = serde_json::from_value(state).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+/// Throttles entries while the realized volatility is at or above a percentile of the ones of the last `lookback`
+/// candles: their size is multiplied by `size_factor`, and they are blocked when it is 0.
+pub struct VolatilityThrottle {
+    window: usize,
+    percentile: f64,
+    size_factor: f64,
+    lookback: usize,
+    closes: Vec<f64>,
+    volatilities: VecDeque<f64>,
+}
+
+impl VolatilityThrottle {
+    /// Percentile of the current volatility among the ones of the lookback, `None` before the first one.
+    fn current_percentile(&self) -> Option<f64> {
+        let current = *self.volatilities.back()?;
+        let below = self.volatilities.iter().filter(|volatility| **volatility < current).count();
+        Some(below as f64 / self.volatilities.len() as f64 * 100.0)
+    }
+}
+
+impl RiskManager for VolatilityThrottle {
+    fn name(&self) -> &str {
+        "volatility_throttle"
+    }
+
+    fn on_bar(&mut self, kline: &Kline) {
+        push_close(&mut self.closes, kline.close, self.window);
+        if let Some(volatility) = realized_volatility(&self.closes, self.window) {
+            self.volatilities.push_back(volatility);
+            if self.volatilities.len() > self.lookback {
+                self.volatilities.pop_front();
+            }
+        }
+    }
+
+    fn review(&mut self, order: &OrderEvent, _context: &RiskContext) -> RiskDecision {
+        match self.current_percentile() {
+            Some(percentile) if percentile >= self.percentile => {
+                if self.size_factor > 0.0 {
+                    RiskDecision::Resize(order.size * self.size_factor)
+                } else {
+                    let volatility = self.volatilities.back().unwrap();
+                    RiskDecision::Veto(format!("realized volatility of {:.2}% is above {:.0}% of the recent ones, entries paused from {:.0}%", volatility, percentile, self.percentile))
+                }
+            }
+            _ => RiskDecision::Accept,
+        }
+    }
+
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::json!({ "closes": self.closes, "volatilities": self.volatilities })
+    }
+
+    fn load_state(&mut self, mut state: serde_json::Value) -> Result<(), String> {
+        self.closes = serde_json::from_value(state["closes"].take()).map_err(|e| e.to_string())?;
+        let volatilities: VecDeque<f64> = serde_json::from_value(state["volatilities"].take()).map_err(|e| e.to_string())?;
+        // the lookback may have been shortened since the checkpoint
+        self.volatilities = volatilities.into_iter().rev().take(self.lookback).rev().collect();
+        Ok(())
+    }
+}
+
+pub fn build_risk_managers(configs: &[RiskManagerConfig]) -> Vec<Box<dyn RiskManager>> {
+    configs
+        .iter()
+        .map(|config| -> Box<dyn RiskManager> {
+            match config {
+                RiskManagerConfig::MaxExposure { max_fraction } => Box::new(MaxExposure {
+                    max_fraction: *max_fraction,
+                }),
+                RiskManagerConfig::MaxGrossExposure { max_fraction } => Box::new(MaxGrossExposure {
+                    max_fraction: *max_fraction,
+                }),
+                RiskManagerConfig::MaxNetExposure { max_fraction } => Box::new(MaxNetExposure {
+                    max_fraction: *max_fraction,
+                }),
+                RiskManagerConfig::VolatilityScaled { window, target_volatility } => Box::new(VolatilityScaled {
+                    window: *window,
+                    target_volatility: *target_volatility,
+                    closes: Vec::new(),
+                }),
+                RiskManagerConfig::VolatilityThrottle { window, percentile, size_factor, lookback } => Box::new(VolatilityThrottle {
+                    window: *window,
+                    percentile: *percentile,
+                    size_factor: *size_factor,
+                    lookback: *lookback,
+                    closes: Vec::new(),
+                    volatilities: VecDeque::new(),
+                }),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, NaiveDate};
+
+    fn throttle(lookback: usize) -> Box<dyn RiskManager> {
+        build_risk_managers(&[RiskManagerConfig::VolatilityThrottle {
+            window: 3,
+            percentile: 80.0,
+            size_factor: 0.0,
+            lookback,
+        }])
+        .pop()
+        .unwrap()
+    }
+
+    /// Hourly candles whose moves widen with time, so that each volatility is above the previous ones.
+    fn klines(count: usize) -> Vec<Kline> {
+        let start = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+        let mut close = 100.0;
+        (0..count)
+            .map(|i| {
+                close += if i % 2 == 0 { 0.1 * i as f64 } else { -0.05 * i as f64 };
+                Kline {
+                    timestamp: start + Duration::hours(i as i64),
+                    open: close,
+                    high: close,
+                    low: close,
+                    close,
+                    volume: 1.0,
+                }
+            })
+            .collect()
+    }
+
+    fn entry() -> OrderEvent {
+        OrderEvent {
+            id: "O1".to_string(),
+            date: NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap(),
+            price: 100.0,
+            signal_price: 100.0,
+            action: OrderAction::Enter(Direction::Long),
+            size: 1000.0,
+        }
+    }
+
+    fn context() -> RiskContext {
+        RiskContext {
+            equity: 10000.0,
+            total_equity: 10000.0,
+            long_exposure: 0.0,
+            short_exposure: 0.0,
+        }
+    }
+
+    #[test]
+    fn volatilities_are_kept_for_the_lookback_only() {
+        let mut manager = throttle(10);
+        for kline in klines(100).iter() {
+            manager.on_bar(kline);
+        }
+        let state = manager.save_state();
+        assert_eq!(state["volatilities"].as_array().unwrap().len(), 10);
+        assert_eq!(state["closes"].as_array().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn restored_state_gives_the_same_decisions() {
+        let klines = klines(50);
+        let mut manager = throttle(20);
+        for kline in klines.iter() {
+            manager.on_bar(kline);
+        }
+        let mut restored = throttle(20);
+        restored.load_state(manager.save_state()).unwrap();
+        assert!(matches!(manager.review(&entry(), &context()), RiskDecision::Veto(_)));
+        assert!(matches!(restored.review(&entry(), &context()), RiskDecision::Veto(_)));
+        assert!(matches!(throttle(20).review(&entry(), &context()), RiskDecision::Accept));
+    }
+}
@@ -0,0 +1,62 @@
+use crate::config::Sizing;
+use crate::historical::Kline;
+use crate::indicators::Atr;
+
+/// Decides how much quote currency to allocate to a new trade.
+pub struct PositionSizer {
+    sizing: Sizing,
+    atr: Option<Atr>,
+}
+
+impl PositionSizer {
+    pub fn new(sizing: &Sizing) -> Self {
+        let atr = match sizing {
+            Sizing::VolatilityTarget { atr_window, .. } => Some(Atr::new(*atr_window)),
+            _ => None,
+        };
+        Self {
+            sizing: sizing.clone(),
+            atr,
+        }
+    }
+
+    pub fn on_bar(&mut self, kline: &Kline) {
+        if let Some(atr) = self.atr.as_mut() {
+            atr.update(kline);
+        }
+    }
+
+    /// State of the average true range of `VolatilityTarget` for a checkpoint, null for the other sizings.
+    pub fn save_state(&self) -> serde_json::Value {
+        self.atr.as_ref().map(|atr| serde_json::to_value(atr).expect("error while serializing sizing ATR")).unwrap_or_default()
+    }
+
+    /// Restores a state previously returned by `save_state`. A null state, from another sizing or a checkpoint
+    /// written before the sizer was saved, leaves the sizer as it is.
+    pub fn load_state(&mut self, state: serde_json::Value) -> Result<(), String> {
+        if let (Some(atr), false) = (self.atr.as_mut(), state.is_null()) {
+            *atr = serde_json::from_value(state).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    pub fn size(&self, cash: f64, equity: f64, price: f64) -> f64 {
+        match &self.sizing {
+            Sizing::Fraction { fraction } => cash * fraction,
+            // not capped to the cash, so entries that cannot be afforded are skipped instead of shrunk
+            Sizing::FixedNotional { amount } => *amount,
+            // like a fixed notional, entries larger than the cash are skipped
+            Sizing::Lots { lots, size } => lots * size.units() * price,
+            Sizing::VolatilityTarget { risk_fraction, atr_multiplier, fallback_fraction, .. } => {
+                match self.atr.as_ref().and_then(|atr| atr.value()) {
+                    Some(atr) if atr > 0.0 => {
+                        // a move of `atr_multiplier` ATRs against the position loses `risk_fraction` of the equity
+                        let units = equity * risk_fraction / (atr * atr_multiplier);
+                        (units * price).min(cash)
+                    }
+                    _ => cash * fallback_fraction,
+                }
+            }
+        }
+    }
+}
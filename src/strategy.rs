@@ -1,16 +1,85 @@
+use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
+use crate::config::{Config, StrategyConfig, TrailingStop};
+use crate::events::{LimitOrder, Side};
 use crate::historical::Kline;
+use crate::indicators::{Anchor, AnchoredVwap, Ichimoku, SuperTrend, Vwap};
+use crate::regime::Regime;
 use crate::testing::Direction;
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Signal {
     Buy,
     Sell,
     Hold,
+    /// A buy whose trade gets its own exits, the levels left to `None` keeping the ones of the config.
+    BuyWith(ExitLevels),
+    /// A sell whose trade gets its own exits, see `BuyWith`.
+    SellWith(ExitLevels),
+}
+
+impl Signal {
+    /// Side of the trade the signal opens, `None` for `Hold`.
+    pub fn direction(&self) -> Option<Direction> {
+        match self {
+            Signal::Buy | Signal::BuyWith(_) => Some(Direction::Long),
+            Signal::Sell | Signal::SellWith(_) => Some(Direction::Short),
+            Signal::Hold => None,
+        }
+    }
+
+    /// Exits attached to the signal, none for a plain `Buy` or `Sell`.
+    pub fn exit_levels(&self) -> ExitLevels {
+        match self {
+            Signal::BuyWith(levels) | Signal::SellWith(levels) => *levels,
+            _ => ExitLevels::default(),
+        }
+    }
 }
 
 pub trait Strategy {
     fn on_tick(&mut self, kline: &Kline) -> Option<Signal>;
+
+    /// Named values the last signal was based on, like the indicators of the strategy, logged with the signals.
+    fn indicators(&self) -> Vec<(String, f64)> {
+        Vec::new()
+    }
+
+    /// Returns the internal state of the strategy, saved in checkpoints.
+    /// Stateless strategies can keep the default implementation.
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::Value::Null
+    }
+
+    /// Restores a state previously returned by `save_state`.
+    fn load_state(&mut self, _state: serde_json::Value) {}
+
+    /// Limit orders to keep resting from the next candle on, replacing the ones returned after the previous candle.
+    /// Called after every candle. Strategies that only trade on signals keep the default, which places none.
+    fn limit_orders(&self) -> Vec<LimitOrder> {
+        Vec::new()
+    }
+
+    /// Called when one of the limit orders went through, after `on_tick` of the candle that reached it.
+    fn on_fill(&mut self, _order: &LimitOrder) {}
+
+    /// Called before `on_tick` with the regime of the candle, when `regime` is configured and the candle is classified.
+    fn on_regime(&mut self, _regime: Regime) {}
+
 }
 
+/// Prices of the exits a strategy attaches to its entries with `Signal::BuyWith` and `Signal::SellWith`. They replace
+/// the ones computed from `stop_loss`, `take_profit` and `trailing_stop` of the config when the entry is filled, and
+/// are ignored when on the wrong side of its price, or for a trailing stop, when its distance is not positive.
+/// `TrailingStop::None` turns off the trailing stop of the config. The lots of limit orders have no exit levels.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ExitLevels {
+    pub stop_loss: Option<f64>,
+    pub take_profit: Option<f64>,
+    pub trailing_stop: Option<TrailingStop>,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct SimpleStrategy {
     position: Direction,
     sma_window: usize,
@@ -54,6 +123,541 @@ impl Strategy for SimpleStrategy {
                 return Some(Signal::Sell);
             }
         }
-        return Some(Signal::Hold);
+        Some(Signal::Hold)
+    }
+
+    fn indicators(&self) -> Vec<(String, f64)> {
+        self.calculate_sma().map(|sma| ("sma".to_string(), sma)).into_iter().collect()
+    }
+
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("error while serializing strategy state")
+    }
+
+    fn load_state(&mut self, state: serde_json::Value) {
+        *self = serde_json::from_value(state).expect("error while parsing strategy state");
+    }
+}
+
+/// Trades the close against the volume-weighted average price: long above it, short below, with `band` percent of
+/// margin on each side to avoid flipping around it. The VWAP is the one of the session, or anchored to the last swing
+/// low when `swing_window` is set.
+#[derive(Serialize, Deserialize)]
+pub struct VwapStrategy {
+    position: Direction,
+    band: f64,
+    session: Vwap,
+    anchored: Option<AnchoredVwap>,
+}
+
+impl VwapStrategy {
+    pub fn new(swing_window: usize, band: f64) -> Self {
+        Self {
+            position: Direction::Flat,
+            band,
+            session: Vwap::new(),
+            anchored: (swing_window > 0).then(|| AnchoredVwap::new(Anchor::SwingLow(swing_window))),
+        }
+    }
+
+    fn vwap(&self) -> Option<f64> {
+        match &self.anchored {
+            Some(anchored) => anchored.value(),
+            None => self.session.value(),
+        }
+    }
+}
+
+impl Strategy for VwapStrategy {
+    fn on_tick(&mut self, kline: &Kline) -> Option<Signal> {
+        self.session.update(kline);
+        if let Some(anchored) = self.anchored.as_mut() {
+            anchored.update(kline);
+        }
+        if let Some(vwap) = self.vwap() {
+            if kline.close > vwap * (1.0 + self.band / 100.0) && self.position != Direction::Long {
+                self.position = Direction::Long;
+                return Some(Signal::Buy);
+            } else if kline.close < vwap * (1.0 - self.band / 100.0) && self.position != Direction::Short {
+                self.position = Direction::Short;
+                return Some(Signal::Sell);
+            }
+        }
+        Some(Signal::Hold)
+    }
+
+    fn indicators(&self) -> Vec<(String, f64)> {
+        self.vwap().map(|vwap| ("vwap".to_string(), vwap)).into_iter().collect()
+    }
+
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("error while serializing strategy state")
+    }
+
+    fn load_state(&mut self, state: serde_json::Value) {
+        *self = serde_json::from_value(state).expect("error while parsing strategy state");
+    }
+}
+
+/// Follows the trend of a SuperTrend band: buys when it turns up, sells when it turns down.
+#[derive(Serialize, Deserialize)]
+pub struct SuperTrendStrategy {
+    position: Direction,
+    supertrend: SuperTrend,
+}
+
+impl SuperTrendStrategy {
+    pub fn new(window: usize, multiplier: f64) -> Self {
+        Self {
+            position: Direction::Flat,
+            supertrend: SuperTrend::new(window, multiplier),
+        }
+    }
+}
+
+impl Strategy for SuperTrendStrategy {
+    fn on_tick(&mut self, kline: &Kline) -> Option<Signal> {
+        self.supertrend.update(kline);
+        if self.supertrend.value().is_some() {
+            if self.supertrend.uptrend() && self.position != Direction::Long {
+                self.position = Direction::Long;
+                return Some(Signal::Buy);
+            } else if !self.supertrend.uptrend() && self.position != Direction::Short {
+                self.position = Direction::Short;
+                return Some(Signal::Sell);
+            }
+        }
+        Some(Signal::Hold)
+    }
+
+    fn indicators(&self) -> Vec<(String, f64)> {
+        self.supertrend.value().map(|level| ("supertrend".to_string(), level)).into_iter().collect()
+    }
+
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("error while serializing strategy state")
+    }
+
+    fn load_state(&mut self, state: serde_json::Value) {
+        *self = serde_json::from_value(state).expect("error while parsing strategy state");
+    }
+}
+
+/// Buys when the close is above the Ichimoku cloud, the conversion line above the base line and the lagging span
+/// above the price it lags, and sells when all three point down.
+#[derive(Serialize, Deserialize)]
+pub struct IchimokuStrategy {
+    position: Direction,
+    ichimoku: Ichimoku,
+}
+
+impl IchimokuStrategy {
+    pub fn new(tenkan: usize, kijun: usize, span_b: usize) -> Self {
+        Self {
+            position: Direction::Flat,
+            ichimoku: Ichimoku::new(tenkan, kijun, span_b),
+        }
+    }
+}
+
+impl Strategy for IchimokuStrategy {
+    fn on_tick(&mut self, kline: &Kline) -> Option<Signal> {
+        self.ichimoku.update(kline);
+        if let Some(lines) = self.ichimoku.value() {
+            let bullish = kline.close > lines.cloud_top() && lines.tenkan > lines.kijun && kline.close > lines.lagging_close;
+            let bearish = kline.close < lines.cloud_bottom() && lines.tenkan < lines.kijun && kline.close < lines.lagging_close;
+            if bullish && self.position != Direction::Long {
+                self.position = Direction::Long;
+                return Some(Signal::Buy);
+            } else if bearish && self.position != Direction::Short {
+                self.position = Direction::Short;
+                return Some(Signal::Sell);
+            }
+        }
+        Some(Signal::Hold)
+    }
+
+    fn indicators(&self) -> Vec<(String, f64)> {
+        let Some(lines) = self.ichimoku.value() else {
+            return Vec::new();
+        };
+        vec![
+            ("tenkan".to_string(), lines.tenkan),
+            ("kijun".to_string(), lines.kijun),
+            ("span_a".to_string(), lines.span_a),
+            ("span_b".to_string(), lines.span_b),
+        ]
+    }
+
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("error while serializing strategy state")
+    }
+
+    fn load_state(&mut self, state: serde_json::Value) {
+        *self = serde_json::from_value(state).expect("error while parsing strategy state");
+    }
+}
+
+/// Ladder of buy limit orders `spacing` percent apart below a reference price, the first close. Every lot bought is
+/// sold one step higher, and the level is bought again once it is sold, so the strategy keeps buying dips and selling
+/// bounces inside the grid. Once the price has run above the grid with nothing held, the grid moves up to it.
+#[derive(Serialize, Deserialize)]
+pub struct GridStrategy {
+    levels: usize,
+    spacing: f64,
+    order_size: f64,
+    recenter: bool,
+    reference: Option<f64>,
+    /// Whether a lot is held at each level, the first one being the closest to the reference.
+    held: Vec<bool>,
+}
+
+impl GridStrategy {
+    pub fn new(levels: usize, spacing: f64, order_size: f64, recenter: bool) -> Self {
+        Self {
+            levels,
+            spacing,
+            order_size,
+            recenter,
+            reference: None,
+            held: vec![false; levels],
+        }
+    }
+
+    /// Price of the `step`th level below the reference, the reference itself being step 0.
+    fn level_price(&self, reference: f64, step: usize) -> f64 {
+        reference * (1.0 - self.spacing * step as f64 / 100.0)
+    }
+}
+
+impl Strategy for GridStrategy {
+    fn on_tick(&mut self, kline: &Kline) -> Option<Signal> {
+        let reference = *self.reference.get_or_insert(kline.close);
+        let above_grid = kline.close > reference * (1.0 + self.spacing / 100.0);
+        if self.recenter && above_grid && !self.held.contains(&true) {
+            self.reference = Some(kline.close);
+        }
+        Some(Signal::Hold)
+    }
+
+    fn indicators(&self) -> Vec<(String, f64)> {
+        let Some(reference) = self.reference else {
+            return Vec::new();
+        };
+        vec![
+            ("grid_reference".to_string(), reference),
+            ("grid_lots".to_string(), self.held.iter().filter(|held| **held).count() as f64),
+        ]
+    }
+
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("error while serializing strategy state")
+    }
+
+    fn load_state(&mut self, state: serde_json::Value) {
+        *self = serde_json::from_value(state).expect("error while parsing strategy state");
+    }
+
+    fn limit_orders(&self) -> Vec<LimitOrder> {
+        let Some(reference) = self.reference else {
+            return Vec::new();
+        };
+        self.held
+            .iter()
+            .enumerate()
+            .map(|(index, held)| {
+                if *held {
+                    LimitOrder { id: index, side: Side::Sell, price: self.level_price(reference, index), size: 0.0 }
+                } else {
+                    LimitOrder { id: index, side: Side::Buy, price: self.level_price(reference, index + 1), size: self.order_size }
+                }
+            })
+            .collect()
+    }
+
+    fn on_fill(&mut self, order: &LimitOrder) {
+        self.held[order.id] = order.side == Side::Buy;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParameterKind {
+    Integer,
+    Float,
+    Boolean,
+}
+
+/// A parameter a strategy accepts, checked when the config is loaded and used by the optimizer to fill in the
+/// bounds of its ranges.
+#[derive(Debug, Clone)]
+pub struct ParameterSpec {
+    pub name: String,
+    pub kind: ParameterKind,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    /// Used when the config does not set the parameter.
+    pub default: serde_json::Value,
+}
+
+impl ParameterSpec {
+    pub fn integer(name: &str, default: i64, min: Option<f64>, max: Option<f64>) -> Self {
+        Self { name: name.to_string(), kind: ParameterKind::Integer, min, max, default: default.into() }
+    }
+
+    pub fn float(name: &str, default: f64, min: Option<f64>, max: Option<f64>) -> Self {
+        Self { name: name.to_string(), kind: ParameterKind::Float, min, max, default: default.into() }
+    }
+
+    pub fn boolean(name: &str, default: bool) -> Self {
+        Self { name: name.to_string(), kind: ParameterKind::Boolean, min: None, max: None, default: default.into() }
+    }
+
+    /// Checks the type and the bounds of a value.
+    pub fn check(&self, value: &serde_json::Value) -> Result<(), String> {
+        let number = match (self.kind, value) {
+            (ParameterKind::Boolean, serde_json::Value::Bool(_)) => return Ok(()),
+            (ParameterKind::Integer, value) if value.is_i64() || value.is_u64() => value.as_f64(),
+            (ParameterKind::Float, value) => value.as_f64(),
+            _ => None,
+        };
+        let Some(number) = number else {
+            return Err(format!("expected {}, got {}", self.kind.describe(), value));
+        };
+        match (self.min, self.max) {
+            (Some(min), _) if number < min => Err(format!("{} is below the minimum of {}", value, min)),
+            (_, Some(max)) if number > max => Err(format!("{} is above the maximum of {}", value, max)),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl ParameterKind {
+    fn describe(&self) -> &'static str {
+        match self {
+            ParameterKind::Integer => "an integer",
+            ParameterKind::Float => "a number",
+            ParameterKind::Boolean => "true or false",
+        }
+    }
+}
+
+/// Parameters of a strategy. Once checked against its specs, every declared parameter is there with the right type.
+pub struct Parameters(serde_json::Map<String, serde_json::Value>);
+
+impl Parameters {
+    /// Checks the parameters of the config against the specs, filling in the defaults. Unknown parameters are refused.
+    pub fn check(specs: &[ParameterSpec], params: &serde_json::Value) -> Result<Self, String> {
+        let mut values = match params {
+            serde_json::Value::Null => serde_json::Map::new(),
+            serde_json::Value::Object(values) => values.clone(),
+            value => return Err(format!("expected an object of parameters, got {}", value)),
+        };
+        if let Some(name) = values.keys().find(|name| !specs.iter().any(|spec| &spec.name == *name)) {
+            let known: Vec<&str> = specs.iter().map(|spec| spec.name.as_str()).collect();
+            return Err(format!("unknown parameter `{}`, expected one of: {}", name, known.join(", ")));
+        }
+        for spec in specs.iter() {
+            match values.get(&spec.name) {
+                Some(value) => spec.check(value).map_err(|e| format!("{}: {}", spec.name, e))?,
+                None => {
+                    values.insert(spec.name.clone(), spec.default.clone());
+                }
+            }
+        }
+        Ok(Self(values))
+    }
+
+    /// The parameters as they are, for strategies that do not declare them.
+    pub fn unchecked(params: &serde_json::Value) -> Self {
+        Self(params.as_object().cloned().unwrap_or_default())
+    }
+
+    fn get(&self, name: &str) -> &serde_json::Value {
+        self.0.get(name).unwrap_or_else(|| panic!("strategy parameter `{}` is not declared", name))
+    }
+
+    pub fn integer(&self, name: &str) -> i64 {
+        self.get(name).as_i64().unwrap_or_else(|| panic!("strategy parameter `{}` is not an integer", name))
+    }
+
+    pub fn float(&self, name: &str) -> f64 {
+        self.get(name).as_f64().unwrap_or_else(|| panic!("strategy parameter `{}` is not a number", name))
+    }
+
+    pub fn boolean(&self, name: &str) -> bool {
+        self.get(name).as_bool().unwrap_or_else(|| panic!("strategy parameter `{}` is not a boolean", name))
+    }
+
+    pub fn to_value(&self) -> serde_json::Value {
+        serde_json::Value::Object(self.0.clone())
+    }
+}
+
+/// Provides strategies that are not built in, like the ones of plugins.
+pub trait StrategyFactory {
+    fn name(&self) -> &str;
+
+    /// Parameters the strategy accepts, `None` to receive them unchecked.
+    fn parameters(&self) -> Option<Vec<ParameterSpec>> {
+        None
+    }
+
+    /// Receives the parameters with their defaults filled in when they are declared.
+    fn create(&self, params: &serde_json::Value) -> Box<dyn Strategy>;
+
+    /// Version of the code of the strategy, recorded with the results, see `fingerprint`.
+    fn version(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Version of the program, with the git commit it was built from when known (`-dirty` with uncommitted changes).
+/// It identifies the code of the built-in strategies.
+pub fn build_version() -> String {
+    match option_env!("RETROVAL_GIT_HASH") {
+        Some(hash) => format!("retroval {} ({})", env!("CARGO_PKG_VERSION"), hash),
+        None => format!("retroval {}", env!("CARGO_PKG_VERSION")),
+    }
+}
+
+/// Identifies the revision of the code of the strategy `name`, so results are not attributed to another version of
+/// it: `build_version` for the built-in strategies, the version reported by the factory for the others.
+pub fn fingerprint(name: &str, factories: &[Box<dyn StrategyFactory>]) -> String {
+    if builtin_parameters(name).is_some() {
+        return build_version();
+    }
+    match factories.iter().find(|factory| factory.name() == name) {
+        Some(factory) => factory.version().unwrap_or("unversioned".to_string()),
+        None => "unknown strategy".to_string(),
+    }
+}
+
+/// Strategies registered with `register_strategy`.
+static REGISTERED: Mutex<Vec<Arc<dyn StrategyFactory + Send + Sync>>> = Mutex::new(Vec::new());
+
+/// A registered factory, shared by every run.
+struct Registered(Arc<dyn StrategyFactory + Send + Sync>);
+
+impl StrategyFactory for Registered {
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    fn parameters(&self) -> Option<Vec<ParameterSpec>> {
+        self.0.parameters()
+    }
+
+    fn create(&self, params: &serde_json::Value) -> Box<dyn Strategy> {
+        self.0.create(params)
+    }
+
+    fn version(&self) -> Option<String> {
+        self.0.version()
+    }
+}
+
+/// Makes a strategy written against the `Strategy` trait by a program using the library available in the configs
+/// under the name of its factory, like the strategies of plugins. Registering a name again replaces the previous
+/// factory, and the built-in strategies cannot be replaced.
+pub fn register_strategy(factory: impl StrategyFactory + Send + Sync + 'static) {
+    let mut registered = REGISTERED.lock().unwrap();
+    registered.retain(|existing| existing.name() != factory.name());
+    registered.push(Arc::new(factory));
+}
+
+/// The strategies registered with `register_strategy`.
+pub fn registered_strategies() -> Vec<Box<dyn StrategyFactory>> {
+    REGISTERED.lock().unwrap().iter().map(|factory| Box::new(Registered(factory.clone())) as Box<dyn StrategyFactory>).collect()
+}
+
+/// Parameters of the built-in strategies, `None` for the other names.
+fn builtin_parameters(name: &str) -> Option<Vec<ParameterSpec>> {
+    match name {
+        "simple" => Some(vec![ParameterSpec::integer("sma_window", 14, Some(1.0), Some(1000.0))]),
+        "vwap" => Some(vec![
+            ParameterSpec::integer("swing_window", 0, Some(0.0), Some(500.0)),
+            ParameterSpec::float("band", 0.0, Some(0.0), Some(50.0)),
+        ]),
+        "supertrend" => Some(vec![
+            ParameterSpec::integer("atr_window", 10, Some(1.0), Some(500.0)),
+            ParameterSpec::float("multiplier", 3.0, Some(0.1), Some(20.0)),
+        ]),
+        "ichimoku" => Some(vec![
+            ParameterSpec::integer("tenkan", 9, Some(1.0), Some(500.0)),
+            ParameterSpec::integer("kijun", 26, Some(1.0), Some(500.0)),
+            ParameterSpec::integer("span_b", 52, Some(1.0), Some(1000.0)),
+        ]),
+        "grid" => Some(vec![
+            ParameterSpec::integer("levels", 5, Some(1.0), Some(100.0)),
+            ParameterSpec::float("spacing", 1.0, Some(0.01), Some(50.0)),
+            ParameterSpec::float("order_size", 100.0, Some(0.0), None),
+            ParameterSpec::boolean("recenter", true),
+        ]),
+        _ => None,
+    }
+}
+
+/// Parameters declared by a strategy, built in or provided by one of the factories.
+pub fn strategy_parameters(name: &str, factories: &[Box<dyn StrategyFactory>]) -> Option<Vec<ParameterSpec>> {
+    builtin_parameters(name).or_else(|| factories.iter().find(|factory| factory.name() == name).and_then(|factory| factory.parameters()))
+}
+
+/// Builds a strategy from its name and parameters. Names that are not built in are looked up in the factories.
+pub fn build_strategy(config: &StrategyConfig, factories: &[Box<dyn StrategyFactory>]) -> Box<dyn Strategy> {
+    try_build_strategy(config, factories).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Like `build_strategy`, with an error for an unknown strategy or invalid parameters.
+pub fn try_build_strategy(config: &StrategyConfig, factories: &[Box<dyn StrategyFactory>]) -> Result<Box<dyn Strategy>, String> {
+    let params = match strategy_parameters(&config.name, factories) {
+        Some(specs) => Parameters::check(&specs, &config.params).map_err(|e| format!("invalid parameters of strategy {}: {}", config.name, e))?,
+        None => Parameters::unchecked(&config.params),
+    };
+    Ok(match config.name.as_str() {
+        "simple" => Box::new(SimpleStrategy::new(params.integer("sma_window") as usize)),
+        "vwap" => Box::new(VwapStrategy::new(params.integer("swing_window") as usize, params.float("band"))),
+        "supertrend" => Box::new(SuperTrendStrategy::new(params.integer("atr_window") as usize, params.float("multiplier"))),
+        "ichimoku" => Box::new(IchimokuStrategy::new(params.integer("tenkan") as usize, params.integer("kijun") as usize, params.integer("span_b") as usize)),
+        "grid" => Box::new(GridStrategy::new(params.integer("levels") as usize, params.float("spacing"), params.float("order_size"), params.boolean("recenter"))),
+        name => match factories.iter().find(|factory| factory.name() == name) {
+            Some(factory) => factory.create(&params.to_value()),
+            None => return Err(format!("unknown strategy: {}", name)),
+        },
+    })
+}
+
+/// Checks the parameters of the built-in strategies of the config: the strategy, the strategies, and every value
+/// and range of the sweep. Other strategies, like the ones of plugins, are checked when they are built.
+pub fn check_config(config: &Config) -> Result<(), String> {
+    let check = |path: &str, strategy: &StrategyConfig| match builtin_parameters(&strategy.name) {
+        Some(specs) => Parameters::check(&specs, &strategy.params).map(|_| ()).map_err(|e| format!("{}.params: {}", path, e)),
+        None => Ok(()),
+    };
+    if let Some(strategy) = &config.strategy {
+        check("strategy", strategy)?;
+    }
+    for (index, allocation) in config.strategies.iter().enumerate() {
+        check(&format!("strategies.{}", index), &allocation.strategy)?;
+    }
+    if let Some(sweep) = &config.sweep {
+        if !config.strategies.is_empty() {
+            return Err("sweep: cannot be used with strategies".to_string());
+        }
+        if let Some((name, _)) = sweep.params.iter().find(|(_, values)| values.as_array().is_some_and(|values| values.is_empty())) {
+            return Err(format!("sweep.params.{}: lists no value to try", name));
+        }
+        for variant in sweep.variants().iter() {
+            check("sweep", variant)?;
+        }
+        crate::optimizer::resolve_ranges(sweep, builtin_parameters(&sweep.name).as_deref()).map_err(|e| format!("sweep.ranges: {}", e))?;
+        if sweep.optimizer.is_some() {
+            if let Some((name, _)) = sweep.params.iter().find(|(_, values)| values.as_array().is_some_and(|values| values.len() > 1)) {
+                return Err(format!("sweep.params.{}: with an optimizer, the parameters to search go in `ranges`, not in lists", name));
+            }
+            if sweep.ranges.is_empty() {
+                return Err("sweep.ranges: the optimizer needs at least one parameter to search".to_string());
+            }
+        }
     }
+    Ok(())
 }
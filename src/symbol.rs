@@ -0,0 +1,120 @@
+//! The traded instrument: its base asset, its quote currency, and how the exchange rounds its prices and quantities.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub base: String,
+    pub quote: String,
+    /// Decimals of the prices, when the exchange rounds them.
+    pub price_precision: Option<u32>,
+    /// Smallest increment of the quantities, when the exchange rounds them.
+    pub quantity_step: Option<f64>,
+}
+
+/// How a symbol is written in the config.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum SymbolSpec {
+    /// Base and quote separated by a slash, like `BTC/USDT`.
+    Pair(String),
+    Detailed(SymbolRules),
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct SymbolRules {
+    pair: String,
+    #[serde(default)]
+    price_precision: Option<u32>,
+    #[serde(default)]
+    quantity_step: Option<f64>,
+}
+
+impl Symbol {
+    /// Reads `BASE/QUOTE`, like `BTC/USDT`.
+    pub fn parse(pair: &str) -> Result<Self, String> {
+        match pair.split_once('/') {
+            Some((base, quote)) if !base.trim().is_empty() && !quote.trim().is_empty() && !quote.contains('/') => Ok(Self {
+                base: base.trim().to_string(),
+                quote: quote.trim().to_string(),
+                price_precision: None,
+                quantity_step: None,
+            }),
+            _ => Err(format!("invalid symbol `{}`, expected BASE/QUOTE like BTC/USDT", pair)),
+        }
+    }
+
+    /// `price` rounded to `price_precision`, unchanged without it.
+    pub fn round_price(&self, price: f64) -> f64 {
+        match self.price_precision {
+            Some(decimals) => {
+                let scale = 10f64.powi(decimals as i32);
+                (price * scale).round() / scale
+            }
+            None => price,
+        }
+    }
+
+    /// `quantity` rounded down to a multiple of `quantity_step`, unchanged without it.
+    pub fn round_quantity(&self, quantity: f64) -> f64 {
+        match self.quantity_step {
+            // the small tolerance keeps 0.3 / 0.1 from flooring to 2
+            Some(step) if step > 0.0 => (quantity / step + 1e-9).floor() * step,
+            _ => quantity,
+        }
+    }
+}
+
+impl std::fmt::Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}/{}", self.base, self.quote)
+    }
+}
+
+impl<'de> Deserialize<'de> for Symbol {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let spec = SymbolSpec::deserialize(deserializer)
+            .map_err(|_| serde::de::Error::custom("expected a symbol like \"BTC/USDT\", or {\"pair\": \"BTC/USDT\", \"price_precision\": 2, \"quantity_step\": 0.00001}"))?;
+        let rules = match spec {
+            SymbolSpec::Pair(pair) => SymbolRules {
+                pair,
+                price_precision: None,
+                quantity_step: None,
+            },
+            SymbolSpec::Detailed(rules) => rules,
+        };
+        let mut symbol = Symbol::parse(&rules.pair).map_err(serde::de::Error::custom)?;
+        if rules.quantity_step.is_some_and(|step| step <= 0.0) {
+            return Err(serde::de::Error::custom("quantity_step must be positive"));
+        }
+        symbol.price_precision = rules.price_precision;
+        symbol.quantity_step = rules.quantity_step;
+        Ok(symbol)
+    }
+}
+
+impl Serialize for Symbol {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let spec = match (self.price_precision, self.quantity_step) {
+            (None, None) => SymbolSpec::Pair(self.to_string()),
+            (price_precision, quantity_step) => SymbolSpec::Detailed(SymbolRules {
+                pair: self.to_string(),
+                price_precision,
+                quantity_step,
+            }),
+        };
+        spec.serialize(serializer)
+    }
+}
+
+impl JsonSchema for Symbol {
+    fn schema_name() -> String {
+        "Symbol".to_string()
+    }
+
+    fn json_schema(generator: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        SymbolSpec::json_schema(generator)
+    }
+}
@@ -0,0 +1,279 @@
+//! Tax lots: every buy of the base asset is a lot, and every sale is matched against the lots still held, in the order
+//! of the configured method, to report the realized gains by calendar year with their holding periods.
+
+use chrono::{Datelike, NaiveDateTime};
+use serde::Serialize;
+use crate::config::{LotMethod, TaxConfig};
+use crate::locale::Formatter;
+use crate::testing::{Direction, SessionRecap, Trade};
+
+/// The part of a sale matched against one lot.
+#[derive(Debug, Serialize)]
+pub struct Disposal {
+    /// Trade whose exit sold the asset.
+    pub trade: String,
+    /// Trade whose entry bought the lot.
+    pub lot: String,
+    pub acquired: NaiveDateTime,
+    pub disposed: NaiveDateTime,
+    pub quantity: f64,
+    pub cost: f64,
+    /// Share of the proceeds of the sale, net of the commissions of the trade.
+    pub proceeds: f64,
+    pub gain: f64,
+    pub holding_days: i64,
+    pub long_term: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TaxYear {
+    pub year: i32,
+    pub disposals: usize,
+    pub proceeds: f64,
+    pub cost: f64,
+    pub short_term_gain: f64,
+    pub long_term_gain: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TaxReport {
+    pub method: LotMethod,
+    pub years: Vec<TaxYear>,
+    pub disposals: Vec<Disposal>,
+    /// Lots still held at the end of the run, partly sold ones included.
+    pub open_lots: usize,
+}
+
+struct Lot<'a> {
+    trade: &'a Trade,
+    quantity: f64,
+    unit_cost: f64,
+}
+
+/// Matches the sales of the closed trades against the lots bought by every trade, open ones included. Short trades
+/// are their own lot, from their entry to their exit.
+pub fn report(recap: &SessionRecap, config: &TaxConfig) -> TaxReport {
+    let trades: Vec<&Trade> = recap.trades.iter().chain(recap.open_trades.iter()).chain(recap.open_lots.iter()).collect();
+    // on a shared timestamp, an exit and the entry it made room for happen in that order, but a trade opened and
+    // closed on the same candle sells after its own entry
+    let mut events: Vec<(NaiveDateTime, bool, &Trade)> = trades.iter().map(|trade| (trade.entry_date, false, *trade)).collect();
+    events.extend(trades.iter().filter_map(|trade| trade.exit_date.map(|date| (date, true, *trade))));
+    events.sort_by_key(|(date, is_sale, trade)| {
+        let rank = match is_sale {
+            true if trade.entry_date < *date => 0,
+            false => 1,
+            true => 2,
+        };
+        (*date, rank)
+    });
+    let mut lots: Vec<Lot> = Vec::new();
+    let mut disposals = Vec::new();
+    for (date, is_sale, trade) in events {
+        let quantity = trade.quantity().abs();
+        let proceeds = trade.allocated + trade.profit.unwrap_or(0.0);
+        if trade.direction == Direction::Short {
+            if is_sale {
+                disposals.push(disposal(trade, trade, date, quantity, trade.allocated, proceeds, config));
+            }
+            continue;
+        }
+        if !is_sale {
+            lots.push(Lot {
+                trade,
+                quantity,
+                unit_cost: trade.allocated / quantity,
+            });
+            continue;
+        }
+        let mut remaining = quantity;
+        while remaining > 1e-12 {
+            let held = lots.iter().enumerate().filter(|(_, lot)| lot.quantity > 1e-12);
+            let index = match config.method {
+                LotMethod::Fifo => held.map(|(index, _)| index).next(),
+                LotMethod::Lifo => held.map(|(index, _)| index).next_back(),
+                LotMethod::Hifo => held.max_by(|(_, a), (_, b)| a.unit_cost.total_cmp(&b.unit_cost)).map(|(index, _)| index),
+            };
+            let Some(index) = index else {
+                break;
+            };
+            let lot = &mut lots[index];
+            let sold = remaining.min(lot.quantity);
+            lot.quantity -= sold;
+            remaining -= sold;
+            disposals.push(disposal(trade, lot.trade, date, sold, sold * lot.unit_cost, proceeds * sold / quantity, config));
+        }
+    }
+    let mut years: Vec<TaxYear> = Vec::new();
+    for disposal in disposals.iter() {
+        let year = disposal.disposed.year();
+        if years.last().is_none_or(|last| last.year != year) {
+            years.push(TaxYear {
+                year,
+                disposals: 0,
+                proceeds: 0.0,
+                cost: 0.0,
+                short_term_gain: 0.0,
+                long_term_gain: 0.0,
+            });
+        }
+        let entry = years.last_mut().unwrap();
+        entry.disposals += 1;
+        entry.proceeds += disposal.proceeds;
+        entry.cost += disposal.cost;
+        if disposal.long_term {
+            entry.long_term_gain += disposal.gain;
+        } else {
+            entry.short_term_gain += disposal.gain;
+        }
+    }
+    TaxReport {
+        method: config.method,
+        years,
+        disposals,
+        open_lots: lots.iter().filter(|lot| lot.quantity > 1e-12).count(),
+    }
+}
+
+fn disposal(trade: &Trade, lot: &Trade, disposed: NaiveDateTime, quantity: f64, cost: f64, proceeds: f64, config: &TaxConfig) -> Disposal {
+    let acquired = lot.entry_date;
+    let holding_days = (disposed - acquired).num_days();
+    Disposal {
+        trade: trade.id.clone(),
+        lot: lot.id.clone(),
+        acquired,
+        disposed,
+        quantity,
+        cost,
+        proceeds,
+        gain: proceeds - cost,
+        holding_days,
+        long_term: holding_days > config.long_term_days,
+    }
+}
+
+/// Writes one row per disposal, in the order of the sales.
+pub fn write_disposals_csv(report: &TaxReport, file_path: &str, format: &Formatter) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = format.csv_writer(file_path)?;
+    writer.write_record(["trade", "lot", "acquired", "disposed", "quantity", "cost", "proceeds", "gain", "holding_days", "term"])?;
+    for disposal in report.disposals.iter() {
+        writer.write_record([
+            disposal.trade.clone(),
+            disposal.lot.clone(),
+            disposal.acquired.to_string(),
+            disposal.disposed.to_string(),
+            format.csv_number(disposal.quantity),
+            format.csv_number(disposal.cost),
+            format.csv_number(disposal.proceeds),
+            format.csv_number(disposal.gain),
+            disposal.holding_days.to_string(),
+            if disposal.long_term { "long_term" } else { "short_term" }.to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, NaiveDate};
+    use crate::testing::Metrics;
+
+    fn hour(hours: i64) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap() + Duration::hours(hours)
+    }
+
+    /// A long trade of one unit bought at `entry_price` and sold at `exit_price`, `None` while still open.
+    fn trade(id: &str, entry: i64, exit: Option<i64>, entry_price: f64, exit_price: f64) -> Trade {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "entry_date": hour(entry),
+            "exit_date": exit.map(hour),
+            "entry_price": entry_price,
+            "exit_price": exit.map(|_| exit_price),
+            "direction": "Long",
+            "allocated": entry_price,
+            "profit": exit.map(|_| exit_price - entry_price),
+            "commission": 0.0,
+            "ambiguous_exit": false,
+        }))
+        .unwrap()
+    }
+
+    fn recap(trades: Vec<Trade>, open_trades: Vec<Trade>) -> SessionRecap {
+        SessionRecap::new(trades, open_trades, Vec::new(), vec![(hour(0), 1000.0)], Metrics::new())
+    }
+
+    fn config(method: LotMethod) -> TaxConfig {
+        TaxConfig {
+            method,
+            long_term_days: 365,
+            report_file: None,
+        }
+    }
+
+    #[test]
+    fn a_trade_closed_on_its_entry_candle_sells_its_own_lot() {
+        let report = report(&recap(vec![trade("T1", 2, Some(2), 100.0, 110.0)], Vec::new()), &config(LotMethod::Fifo));
+        assert_eq!(report.disposals.len(), 1);
+        assert_eq!(report.disposals[0].lot, "T1");
+        assert!((report.disposals[0].gain - 10.0).abs() < 1e-9);
+        assert_eq!(report.open_lots, 0);
+    }
+
+    #[test]
+    fn an_exit_makes_room_for_the_entry_of_the_same_candle() {
+        let report = report(&recap(vec![trade("T1", 0, Some(4), 100.0, 120.0)], vec![trade("T2", 4, None, 120.0, 0.0)]), &config(LotMethod::Lifo));
+        assert_eq!(report.disposals.len(), 1);
+        assert_eq!(report.disposals[0].lot, "T1");
+        assert_eq!(report.open_lots, 1);
+    }
+
+    /// Lots of one unit bought at 100, 120 and 110 and still held, then a trade of two units bought at 90 and sold at 100.
+    fn lots_and_sale() -> SessionRecap {
+        let mut sale = trade("T4", 3, Some(4), 90.0, 100.0);
+        sale.allocated = 180.0;
+        sale.profit = Some(20.0);
+        let lots = vec![trade("T1", 0, None, 100.0, 0.0), trade("T2", 1, None, 120.0, 0.0), trade("T3", 2, None, 110.0, 0.0)];
+        recap(vec![sale], lots)
+    }
+
+    fn matched(report: &TaxReport) -> Vec<(&str, f64)> {
+        report.disposals.iter().map(|disposal| (disposal.lot.as_str(), disposal.quantity)).collect()
+    }
+
+    #[test]
+    fn a_sale_is_matched_against_the_lots_in_the_order_of_the_method() {
+        let fifo = report(&lots_and_sale(), &config(LotMethod::Fifo));
+        assert_eq!(matched(&fifo), vec![("T1", 1.0), ("T2", 1.0)]);
+        assert!((fifo.disposals[1].gain - -20.0).abs() < 1e-9);
+        assert_eq!(fifo.open_lots, 2);
+        let lifo = report(&lots_and_sale(), &config(LotMethod::Lifo));
+        assert_eq!(matched(&lifo), vec![("T4", 2.0)]);
+        assert_eq!(lifo.open_lots, 3);
+        let hifo = report(&lots_and_sale(), &config(LotMethod::Hifo));
+        assert_eq!(matched(&hifo), vec![("T2", 1.0), ("T3", 1.0)]);
+        assert!((hifo.years[0].short_term_gain - -30.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn a_partly_sold_lot_keeps_the_rest_of_its_quantity() {
+        let mut lot = trade("T1", 0, None, 100.0, 0.0);
+        lot.allocated = 300.0;
+        let report = report(&recap(vec![trade("T2", 1, Some(2), 100.0, 90.0)], vec![lot]), &config(LotMethod::Fifo));
+        assert_eq!(matched(&report), vec![("T1", 1.0)]);
+        assert_eq!(report.open_lots, 2);
+        assert!((report.disposals[0].cost - 100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn gains_are_long_term_past_the_holding_period_and_grouped_by_year() {
+        let days = |days: i64| days * 24;
+        let trades = vec![trade("T1", 0, Some(days(200)), 100.0, 110.0), trade("T2", days(10), Some(days(400)), 100.0, 150.0)];
+        let report = report(&recap(trades, Vec::new()), &config(LotMethod::Fifo));
+        assert_eq!(report.disposals.iter().map(|disposal| (disposal.holding_days, disposal.long_term)).collect::<Vec<_>>(), vec![(200, false), (390, true)]);
+        assert_eq!(report.years.iter().map(|year| year.year).collect::<Vec<_>>(), vec![2024, 2025]);
+        assert!((report.years[0].short_term_gain - 10.0).abs() < 1e-9);
+        assert!((report.years[1].long_term_gain - 50.0).abs() < 1e-9);
+    }
+}
@@ -1,9 +1,20 @@
-use std::{fs::OpenOptions, io::{BufWriter, Write}};
-use chrono::NaiveDateTime;
-use crate::{config, historical};
-use crate::strategy::{Strategy, Signal, SimpleStrategy};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+use chrono::{Datelike, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use crate::{analysis, checkpoint, config, distributed, historical, optimizer, outage, regime, reporting, risk};
+use crate::blackout::Blackouts;
+use crate::debugger::{Debugger, Snapshot};
+use crate::montecarlo::{self, MonteCarloResult};
+use crate::sizing::PositionSizer;
+use crate::regime::{Regime, RegimeBreakdown, RegimeClassifier};
+use crate::risk::{Exposure, RiskContext, RiskDecision, RiskEvent};
+use crate::indicators::Atr;
+use crate::symbol::Symbol;
+use crate::events::{BarEvent, Event, ExecutionHandler, FillEvent, LimitOrder, MarketExecution, OrderAction, OrderEvent, RejectionModel, Side, SignalEvent};
+use crate::strategy::{self, ExitLevels, Strategy, StrategyFactory, Signal, SimpleStrategy};
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum Direction {
     Long,
     Short,
@@ -20,227 +31,1132 @@ impl std::fmt::Display for Direction {
     }
 }
 
-#[derive(Debug, Clone)]
+/// Why an entry signal could not be turned into a trade.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SkipReason {
+    PositionOpen,
+    InsufficientCash,
+    RiskLimit,
+    Cooldown,
+    MinNotional,
+    Rejected,
+    Blackout,
+}
+
+impl std::fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SkipReason::PositionOpen => write!(f, "position_open"),
+            SkipReason::InsufficientCash => write!(f, "insufficient_cash"),
+            SkipReason::RiskLimit => write!(f, "risk_limit"),
+            SkipReason::Cooldown => write!(f, "cooldown"),
+            SkipReason::MinNotional => write!(f, "min_notional"),
+            SkipReason::Rejected => write!(f, "rejected"),
+            SkipReason::Blackout => write!(f, "blackout"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SkippedSignal {
+    pub date: NaiveDateTime,
+    pub direction: Direction,
+    pub reason: SkipReason,
+}
+
+/// Why a trade was closed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ExitReason {
+    Signal,
+    EndOfData,
+    StopLoss,
+    TakeProfit,
+    /// The stop-loss after a trailing stop moved it.
+    TrailingStop,
+    /// The lot was sold by a limit order of the strategy.
+    LimitOrder,
+    /// Closed when a blackout window started.
+    Blackout,
+}
+
+impl std::fmt::Display for ExitReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ExitReason::Signal => write!(f, "signal"),
+            ExitReason::EndOfData => write!(f, "end_of_data"),
+            ExitReason::StopLoss => write!(f, "stop_loss"),
+            ExitReason::TakeProfit => write!(f, "take_profit"),
+            ExitReason::TrailingStop => write!(f, "trailing_stop"),
+            ExitReason::LimitOrder => write!(f, "limit_order"),
+            ExitReason::Blackout => write!(f, "blackout"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Trade {
+    /// `T1` for the first position opened, prefixed with the strategy (`S2.T1`) in multi-strategy sessions.
+    #[serde(default)]
+    pub id: String,
+    /// Id of the order that opened the trade, `O1` for the first order, prefixed like the trade id.
+    #[serde(default)]
+    pub entry_order: String,
+    /// Id of the order that closed the trade, `None` while open or when closed by the end of the data.
+    #[serde(default)]
+    pub exit_order: Option<String>,
     pub entry_date: NaiveDateTime,
     pub exit_date: Option<NaiveDateTime>,
     pub entry_price: f64,
     pub exit_price: Option<f64>,
+    /// Prices the orders of the trade were decided at, see `OrderEvent::signal_price`. `entry_price` and `exit_price`
+    /// are the ones paid after the execution, slippage included.
+    #[serde(default)]
+    pub signal_entry_price: f64,
+    #[serde(default)]
+    pub signal_exit_price: Option<f64>,
     pub direction: Direction,
     pub allocated: f64,
     pub profit: Option<f64>,
     pub commission: f64,
+    pub exit_reason: Option<ExitReason>,
+    pub stop_loss: Option<f64>,
+    pub take_profit: Option<f64>,
+    /// Moves `stop_loss` with the price, see `Trade::trail`.
+    #[serde(default)]
+    pub trailing_stop: Option<config::TrailingStop>,
+    /// Set once the trailing stop moved `stop_loss`.
+    #[serde(default)]
+    pub trailed: bool,
+    /// Set when the stop-loss and take-profit were both inside the exit candle.
+    pub ambiguous_exit: bool,
+    /// Base asset received, net of the entry commission, with `Spot` accounting.
+    #[serde(default)]
+    pub received: Option<f64>,
+    /// Interest paid on the borrowed asset of a short trade, see `config.borrow_rate`.
+    #[serde(default)]
+    pub financing: f64,
+}
+
+impl Trade {
+    /// Pips earned between the effective entry and exit prices, negative for a loss. `None` while the trade is open.
+    pub fn pips(&self, pip: f64) -> Option<f64> {
+        let moved = self.exit_price? - self.entry_price;
+        Some(if self.direction == Direction::Short { -moved } else { moved } / pip)
+    }
+
+    /// Moves the stop-loss to the distance of the trailing stop behind `best`, the best price reached, if that
+    /// tightens it. An ATR trail waits for `atr` to be known.
+    fn trail(&mut self, best: f64, atr: Option<f64>, symbol: &Symbol) {
+        let distance = match self.trailing_stop {
+            Some(config::TrailingStop::Percent(pct)) => best * pct / 100.0,
+            Some(config::TrailingStop::Atr(multiplier)) => match atr {
+                Some(atr) => multiplier * atr,
+                None => return,
+            },
+            Some(config::TrailingStop::None) | None => return,
+        };
+        let side = if self.direction == Direction::Long { 1.0 } else { -1.0 };
+        let level = symbol.round_price(best - side * distance);
+        if self.stop_loss.is_none_or(|stop| side * (level - stop) > 0.0) {
+            self.stop_loss = Some(level);
+            self.trailed = true;
+        }
+    }
+
+    /// Amount of the base asset bought by the trade, negative when it was sold short.
+    pub fn quantity(&self) -> f64 {
+        let quantity = self.received.unwrap_or(self.allocated / self.entry_price);
+        if self.direction == Direction::Short { -quantity } else { quantity }
+    }
+}
+
+/// The metrics of the run so far, taken on a candle, see `config::MetricSnapshots`.
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricSnapshot {
+    pub date: NaiveDateTime,
+    /// Candles processed, those before the checkpoint the run resumed from included.
+    pub bars: usize,
+    pub equity: f64,
+    pub cash: f64,
+    /// How far the equity is below its highest value so far, in percentage.
+    pub drawdown: f64,
+    pub open_positions: usize,
+    pub closed_trades: usize,
+    pub total_profit: f64,
+    pub win_rate: f64,
+    #[serde(serialize_with = "float_or_inf::serialize")]
+    pub profit_factor: f64,
+    pub sharpe_ratio: f64,
+}
+
+/// The run in progress, published every few candles with `dashboard`, see `live_status`.
+#[derive(Debug, Clone, Serialize)]
+pub struct LiveStatus {
+    pub date: NaiveDateTime,
+    /// Candles processed and candles of the data, those before the checkpoint the run resumed from included.
+    pub bars: usize,
+    pub total_bars: usize,
+    pub equity: f64,
+    pub cash: f64,
+    /// The equity curve so far, thinned to at most `LIVE_CURVE_POINTS` points.
+    pub equity_curve: Vec<(NaiveDateTime, f64)>,
+    pub open_trades: Vec<Trade>,
+    /// The last closed trades, the most recent first.
+    pub recent_trades: Vec<Trade>,
+    pub closed_trades: usize,
+    pub total_profit: f64,
+    /// Set once the data has run out, the status being the final one.
+    pub finished: bool,
+}
+
+/// Points of the equity curve of `LiveStatus`, and number of updates published over a run.
+const LIVE_CURVE_POINTS: usize = 500;
+/// Closed trades kept in `LiveStatus::recent_trades`.
+const LIVE_RECENT_TRADES: usize = 20;
+
+static LIVE_STATUS: Mutex<Option<LiveStatus>> = Mutex::new(None);
+
+/// The last status published by a run with `dashboard`, `None` before the first one.
+pub fn live_status() -> Option<LiveStatus> {
+    LIVE_STATUS.lock().unwrap().clone()
+}
+
+/// Publishes the status of the run for `live_status`.
+#[allow(clippy::too_many_arguments)]
+fn publish_status(date: NaiveDateTime, bars: usize, total_bars: usize, equity: f64, cash: f64, equity_curve: &[(NaiveDateTime, f64)], open_trades: Vec<Trade>, closed_trades: &[Trade], finished: bool) {
+    let step = equity_curve.len().div_ceil(LIVE_CURVE_POINTS).max(1);
+    let mut curve: Vec<(NaiveDateTime, f64)> = equity_curve.iter().step_by(step).copied().collect();
+    if let Some(last) = equity_curve.last().filter(|last| curve.last() != Some(*last)) {
+        curve.push(*last);
+    }
+    *LIVE_STATUS.lock().unwrap() = Some(LiveStatus {
+        date,
+        bars,
+        total_bars,
+        equity,
+        cash,
+        equity_curve: curve,
+        open_trades,
+        recent_trades: closed_trades.iter().rev().take(LIVE_RECENT_TRADES).cloned().collect(),
+        closed_trades: closed_trades.len(),
+        total_profit: closed_trades.iter().filter_map(|trade| trade.profit).sum(),
+        finished,
+    });
+}
+
+/// A strategy and the revision of its code, see `strategy::fingerprint`.
+#[derive(Debug, Clone, Serialize)]
+pub struct StrategyFingerprint {
+    /// Name and parameters of the strategy.
+    pub strategy: String,
+    pub fingerprint: String,
+}
+
+/// What one strategy of a multi-strategy session did with its share of the capital.
+pub struct StrategyContribution {
+    pub name: String,
+    pub initial_weight: f64,
+    pub final_weight: f64,
+    pub recap: SessionRecap,
 }
 
 pub struct SessionRecap {
     pub trades: Vec<Trade>,
+    /// Trades still open at the end of the data, only set when they are marked to market. A single strategy leaves
+    /// at most one, a multi-strategy session one per strategy.
+    pub open_trades: Vec<Trade>,
+    /// Lots bought by limit orders and still open at the end of the data, only set when they are marked to market.
+    pub open_lots: Vec<Trade>,
+    /// Timestamp of the checkpoint the run was resumed from, if any.
+    pub resumed_at: Option<NaiveDateTime>,
+    /// Taken during the run with `metric_snapshots`, those of this run only when it was resumed.
+    pub snapshots: Vec<MetricSnapshot>,
+    /// Revision of the code of each strategy run, see `strategy_fingerprints`.
+    pub fingerprints: Vec<StrategyFingerprint>,
+    /// Orders changed or vetoed by the risk managers.
+    pub risk_events: Vec<RiskEvent>,
+    /// One entry per strategy in multi-strategy sessions, empty otherwise.
+    pub contributions: Vec<StrategyContribution>,
+    /// Benchmark aligned on the equity curve and rebased to the same start, empty without a benchmark.
+    pub benchmark_curve: Vec<(NaiveDateTime, f64)>,
+    /// Set when `monte_carlo` is configured and at least one trade was closed.
+    pub monte_carlo: Option<MonteCarloResult>,
+    /// Set when `exit_analysis` is configured.
+    pub exit_analysis: Option<crate::exits::ExitAnalysis>,
+    /// Returns after the entries at each horizon, empty without `forward_returns`.
+    pub forward_returns: Vec<analysis::ForwardReturn>,
+    /// Hit rates of the entries of each strategy, empty without `signal_quality`.
+    pub signal_quality: Vec<analysis::SignalQuality>,
+    /// Values returned by `Strategy::indicators` on every candle, by name, when `record_indicators` is set.
+    /// In multi-strategy sessions the names are prefixed with the label of the strategy.
+    pub indicators: BTreeMap<String, Vec<(NaiveDateTime, f64)>>,
+    /// Every variant of the sweep when there was one, this recap being the one of the best variant.
+    pub sweep: Option<SweepResult>,
+    /// Regime of every candle once classified, when `regime` is configured.
+    pub regimes: Vec<(NaiveDateTime, Regime)>,
+    /// Results in each regime, empty without `regime`.
+    pub regime_breakdown: Vec<RegimeBreakdown>,
+    /// Set when `outages` is configured.
+    pub outage_impact: Option<outage::OutageImpact>,
+    /// Set when `monthly_restart` is enabled.
+    pub monthly_restart: Option<MonthlyRestart>,
+    /// Holdings by currency at the end of the run, see `Portfolio::balances`.
+    pub balances: BTreeMap<String, f64>,
+    /// Deposits and withdrawals made during the run, see `Portfolio::cash_flows`.
+    pub cash_flows: Vec<(NaiveDateTime, f64)>,
+    /// Set when `reporting_currency` is configured.
+    pub reporting: Option<reporting::ReportingResult>,
+    pub skipped_signals: Vec<SkippedSignal>,
     pub equity_curve: Vec<(NaiveDateTime, f64)>,
     pub metrics: Metrics,
 }
 
 impl SessionRecap {
-    pub fn new(trades: Vec<Trade>, equity_curve: Vec<(NaiveDateTime, f64)>, metrics: Metrics) -> Self {
+    pub fn new(trades: Vec<Trade>, open_trades: Vec<Trade>, skipped_signals: Vec<SkippedSignal>, equity_curve: Vec<(NaiveDateTime, f64)>, metrics: Metrics) -> Self {
         Self {
             trades,
+            open_trades,
+            open_lots: Vec::new(),
+            resumed_at: None,
+            snapshots: Vec::new(),
+            fingerprints: Vec::new(),
+            risk_events: Vec::new(),
+            contributions: Vec::new(),
+            benchmark_curve: Vec::new(),
+            monte_carlo: None,
+            exit_analysis: None,
+            forward_returns: Vec::new(),
+            signal_quality: Vec::new(),
+            indicators: BTreeMap::new(),
+            sweep: None,
+            regimes: Vec::new(),
+            regime_breakdown: Vec::new(),
+            outage_impact: None,
+            monthly_restart: None,
+            balances: BTreeMap::new(),
+            cash_flows: Vec::new(),
+            reporting: None,
+            skipped_signals,
             equity_curve,
             metrics,
         }
     }
 }
 
+/// The part of a `Portfolio` that is saved in checkpoints.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PortfolioState {
+    /// Quote currency free for new entries.
+    pub cash: f64,
+    /// Missing from the checkpoints of earlier versions, rebuilt from `cash` and the positions then.
+    #[serde(default)]
+    pub balances: BTreeMap<String, f64>,
+    pub open_trade: Option<Trade>,
+    #[serde(default)]
+    pub lots: BTreeMap<usize, Trade>,
+    #[serde(default)]
+    pub limit_orders: Vec<LimitOrder>,
+    pub closed_trades: Vec<Trade>,
+    pub skipped_signals: Vec<SkippedSignal>,
+    pub bars_since_exit: Option<usize>,
+    pub equity_curve: Vec<(NaiveDateTime, f64)>,
+    /// Number of orders sent so far, for the ids of the next ones.
+    #[serde(default)]
+    pub orders: usize,
+    #[serde(default)]
+    pub cash_flows: Vec<(NaiveDateTime, f64)>,
+}
+
 struct Portfolio<'a> {
-    cash: f64,
+    /// Holdings by currency: the quote currency and the base asset of the pair. A long position is base asset held, a
+    /// short one is base asset owed, with the proceeds of its sale in the quote currency until it is bought back.
+    balances: BTreeMap<String, f64>,
     open_trade: Option<Trade>,
+    /// Positions opened by the buy limit orders of the strategy, by order id. They live next to `open_trade`.
+    lots: BTreeMap<usize, Trade>,
+    /// Limit orders resting until the next candle, as returned by the strategy after the last one.
+    limit_orders: Vec<LimitOrder>,
     closed_trades: Vec<Trade>,
+    skipped_signals: Vec<SkippedSignal>,
+    bars_since_exit: Option<usize>,
     equity_curve: Vec<(NaiveDateTime, f64)>,
+    orders: usize,
+    /// Deposits and withdrawals made so far, withdrawals being negative.
+    cash_flows: Vec<(NaiveDateTime, f64)>,
+    /// Prefix of the trade and order ids, telling apart the strategies of multi-strategy sessions.
+    id_prefix: String,
     commission_rate: f64,
     slippage: f64,
-    trade_fraction: f64,
     log_buffer: Vec<String>,
     log_buffer_size: usize,
     config: &'a config::Config,
 }
 
+/// The last lines of the log are written when the portfolio goes away, at the end of the run or while a panic unwinds.
+impl Drop for Portfolio<'_> {
+    fn drop(&mut self) {
+        // a failed write must not panic again while unwinding
+        if let Err(e) = self.write_log_buffer() {
+            eprintln!("Error while writing log file: {}", e);
+        }
+    }
+}
+
 impl<'a> Portfolio<'a> {
-    fn new(initial_equity: f64, commission_rate: f64, slippage: f64, trade_fraction: f64, config: &'a config::Config) -> Self {
+    fn new(initial_equity: f64, commission_rate: f64, slippage: f64, config: &'a config::Config) -> Self {
         Self {
-            cash: initial_equity,
+            balances: BTreeMap::from([(config.symbol.quote.clone(), initial_equity)]),
             open_trade: None,
+            lots: BTreeMap::new(),
+            limit_orders: Vec::new(),
             closed_trades: Vec::new(),
+            skipped_signals: Vec::new(),
+            bars_since_exit: None,
             equity_curve: Vec::new(),
+            orders: 0,
+            cash_flows: Vec::new(),
+            id_prefix: String::new(),
             commission_rate,
             slippage,
-            trade_fraction,
             log_buffer: Vec::new(),
             log_buffer_size: 10,
             config,
         }
     }
 
+    fn state(&self) -> PortfolioState {
+        PortfolioState {
+            cash: self.cash(),
+            balances: self.balances.clone(),
+            open_trade: self.open_trade.clone(),
+            lots: self.lots.clone(),
+            limit_orders: self.limit_orders.clone(),
+            closed_trades: self.closed_trades.clone(),
+            skipped_signals: self.skipped_signals.clone(),
+            bars_since_exit: self.bars_since_exit,
+            equity_curve: self.equity_curve.clone(),
+            orders: self.orders,
+            cash_flows: self.cash_flows.clone(),
+        }
+    }
+
+    fn restore(&mut self, state: PortfolioState) {
+        self.open_trade = state.open_trade;
+        self.lots = state.lots;
+        self.balances = state.balances;
+        if self.balances.is_empty() {
+            let quantity: f64 = self.open_trade.iter().chain(self.lots.values()).map(Trade::quantity).sum();
+            self.add_balance(&self.config.symbol.base, quantity);
+            self.set_cash(state.cash);
+        }
+        self.limit_orders = state.limit_orders;
+        self.closed_trades = state.closed_trades;
+        self.skipped_signals = state.skipped_signals;
+        self.bars_since_exit = state.bars_since_exit;
+        self.equity_curve = state.equity_curve;
+        self.orders = state.orders;
+        self.cash_flows = state.cash_flows;
+    }
+
+    /// Adds the holdings and the position of `initial` to the free cash, the position being opened as the first trade at
+    /// its own price and date, `date` when it has none, without slippage nor commission: they were paid before the
+    /// backtest. A short holds its proceeds and its margin like one opened by `open`.
+    fn seed(&mut self, initial: &config::InitialPortfolio, date: NaiveDateTime, log_level: &config::LogLevel) {
+        let symbol = &self.config.symbol;
+        for (currency, amount) in initial.holdings.iter() {
+            self.add_balance(currency, *amount);
+        }
+        let Some(position) = &initial.position else {
+            return;
+        };
+        let direction = match position.direction {
+            config::PositionSide::Long => Direction::Long,
+            config::PositionSide::Short => Direction::Short,
+        };
+        // the holdings, the position and its date were checked when the config was loaded
+        let entry_date = position.entry_date.as_deref().and_then(|entry_date| config::parse_date(entry_date).ok()).unwrap_or(date);
+        let side = if direction == Direction::Long { 1.0 } else { -1.0 };
+        let allocated = position.quantity * position.entry_price;
+        let order_id = self.next_order_id();
+        let trade = Trade {
+            id: format!("{}T{}", self.id_prefix, self.entries() + 1),
+            entry_order: order_id,
+            exit_order: None,
+            entry_date,
+            exit_date: None,
+            entry_price: position.entry_price,
+            exit_price: None,
+            signal_entry_price: position.entry_price,
+            signal_exit_price: None,
+            direction,
+            allocated,
+            profit: None,
+            commission: 0.0,
+            exit_reason: None,
+            stop_loss: position.stop_loss.or(self.config.stop_loss.map(|pct| symbol.round_price(position.entry_price * (1.0 - side * pct / 100.0)))),
+            take_profit: position.take_profit.or(self.config.take_profit.map(|pct| symbol.round_price(position.entry_price * (1.0 + side * pct / 100.0)))),
+            trailing_stop: self.config.trailing_stop,
+            trailed: false,
+            ambiguous_exit: false,
+            received: (self.config.accounting == config::Accounting::Spot).then_some(position.quantity),
+            financing: 0.0,
+        };
+        self.add_balance(&symbol.base, trade.quantity());
+        if direction == Direction::Short {
+            self.add_balance(&symbol.quote, 2.0 * allocated);
+        }
+        let (id, entry_order) = (trade.id.clone(), trade.entry_order.clone());
+        self.open_trade = Some(trade);
+        if let config::LogLevel::All = log_level {
+            self.log_buffer.push(format!(
+                "{}: Starting with {} trade {} (order {}) entered {} at price {:.decimals$}: {:.4} {} ({:.2} {}), {:.2} {} free",
+                date,
+                direction,
+                id,
+                entry_order,
+                entry_date,
+                position.entry_price,
+                position.quantity,
+                symbol.base,
+                allocated,
+                symbol.quote,
+                self.cash(),
+                symbol.quote,
+                decimals = self.config.price_decimals()
+            ));
+            self.flush_log_buffer();
+        }
+    }
+
+    /// Writes the buffered lines once there are `log_buffer_size` of them.
     fn flush_log_buffer(&mut self) {
         if self.log_buffer.len() < self.log_buffer_size {
             return;
         }
-        let log_file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&self.config.log_file)
-            .unwrap();
-        let mut log_writer = BufWriter::new(log_file);
-        for log in self.log_buffer.iter() {
-            log_writer.write_all(log.as_bytes()).unwrap();
-            log_writer.write_all(b"\n").unwrap();
+        self.write_log_buffer().expect("Error while writing log file");
+    }
+
+    fn write_log_buffer(&mut self) -> std::io::Result<()> {
+        // without the `files` feature the config is rejected unless its log_level is None, so nothing is buffered
+        #[cfg(feature = "files")]
+        if !self.log_buffer.is_empty() {
+            crate::logging::append(self.config, &self.log_buffer)?;
         }
         self.log_buffer.clear();
+        Ok(())
+    }
+
+    fn balance(&self, currency: &str) -> f64 {
+        self.balances.get(currency).copied().unwrap_or(0.0)
+    }
+
+    fn add_balance(&mut self, currency: &str, amount: f64) {
+        let balance = self.balances.entry(currency.to_string()).or_default();
+        *balance += amount;
+        // what is left of a position once closed is rounding error, not dust worth reporting
+        if balance.abs() < 1e-12 {
+            *balance = 0.0;
+        }
+    }
+
+    /// Quote currency free for new entries. A short sale holds its proceeds, and as much again as margin, until it is
+    /// bought back.
+    fn cash(&self) -> f64 {
+        let held: f64 = self.open_trade.iter().chain(self.lots.values()).filter(|trade| trade.direction == Direction::Short).map(|trade| 2.0 * trade.allocated).sum();
+        self.balance(&self.config.symbol.quote) - held
+    }
+
+    fn set_cash(&mut self, cash: f64) {
+        let difference = cash - self.cash();
+        self.add_balance(&self.config.symbol.quote, difference);
+    }
+
+    /// Pays the interest of one candle on the base asset borrowed by the short positions, valued at `price`.
+    fn accrue_financing(&mut self, price: f64, periods_per_year: f64) {
+        let rate = self.config.borrow_rate / 100.0 / periods_per_year;
+        let mut paid = 0.0;
+        for trade in self.open_trade.iter_mut().chain(self.lots.values_mut()).filter(|trade| trade.direction == Direction::Short) {
+            let interest = trade.quantity().abs() * price * rate;
+            trade.financing += interest;
+            paid += interest;
+        }
+        self.add_balance(&self.config.symbol.quote, -paid);
+    }
+
+    /// Deposits `amount` of the quote currency, or withdraws it when negative. A withdrawal takes at most the free cash.
+    fn transfer(&mut self, date: NaiveDateTime, amount: f64, log_level: &config::LogLevel) {
+        let transferred = amount.max(-self.cash().max(0.0));
+        self.add_balance(&self.config.symbol.quote, transferred);
+        self.cash_flows.push((date, transferred));
+        match log_level {
+            config::LogLevel::None => {}
+            _ => {
+                let shortfall = if transferred != amount { format!(" out of {:.2} requested", -amount) } else { String::new() };
+                let kind = if transferred < 0.0 { "Withdrawal" } else { "Deposit" };
+                self.log_buffer.push(format!("{}: {} of {:.2} {}{}, now holding {:.2} {}.", date, kind, transferred.abs(), self.config.symbol.quote, shortfall, self.cash(), self.config.symbol.quote));
+                self.flush_log_buffer();
+            }
+        }
+    }
+
+    /// Market value of the base asset of a position, whichever its side.
+    fn trade_value(trade: &Trade, current_price: f64) -> f64 {
+        trade.quantity().abs() * current_price
     }
 
+    /// Every balance valued in the quote currency at `current_price`.
     fn total_equity(&self, current_price: f64) -> f64 {
-        if let Some(trade) = &self.open_trade {
-            let trade_value = if trade.direction == Direction::Long {
-                // Long: value scales with price / entry_price.
-                trade.allocated * (current_price / trade.entry_price)
-            } else {
-                // Short: profit when price falls.
-                trade.allocated * (trade.entry_price / current_price)
-            };
-            self.cash + trade_value
-        } else {
-            self.cash
+        self.balance(&self.config.symbol.quote) + self.balance(&self.config.symbol.base) * current_price
+    }
+
+    fn exposure(&self, current_price: f64) -> Exposure {
+        let mut exposure = Exposure {
+            equity: self.total_equity(current_price),
+            ..Exposure::default()
+        };
+        for trade in self.open_trade.iter().chain(self.lots.values()) {
+            match trade.direction {
+                Direction::Short => exposure.short += Self::trade_value(trade, current_price),
+                _ => exposure.long += Self::trade_value(trade, current_price),
+            }
         }
+        exposure
+    }
+
+    /// Whether there is no position at all, neither a trade nor a lot.
+    fn is_flat(&self) -> bool {
+        self.open_trade.is_none() && self.lots.is_empty()
+    }
+
+    /// Number of positions opened so far, closed or not.
+    fn entries(&self) -> usize {
+        self.closed_trades.len() + self.open_trade.iter().count() + self.lots.len()
+    }
+
+    /// Id of a new order. Every order gets one, whether it goes through or not.
+    fn next_order_id(&mut self) -> String {
+        self.orders += 1;
+        format!("{}O{}", self.id_prefix, self.orders)
     }
 
     fn update(&mut self, date: NaiveDateTime, price: f64) {
         let equity = self.total_equity(price);
         self.equity_curve.push((date, equity));
+        if let Some(bars) = self.bars_since_exit.as_mut() {
+            *bars += 1;
+        }
     }
 
-    pub fn enter_trade(&mut self, date: NaiveDateTime, price: f64, direction: Direction, log_level: &config::LogLevel) {
-        if !self.open_trade.is_none() {
-            match log_level {
-                config::LogLevel::None => {}
-                _ => {
-                    self.log_buffer.push(format!("{}: Trade already open, cannot enter another trade.", date));
-                    self.flush_log_buffer();
-                }
+    fn skip_entry(&mut self, date: NaiveDateTime, direction: Direction, reason: SkipReason, order_id: &str, message: &str, log_level: &config::LogLevel) {
+        self.skipped_signals.push(SkippedSignal { date, direction, reason });
+        match log_level {
+            config::LogLevel::None => {}
+            _ => {
+                self.log_buffer.push(format!("{}: Entry order {} skipped [{}]: {}", date, order_id, reason, message));
+                self.flush_log_buffer();
             }
+        }
+    }
+
+    pub fn enter_trade(&mut self, date: NaiveDateTime, price: f64, direction: Direction, allocated: f64, order_id: &str, log_level: &config::LogLevel) {
+        if self.open_trade.is_some() {
+            self.skip_entry(date, direction, SkipReason::PositionOpen, order_id, "Trade already open, cannot enter another trade.", log_level);
             return;
         }
-        let allocated = self.cash * self.trade_fraction;
-        if allocated <= 0.0 {
-            match log_level {
-                config::LogLevel::None => {}
-                _ => {
-                    self.log_buffer.push(format!("{}: Not enough cash to enter trade.", date));
-                    self.flush_log_buffer();
-                }
+        if let Some(bars) = self.bars_since_exit {
+            if bars < self.config.cooldown_bars {
+                let message = format!("Cooldown active, {} of {} bars elapsed since last exit.", bars, self.config.cooldown_bars);
+                self.skip_entry(date, direction, SkipReason::Cooldown, order_id, &message, log_level);
+                return;
             }
+        }
+        if !self.can_afford(date, price, direction, allocated, order_id, log_level) {
             return;
         }
-        let effective_entry_price = if direction == Direction::Long {
-            price * (1.0 + self.slippage)
+        let mut trade = self.open(date, price, direction, allocated, order_id);
+        let side = if direction == Direction::Long { 1.0 } else { -1.0 };
+        let symbol = &self.config.symbol;
+        trade.stop_loss = self.config.stop_loss.map(|pct| symbol.round_price(trade.entry_price * (1.0 - side * pct / 100.0)));
+        trade.take_profit = self.config.take_profit.map(|pct| symbol.round_price(trade.entry_price * (1.0 + side * pct / 100.0)));
+        let purchased_amount = trade.quantity().abs();
+
+        if let config::LogLevel::All = log_level {
+            self.log_buffer.push(format!(
+                "{}: Entering {} trade {} (order {}) at effective price {:.decimals$}. Entry commission is {:.2}. Allocated: {:.2} {} ({:.4} {}), {:.2} {} remaining)",
+                date,
+                direction,
+                trade.id,
+                order_id,
+                trade.entry_price,
+                trade.commission,
+                trade.allocated,
+                self.config.symbol.quote,
+                purchased_amount,
+                self.config.symbol.base,
+                self.cash(),
+                self.config.symbol.quote,
+                decimals = self.config.price_decimals()
+            ));
+            self.flush_log_buffer();
+        }
+
+        self.open_trade = Some(trade);
+    }
+
+    /// Checks there is enough cash for an entry at `price` and that it is above the minimum notional and the quantity
+    /// step of the symbol, skipping it otherwise.
+    fn can_afford(&mut self, date: NaiveDateTime, price: f64, direction: Direction, allocated: f64, order_id: &str, log_level: &config::LogLevel) -> bool {
+        if allocated <= 0.0 || allocated > self.cash() {
+            self.skip_entry(date, direction, SkipReason::InsufficientCash, order_id, "Not enough cash to enter trade.", log_level);
+            return false;
+        }
+        if allocated < self.config.min_notional {
+            let message = format!("Allocation of {:.2} {} is below the minimum notional of {:.2} {}.", allocated, self.config.symbol.quote, self.config.min_notional, self.config.symbol.quote);
+            self.skip_entry(date, direction, SkipReason::MinNotional, order_id, &message, log_level);
+            return false;
+        }
+        if let Some(step) = self.config.symbol.quantity_step.filter(|_| self.config.symbol.round_quantity(allocated / price) <= 0.0) {
+            let message = format!("Allocation of {:.2} {} buys less than the quantity step of {} {}.", allocated, self.config.symbol.quote, step, self.config.symbol.base);
+            self.skip_entry(date, direction, SkipReason::MinNotional, order_id, &message, log_level);
+            return false;
+        }
+        true
+    }
+
+    /// Swaps `allocated` of the quote currency for the base asset, or the other way around for a short sale, and returns
+    /// the trade `order_id` opens at `price`, with slippage and the entry commission.
+    fn open(&mut self, date: NaiveDateTime, price: f64, direction: Direction, allocated: f64, order_id: &str) -> Trade {
+        let symbol = &self.config.symbol;
+        let effective_entry_price = symbol.round_price(if direction == Direction::Long {
+            price * (1.0 + self.slippage(allocated)) + self.half_spread()
         } else { // Short
-            price * (1.0 - self.slippage)
+            price * (1.0 - self.slippage(allocated)) - self.half_spread()
+        });
+        // the exchange only fills whole steps of the asset
+        let allocated = match symbol.quantity_step {
+            Some(_) => symbol.round_quantity(allocated / effective_entry_price) * effective_entry_price,
+            None => allocated,
         };
-        let purchased_amount = allocated / effective_entry_price;
-        let entry_commission = (self.commission_rate * allocated) / 100.0;
-        self.cash -= allocated;
+        let commission = (self.commission_rate * allocated) / 100.0;
+        let spot = self.config.accounting == config::Accounting::Spot;
         let trade = Trade {
+            id: format!("{}T{}", self.id_prefix, self.entries() + 1),
+            entry_order: order_id.to_string(),
+            exit_order: None,
             entry_date: date,
             exit_date: None,
             entry_price: effective_entry_price,
             exit_price: None,
+            signal_entry_price: price,
+            signal_exit_price: None,
             direction,
             allocated,
             profit: None,
-            commission: entry_commission,
+            commission,
+            exit_reason: None,
+            stop_loss: None,
+            take_profit: None,
+            trailing_stop: None,
+            trailed: false,
+            ambiguous_exit: false,
+            // the exchange keeps its commission out of the asset bought
+            received: spot.then_some((allocated - commission) / effective_entry_price),
+            financing: 0.0,
         };
+        // in `Cfd` accounting both commissions are paid when the trade is closed
+        self.add_balance(&self.config.symbol.base, trade.quantity());
+        self.add_balance(&self.config.symbol.quote, -allocated * trade.quantity().signum());
+        trade
+    }
 
-        match log_level {
-            config::LogLevel::All => {
+    /// Opens a lot for a filled buy limit order, or closes the lot of a filled sell. Returns whether the order went
+    /// through: a buy for a lot that is already open, or without enough cash, is skipped.
+    fn fill_limit(&mut self, fill: &FillEvent, order: &LimitOrder, log_level: &config::LogLevel) -> bool {
+        match order.side {
+            Side::Buy => {
+                if self.lots.contains_key(&order.id) {
+                    let message = format!("Lot {} is already open.", order.id);
+                    self.skip_entry(fill.date, Direction::Long, SkipReason::PositionOpen, &fill.order_id, &message, log_level);
+                    return false;
+                }
+                if !self.can_afford(fill.date, fill.price, Direction::Long, fill.size, &fill.order_id, log_level) {
+                    return false;
+                }
+                let trade = self.open(fill.date, fill.price, Direction::Long, fill.size, &fill.order_id);
+                if let config::LogLevel::All = log_level {
+                    self.log_buffer.push(format!(
+                        "{}: Buy limit order {} (order {}) filled at effective price {:.decimals$}, opening lot {} as trade {}. Entry commission is {:.2}. Allocated: {:.2} {}, {:.2} {} remaining",
+                        fill.date,
+                        order.id,
+                        fill.order_id,
+                        trade.entry_price,
+                        order.id,
+                        trade.id,
+                        trade.commission,
+                        trade.allocated,
+                        self.config.symbol.quote,
+                        self.cash(),
+                        self.config.symbol.quote,
+                        decimals = self.config.price_decimals()
+                    ));
+                    self.flush_log_buffer();
+                }
+                self.lots.insert(order.id, trade);
+                true
+            }
+            Side::Sell => match self.lots.remove(&order.id) {
+                Some(trade) => {
+                    self.close(trade, fill.date, fill.price, ExitReason::LimitOrder, Some(&fill.order_id), log_level);
+                    true
+                }
+                None => {
+                    match log_level {
+                        config::LogLevel::None => {}
+                        _ => {
+                            self.log_buffer.push(format!("{}: No lot {} to sell (order {}).", fill.date, order.id, fill.order_id));
+                            self.flush_log_buffer();
+                        }
+                    }
+                    false
+                }
+            },
+        }
+    }
+
+    /// Moves the trailing stop of the trade open before `kline` with its extreme in the direction of the trade. The
+    /// candle of the entry is skipped, the price may have gone there before the entry, unless trades are entered at
+    /// the open of the candle.
+    fn trail_stop(&mut self, kline: &historical::Kline, atr: Option<f64>, entered_at_open: bool) {
+        let symbol = &self.config.symbol;
+        if let Some(trade) = self.open_trade.as_mut().filter(|trade| entered_at_open || trade.entry_date < kline.timestamp) {
+            let best = if trade.direction == Direction::Long { kline.high } else { kline.low };
+            trade.trail(best, atr, symbol);
+        }
+    }
+
+    /// Returns an exit order if the candle reached the stop-loss or take-profit of the open trade.
+    /// When both levels are inside the candle, `assumption` decides which one was hit first.
+    fn exit_level_order(&mut self, kline: &historical::Kline, assumption: &config::IntrabarAssumption) -> Option<OrderEvent> {
+        let trade = self.open_trade.as_mut()?;
+        let long = trade.direction == Direction::Long;
+        let stop_hit = trade.stop_loss.is_some_and(|level| if long { kline.low <= level } else { kline.high >= level });
+        let target_hit = trade.take_profit.is_some_and(|level| if long { kline.high >= level } else { kline.low <= level });
+        let stop_first = match (stop_hit, target_hit) {
+            (false, false) => return None,
+            (true, false) => true,
+            (false, true) => false,
+            (true, true) => {
+                trade.ambiguous_exit = true;
+                match assumption {
+                    config::IntrabarAssumption::WorstCase => true,
+                    config::IntrabarAssumption::BestCase => false,
+                    config::IntrabarAssumption::OhlcPath => {
+                        // the candle is assumed to travel to the closest extreme first
+                        let high_first = kline.high - kline.open < kline.open - kline.low;
+                        high_first != long
+                    }
+                }
+            }
+        };
+        let (reason, level) = match (stop_first, trade.trailed) {
+            (true, false) => (ExitReason::StopLoss, trade.stop_loss.unwrap()),
+            (true, true) => (ExitReason::TrailingStop, trade.stop_loss.unwrap()),
+            (false, _) => (ExitReason::TakeProfit, trade.take_profit.unwrap()),
+        };
+        // a candle opening past the level fills at the open
+        let gapped = if long == stop_first { kline.open < level } else { kline.open > level };
+        let price = if gapped { kline.open } else { level };
+        Some(OrderEvent {
+            id: self.next_order_id(),
+            date: kline.timestamp,
+            price,
+            signal_price: level,
+            action: OrderAction::Exit(reason),
+            size: 0.0,
+        })
+    }
+
+    /// Returns the limit order of the fill when it went through, for the strategy to be told.
+    fn on_fill(&mut self, fill: &FillEvent, log_level: &config::LogLevel) -> Option<LimitOrder> {
+        let filled = match fill.action {
+            OrderAction::Enter(direction) => {
+                self.enter_trade(fill.date, fill.price, direction, fill.size, &fill.order_id, log_level);
+                None
+            }
+            OrderAction::Exit(reason) => {
+                self.exit_trade(fill.date, fill.price, reason, Some(&fill.order_id), log_level);
+                None
+            }
+            OrderAction::Limit(order) => self.fill_limit(fill, &order, log_level).then_some(order),
+        };
+        // the trades were booked at the price of the fill, their execution is measured from the one of the order
+        let order_id = Some(fill.order_id.as_str());
+        for trade in self.open_trade.iter_mut().chain(self.lots.values_mut()).filter(|trade| trade.entry_order == fill.order_id) {
+            trade.signal_entry_price = fill.signal_price;
+        }
+        for trade in self.closed_trades.iter_mut().rev().take_while(|trade| trade.exit_order.as_deref() == order_id) {
+            trade.signal_exit_price = Some(fill.signal_price);
+        }
+        filled
+    }
+
+    /// Slippage of an order of `notional`, from `slippage_model` when it is set.
+    fn slippage(&self, notional: f64) -> f64 {
+        match &self.config.slippage_model {
+            Some(model) => model.slippage(notional),
+            None => self.slippage,
+        }
+    }
+
+    /// Distance from the middle price to the bid or the ask, with `fx`: buys pay it above the price, sells below.
+    fn half_spread(&self) -> f64 {
+        self.config.fx.as_ref().map_or(0.0, |fx| fx.spread_pips * fx.pip(&self.config.symbol) / 2.0)
+    }
+
+    /// Effective exit price, exit commission and raw profit of `trade` if it were closed at `price`.
+    fn settle(&self, trade: &Trade, price: f64) -> (f64, f64, f64) {
+        let effective_exit_price = self.config.symbol.round_price(if trade.direction == Direction::Long {
+            price * (1.0 - self.slippage(trade.allocated)) - self.half_spread()
+        } else {
+            price * (1.0 + self.slippage(trade.allocated)) + self.half_spread()
+        });
+        if trade.received.is_some() {
+            // spot: the asset held is sold, and the commission is kept out of the proceeds
+            let proceeds = trade.quantity() * effective_exit_price;
+            let exit_commission = (self.commission_rate * proceeds) / 100.0;
+            return (effective_exit_price, exit_commission, proceeds - trade.allocated + trade.commission);
+        }
+        let exit_commission = (self.commission_rate * trade.allocated) / 100.0;
+        let raw_profit = if trade.direction == Direction::Long {
+            trade.allocated * ((effective_exit_price - trade.entry_price) / trade.entry_price)
+        } else {
+            trade.allocated * ((trade.entry_price - effective_exit_price) / trade.entry_price)
+        };
+        (effective_exit_price, exit_commission, raw_profit)
+    }
+
+    /// Values the open trade and lots at `price` without closing them, storing the unrealized profit on each of them.
+    fn mark_to_market(&mut self, date: NaiveDateTime, price: f64, log_level: &config::LogLevel) {
+        let marked: Vec<(String, f64, f64)> = self
+            .open_trade
+            .iter()
+            .chain(self.lots.values())
+            .map(|trade| {
+                let (effective_exit_price, exit_commission, raw_profit) = self.settle(trade, price);
+                (trade.id.clone(), effective_exit_price, raw_profit - trade.commission - exit_commission - trade.financing)
+            })
+            .collect();
+        for (trade, (_, _, unrealized)) in self.open_trade.iter_mut().chain(self.lots.values_mut()).zip(marked.iter()) {
+            trade.profit = Some(*unrealized);
+        }
+        if let config::LogLevel::All = log_level {
+            for (id, effective_exit_price, unrealized) in marked {
                 self.log_buffer.push(format!(
-                    "{}: Entering {} trade at effective price {:.2}. Entry commission is {:.2}. Allocated: {:.2} {} ({:.4} {}), {:.2} {} remaining)",
+                    "{}: End of data, open trade {} marked to market at effective price {:.decimals$}, unrealized profit: {:.2} {}.",
                     date,
-                    direction,
-                    effective_entry_price,
-                    entry_commission,
-                    allocated,
-                    self.config.quote_currency,
-                    purchased_amount,
-                    self.config.base_currency,
-                    self.cash,
-                    self.config.quote_currency
+                    id,
+                    effective_exit_price,
+                    unrealized,
+                    self.config.symbol.quote,
+                    decimals = self.config.price_decimals()
                 ));
                 self.flush_log_buffer();
             }
-            _ => {}
         }
+    }
 
-        self.open_trade = Some(trade);
+    /// Closes the open trade and every lot at `price`.
+    fn close_all(&mut self, date: NaiveDateTime, price: f64, reason: ExitReason, order_id: Option<&str>, log_level: &config::LogLevel) {
+        if self.open_trade.is_some() {
+            self.exit_trade(date, price, reason, order_id, log_level);
+        }
+        for (_, trade) in std::mem::take(&mut self.lots) {
+            self.close(trade, date, price, reason, order_id, log_level);
+        }
     }
 
-    fn exit_trade(&mut self, date: NaiveDateTime, price: f64, log_level: &config::LogLevel) {
-        let mut trade = match self.open_trade.take() {
+    fn exit_trade(&mut self, date: NaiveDateTime, price: f64, reason: ExitReason, order_id: Option<&str>, log_level: &config::LogLevel) {
+        let trade = match self.open_trade.take() {
             Some(trade) => trade,
             None => {
                 match log_level {
                     config::LogLevel::None => {}
                     _ => {
-                        self.log_buffer.push(format!("{}: No trade to exit.", date));
+                        self.log_buffer.push(format!("{}: No trade to exit{}.", date, order_id.map(|id| format!(" (order {})", id)).unwrap_or_default()));
                         self.flush_log_buffer();
                     }
                 }
                 return;
             }
         };
-        let effective_exit_price = if trade.direction == Direction::Long {
-            price * (1.0 - self.slippage)
-        } else {
-            price * (1.0 + self.slippage)
-        };
-        let exit_commission = (self.commission_rate * trade.allocated) / 100.0;
-        let raw_profit = if trade.direction == Direction::Long {
-            trade.allocated * ((effective_exit_price - trade.entry_price) / trade.entry_price)
-        } else {
-            trade.allocated * ((trade.entry_price - effective_exit_price) / trade.entry_price)
-        };
+        self.close(trade, date, price, reason, order_id, log_level);
+    }
+
+    /// Settles a trade taken out of the open positions and adds it to the closed trades. `order_id` is the order that
+    /// closed it, `None` when the end of the data did.
+    fn close(&mut self, mut trade: Trade, date: NaiveDateTime, price: f64, reason: ExitReason, order_id: Option<&str>, log_level: &config::LogLevel) {
+        let (effective_exit_price, exit_commission, raw_profit) = self.settle(&trade, price);
+        let unpaid = if trade.received.is_some() { exit_commission } else { trade.commission + exit_commission };
         trade.commission += exit_commission;
-        let net_profit = raw_profit - trade.commission;
+        // the financing was paid along the way
+        let net_profit = raw_profit - trade.commission - trade.financing;
         trade.exit_date = Some(date);
         trade.exit_price = Some(effective_exit_price);
+        trade.signal_exit_price = Some(price);
         trade.profit = Some(net_profit);
-        let final_trade_value = trade.allocated + net_profit;
-        self.cash += final_trade_value;
+        trade.exit_reason = Some(reason);
+        trade.exit_order = order_id.map(str::to_string);
+        self.add_balance(&self.config.symbol.base, -trade.quantity());
+        self.add_balance(&self.config.symbol.quote, trade.quantity() * effective_exit_price - unpaid);
+        self.bars_since_exit = Some(0);
 
-        match log_level {
-            config::LogLevel::All => {
-                self.log_buffer.push(format!(
-                    "{}: Exiting trade at effective price {:.2}, net profit: {:.2}. Total broker commission is {:.2} {} Now holding {:.2} {}.",
-                    date,
-                    effective_exit_price,
-                    net_profit,
-                    trade.commission,
-                    self.config.quote_currency,
-                    self.cash,
-                    self.config.quote_currency
-                ));
-                self.flush_log_buffer();
-            }
-            _ => {}
+        if let config::LogLevel::All = log_level {
+            self.log_buffer.push(format!(
+                "{}: Exiting trade {} [{}]{} at effective price {:.decimals$}, net profit: {:.2}{}. Total broker commission is {:.2} {} Now holding {:.2} {}.",
+                date,
+                trade.id,
+                reason,
+                order_id.map(|id| format!(" (order {})", id)).unwrap_or_default(),
+                effective_exit_price,
+                net_profit,
+                self.config.fx.as_ref().map(|fx| format!(" ({:+.1} pips)", trade.pips(fx.pip(&self.config.symbol)).unwrap_or(0.0))).unwrap_or_default(),
+                trade.commission,
+                self.config.symbol.quote,
+                self.cash(),
+                self.config.symbol.quote,
+                decimals = self.config.price_decimals()
+            ));
+            self.flush_log_buffer();
         }
 
         self.closed_trades.push(trade);
     }
 }
 
+/// The amounts of `Metrics` in percentage of an equity.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct MetricPercentages {
+    pub total_profit: f64,
+    pub total_commission: f64,
+    pub total_financing: f64,
+    pub avg_profit: f64,
+    pub avg_loss: f64,
+    pub max_drawdown: f64,
+    pub unrealized_profit: f64,
+    pub net_deposits: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Metrics {
     pub total_trades: usize,
     pub total_profit: f64,
     pub total_commission: f64,
+    /// Interest paid on borrowed assets, included in the profit of the trades.
+    #[serde(default)]
+    pub total_financing: f64,
     pub win_rate: f64,
     pub avg_profit: f64,
     pub avg_loss: f64,
     pub max_drawdown: f64,
     pub max_drawdown_duration: usize,
+    pub skipped_signals: usize,
+    pub skipped_by_reason: Vec<(SkipReason, usize)>,
+    pub unrealized_profit: f64,
+    pub ambiguous_exits: usize,
+    pub risk_resizes: usize,
+    pub risk_vetoes: usize,
+    /// Entries filled at a worse price than asked, see `events::RejectionModel`.
+    #[serde(default)]
+    pub requotes: usize,
+    /// Deposits minus withdrawals, see `config::CashFlow`.
+    #[serde(default)]
+    pub net_deposits: f64,
+    /// Growth of the equity in percentage, chaining the returns of the candles so the cash flows do not count.
+    #[serde(default)]
+    pub time_weighted_return: f64,
+    /// Annualized internal rate of return of the capital, the cash flows and the final equity, in percentage.
+    #[serde(default)]
+    pub money_weighted_return: f64,
+    /// Annualized Sharpe ratio of the per-bar returns of the equity curve, with a zero risk-free rate.
+    pub sharpe_ratio: f64,
+    /// Gross profit divided by gross loss, infinite when no trade lost money, see `float_or_inf`.
+    #[serde(with = "float_or_inf")]
+    pub profit_factor: f64,
+    /// The amounts in percentage of the capital at the start of the run.
+    #[serde(default)]
+    pub percent_of_initial: MetricPercentages,
+    /// The amounts in percentage of the highest equity of the run.
+    #[serde(default)]
+    pub percent_of_peak: MetricPercentages,
+}
+
+/// Annual rate at which the equity at the start, the cash flows and the final equity are worth nothing together, found
+/// by bisection. The flows of the first candle are part of the equity at the start. Zero when there is none.
+fn money_weighted_return(equity_curve: &[(NaiveDateTime, f64)], cash_flows: &[(NaiveDateTime, f64)]) -> f64 {
+    let (Some(&(start, initial)), Some(&(end, last))) = (equity_curve.first(), equity_curve.last()) else {
+        return 0.0;
+    };
+    if end <= start || initial <= 0.0 {
+        return 0.0;
+    }
+    let years = |date: NaiveDateTime| (date - start).num_seconds() as f64 / (365.25 * 86400.0);
+    let mut flows = vec![(0.0, -initial)];
+    flows.extend(cash_flows.iter().filter(|(date, _)| *date > start).map(|(date, amount)| (years(*date), -amount)));
+    flows.push((years(end), last));
+    let value = |rate: f64| flows.iter().map(|(time, amount)| amount / (1.0 + rate).powf(*time)).sum::<f64>();
+    let (mut low, mut high) = (-0.999999, 1.0);
+    while value(high) > 0.0 && high < 1e9 {
+        high *= 2.0;
+    }
+    if value(low) < 0.0 || value(high) > 0.0 {
+        return 0.0;
+    }
+    for _ in 0..200 {
+        let middle = (low + high) / 2.0;
+        if value(middle) > 0.0 {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    (low + high) / 2.0
+}
+
+/// JSON has no infinity, which serde_json writes as `null`: an infinite value is written as the string `"inf"` (or
+/// `"-inf"`) instead, and read back from it. The `null` of older files is read as infinite.
+pub(crate) mod float_or_inf {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Number(f64),
+        Text(String),
+    }
+
+    pub fn serialize<S: Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+        match *value {
+            f64::INFINITY => serializer.serialize_str("inf"),
+            f64::NEG_INFINITY => serializer.serialize_str("-inf"),
+            value => serializer.serialize_f64(value),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+        match Option::<Value>::deserialize(deserializer)? {
+            Some(Value::Number(value)) => Ok(value),
+            Some(Value::Text(text)) if text == "inf" => Ok(f64::INFINITY),
+            Some(Value::Text(text)) if text == "-inf" => Ok(f64::NEG_INFINITY),
+            Some(Value::Text(text)) => Err(serde::de::Error::custom(format!("expected a number or \"inf\", got \"{}\"", text))),
+            None => Ok(f64::INFINITY),
+        }
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Metrics {
@@ -249,19 +1165,74 @@ impl Metrics {
             total_trades: 0,
             total_profit: 0.0,
             total_commission: 0.0,
+            total_financing: 0.0,
             win_rate: 0.0,
             avg_profit: 0.0,
             avg_loss: 0.0,
             max_drawdown: 0.0,
             max_drawdown_duration: 0,
+            skipped_signals: 0,
+            skipped_by_reason: Vec::new(),
+            unrealized_profit: 0.0,
+            ambiguous_exits: 0,
+            risk_resizes: 0,
+            risk_vetoes: 0,
+            requotes: 0,
+            net_deposits: 0.0,
+            time_weighted_return: 0.0,
+            money_weighted_return: 0.0,
+            sharpe_ratio: 0.0,
+            profit_factor: 0.0,
+            percent_of_initial: MetricPercentages::default(),
+            percent_of_peak: MetricPercentages::default(),
+        }
+    }
+
+    /// Fills `percent_of_initial` and `percent_of_peak` from the amounts, once they are all set. Zero when the equity
+    /// is not positive.
+    pub fn compute_percentages(&mut self, initial_equity: f64, equity_curve: &[(NaiveDateTime, f64)]) {
+        let peak = equity_curve.iter().map(|(_, equity)| *equity).fold(initial_equity, f64::max);
+        let percentages = |equity: f64| {
+            let percent = |amount: f64| if equity > 0.0 { amount / equity * 100.0 } else { 0.0 };
+            MetricPercentages {
+                total_profit: percent(self.total_profit),
+                total_commission: percent(self.total_commission),
+                total_financing: percent(self.total_financing),
+                avg_profit: percent(self.avg_profit),
+                avg_loss: percent(self.avg_loss),
+                max_drawdown: percent(self.max_drawdown),
+                unrealized_profit: percent(self.unrealized_profit),
+                net_deposits: percent(self.net_deposits),
+            }
+        };
+        self.percent_of_initial = percentages(initial_equity);
+        self.percent_of_peak = percentages(peak);
+    }
+
+    /// `cash_flows` are the deposits and withdrawals made on the candles of the curve, taken out of their returns.
+    pub fn compute_returns(&mut self, equity_curve: &[(NaiveDateTime, f64)], cash_flows: &[(NaiveDateTime, f64)], periods_per_year: f64) {
+        let flow_at = |date: NaiveDateTime| cash_flows.iter().filter(|(flow_date, _)| *flow_date == date).map(|(_, amount)| amount).sum::<f64>();
+        let returns: Vec<f64> = equity_curve.windows(2).map(|w| if w[0].1 != 0.0 { (w[1].1 - flow_at(w[1].0)) / w[0].1 - 1.0 } else { 0.0 }).collect();
+        self.net_deposits = cash_flows.iter().map(|(_, amount)| amount).sum();
+        self.time_weighted_return = (returns.iter().map(|r| 1.0 + r).product::<f64>() - 1.0) * 100.0;
+        self.money_weighted_return = money_weighted_return(equity_curve, cash_flows) * 100.0;
+        if returns.len() < 2 {
+            self.sharpe_ratio = 0.0;
+            return;
         }
+        let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+        let deviation = (returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (returns.len() - 1) as f64).sqrt();
+        self.sharpe_ratio = if deviation > 0.0 { mean / deviation * periods_per_year.sqrt() } else { 0.0 };
     }
 
-    pub fn compute(&mut self, trade_list: &Vec<Trade>) {
+    pub fn compute(&mut self, trade_list: &[Trade], skipped_list: &[SkippedSignal]) {
         let mut total_profit = 0.0;
         let mut total_commission = 0.0;
+        self.total_financing = 0.0;
         let mut total_wins = 0;
         let mut total_losses = 0;
+        let mut gross_profit = 0.0;
+        let mut gross_loss = 0.0;
         let mut max_drawdown = 0.0;
         let mut max_drawdown_duration = 0;
         let mut current_drawdown = 0.0;
@@ -270,10 +1241,13 @@ impl Metrics {
         for trade in trade_list.iter() {
             total_profit += trade.profit.unwrap();
             total_commission += trade.commission;
+            self.total_financing += trade.financing;
             if trade.profit.unwrap() > 0.0 {
                 total_wins += 1;
+                gross_profit += trade.profit.unwrap();
             } else {
                 total_losses += 1;
+                gross_loss -= trade.profit.unwrap();
             }
             if trade.profit.unwrap() < 0.0 {
                 current_drawdown += trade.profit.unwrap();
@@ -312,38 +1286,1687 @@ impl Metrics {
         self.avg_loss = avg_loss;
         self.max_drawdown = max_drawdown;
         self.max_drawdown_duration = max_drawdown_duration;
+        self.profit_factor = if gross_loss > 0.0 {
+            gross_profit / gross_loss
+        } else if gross_profit > 0.0 {
+            f64::INFINITY
+        } else {
+            0.0
+        };
+
+        let mut skipped_by_reason: Vec<(SkipReason, usize)> = Vec::new();
+        for skipped in skipped_list.iter() {
+            match skipped_by_reason.iter_mut().find(|(reason, _)| *reason == skipped.reason) {
+                Some((_, count)) => *count += 1,
+                None => skipped_by_reason.push((skipped.reason, 1)),
+            }
+        }
+        self.ambiguous_exits = trade_list.iter().filter(|trade| trade.ambiguous_exit).count();
+        self.skipped_signals = skipped_list.len();
+        self.skipped_by_reason = skipped_by_reason;
     }
 }
 
-pub fn run_simulation(config: &config::Config, klines: &Vec<historical::Kline>) -> SessionRecap {
-    let mut portfolio = Portfolio::new(
-        config.base_funds,
-        config.transaction_fee,
-        config.slippage,
-        0.1,
-        config
-    );
-    let mut strategy = SimpleStrategy::new(14);
-    for kline in klines.iter() {
-        let signal = strategy.on_tick(kline);
-        match signal {
-            Some(Signal::Buy) => {
-                portfolio.enter_trade(kline.timestamp, kline.close, Direction::Long, &config.log_level);
-            }
-            Some(Signal::Sell) => {
-                portfolio.exit_trade(kline.timestamp, kline.close, &config.log_level);
-            }
-            Some(Signal::Hold) => {}
-            None => { continue; }
+fn save_checkpoint(path: &str, bars_processed: usize, last_timestamp: NaiveDateTime, engine: &Engine) -> Result<(), SimulationError> {
+    let saved = checkpoint::Checkpoint {
+        bars_processed,
+        last_timestamp,
+        portfolio: engine.portfolio.state(),
+        strategy: engine.strategy.save_state(),
+        regime: engine.regime.as_ref().map(|classifier| serde_json::to_value(classifier).expect("error while serializing regime classifier")).unwrap_or_default(),
+        regimes: engine.regimes.clone(),
+        rejections: engine.rejections.as_ref().map(|model| serde_json::to_value(model).expect("error while serializing rejection model")).unwrap_or_default(),
+        requotes: engine.requotes,
+        risk_managers: engine.risk_managers.iter().map(|manager| manager.save_state()).collect(),
+        pending_signal: engine.pending_signal.as_ref().map(|(signal, price)| (signal.clone(), *price)),
+        trailing_atr: serde_json::to_value(&engine.trailing_atr).expect("error while serializing trailing ATR"),
+        sizer: engine.sizer.save_state(),
+    };
+    checkpoint::save_checkpoint(path, &saved).map_err(|e| SimulationError::Checkpoint(format!("error while writing checkpoint: {}", e)))
+}
+
+/// Why a simulation could not run.
+#[derive(Debug)]
+pub enum SimulationError {
+    /// A setting is invalid, or cannot be used with another one.
+    InvalidConfig(String),
+    /// The strategy is unknown or its parameters are invalid.
+    Strategy(String),
+    /// The checkpoint to resume from could not be read, or a checkpoint could not be written.
+    Checkpoint(String),
+}
+
+impl std::fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SimulationError::InvalidConfig(message) | SimulationError::Strategy(message) | SimulationError::Checkpoint(message) => write!(f, "{}", message),
         }
-        portfolio.update(kline.timestamp, kline.close);
     }
-    if portfolio.open_trade.is_some() {
-        portfolio.exit_trade(klines.last().unwrap().timestamp, klines.last().unwrap().close, &config.log_level);
+}
+
+impl std::error::Error for SimulationError {}
+
+/// How the orders of a simulation are filled.
+#[derive(Debug, Clone)]
+pub enum ExecutionMode {
+    /// Every order is filled at its price, see `events::MarketExecution`.
+    Market,
+    /// Entry orders can be rejected or requoted, see `events::RejectionModel`.
+    Rejections(config::RejectionConfig),
+}
+
+/// What the engine does around the strategy: how orders are filled and sized, which risk managers review them, and
+/// what happens to the positions still open at the end of the data. `EngineOptions::from_config` takes them from the
+/// config; programs driving the library can build them directly.
+#[derive(Debug, Clone)]
+pub struct EngineOptions {
+    pub execution: ExecutionMode,
+    pub sizing: config::Sizing,
+    pub risk_managers: Vec<config::RiskManagerConfig>,
+    pub end_of_data: config::EndOfData,
+    pub signal_fill: config::SignalFill,
+}
+
+impl EngineOptions {
+    pub fn from_config(config: &config::Config) -> Self {
+        Self {
+            execution: match &config.rejections {
+                Some(rejections) => ExecutionMode::Rejections(rejections.clone()),
+                None => ExecutionMode::Market,
+            },
+            sizing: config.sizing.clone(),
+            risk_managers: config.risk_managers.clone(),
+            end_of_data: config.end_of_data.clone(),
+            signal_fill: config.signal_fill.clone(),
+        }
+    }
+}
+
+/// One strategy trading its own portfolio, fed one candle at a time.
+struct Engine<'a> {
+    portfolio: Portfolio<'a>,
+    strategy: Box<dyn Strategy>,
+    execution: MarketExecution,
+    /// Replaces `execution` when `rejections` is set.
+    rejections: Option<RejectionModel>,
+    requotes: usize,
+    sizer: PositionSizer,
+    risk_managers: Vec<Box<dyn risk::RiskManager>>,
+    risk_events: Vec<RiskEvent>,
+    indicators: BTreeMap<String, Vec<(NaiveDateTime, f64)>>,
+    regime: Option<RegimeClassifier>,
+    regimes: Vec<(NaiveDateTime, Regime)>,
+    /// First and last candle of each outage, see `outage::windows`.
+    outages: Vec<(NaiveDateTime, NaiveDateTime)>,
+    blackouts: Option<Blackouts>,
+    /// Equity and positions of the other strategies of a multi-strategy session, for the risk managers.
+    others: Exposure,
+    /// Deposits and withdrawals still to come, in order, see `cash_flow_schedule`.
+    cash_flows: VecDeque<(NaiveDateTime, f64)>,
+    /// Capital the engine started with, before any checkpoint.
+    capital: f64,
+    end_of_data: config::EndOfData,
+    signal_fill: config::SignalFill,
+    /// Signal of the previous candle and its close, filled at the open of this one with `SignalFill::NextOpen`.
+    pending_signal: Option<(Signal, f64)>,
+    /// Average true range of the candles so far, for the `Atr` trailing stops.
+    trailing_atr: Atr,
+    config: &'a config::Config,
+}
+
+impl<'a> Engine<'a> {
+    /// Pauses in the debugger when the candle hit a breakpoint.
+    fn debug(&mut self, debugger: &mut Debugger, kline: &historical::Kline) {
+        let equity = self.portfolio.total_equity(kline.close);
+        let transferred: f64 = self.portfolio.cash_flows.iter().rev().take_while(|(date, _)| *date == kline.timestamp).map(|(_, amount)| amount).sum();
+        if let Some(reason) = debugger.check(kline.timestamp, equity, transferred, self.portfolio.entries()) {
+            // the log is flushed first so it can be read along
+            self.portfolio.write_log_buffer().expect("Error while writing log file");
+            let snapshot = Snapshot {
+                kline,
+                equity,
+                portfolio: self.portfolio.state(),
+                strategy: self.strategy.save_state(),
+                indicators: self.strategy.indicators(),
+            };
+            debugger.pause(&reason, &snapshot);
+        }
+    }
+
+    fn new(config: &'a config::Config, options: &EngineOptions, strategy: Box<dyn Strategy>, capital: f64) -> Result<Self, SimulationError> {
+        Ok(Self {
+            portfolio: Portfolio::new(capital, config.transaction_fee, config.slippage, config),
+            strategy,
+            execution: MarketExecution,
+            rejections: match &options.execution {
+                ExecutionMode::Market => None,
+                ExecutionMode::Rejections(rejections) => Some(RejectionModel::new(rejections, rejections.seed)),
+            },
+            requotes: 0,
+            sizer: PositionSizer::new(&options.sizing),
+            risk_managers: risk::build_risk_managers(&options.risk_managers),
+            risk_events: Vec::new(),
+            indicators: BTreeMap::new(),
+            regime: config.regime.as_ref().map(RegimeClassifier::new),
+            regimes: Vec::new(),
+            outages: Vec::new(),
+            blackouts: config.blackouts.as_ref().map(Blackouts::new).transpose().map_err(SimulationError::InvalidConfig)?,
+            others: Exposure::default(),
+            cash_flows: VecDeque::new(),
+            capital,
+            end_of_data: options.end_of_data.clone(),
+            signal_fill: options.signal_fill.clone(),
+            pending_signal: None,
+            trailing_atr: Atr::new(config.trailing_atr_window.max(1)),
+            config,
+        })
+    }
+
+    /// Starts a new account with `cash` once every position was closed, as `monthly_restart` does at the end of each
+    /// month: the orders of the previous account, resting limit orders and a signal waiting for the next open, are
+    /// dropped. What was learned from the candles is kept, the indicators of the strategy like the volatilities of
+    /// the risk managers and of the regime classifier, as a trader starting a new evaluation still knows the market.
+    fn restart(&mut self, cash: f64) {
+        self.portfolio.set_cash(cash);
+        self.portfolio.limit_orders.clear();
+        self.pending_signal = None;
+    }
+
+    /// Takes the metrics of the run so far at the close of `kline`, logging them.
+    fn snapshot(&mut self, kline: &historical::Kline, bars: usize) -> MetricSnapshot {
+        let portfolio = &mut self.portfolio;
+        let equity = portfolio.total_equity(kline.close);
+        let peak = portfolio.equity_curve.iter().map(|(_, equity)| *equity).fold(equity.max(self.capital), f64::max);
+        let mut metrics = Metrics::new();
+        metrics.compute(&portfolio.closed_trades, &portfolio.skipped_signals);
+        metrics.compute_returns(&portfolio.equity_curve, &portfolio.cash_flows, analysis::annualization(self.config));
+        let snapshot = MetricSnapshot {
+            date: kline.timestamp,
+            bars,
+            equity,
+            cash: portfolio.cash(),
+            drawdown: if peak > 0.0 { (peak - equity) / peak * 100.0 } else { 0.0 },
+            open_positions: portfolio.open_trade.iter().count() + portfolio.lots.len(),
+            closed_trades: metrics.total_trades,
+            total_profit: metrics.total_profit,
+            win_rate: metrics.win_rate,
+            profit_factor: metrics.profit_factor,
+            sharpe_ratio: metrics.sharpe_ratio,
+        };
+        match self.config.log_level {
+            config::LogLevel::None => {}
+            _ => {
+                portfolio.log_buffer.push(format!(
+                    "{}: Snapshot after {} bars: equity {:.2} {} ({:.2}% below peak), {} closed trades, profit {:.2} {}, win rate {:.2}%, profit factor {:.2}, Sharpe ratio {:.2}.",
+                    kline.timestamp,
+                    bars,
+                    equity,
+                    self.config.symbol.quote,
+                    snapshot.drawdown,
+                    snapshot.closed_trades,
+                    snapshot.total_profit,
+                    self.config.symbol.quote,
+                    snapshot.win_rate * 100.0,
+                    snapshot.profit_factor,
+                    snapshot.sharpe_ratio
+                ));
+                portfolio.flush_log_buffer();
+            }
+        }
+        snapshot
+    }
+
+    /// Starts from `initial_portfolio` at the first candle, the initial capital becoming the equity at its open.
+    fn seed(&mut self, first: &historical::Kline) {
+        let Some(initial) = &self.config.initial_portfolio else {
+            return;
+        };
+        self.portfolio.seed(initial, first.timestamp, &self.config.log_level);
+        self.capital = self.portfolio.total_equity(first.open);
+    }
+
+    /// Runs every event triggered by `kline`. Returns `false` when the strategy had no signal for it.
+    fn step(&mut self, kline: &historical::Kline) -> bool {
+        let config = self.config;
+        let portfolio = &mut self.portfolio;
+        // the positions held over the candle pay their interest, whether the exchange is up or not
+        if config.borrow_rate > 0.0 {
+            portfolio.accrue_financing(kline.close, analysis::annualization(config));
+        }
+        // transfers come from outside the exchange, they are made whether it is up or not
+        while let Some(&(_, amount)) = self.cash_flows.front().filter(|(date, _)| *date <= kline.timestamp) {
+            self.cash_flows.pop_front();
+            portfolio.transfer(kline.timestamp, amount, &config.log_level);
+        }
+        // during an outage nothing reaches the strategy and nothing is filled, but the positions keep their value
+        if let Some((start, end)) = self.outages.iter().find(|(start, end)| *start <= kline.timestamp && kline.timestamp <= *end) {
+            if *start == kline.timestamp && !matches!(config.log_level, config::LogLevel::None) {
+                portfolio.log_buffer.push(format!("{}: Exchange outage until {}, no data and no fills.", start, end));
+                portfolio.flush_log_buffer();
+            }
+            portfolio.update(kline.timestamp, kline.close);
+            return false;
+        }
+        let mut has_signal = true;
+        let mut queue: VecDeque<Event> = VecDeque::new();
+        // the signal reversing the open trade, held until its exit is filled
+        let mut reversal: Option<SignalEvent> = None;
+        // the exits attached to the signal of the entry order, until it is filled
+        let mut entry_levels: Option<(String, ExitLevels)> = None;
+        // the signal of the last candle is filled at the open, before the candle is known, so the trade it opens has
+        // its stops checked on this candle
+        if let Some((signal, signal_price)) = self.pending_signal.take() {
+            queue.push_back(Event::Signal(SignalEvent {
+                date: kline.timestamp,
+                price: kline.open,
+                signal_price,
+                signal,
+            }));
+        }
+        let mut bar = Some(Event::Bar(BarEvent { kline }));
+        while let Some(event) = queue.pop_front().or_else(|| bar.take()) {
+            match event {
+                Event::Bar(bar) => {
+                    // no entry can happen inside a window, so only the positions held when it started are closed
+                    if self.blackouts.as_ref().is_some_and(|blackouts| blackouts.flatten && blackouts.contains(bar.kline.timestamp)) && !portfolio.is_flat() {
+                        let order_id = portfolio.next_order_id();
+                        portfolio.close_all(bar.kline.timestamp, bar.kline.open, ExitReason::Blackout, Some(&order_id), &config.log_level);
+                    }
+                    self.sizer.on_bar(bar.kline);
+                    for manager in self.risk_managers.iter_mut() {
+                        manager.on_bar(bar.kline);
+                    }
+                    if let Some(order) = portfolio.exit_level_order(bar.kline, &config.intrabar_assumption) {
+                        queue.push_back(Event::Order(order));
+                    }
+                    for order in portfolio.limit_orders.clone() {
+                        if let Some(price) = order.fill_price(bar.kline) {
+                            queue.push_back(Event::Order(OrderEvent {
+                                id: portfolio.next_order_id(),
+                                date: bar.kline.timestamp,
+                                price,
+                                signal_price: order.price,
+                                action: OrderAction::Limit(order),
+                                size: order.size,
+                            }));
+                        }
+                    }
+                    if let Some(regime) = self.regime.as_mut().and_then(|classifier| classifier.update(bar.kline)) {
+                        self.regimes.push((bar.kline.timestamp, regime));
+                        self.strategy.on_regime(regime);
+                    }
+                    let signal = self.strategy.on_tick(bar.kline);
+                    if config.record_indicators || config.indicators_file.is_some() {
+                        for (name, value) in self.strategy.indicators() {
+                            self.indicators.entry(name).or_default().push((bar.kline.timestamp, value));
+                        }
+                    }
+                    if let config::LogLevel::All | config::LogLevel::Signals = config.log_level {
+                        let mut line = format!("{}: Signal: {} at close {:.*}", bar.kline.timestamp, signal.as_ref().map(|signal| format!("{:?}", signal)).unwrap_or("no signal".to_string()), config.price_decimals(), bar.kline.close);
+                        let indicators: Vec<String> = self.strategy.indicators().iter().map(|(name, value)| format!("{}={:.2}", name, value)).collect();
+                        if !indicators.is_empty() {
+                            line.push_str(&format!(" [{}]", indicators.join(", ")));
+                        }
+                        portfolio.log_buffer.push(line);
+                        portfolio.flush_log_buffer();
+                    }
+                    match signal {
+                        Some(signal) if matches!(self.signal_fill, config::SignalFill::NextOpen) => self.pending_signal = Some((signal, bar.kline.close)),
+                        Some(signal) => queue.push_back(Event::Signal(SignalEvent {
+                            date: bar.kline.timestamp,
+                            price: bar.kline.close,
+                            signal_price: bar.kline.close,
+                            signal,
+                        })),
+                        None => has_signal = false,
+                    }
+                }
+                Event::Signal(signal) => {
+                    let Some(direction) = signal.signal.direction() else {
+                        continue;
+                    };
+                    let allowed = match config.direction_filter {
+                        config::DirectionFilter::LongOnly => direction == Direction::Long,
+                        config::DirectionFilter::ShortOnly => direction == Direction::Short,
+                        config::DirectionFilter::Both => true,
+                    };
+                    let reverses = portfolio.open_trade.as_ref().is_some_and(|trade| trade.direction != direction);
+                    // a signal for a side that is not traded only closes the open trade
+                    let (action, size) = if allowed && !reverses {
+                        let size = self.sizer.size(portfolio.cash(), portfolio.total_equity(signal.price), signal.price);
+                        (OrderAction::Enter(direction), size)
+                    } else {
+                        (OrderAction::Exit(ExitReason::Signal), 0.0)
+                    };
+                    let id = portfolio.next_order_id();
+                    if let OrderAction::Enter(_) = action {
+                        entry_levels = Some((id.clone(), signal.signal.exit_levels()));
+                    }
+                    queue.push_back(Event::Order(OrderEvent {
+                        id,
+                        date: signal.date,
+                        price: signal.price,
+                        signal_price: signal.signal_price,
+                        action,
+                        size,
+                    }));
+                    if allowed && reverses {
+                        reversal = Some(signal);
+                    }
+                }
+                Event::Order(mut order) => {
+                    let entry = match order.action {
+                        OrderAction::Enter(direction) => Some(direction),
+                        OrderAction::Limit(LimitOrder { side: Side::Buy, .. }) => Some(Direction::Long),
+                        _ => None,
+                    };
+                    if let Some(direction) = entry.filter(|_| self.blackouts.as_ref().is_some_and(|blackouts| blackouts.contains(order.date))) {
+                        portfolio.skip_entry(order.date, direction, SkipReason::Blackout, &order.id, "Blackout window, no new entries.", &config.log_level);
+                        continue;
+                    }
+                    if let Some(direction) = entry {
+                        let session = portfolio.exposure(order.price) + self.others;
+                        let context = RiskContext {
+                            equity: portfolio.total_equity(order.price),
+                            total_equity: session.equity,
+                            long_exposure: session.long,
+                            short_exposure: session.short,
+                        };
+                        let mut vetoed = false;
+                        for manager in self.risk_managers.iter_mut() {
+                            let decision = manager.review(&order, &context);
+                            match &decision {
+                                RiskDecision::Accept => continue,
+                                RiskDecision::Resize(size) => {
+                                    if let config::LogLevel::All = config.log_level {
+                                        portfolio.log_buffer.push(format!("{}: Order {} resized by {} from {:.2} to {:.2} {}.", order.date, order.id, manager.name(), order.size, size, config.symbol.quote));
+                                        portfolio.flush_log_buffer();
+                                    }
+                                    order.size = *size;
+                                }
+                                RiskDecision::Veto(reason) => {
+                                    let message = format!("Vetoed by {}: {}.", manager.name(), reason);
+                                    portfolio.skip_entry(order.date, direction, SkipReason::RiskLimit, &order.id, &message, &config.log_level);
+                                    vetoed = true;
+                                }
+                            }
+                            self.risk_events.push(RiskEvent {
+                                manager: manager.name().to_string(),
+                                decision,
+                            });
+                            if vetoed {
+                                break;
+                            }
+                        }
+                        if vetoed {
+                            continue;
+                        }
+                    }
+                    let fill = match self.rejections.as_mut() {
+                        Some(model) => model.execute(&order),
+                        None => self.execution.execute(&order),
+                    };
+                    match (fill, entry) {
+                        (Some(fill), _) => {
+                            if fill.price != order.price {
+                                self.requotes += 1;
+                                if let config::LogLevel::All = config.log_level {
+                                    portfolio.log_buffer.push(format!("{}: Order {} requoted from {:.*} to {:.*}.", order.date, order.id, config.price_decimals(), order.price, config.price_decimals(), fill.price));
+                                    portfolio.flush_log_buffer();
+                                }
+                            }
+                            queue.push_back(Event::Fill(fill));
+                        }
+                        (None, Some(direction)) => portfolio.skip_entry(order.date, direction, SkipReason::Rejected, &order.id, "Rejected by the exchange.", &config.log_level),
+                        (None, None) => {}
+                    }
+                }
+                Event::Fill(fill) => {
+                    if let Some(order) = portfolio.on_fill(&fill, &config.log_level) {
+                        self.strategy.on_fill(&order);
+                    }
+                    // the signal may set the exits of the trade it opened
+                    if let Some(trade) = portfolio.open_trade.as_mut().filter(|trade| matches!(fill.action, OrderAction::Enter(_)) && trade.entry_order == fill.order_id) {
+                        let levels = entry_levels.take().filter(|(id, _)| *id == fill.order_id).map(|(_, levels)| levels).unwrap_or_default();
+                        let side = if trade.direction == Direction::Long { 1.0 } else { -1.0 };
+                        let mut ignored = Vec::new();
+                        match levels.stop_loss {
+                            Some(price) if (trade.entry_price - price) * side > 0.0 => trade.stop_loss = Some(config.symbol.round_price(price)),
+                            Some(price) => ignored.push(format!("stop-loss {:.*} on the wrong side of the entry price", config.price_decimals(), price)),
+                            None => {}
+                        }
+                        match levels.take_profit {
+                            Some(price) if (price - trade.entry_price) * side > 0.0 => trade.take_profit = Some(config.symbol.round_price(price)),
+                            Some(price) => ignored.push(format!("take-profit {:.*} on the wrong side of the entry price", config.price_decimals(), price)),
+                            None => {}
+                        }
+                        trade.trailing_stop = match levels.trailing_stop {
+                            Some(config::TrailingStop::None) => None,
+                            Some(config::TrailingStop::Percent(distance) | config::TrailingStop::Atr(distance)) if !(distance.is_finite() && distance > 0.0) => {
+                                ignored.push(format!("trailing stop of {}, not positive", distance));
+                                config.trailing_stop
+                            }
+                            Some(stop) => Some(stop),
+                            None => config.trailing_stop,
+                        };
+                        trade.trail(trade.entry_price, self.trailing_atr.value(), &config.symbol);
+                        if let (config::LogLevel::All, false) = (&config.log_level, ignored.is_empty()) {
+                            let line = format!("{}: Ignored exit levels of trade {} entered at {:.*}: {}.", fill.date, trade.id, config.price_decimals(), trade.entry_price, ignored.join(", "));
+                            portfolio.log_buffer.push(line);
+                            portfolio.flush_log_buffer();
+                        }
+                    }
+                    // the other side is entered once the exit freed its cash
+                    if let OrderAction::Exit(ExitReason::Signal) = fill.action {
+                        if let Some(signal) = reversal.take() {
+                            queue.push_back(Event::Signal(signal));
+                        }
+                    }
+                }
+            }
+        }
+        // the orders of this candle only rest from the next one, a fill cannot trigger the order it makes room for
+        portfolio.limit_orders = self.strategy.limit_orders();
+        // the stop checked on the next candle follows this one
+        self.trailing_atr.update(kline);
+        portfolio.trail_stop(kline, self.trailing_atr.value(), matches!(self.signal_fill, config::SignalFill::NextOpen));
+        if has_signal {
+            portfolio.update(kline.timestamp, kline.close);
+        }
+        has_signal
+    }
+
+    /// Applies the end-of-data policy and computes the metrics.
+    fn finish(mut self, last: Option<&historical::Kline>) -> SessionRecap {
+        let config = self.config;
+        let portfolio = &mut self.portfolio;
+        let mut excluded_trades = 0;
+        if let (Some(last), false) = (last, portfolio.is_flat()) {
+            match self.end_of_data {
+                config::EndOfData::ForceClose => {
+                    portfolio.close_all(last.timestamp, last.close, ExitReason::EndOfData, None, &config.log_level);
+                }
+                config::EndOfData::MarkToMarket => {
+                    portfolio.mark_to_market(last.timestamp, last.close, &config.log_level);
+                }
+                config::EndOfData::Exclude => {
+                    excluded_trades = portfolio.open_trade.iter().count() + portfolio.lots.len();
+                    portfolio.close_all(last.timestamp, last.close, ExitReason::EndOfData, None, &config.log_level);
+                }
+            }
+        }
+        let trade_list = portfolio.closed_trades.clone();
+        let open_trade = portfolio.open_trade.clone();
+        let open_lots: Vec<Trade> = portfolio.lots.values().cloned().collect();
+        let skipped_signals = portfolio.skipped_signals.clone();
+        let equity_curve = portfolio.equity_curve.clone();
+        let counted = &trade_list[..trade_list.len() - excluded_trades];
+        let mut metrics = Metrics::new();
+        metrics.compute(counted, &skipped_signals);
+        metrics.compute_returns(&equity_curve, &portfolio.cash_flows, analysis::annualization(config));
+        let regime_breakdown = match config.regime {
+            Some(_) => regime::breakdown(counted, &equity_curve, &self.regimes, analysis::annualization(config)),
+            None => Vec::new(),
+        };
+        metrics.unrealized_profit = open_trade.iter().chain(open_lots.iter()).filter_map(|trade| trade.profit).sum();
+        metrics.risk_resizes = self.risk_events.iter().filter(|event| matches!(event.decision, RiskDecision::Resize(_))).count();
+        metrics.risk_vetoes = self.risk_events.iter().filter(|event| matches!(event.decision, RiskDecision::Veto(_))).count();
+        metrics.requotes = self.requotes;
+        metrics.compute_percentages(self.capital, &equity_curve);
+        let mut recap = SessionRecap::new(trade_list, open_trade.into_iter().collect(), skipped_signals, equity_curve, metrics);
+        recap.open_lots = open_lots;
+        recap.regime_breakdown = regime_breakdown;
+        recap.regimes = self.regimes;
+        recap.risk_events = self.risk_events;
+        recap.indicators = self.indicators;
+        recap.balances = portfolio.balances.clone();
+        recap.cash_flows = portfolio.cash_flows.clone();
+        recap
+    }
+}
+
+/// Result of one calendar month of a monthly restart, see `monthly_restart`.
+#[derive(Debug, Serialize)]
+pub struct MonthResult {
+    /// Like `2023-01`.
+    pub month: String,
+    pub profit: f64,
+    /// Profit in percentage of `base_funds`.
+    pub return_pct: f64,
+    /// Trades closed in the month, those closed at its end included.
+    pub trades: usize,
+    /// Largest fall of the equity from its peak within the month, at the closes, in percentage.
+    pub max_drawdown: f64,
+}
+
+/// The run cut in calendar months, each one starting flat with `base_funds`, the way prop firms evaluate traders.
+#[derive(Debug, Serialize)]
+pub struct MonthlyRestart {
+    pub months: Vec<MonthResult>,
+    pub mean_return: f64,
+    pub median_return: f64,
+    /// Sample standard deviation of the monthly returns.
+    pub deviation: f64,
+    pub best_return: f64,
+    pub worst_return: f64,
+    /// Share of the months with a profit, in percentage.
+    pub positive_share: f64,
+    pub worst_drawdown: f64,
+}
+
+/// Runs the strategy again, quietly, closing every position on the last candle of each calendar month and resetting
+/// the cash to `base_funds` for the next one. The strategy is not restarted, so its indicators stay warm across months.
+pub fn monthly_restart(config: &config::Config, klines: &[historical::Kline]) -> Result<MonthlyRestart, SimulationError> {
+    let mut quiet = config.clone();
+    quiet.log_level = config::LogLevel::None;
+    quiet.dashboard = false;
+    quiet.record_indicators = false;
+    quiet.indicators_file = None;
+    quiet.checkpoint_file = None;
+    quiet.resume_from = None;
+    quiet.debug = None;
+    quiet.metric_snapshots = None;
+    quiet.initial_portfolio = None;
+    quiet.cash_flows = Vec::new();
+    let strategy: Box<dyn Strategy> = match &quiet.strategy {
+        Some(strategy) => strategy::try_build_strategy(strategy, &load_strategy_plugins(&quiet)).map_err(SimulationError::Strategy)?,
+        None => Box::new(SimpleStrategy::new(14)),
+    };
+    let options = EngineOptions::from_config(&quiet);
+    let mut engine = Engine::new(&quiet, &options, strategy, quiet.base_funds)?;
+    engine.outages = outage_windows(&quiet, klines).map_err(SimulationError::InvalidConfig)?;
+    let mut months = Vec::new();
+    let (mut peak, mut max_drawdown, mut closed_before) = (quiet.base_funds, 0.0f64, 0);
+    for (index, kline) in klines.iter().enumerate() {
+        engine.step(kline);
+        let portfolio = &mut engine.portfolio;
+        let equity = portfolio.total_equity(kline.close);
+        peak = peak.max(equity);
+        max_drawdown = max_drawdown.max(if peak > 0.0 { (peak - equity) / peak * 100.0 } else { 0.0 });
+        if klines.get(index + 1).is_some_and(|next| (next.timestamp.year(), next.timestamp.month()) == (kline.timestamp.year(), kline.timestamp.month())) {
+            continue;
+        }
+        portfolio.close_all(kline.timestamp, kline.close, ExitReason::EndOfData, None, &quiet.log_level);
+        let profit = portfolio.total_equity(kline.close) - quiet.base_funds;
+        months.push(MonthResult {
+            month: kline.timestamp.format("%Y-%m").to_string(),
+            profit,
+            return_pct: profit / quiet.base_funds * 100.0,
+            trades: portfolio.closed_trades.len() - closed_before,
+            max_drawdown,
+        });
+        closed_before = portfolio.closed_trades.len();
+        engine.restart(quiet.base_funds);
+        (peak, max_drawdown) = (quiet.base_funds, 0.0);
+    }
+    let mut returns: Vec<f64> = months.iter().map(|month| month.return_pct).collect();
+    returns.sort_by(|a, b| a.total_cmp(b));
+    let mean_return = analysis::mean(&returns);
+    Ok(MonthlyRestart {
+        mean_return,
+        median_return: if returns.is_empty() { 0.0 } else { montecarlo::percentile(&returns, 50.0) },
+        deviation: if returns.len() > 1 { (returns.iter().map(|value| (value - mean_return).powi(2)).sum::<f64>() / (returns.len() - 1) as f64).sqrt() } else { 0.0 },
+        best_return: returns.last().copied().unwrap_or(0.0),
+        worst_return: returns.first().copied().unwrap_or(0.0),
+        positive_share: if returns.is_empty() { 0.0 } else { returns.iter().filter(|value| **value > 0.0).count() as f64 / returns.len() as f64 * 100.0 },
+        worst_drawdown: months.iter().map(|month| month.max_drawdown).fold(0.0, f64::max),
+        months,
+    })
+}
+
+/// Every deposit and withdrawal of `cash_flows` until the last candle, in order.
+fn cash_flow_schedule(config: &config::Config, klines: &[historical::Kline]) -> Result<VecDeque<(NaiveDateTime, f64)>, String> {
+    let Some(last) = klines.last() else {
+        return Ok(VecDeque::new());
+    };
+    let mut schedule = Vec::new();
+    for (index, flow) in config.cash_flows.iter().enumerate() {
+        let parse = |date: &str| config::parse_date(date).map_err(|e| format!("cash_flows.{}: {}", index, e));
+        let first = parse(&flow.date)?;
+        let until = flow.until.as_deref().map(parse).transpose()?.unwrap_or(last.timestamp).min(last.timestamp);
+        let mut count = 0;
+        loop {
+            // counted from the first date, so a monthly transfer on the 31st comes back on the 31st after a short month
+            let date = match flow.every {
+                None if count > 0 => break,
+                None => Some(first),
+                Some(config::Recurrence::Daily) => first.checked_add_days(chrono::Days::new(count)),
+                Some(config::Recurrence::Weekly) => first.checked_add_days(chrono::Days::new(7 * count)),
+                Some(config::Recurrence::Monthly) => first.checked_add_months(chrono::Months::new(count as u32)),
+            };
+            match date {
+                Some(date) if date <= until => schedule.push((date, flow.amount)),
+                _ => break,
+            }
+            count += 1;
+        }
+    }
+    schedule.sort_by_key(|(date, _)| *date);
+    Ok(schedule.into())
+}
+
+fn outage_windows(config: &config::Config, klines: &[historical::Kline]) -> Result<Vec<(NaiveDateTime, NaiveDateTime)>, String> {
+    match &config.outages {
+        Some(outages) => outage::windows(outages, klines),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Calls `on_trade` with the trades closed since `reported`, and returns the new number of reported trades.
+fn report_trades(trades: &[Trade], reported: usize, on_trade: &mut dyn FnMut(&Trade)) -> usize {
+    for trade in trades[reported..].iter() {
+        on_trade(trade);
+    }
+    trades.len()
+}
+
+/// Runs the strategy of the config, the default one when it has none, with the engine set up by `options`. `on_trade`
+/// is called with every trade as soon as it is closed.
+pub fn run_simulation(config: &config::Config, options: &EngineOptions, klines: &[historical::Kline], on_trade: &mut dyn FnMut(&Trade)) -> Result<SessionRecap, SimulationError> {
+    let factories = load_strategy_plugins(config);
+    let strategy: Box<dyn Strategy> = match &config.strategy {
+        Some(strategy) => strategy::try_build_strategy(strategy, &factories).map_err(SimulationError::Strategy)?,
+        None => Box::new(SimpleStrategy::new(14)),
+    };
+    let mut engine = Engine::new(config, options, strategy, config.base_funds)?;
+    engine.outages = outage_windows(config, klines).map_err(SimulationError::InvalidConfig)?;
+    engine.cash_flows = cash_flow_schedule(config, klines).map_err(SimulationError::InvalidConfig)?;
+    let mut start = 0;
+    let mut bars_before = 0;
+    let mut resumed_at = None;
+    if let Some(path) = &config.resume_from {
+        let saved = checkpoint::load_checkpoint(path).map_err(|e| SimulationError::Checkpoint(format!("error while loading checkpoint: {}", e)))?;
+        // bars are matched by timestamp, so the data file may be the full history with new bars appended or only the new bars
+        start = klines.iter().position(|kline| kline.timestamp > saved.last_timestamp).unwrap_or(klines.len());
+        bars_before = saved.bars_processed;
+        resumed_at = Some(saved.last_timestamp);
+        engine.portfolio.restore(saved.portfolio);
+        engine.strategy.load_state(saved.strategy);
+        engine.pending_signal = saved.pending_signal;
+        if !saved.trailing_atr.is_null() {
+            engine.trailing_atr = serde_json::from_value(saved.trailing_atr).map_err(|e| SimulationError::Checkpoint(format!("error while parsing trailing ATR: {}", e)))?;
+        }
+        engine.sizer.load_state(saved.sizer).map_err(|e| SimulationError::Checkpoint(format!("error while parsing position sizer: {}", e)))?;
+        engine.cash_flows.retain(|(date, _)| *date > saved.last_timestamp);
+        if let (Some(classifier), false) = (engine.regime.as_mut(), saved.regime.is_null()) {
+            *classifier = serde_json::from_value(saved.regime).map_err(|e| SimulationError::Checkpoint(format!("error while parsing regime classifier: {}", e)))?;
+            engine.regimes = saved.regimes;
+        }
+        if let (Some(model), false) = (engine.rejections.as_mut(), saved.rejections.is_null()) {
+            *model = serde_json::from_value(saved.rejections).map_err(|e| SimulationError::Checkpoint(format!("error while parsing rejection model: {}", e)))?;
+            engine.requotes = saved.requotes;
+        }
+        // checkpoints saved before the risk managers had a state hold none
+        if !saved.risk_managers.is_empty() {
+            if saved.risk_managers.len() != engine.risk_managers.len() {
+                return Err(SimulationError::Checkpoint(format!("the checkpoint has {} risk managers, the config {}", saved.risk_managers.len(), engine.risk_managers.len())));
+            }
+            for (manager, state) in engine.risk_managers.iter_mut().zip(saved.risk_managers) {
+                manager.load_state(state).map_err(|e| SimulationError::Checkpoint(format!("error while parsing the state of {}: {}", manager.name(), e)))?;
+            }
+        }
+        match (&config.log_level, config.headless) {
+            (config::LogLevel::None, _) | (_, true) => {}
+            _ => {
+                println!("Resuming from checkpoint at {} with {} new bars", saved.last_timestamp, klines.len() - start);
+            }
+        }
+    }
+    // a checkpoint holds the positions of the run it was taken from
+    if let (None, Some(first)) = (resumed_at, klines.first()) {
+        engine.seed(first);
+    }
+    let mut debugger = config.debug.as_ref().map(|debug| Debugger::new(debug, engine.portfolio.entries())).transpose().map_err(SimulationError::InvalidConfig)?;
+    let mut snapshots = Vec::new();
+    let mut reported = engine.portfolio.closed_trades.len();
+    let total_bars = bars_before + klines.len() - start;
+    let publish_every = (klines.len() - start).div_ceil(LIVE_CURVE_POINTS).max(1);
+    for (index, kline) in klines.iter().enumerate().skip(start) {
+        let bars_processed = bars_before + index - start + 1;
+        let has_signal = engine.step(kline);
+        reported = report_trades(&engine.portfolio.closed_trades, reported, on_trade);
+        if let Some(debugger) = debugger.as_mut() {
+            engine.debug(debugger, kline);
+        }
+        if config.dashboard && (index - start + 1).is_multiple_of(publish_every) {
+            let portfolio = &engine.portfolio;
+            let open_trades = portfolio.open_trade.iter().chain(portfolio.lots.values()).cloned().collect();
+            publish_status(kline.timestamp, bars_processed, total_bars, portfolio.total_equity(kline.close), portfolio.cash(), &portfolio.equity_curve, open_trades, &portfolio.closed_trades, false);
+        }
+        if let Some(rule) = &config.metric_snapshots {
+            if snapshot_due(rule, bars_processed, kline, klines.get(index + 1)) {
+                let snapshot = engine.snapshot(kline, bars_processed);
+                #[cfg(feature = "files")]
+                if let Some(file) = &rule.file {
+                    crate::logging::append_snapshot(file, &snapshot).map_err(|e| SimulationError::InvalidConfig(format!("error while writing {}: {}", file, e)))?;
+                }
+                snapshots.push(snapshot);
+            }
+        }
+        if !has_signal {
+            continue;
+        }
+        if let (Some(path), Some(interval)) = (&config.checkpoint_file, config.checkpoint_interval) {
+            if bars_processed % interval == 0 {
+                save_checkpoint(path, bars_processed, kline.timestamp, &engine)?;
+            }
+        }
+    }
+    // the checkpoint is taken before the end-of-data policy so the run can be resumed as if the data never ended
+    if let (Some(path), Some(last)) = (&config.checkpoint_file, klines.last()) {
+        if start < klines.len() {
+            save_checkpoint(path, bars_before + klines.len() - start, last.timestamp, &engine)?;
+        }
+    }
+    let mut recap = engine.finish(klines.last());
+    report_trades(&recap.trades, reported, on_trade);
+    recap.fingerprints = strategy_fingerprints(config, &factories);
+    recap.resumed_at = resumed_at;
+    recap.snapshots = snapshots;
+    if let (true, Some(last)) = (config.dashboard, klines.last()) {
+        let equity = recap.equity_curve.last().map(|(_, equity)| *equity).unwrap_or(config.base_funds);
+        let open_trades = recap.open_trades.iter().chain(recap.open_lots.iter()).cloned().collect();
+        publish_status(last.timestamp, total_bars, total_bars, equity, recap.balances.get(&config.symbol.quote).copied().unwrap_or(0.0), &recap.equity_curve, open_trades, &recap.trades, true);
+    }
+    Ok(recap)
+}
+
+/// Whether a snapshot is taken after `kline`: every `bars` candles, and on the last candle of each period of `every`.
+/// The last candle of the data is left to the final metrics.
+fn snapshot_due(rule: &config::MetricSnapshots, bars_processed: usize, kline: &historical::Kline, next: Option<&historical::Kline>) -> bool {
+    let period = |date: NaiveDateTime, every: config::Recurrence| match every {
+        config::Recurrence::Daily => (date.year(), date.ordinal()),
+        config::Recurrence::Weekly => (date.iso_week().year(), date.iso_week().week()),
+        config::Recurrence::Monthly => (date.year(), date.month()),
+    };
+    let Some(next) = next else {
+        return false;
+    };
+    rule.bars.is_some_and(|bars| bars_processed.is_multiple_of(bars)) || rule.every.is_some_and(|every| period(kline.timestamp, every) != period(next.timestamp, every))
+}
+
+/// Moves the cash of the strategies without an open position between them, in proportion to their
+/// configured weight scaled by their return over the last `lookback` candles.
+fn reallocate(engines: &mut [Engine], weights: &[f64], lookback: usize, date: NaiveDateTime) {
+    let flat: Vec<usize> = (0..engines.len()).filter(|&i| engines[i].portfolio.is_flat()).collect();
+    if flat.len() < 2 {
+        return;
+    }
+    let scores: Vec<f64> = flat
+        .iter()
+        .map(|&i| {
+            let portfolio = &engines[i].portfolio;
+            let curve = &portfolio.equity_curve;
+            let trailing_return = match (curve.len().checked_sub(lookback + 1), curve.last()) {
+                (Some(start), Some((_, last))) if curve[start].1 > 0.0 => {
+                    // the cash moved by the previous reallocations is not a return of the strategy
+                    let (start_date, start_equity) = curve[start];
+                    let moved: f64 = portfolio.cash_flows.iter().filter(|(flow_date, _)| *flow_date >= start_date).map(|(_, amount)| amount).sum();
+                    (last - moved) / start_equity - 1.0
+                }
+                _ => 0.0,
+            };
+            weights[i] * (1.0 + trailing_return).max(0.0)
+        })
+        .collect();
+    let total_score: f64 = scores.iter().sum();
+    let pool: f64 = flat.iter().map(|&i| engines[i].portfolio.cash()).sum();
+    for (&i, score) in flat.iter().zip(scores.iter()) {
+        let cash = if total_score > 0.0 {
+            pool * score / total_score
+        } else {
+            pool * weights[i] / flat.iter().map(|&j| weights[j]).sum::<f64>()
+        };
+        let portfolio = &mut engines[i].portfolio;
+        let moved = cash - portfolio.cash();
+        portfolio.set_cash(cash);
+        portfolio.cash_flows.push((date, moved));
+    }
+}
+
+/// The strategies registered by the program, then the ones of the plugins of `plugins_dir`.
+#[cfg(feature = "plugins")]
+fn load_strategy_plugins(config: &config::Config) -> Vec<Box<dyn StrategyFactory>> {
+    let mut factories = strategy::registered_strategies();
+    if let Some(directory) = &config.plugins_dir {
+        factories.extend(crate::plugin::load_plugins(directory).into_iter().map(|plugin| Box::new(plugin) as Box<dyn StrategyFactory>));
+    }
+    factories
+}
+
+/// Without the `plugins` feature, a config with `plugins_dir` is rejected by `Config::validate`.
+#[cfg(not(feature = "plugins"))]
+fn load_strategy_plugins(_config: &config::Config) -> Vec<Box<dyn StrategyFactory>> {
+    strategy::registered_strategies()
+}
+
+/// The strategies the config runs, the default one when it has none, with the revision of their code as given by the
+/// factories they were built from.
+fn strategy_fingerprints(config: &config::Config, factories: &[Box<dyn StrategyFactory>]) -> Vec<StrategyFingerprint> {
+    let default = config::StrategyConfig {
+        name: "simple".to_string(),
+        params: serde_json::Value::Null,
+    };
+    let strategies: Vec<&config::StrategyConfig> = match (&config.strategy, config.strategies.is_empty()) {
+        (_, false) => config.strategies.iter().map(|allocation| &allocation.strategy).collect(),
+        (Some(strategy), true) => vec![strategy],
+        (None, true) => vec![&default],
+    };
+    strategies
+        .into_iter()
+        .map(|strategy| StrategyFingerprint {
+            strategy: strategy.label(),
+            fingerprint: strategy::fingerprint(&strategy.name, factories),
+        })
+        .collect()
+}
+
+/// Runs every strategy of `config.strategies` on its own share of a single cash pool, and combines the results.
+/// `on_trade` is called with every trade as soon as it is closed, whichever strategy it belongs to.
+pub fn run_strategy_portfolio(config: &config::Config, klines: &[historical::Kline], on_trade: &mut dyn FnMut(&Trade)) -> Result<SessionRecap, SimulationError> {
+    let total_weight: f64 = config.strategies.iter().map(|allocation| allocation.weight).sum();
+    let weights: Vec<f64> = config.strategies.iter().map(|allocation| allocation.weight / total_weight).collect();
+    let plugins = load_strategy_plugins(config);
+    let options = EngineOptions::from_config(config);
+    // the exchange is down for every strategy at once
+    let outages = outage_windows(config, klines).map_err(SimulationError::InvalidConfig)?;
+    let mut engines: Vec<Engine> = Vec::new();
+    for (index, (allocation, weight)) in config.strategies.iter().zip(weights.iter()).enumerate() {
+        let strategy = strategy::try_build_strategy(&allocation.strategy, &plugins).map_err(SimulationError::Strategy)?;
+        let mut engine = Engine::new(config, &options, strategy, config.base_funds * weight)?;
+        engine.portfolio.id_prefix = format!("S{}.", index + 1);
+        engine.outages = outages.clone();
+        // each strategy draws its own rejections
+        engine.rejections = config.rejections.as_ref().map(|rejections| RejectionModel::new(rejections, rejections.seed.wrapping_add(index as u64)));
+        engines.push(engine);
     }
-    let trade_list = portfolio.closed_trades.clone();
-    let equity_curve = portfolio.equity_curve.clone();
+    let mut reported = vec![0; engines.len()];
+    for (index, kline) in klines.iter().enumerate() {
+        for current in 0..engines.len() {
+            // the strategies stepped before on this candle already count with their new positions
+            engines[current].others = engines
+                .iter()
+                .enumerate()
+                .filter(|(other, _)| *other != current)
+                .map(|(_, other)| other.portfolio.exposure(kline.close))
+                .fold(Exposure::default(), |sum, exposure| sum + exposure);
+            engines[current].step(kline);
+            reported[current] = report_trades(&engines[current].portfolio.closed_trades, reported[current], on_trade);
+        }
+        if let Some(rule) = &config.reallocation {
+            if (index + 1) % rule.interval == 0 {
+                reallocate(&mut engines, &weights, rule.lookback, kline.timestamp);
+            }
+        }
+    }
+    let recaps: Vec<SessionRecap> = engines.into_iter().map(|engine| engine.finish(klines.last())).collect();
+    for (recap, reported) in recaps.iter().zip(reported) {
+        report_trades(&recap.trades, reported, on_trade);
+    }
+
+    // the combined equity is the sum of every strategy's last known equity at each timestamp
+    let mut equity_by_date: std::collections::BTreeMap<NaiveDateTime, Vec<Option<f64>>> = std::collections::BTreeMap::new();
+    for (i, recap) in recaps.iter().enumerate() {
+        for (date, equity) in recap.equity_curve.iter() {
+            equity_by_date.entry(*date).or_insert_with(|| vec![None; recaps.len()])[i] = Some(*equity);
+        }
+    }
+    let mut last_equity: Vec<f64> = weights.iter().map(|weight| config.base_funds * weight).collect();
+    let equity_curve: Vec<(NaiveDateTime, f64)> = equity_by_date
+        .into_iter()
+        .map(|(date, values)| {
+            for (last, value) in last_equity.iter_mut().zip(values) {
+                if let Some(value) = value {
+                    *last = value;
+                }
+            }
+            (date, last_equity.iter().sum())
+        })
+        .collect();
+
+    let mut trades: Vec<Trade> = recaps.iter().flat_map(|recap| recap.trades.clone()).collect();
+    trades.sort_by_key(|trade| trade.entry_date);
+    let mut skipped_signals: Vec<SkippedSignal> = recaps.iter().flat_map(|recap| recap.skipped_signals.clone()).collect();
+    skipped_signals.sort_by_key(|signal| signal.date);
+    let counted: Vec<Trade> = trades
+        .iter()
+        .filter(|trade| !matches!((&config.end_of_data, trade.exit_reason), (config::EndOfData::Exclude, Some(ExitReason::EndOfData))))
+        .cloned()
+        .collect();
     let mut metrics = Metrics::new();
-    metrics.compute(&trade_list);
-    SessionRecap::new(trade_list, equity_curve, metrics)
+    metrics.compute(&counted, &skipped_signals);
+    metrics.compute_returns(&equity_curve, &[], analysis::annualization(config));
+    metrics.unrealized_profit = recaps.iter().map(|recap| recap.metrics.unrealized_profit).sum();
+    metrics.risk_resizes = recaps.iter().map(|recap| recap.metrics.risk_resizes).sum();
+    metrics.risk_vetoes = recaps.iter().map(|recap| recap.metrics.risk_vetoes).sum();
+    metrics.requotes = recaps.iter().map(|recap| recap.metrics.requotes).sum();
+    metrics.compute_percentages(config.base_funds, &equity_curve);
+
+    let final_equity: Vec<f64> = recaps.iter().map(|recap| recap.equity_curve.last().map(|(_, equity)| *equity).unwrap_or(0.0)).collect();
+    let total_final_equity: f64 = final_equity.iter().sum();
+    // every strategy classified the same candles
+    let regimes = recaps.first().map(|recap| recap.regimes.clone()).unwrap_or_default();
+    let regime_breakdown = match config.regime {
+        Some(_) => regime::breakdown(&counted, &equity_curve, &regimes, analysis::annualization(config)),
+        None => Vec::new(),
+    };
+    let open_trades = recaps.iter().flat_map(|recap| recap.open_trades.clone()).collect();
+    let mut recap = SessionRecap::new(trades, open_trades, skipped_signals, equity_curve, metrics);
+    recap.open_lots = recaps.iter().flat_map(|recap| recap.open_lots.clone()).collect();
+    recap.regimes = regimes;
+    recap.regime_breakdown = regime_breakdown;
+    recap.risk_events = recaps.iter().flat_map(|recap| recap.risk_events.clone()).collect();
+    recap.fingerprints = strategy_fingerprints(config, &plugins);
+    for (currency, balance) in recaps.iter().flat_map(|recap| recap.balances.iter()) {
+        *recap.balances.entry(currency.clone()).or_default() += balance;
+    }
+    for (allocation, strategy_recap) in config.strategies.iter().zip(recaps.iter()) {
+        for (name, series) in strategy_recap.indicators.iter() {
+            recap.indicators.insert(format!("{}.{}", allocation.strategy.label(), name), series.clone());
+        }
+    }
+    recap.contributions = config
+        .strategies
+        .iter()
+        .zip(weights)
+        .zip(final_equity)
+        .zip(recaps)
+        .map(|(((allocation, initial_weight), final_equity), recap)| StrategyContribution {
+            name: allocation.strategy.label(),
+            initial_weight,
+            final_weight: if total_final_equity > 0.0 { final_equity / total_final_equity } else { 0.0 },
+            recap,
+        })
+        .collect();
+    Ok(recap)
+}
+
+/// Out-of-sample results of the variants of a sweep over contiguous folds of the data.
+pub struct CrossValidation {
+    /// First and last candle of each fold, after purging.
+    pub folds: Vec<(NaiveDateTime, NaiveDateTime)>,
+    /// Objective of each variant on each fold, indexed by `[variant][fold]`.
+    pub scores: Vec<Vec<f64>>,
+    /// For each fold, the variant with the best average objective on the other folds.
+    pub selected: Vec<usize>,
+    /// For each fold, the objective of the variant selected without it: the out-of-sample result of the selection.
+    pub out_of_sample: Vec<f64>,
+}
+
+/// Objective of the sweep when a single parameter moves, the others staying at their best values.
+pub struct Sensitivity {
+    pub parameter: String,
+    /// Values tried, and the objective with each of them.
+    pub response: Vec<(serde_json::Value, f64)>,
+    /// Index of the best value in `response`.
+    pub best: usize,
+    /// First and last index of the values around the best one whose objective is less than 10% of the spread of
+    /// the objective over the whole sweep below it.
+    pub safe: (usize, usize),
+}
+
+impl Sensitivity {
+    /// How much the objective moves with this parameter alone.
+    pub fn swing(&self) -> f64 {
+        let finite = self.response.iter().map(|(_, score)| *score).filter(|score| score.is_finite());
+        let (min, max) = finite.fold((f64::MAX, f64::MIN), |(min, max), score| (min.min(score), max.max(score)));
+        if max >= min { max - min } else { 0.0 }
+    }
+}
+
+/// Result of a parameter sweep, see `config::Sweep`.
+pub struct SweepResult {
+    /// One run per combination of parameters, each with the whole capital.
+    pub variants: Vec<StrategyContribution>,
+    /// Parameters of the best variant according to the objective, on average over the folds with cross-validation.
+    pub best: config::StrategyConfig,
+    /// Probability that the best variant only beats not trading by luck, see `montecarlo::reality_check`.
+    pub reality_check: Option<f64>,
+    pub cross_validation: Option<CrossValidation>,
+    /// One per swept parameter, most influential first.
+    pub sensitivity: Vec<Sensitivity>,
+}
+
+/// Runs a variant on its own from the first candle, with the whole capital.
+fn run_variant(config: &config::Config, plugins: &[Box<dyn StrategyFactory>], variant: &config::StrategyConfig, klines: &[historical::Kline]) -> SessionRecap {
+    let mut engine = Engine::new(config, &EngineOptions::from_config(config), strategy::build_strategy(variant, plugins), config.base_funds).unwrap_or_else(|e| panic!("{}", e));
+    engine.outages = outage_windows(config, klines).unwrap_or_else(|e| panic!("{}", e));
+    engine.cash_flows = cash_flow_schedule(config, klines).unwrap_or_else(|e| panic!("{}", e));
+    if let Some(first) = klines.first() {
+        engine.seed(first);
+    }
+    for kline in klines.iter() {
+        engine.step(kline);
+    }
+    engine.finish(klines.last())
+}
+
+/// Splits the candles in `folds` contiguous folds, dropping `purge` candles at both ends of each fold so indicators
+/// and trades near a boundary do not leak from one fold to the next.
+fn fold_slices(klines: &[historical::Kline], folds: usize, purge: usize) -> Result<Vec<&[historical::Kline]>, SimulationError> {
+    let fold_length = klines.len() / folds.max(1);
+    if fold_length <= 2 * purge {
+        let message = format!("sweep.folds: cannot split {} candles in {} folds of more than {} candles", klines.len(), folds, 2 * purge);
+        return Err(SimulationError::InvalidConfig(message));
+    }
+    Ok((0..folds)
+        .map(|fold| {
+            let end = if fold == folds - 1 { klines.len() } else { (fold + 1) * fold_length };
+            &klines[fold * fold_length + purge..end - purge]
+        })
+        .collect())
+}
+
+/// For each fold, selects the variant that did best on the others, from the objective of every variant on every fold.
+fn cross_validate(slices: &[&[historical::Kline]], scores: Vec<Vec<f64>>) -> CrossValidation {
+    let selected: Vec<usize> = (0..slices.len())
+        .map(|fold| {
+            let train = |index: usize| scores[index].iter().enumerate().filter(|(other, _)| *other != fold).map(|(_, score)| score).sum::<f64>();
+            (1..scores.len()).fold(0, |best, index| if train(index) > train(best) { index } else { best })
+        })
+        .collect();
+    CrossValidation {
+        folds: slices.iter().map(|slice| (slice[0].timestamp, slice[slice.len() - 1].timestamp)).collect(),
+        out_of_sample: selected.iter().enumerate().map(|(fold, variant)| scores[*variant][fold]).collect(),
+        scores,
+        selected,
+    }
+}
+
+/// What the results of the variants depend on, besides their parameters: a progress file or a worker with other
+/// settings cannot be used. The code of the strategy is the revision given by the factory it is built from.
+pub fn sweep_settings(config: &config::Config, sweep: &config::Sweep, klines: &[historical::Kline], factories: &[Box<dyn StrategyFactory>]) -> serde_json::Value {
+    let mut settings = serde_json::json!({
+        "candles": [klines.len(), klines.first().map(|kline| kline.timestamp), klines.last().map(|kline| kline.timestamp), historical::fingerprint(klines)],
+        "strategy_fingerprint": strategy::fingerprint(&sweep.name, factories),
+        "base_funds": config.base_funds,
+        "transaction_fee": config.transaction_fee,
+        "slippage": config.slippage,
+        "min_notional": config.min_notional,
+        "cooldown_bars": config.cooldown_bars,
+        "timeframe": config.timeframe,
+        "end_of_data": config.end_of_data,
+        "stop_loss": config.stop_loss,
+        "take_profit": config.take_profit,
+        "trailing_stop": config.trailing_stop,
+        "trailing_atr_window": config.trailing_atr_window,
+        "intrabar_assumption": config.intrabar_assumption,
+        "risk_managers": config.risk_managers,
+        "sizing": config.sizing,
+        "folds": sweep.folds,
+        "purge": sweep.purge,
+    });
+    // only when set, so the progress files of earlier sweeps stay valid
+    if let Some(rejections) = &config.rejections {
+        settings["rejections"] = serde_json::json!(rejections);
+    }
+    if let Some(outages) = &config.outages {
+        settings["outages"] = serde_json::json!(outages);
+    }
+    if let Some(blackouts) = &config.blackouts {
+        settings["blackouts"] = serde_json::json!(blackouts);
+    }
+    if config.direction_filter != config::DirectionFilter::LongOnly {
+        settings["direction_filter"] = serde_json::json!(config.direction_filter);
+    }
+    if config.accounting != config::Accounting::Cfd {
+        settings["accounting"] = serde_json::json!(config.accounting);
+    }
+    if !config.cash_flows.is_empty() {
+        settings["cash_flows"] = serde_json::json!(config.cash_flows);
+    }
+    if let Some(initial_portfolio) = &config.initial_portfolio {
+        settings["initial_portfolio"] = serde_json::json!(initial_portfolio);
+    }
+    if config.borrow_rate > 0.0 {
+        settings["borrow_rate"] = serde_json::json!(config.borrow_rate);
+    }
+    if let Some(slippage_model) = &config.slippage_model {
+        settings["slippage_model"] = serde_json::json!(slippage_model);
+    }
+    if let Some(fx) = &config.fx {
+        settings["fx"] = serde_json::json!(fx);
+    }
+    if matches!(config.signal_fill, config::SignalFill::NextOpen) {
+        settings["signal_fill"] = serde_json::json!(config.signal_fill);
+    }
+    if config.resample {
+        settings["resample"] = serde_json::json!(config.resample);
+    }
+    if let Some(trading_days_per_year) = config.trading_days_per_year {
+        settings["trading_days_per_year"] = serde_json::json!(trading_days_per_year);
+    }
+    // the rounding of the prices and quantities, the minimum notional being above
+    if config.symbol.price_precision.is_some() || config.symbol.quantity_step.is_some() {
+        settings["symbol"] = serde_json::json!(config.symbol);
+    }
+    settings
+}
+
+/// Runs the variants of a sweep, on this machine or on a worker.
+pub struct SweepRunner<'a> {
+    quiet: config::Config,
+    plugins: Vec<Box<dyn StrategyFactory>>,
+    klines: &'a [historical::Kline],
+    slices: Vec<&'a [historical::Kline]>,
+}
+
+impl<'a> SweepRunner<'a> {
+    pub fn new(config: &config::Config, klines: &'a [historical::Kline], sweep: &config::Sweep) -> Result<Self, SimulationError> {
+        let mut quiet = config.clone();
+        quiet.log_level = config::LogLevel::None;
+        quiet.dashboard = false;
+        quiet.record_indicators = false;
+        quiet.indicators_file = None;
+        quiet.checkpoint_file = None;
+        quiet.resume_from = None;
+        Ok(Self {
+            plugins: load_strategy_plugins(config),
+            quiet,
+            klines,
+            slices: sweep.folds.map(|folds| fold_slices(klines, folds, sweep.purge)).transpose()?.unwrap_or_default(),
+        })
+    }
+
+    /// The settings of `sweep_settings`, with the strategies this runner builds the variants from.
+    pub fn settings(&self, config: &config::Config, sweep: &config::Sweep) -> serde_json::Value {
+        sweep_settings(config, sweep, self.klines, &self.plugins)
+    }
+
+    /// Runs a variant on the whole data, and on each fold with cross-validation.
+    pub fn run(&self, variant: &config::StrategyConfig) -> checkpoint::SweepEntry {
+        let recap = run_variant(&self.quiet, &self.plugins, variant, self.klines);
+        checkpoint::SweepEntry {
+            parameters: variant.clone(),
+            metrics: recap.metrics,
+            equity_curve: recap.equity_curve,
+            fold_metrics: self.slices.iter().map(|slice| run_variant(&self.quiet, &self.plugins, variant, slice).metrics).collect(),
+        }
+    }
+}
+
+/// Runs every combination of the parameters of the sweep, except the ones already in `done`, and returns them in
+/// the order of `Sweep::variants`.
+fn run_grid(
+    config: &config::Config,
+    sweep: &config::Sweep,
+    runner: &SweepRunner,
+    settings: serde_json::Value,
+    mut done: Vec<checkpoint::SweepEntry>,
+    save_progress: &dyn Fn(&checkpoint::SweepEntry),
+) -> Vec<checkpoint::SweepEntry> {
+    let parameters = sweep.variants();
+    if parameters.is_empty() {
+        panic!("sweep has no variant, a parameter has an empty list of values");
+    }
+    let mut entries: Vec<Option<checkpoint::SweepEntry>> = parameters
+        .iter()
+        .map(|variant| done.iter().position(|entry| entry.parameters.label() == variant.label()).map(|index| done.swap_remove(index)))
+        .collect();
+    let pending: Vec<(usize, config::StrategyConfig)> = entries
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| entry.is_none())
+        .map(|(index, _)| (index, parameters[index].clone()))
+        .collect();
+    match config.log_level {
+        config::LogLevel::None => {}
+        _ if pending.len() == parameters.len() => {}
+        _ => println!("Resuming sweep: {} of {} variants already run", parameters.len() - pending.len(), parameters.len()),
+    }
+    let mut save = |index: usize, entry: checkpoint::SweepEntry| {
+        save_progress(&entry);
+        entries[index] = Some(entry);
+    };
+    match &sweep.workers {
+        Some(address) if !pending.is_empty() => {
+            distributed::coordinate(address, sweep, settings, pending, &mut save, config).expect("error while coordinating workers");
+        }
+        _ => {
+            for (index, variant) in pending {
+                save(index, runner.run(&variant));
+            }
+        }
+    }
+    entries.into_iter().map(|entry| entry.expect("every variant is run")).collect()
+}
+
+/// Moves each swept parameter of `best` on its own: through its values for the lists of `params`, and through
+/// `sensitivity_points` evenly spaced values for `ranges`. The variants already in `entries` are not run again.
+fn sensitivity(
+    sweep: &config::Sweep,
+    ranges: &[(String, optimizer::ParameterRange)],
+    best: &config::StrategyConfig,
+    entries: &[checkpoint::SweepEntry],
+    runner: &SweepRunner,
+    score: &dyn Fn(&checkpoint::SweepEntry) -> f64,
+) -> Vec<Sensitivity> {
+    let listed = sweep.params.iter().filter_map(|(name, values)| values.as_array().filter(|values| values.len() > 1).map(|values| (name, values.clone())));
+    let ranged = ranges.iter().map(|(name, range)| {
+        let points = sweep.sensitivity_points.max(2);
+        let mut values: Vec<f64> = (0..points).map(|point| range.min + (range.max - range.min) * point as f64 / (points - 1) as f64).collect();
+        values.extend(best.params.get(name).and_then(|value| value.as_f64()));
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let values: Vec<serde_json::Value> = values.into_iter().map(|value| if range.integer { serde_json::json!(value.round() as i64) } else { serde_json::json!(value) }).collect();
+        (name, values)
+    });
+    let spread = {
+        let scores = entries.iter().map(score).filter(|score| score.is_finite());
+        let (min, max) = scores.fold((f64::MAX, f64::MIN), |(min, max), score| (min.min(score), max.max(score)));
+        if max >= min { max - min } else { 0.0 }
+    };
+    let mut sensitivity: Vec<Sensitivity> = listed
+        .chain(ranged)
+        .map(|(name, mut values)| {
+            values.dedup();
+            let response: Vec<(serde_json::Value, f64)> = values
+                .into_iter()
+                .map(|value| {
+                    let mut parameters = best.clone();
+                    parameters.params[name.as_str()] = value.clone();
+                    let score = match entries.iter().find(|entry| entry.parameters.label() == parameters.label()) {
+                        Some(entry) => score(entry),
+                        None => score(&runner.run(&parameters)),
+                    };
+                    (value, score)
+                })
+                .collect();
+            let best_value = best.params.get(name.as_str());
+            let best = response.iter().position(|(value, _)| Some(value) == best_value).unwrap_or(0);
+            let safe = |index: &usize| response[*index].1 >= response[best].1 - 0.1 * spread;
+            let first = (0..best).rev().take_while(safe).last().unwrap_or(best);
+            let last = (best + 1..response.len()).take_while(safe).last().unwrap_or(best);
+            Sensitivity { parameter: name.clone(), response, best, safe: (first, last) }
+        })
+        .collect();
+    sensitivity.sort_by(|a, b| b.swing().partial_cmp(&a.swing()).unwrap_or(std::cmp::Ordering::Equal));
+    sensitivity
+}
+
+/// Runs the variants of the sweep on their own, without logging, and picks the best one: every combination of the
+/// parameters, or the trials of the optimizer. With a `progress_file`, the variants it lists are not run again (or
+/// inform the optimizer), and the others are added to it as they finish. With `workers`, the variants of a grid are
+/// run by the workers that connect instead of this process.
+pub fn run_sweep(config: &config::Config, klines: &[historical::Kline], sweep: &config::Sweep) -> Result<SweepResult, SimulationError> {
+    let runner = SweepRunner::new(config, klines, sweep)?;
+    let specs = strategy::strategy_parameters(&sweep.name, &runner.plugins);
+    // the ranges of a plugin strategy are only known once it is loaded
+    let ranges = optimizer::resolve_ranges(sweep, specs.as_deref()).map_err(|e| SimulationError::InvalidConfig(format!("sweep.ranges: {}", e)))?;
+    let settings = runner.settings(config, sweep);
+    let done = match &sweep.progress_file {
+        Some(path) => checkpoint::load_sweep_progress(path, &settings).expect("error while loading sweep progress"),
+        None => Vec::new(),
+    };
+    let save_progress = |entry: &checkpoint::SweepEntry| {
+        if let Some(path) = &sweep.progress_file {
+            checkpoint::append_sweep_entry(path, entry).expect("error while saving sweep progress");
+        }
+    };
+    let fold_scores = |entry: &checkpoint::SweepEntry| -> Vec<f64> {
+        entry.fold_metrics.iter().map(|metrics| analysis::objective_value(&config.objective, metrics, config.base_funds)).collect()
+    };
+    // on average over the folds with cross-validation
+    let score = |entry: &checkpoint::SweepEntry| match sweep.folds {
+        Some(_) => analysis::mean(&fold_scores(entry)),
+        None => analysis::objective_value(&config.objective, &entry.metrics, config.base_funds),
+    };
+    let entries = match &sweep.optimizer {
+        None => run_grid(config, sweep, &runner, settings, done, &save_progress),
+        Some(config::Optimizer::Tpe { trials, initial_trials }) => {
+            let mut trial = 0;
+            let entries = optimizer::search(
+                sweep,
+                &ranges,
+                *trials,
+                *initial_trials,
+                done,
+                &mut |parameters| {
+                    let entry = runner.run(&parameters);
+                    save_progress(&entry);
+                    trial += 1;
+                    match config.log_level {
+                        config::LogLevel::None => {}
+                        _ => println!("Trial {}/{}: {} -> {:.2}", trial, trials, entry.parameters.label(), score(&entry)),
+                    }
+                    entry
+                },
+                &score,
+            );
+            match config.log_level {
+                config::LogLevel::None => {}
+                _ if entries.len() == trial => {}
+                _ => println!("The optimizer started from {} variants of the progress file", entries.len() - trial),
+            }
+            entries
+        }
+    };
+    if entries.is_empty() {
+        panic!("the sweep has no variant to pick from");
+    }
+    let scores: Vec<f64> = entries.iter().map(score).collect();
+    // the first variant wins ties
+    let best = (1..entries.len()).fold(0, |best, index| if scores[index] > scores[best] { index } else { best });
+    let cross_validation = sweep.folds.map(|_| cross_validate(&runner.slices, entries.iter().map(fold_scores).collect()));
+    let best_parameters = entries[best].parameters.clone();
+    let sensitivity = sensitivity(sweep, &ranges, &best_parameters, &entries, &runner, &score);
+    let variants: Vec<StrategyContribution> = entries
+        .into_iter()
+        .map(|entry| StrategyContribution {
+            name: entry.parameters.label(),
+            initial_weight: 1.0,
+            final_weight: 1.0,
+            // only the metrics and the equity curve of the variants are kept, so resumed and new ones are alike
+            recap: SessionRecap::new(Vec::new(), Vec::new(), Vec::new(), entry.equity_curve, entry.metrics),
+        })
+        .collect();
+    let returns: Vec<Vec<f64>> = variants.iter().map(|variant| analysis::returns(&variant.recap.equity_curve)).collect();
+    Ok(SweepResult {
+        best: best_parameters,
+        reality_check: montecarlo::reality_check(&returns, sweep.reality_check_samples, sweep.block_length, sweep.seed),
+        cross_validation,
+        sensitivity,
+        variants,
+    })
+}
+
+/// Runs the backtest described by the config, with a fresh log file unless `log_append` is set, aligns the benchmark if there is one
+/// and resamples the trades when `monte_carlo` is set. With a sweep, the best variant is run again as the backtest.
+pub fn run_backtest(config: &config::Config, klines: &[historical::Kline], on_trade: &mut dyn FnMut(&Trade)) -> Result<SessionRecap, SimulationError> {
+    if let Some(sweep) = &config.sweep {
+        let result = run_sweep(config, klines, sweep)?;
+        let mut best = config.clone();
+        best.sweep = None;
+        best.strategy = Some(result.best.clone());
+        let mut recap = run_backtest(&best, klines, on_trade)?;
+        recap.sweep = Some(result);
+        return Ok(recap);
+    }
+    // the series are read first, so a bad file is reported before the run rather than after it
+    let benchmark = config.benchmark.as_ref().map(load_benchmark).transpose().map_err(|e| SimulationError::InvalidConfig(format!("error while reading benchmark: {}", e)))?;
+    let rates = config.reporting_currency.as_ref().map(load_rates).transpose().map_err(|e| SimulationError::InvalidConfig(format!("error while reading exchange rates: {}", e)))?;
+    #[cfg(feature = "files")]
+    crate::logging::start_run(config).expect("Error while removing log file");
+    let mut recap = if config.strategies.is_empty() {
+        run_simulation(config, &EngineOptions::from_config(config), klines, on_trade)?
+    } else {
+        run_strategy_portfolio(config, klines, on_trade)?
+    };
+    if let Some(benchmark) = &benchmark {
+        recap.benchmark_curve = analysis::align_benchmark(&recap.equity_curve, benchmark);
+    }
+    if let Some(rates) = &rates {
+        recap.reporting = Some(reporting::convert(&recap, rates, config));
+    }
+    if let Some(rule) = &config.monte_carlo {
+        recap.monte_carlo = montecarlo::simulate(&recap.trades, config.base_funds, rule.simulations, rule.seed);
+    }
+    if let Some(exit_analysis) = &config.exit_analysis {
+        recap.exit_analysis = Some(crate::exits::analyze(&recap.trades, klines, exit_analysis));
+    }
+    if let Some(forward_returns) = &config.forward_returns {
+        let entries: Vec<Trade> = recap.trades.iter().chain(recap.open_trades.iter()).chain(recap.open_lots.iter()).cloned().collect();
+        recap.forward_returns = analysis::forward_returns(&entries, klines, &forward_returns.horizons);
+    }
+    if let Some(signal_quality) = &config.signal_quality {
+        let entries = |recap: &SessionRecap| -> Vec<Trade> { recap.trades.iter().chain(recap.open_trades.iter()).chain(recap.open_lots.iter()).cloned().collect() };
+        recap.signal_quality = if recap.contributions.is_empty() {
+            let name = config.strategy.as_ref().map(|strategy| strategy.label()).unwrap_or("default".to_string());
+            vec![analysis::signal_quality(name, &entries(&recap), klines, signal_quality)]
+        } else {
+            recap.contributions.iter().map(|contribution| analysis::signal_quality(contribution.name.clone(), &entries(&contribution.recap), klines, signal_quality)).collect()
+        };
+    }
+    if config.monthly_restart {
+        recap.monthly_restart = Some(monthly_restart(config, klines)?);
+    }
+    if config.outages.is_some() {
+        // the same run with the exchange always up, quietly
+        let mut always_up = config.clone();
+        always_up.outages = None;
+        always_up.log_level = config::LogLevel::None;
+        always_up.dashboard = false;
+        always_up.record_indicators = false;
+        always_up.indicators_file = None;
+        always_up.checkpoint_file = None;
+        always_up.resume_from = None;
+        always_up.debug = None;
+        let without = if config.strategies.is_empty() {
+            run_simulation(&always_up, &EngineOptions::from_config(&always_up), klines, &mut |_| {})?
+        } else {
+            run_strategy_portfolio(&always_up, klines, &mut |_| {})?
+        };
+        let windows = outage_windows(config, klines).map_err(SimulationError::InvalidConfig)?;
+        recap.outage_impact = Some(outage::OutageImpact::new(windows, klines, &recap.metrics, without.metrics));
+    }
+    Ok(recap)
+}
+
+#[cfg(feature = "files")]
+fn load_benchmark(benchmark: &config::BenchmarkConfig) -> Result<Vec<(NaiveDateTime, f64)>, String> {
+    historical::read_series(&benchmark.path, &benchmark.timestamp_header, &benchmark.value_header)
+}
+
+/// Without the `files` feature, the benchmark can still be aligned on the results with `analysis::align_benchmark`.
+#[cfg(not(feature = "files"))]
+fn load_benchmark(_benchmark: &config::BenchmarkConfig) -> Result<Vec<(NaiveDateTime, f64)>, String> {
+    Err("the benchmark is read from a file, which needs the `files` feature".to_string())
+}
+
+#[cfg(feature = "files")]
+fn load_rates(reporting_currency: &config::ReportingCurrency) -> Result<Vec<(NaiveDateTime, f64)>, String> {
+    historical::read_series(&reporting_currency.path, &reporting_currency.timestamp_header, &reporting_currency.value_header)
+}
+
+/// Without the `files` feature, the results can still be converted with `reporting::convert`.
+#[cfg(not(feature = "files"))]
+fn load_rates(_reporting_currency: &config::ReportingCurrency) -> Result<Vec<(NaiveDateTime, f64)>, String> {
+    Err("the exchange rates are read from a file, which needs the `files` feature".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, NaiveDate};
+
+    fn hour(hours: i64) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap() + Duration::hours(hours)
+    }
+
+    fn kline(hours: i64, open: f64, high: f64, low: f64, close: f64) -> historical::Kline {
+        historical::Kline {
+            timestamp: hour(hours),
+            open,
+            high,
+            low,
+            close,
+            volume: 1.0,
+        }
+    }
+
+    /// A config with a 0.1% commission, no slippage and the settings of `extra`.
+    fn config(extra: serde_json::Value) -> config::Config {
+        let mut value = serde_json::json!({
+            "data_path": "data.csv",
+            "headers": {},
+            "base_funds": 1000.0,
+            "transaction_fee": 0.1,
+            "slippage": 0.0,
+            "symbol": "BTC/USDT",
+            "timeframe": "1h",
+            "log_level": "None",
+            "log_file": "logs.log",
+            "log_graph": false,
+            "log_graph_file": "graph.png",
+        });
+        for (key, setting) in extra.as_object().unwrap() {
+            value[key] = setting.clone();
+        }
+        config::parse_config(&value.to_string()).unwrap()
+    }
+
+    /// A portfolio holding a long trade of 1000 entered at 100 on the first hour.
+    fn long_position(config: &config::Config) -> Portfolio<'_> {
+        let mut portfolio = Portfolio::new(config.base_funds, config.transaction_fee, config.slippage, config);
+        portfolio.enter_trade(hour(0), 100.0, Direction::Long, 1000.0, "O1", &config::LogLevel::None);
+        portfolio
+    }
+
+    #[test]
+    fn a_round_trip_pays_the_commission_on_both_sides() {
+        let config = config(serde_json::json!({}));
+        let mut portfolio = long_position(&config);
+        // in `Cfd` accounting both commissions are paid when the trade is closed
+        assert_eq!(portfolio.cash(), 0.0);
+        assert_eq!(portfolio.open_trade.as_ref().unwrap().quantity(), 10.0);
+        portfolio.exit_trade(hour(1), 110.0, ExitReason::Signal, Some("O2"), &config::LogLevel::None);
+        let trade = &portfolio.closed_trades[0];
+        assert!((trade.profit.unwrap() - 98.0).abs() < 1e-9);
+        assert!((trade.commission - 2.0).abs() < 1e-9);
+        assert!((portfolio.cash() - 1098.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn a_stop_loss_fills_at_its_level_or_at_the_open_past_it() {
+        let config = config(serde_json::json!({ "stop_loss": 5.0 }));
+        let mut portfolio = long_position(&config);
+        assert_eq!(portfolio.open_trade.as_ref().unwrap().stop_loss, Some(95.0));
+        assert!(portfolio.exit_level_order(&kline(1, 99.0, 101.0, 96.0, 97.0), &config.intrabar_assumption).is_none());
+        let order = portfolio.exit_level_order(&kline(1, 99.0, 101.0, 94.0, 97.0), &config.intrabar_assumption).unwrap();
+        assert_eq!((order.price, order.signal_price), (95.0, 95.0));
+        assert!(matches!(order.action, OrderAction::Exit(ExitReason::StopLoss)));
+        // the candle gapped below the stop
+        let order = portfolio.exit_level_order(&kline(1, 93.0, 94.0, 90.0, 92.0), &config.intrabar_assumption).unwrap();
+        assert_eq!((order.price, order.signal_price), (93.0, 95.0));
+    }
+
+    #[test]
+    fn both_levels_in_one_candle_follow_the_intrabar_assumption() {
+        let candle = kline(1, 100.0, 106.0, 94.0, 100.0);
+        let worst = config(serde_json::json!({ "stop_loss": 5.0, "take_profit": 5.0, "intrabar_assumption": "WorstCase" }));
+        let mut portfolio = long_position(&worst);
+        let order = portfolio.exit_level_order(&candle, &worst.intrabar_assumption).unwrap();
+        assert!(matches!(order.action, OrderAction::Exit(ExitReason::StopLoss)));
+        assert!(portfolio.open_trade.as_ref().unwrap().ambiguous_exit);
+        let best = config(serde_json::json!({ "stop_loss": 5.0, "take_profit": 5.0, "intrabar_assumption": "BestCase" }));
+        let mut portfolio = long_position(&best);
+        let order = portfolio.exit_level_order(&candle, &best.intrabar_assumption).unwrap();
+        assert!(matches!(order.action, OrderAction::Exit(ExitReason::TakeProfit)));
+        assert_eq!(order.price, 105.0);
+    }
+
+    #[test]
+    fn a_trailing_stop_follows_the_best_price_and_never_moves_back() {
+        let config = config(serde_json::json!({ "trailing_stop": { "Percent": 2.0 } }));
+        let mut portfolio = long_position(&config);
+        portfolio.open_trade.as_mut().unwrap().trailing_stop = config.trailing_stop;
+        // the entry candle is skipped
+        portfolio.trail_stop(&kline(0, 100.0, 120.0, 99.0, 100.0), None, false);
+        assert_eq!(portfolio.open_trade.as_ref().unwrap().stop_loss, None);
+        portfolio.trail_stop(&kline(1, 100.0, 110.0, 99.0, 108.0), None, false);
+        portfolio.trail_stop(&kline(2, 108.0, 109.0, 104.0, 105.0), None, false);
+        let trade = portfolio.open_trade.as_ref().unwrap();
+        assert!((trade.stop_loss.unwrap() - 107.8).abs() < 1e-9);
+        assert!(trade.trailed);
+        let order = portfolio.exit_level_order(&kline(3, 108.0, 108.5, 107.0, 107.5), &config.intrabar_assumption).unwrap();
+        assert!(matches!(order.action, OrderAction::Exit(ExitReason::TrailingStop)));
+    }
+
+    #[test]
+    fn an_atr_trailing_stop_waits_for_the_average_true_range() {
+        let config = config(serde_json::json!({}));
+        let mut trade = long_position(&config).open_trade.clone().unwrap();
+        trade.trailing_stop = Some(config::TrailingStop::Atr(3.0));
+        trade.trail(110.0, None, &config.symbol);
+        assert_eq!(trade.stop_loss, None);
+        trade.trail(110.0, Some(2.0), &config.symbol);
+        assert_eq!(trade.stop_loss, Some(104.0));
+        trade.trailing_stop = Some(config::TrailingStop::None);
+        trade.trail(120.0, Some(2.0), &config.symbol);
+        assert_eq!(trade.stop_loss, Some(104.0));
+    }
+
+    #[test]
+    fn a_resumed_run_sizes_with_the_average_true_range_of_the_checkpoint() {
+        // swings whose range grows, so a cold average true range would size the entries after the checkpoint differently
+        let klines: Vec<historical::Kline> = (0..240)
+            .map(|hours| {
+                let close = 100.0 + 10.0 * (hours as f64 / 6.0).sin();
+                let range = 0.5 + hours as f64 / 40.0;
+                kline(hours, close, close + range, close - range, close)
+            })
+            .collect();
+        let path = std::env::temp_dir().join(format!("retroval-sizer-checkpoint-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let config = config(serde_json::json!({
+            "sizing": { "VolatilityTarget": { "risk_fraction": 0.01, "atr_window": 20, "atr_multiplier": 2.0, "fallback_fraction": 0.5 } },
+        }));
+        let options = EngineOptions::from_config(&config);
+        let full = run_simulation(&config, &options, &klines, &mut |_| {}).unwrap();
+        // an entry comes less than `atr_window` candles after the checkpoint
+        assert!(full.trades.iter().any(|trade| hour(140) < trade.entry_date && trade.entry_date < hour(160)));
+        let mut first_part = config.clone();
+        first_part.checkpoint_file = Some(path.to_string());
+        run_simulation(&first_part, &options, &klines[..140], &mut |_| {}).unwrap();
+        let mut resumed = config.clone();
+        resumed.resume_from = Some(path.to_string());
+        let resumed = run_simulation(&resumed, &options, &klines, &mut |_| {}).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(resumed.trades.len(), full.trades.len());
+        for (resumed, full) in resumed.trades.iter().zip(full.trades.iter()) {
+            assert_eq!(resumed.entry_date, full.entry_date);
+            // the checkpoint only keeps the floats to the precision of JSON
+            assert!((resumed.allocated - full.allocated).abs() < 1e-9, "{} allocated {} instead of {}", resumed.id, resumed.allocated, full.allocated);
+        }
+    }
+
+    #[test]
+    fn an_entry_filled_at_the_next_open_is_stopped_on_its_entry_candle() {
+        // flat until the close of hour 15 goes above the average, bought at the open of hour 16 whose low drops past the stop
+        let mut klines: Vec<historical::Kline> = (0..16).map(|hours| kline(hours, 100.0, 100.5, 99.5, 100.0)).collect();
+        klines[15].close = 101.0;
+        klines.push(kline(16, 101.0, 101.5, 90.0, 91.0));
+        klines.push(kline(17, 91.0, 92.0, 90.0, 91.0));
+        let config = config(serde_json::json!({ "signal_fill": "NextOpen", "stop_loss": 5.0 }));
+        let recap = run_simulation(&config, &EngineOptions::from_config(&config), &klines, &mut |_| {}).unwrap();
+        let trade = &recap.trades[0];
+        assert_eq!((trade.entry_date, trade.entry_price), (hour(16), 101.0));
+        assert_eq!(trade.exit_date, Some(hour(16)));
+        assert!((trade.exit_price.unwrap() - 95.95).abs() < 1e-9);
+        assert!(matches!(trade.exit_reason, Some(ExitReason::StopLoss)));
+    }
+
+    #[test]
+    fn a_trade_entered_at_the_open_trails_on_its_entry_candle() {
+        let config = config(serde_json::json!({ "trailing_stop": { "Percent": 2.0 } }));
+        let mut portfolio = long_position(&config);
+        portfolio.open_trade.as_mut().unwrap().trailing_stop = config.trailing_stop;
+        portfolio.trail_stop(&kline(0, 100.0, 110.0, 99.0, 108.0), None, true);
+        assert!((portfolio.open_trade.as_ref().unwrap().stop_loss.unwrap() - 107.8).abs() < 1e-9);
+    }
+
+    #[test]
+    fn folds_too_short_for_the_purge_are_an_error() {
+        let klines: Vec<historical::Kline> = (0..10).map(|hours| kline(hours, 100.0, 101.0, 99.0, 100.0)).collect();
+        let slices = fold_slices(&klines, 3, 1).unwrap();
+        assert_eq!(slices.iter().map(|slice| slice.len()).collect::<Vec<_>>(), vec![1, 1, 2]);
+        assert!(matches!(fold_slices(&klines, 3, 2), Err(SimulationError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn infinite_profit_factor_round_trips_through_json() {
+        let mut metrics = Metrics::new();
+        metrics.profit_factor = f64::INFINITY;
+        let json = serde_json::to_value(&metrics).unwrap();
+        assert_eq!(json["profit_factor"], "inf");
+        let read: Metrics = serde_json::from_value(json).unwrap();
+        assert_eq!(read.profit_factor, f64::INFINITY);
+    }
+
+    #[test]
+    fn null_profit_factor_of_older_files_reads_as_infinite() {
+        let mut json = serde_json::to_value(Metrics::new()).unwrap();
+        json["profit_factor"] = serde_json::Value::Null;
+        let read: Metrics = serde_json::from_value(json).unwrap();
+        assert_eq!(read.profit_factor, f64::INFINITY);
+    }
 }
@@ -0,0 +1,117 @@
+//! The trade book: a single HTML page per closed trade, with its graph, its numbers and the lines of the log naming
+//! it, to be read in a browser or printed to PDF, one trade per sheet.
+
+use std::fmt::Write;
+use std::path::Path;
+use crate::config::Config;
+use crate::locale::Formatter;
+use crate::testing::{SessionRecap, Trade};
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Lines of the log of this run, without the run id prefixed with `log_append`. Empty when there is no log, or when
+/// it is appended to without a run id to tell the runs apart.
+fn read_log(config: &Config) -> Vec<String> {
+    let Ok(text) = std::fs::read_to_string(&config.log_file) else {
+        return Vec::new();
+    };
+    let prefix = match (&config.run_id, config.log_append) {
+        (Some(run_id), true) => format!("[{}] ", run_id),
+        (None, true) => return Vec::new(),
+        _ => String::new(),
+    };
+    text.lines().filter_map(|line| line.strip_prefix(prefix.as_str())).map(str::to_string).collect()
+}
+
+/// Whether `line` names the trade or one of its orders.
+fn mentions(line: &str, trade: &Trade) -> bool {
+    let ids: Vec<&str> = [Some(trade.id.as_str()), Some(trade.entry_order.as_str()), trade.exit_order.as_deref()].into_iter().flatten().collect();
+    line.split(|c: char| !c.is_ascii_alphanumeric() && c != '.').any(|word| ids.contains(&word.trim_end_matches('.')))
+}
+
+/// Path of the graph of the trade drawn with `trade_chart_context`, as the book links it: relative when the book is
+/// next to the graphs, absolute otherwise. `None` when it was not drawn by this run, a graph left by a previous one
+/// showing another trade with the same id.
+fn chart_path(config: &Config, book: &Path, trade: &Trade, charts_drawn: bool) -> Option<String> {
+    let graphs = Path::new(&config.log_graph_file).parent().unwrap_or(Path::new(""));
+    let chart = graphs.join("trades").join(format!("trade_{}.png", trade.id));
+    if !charts_drawn || !chart.exists() {
+        return None;
+    }
+    if book.parent().unwrap_or(Path::new("")) == graphs {
+        return Some(format!("trades/trade_{}.png", trade.id));
+    }
+    chart.canonicalize().ok().map(|path| path.to_string_lossy().into_owned())
+}
+
+/// Writes the book of the closed trades of the recap to `file_path`, with the graphs of the trades when
+/// `charts_drawn` tells they were drawn by this run.
+pub fn write_trade_book(config: &Config, recap: &SessionRecap, file_path: &str, charts_drawn: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let log = read_log(config);
+    let format = Formatter::new(config.locale);
+    let quote = config.symbol.quote.as_str();
+    let trades: Vec<&Trade> = recap.trades.iter().filter(|trade| trade.exit_date.is_some()).collect();
+    let mut html = String::new();
+    writeln!(html, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Trade book of {}</title>", escape(&config.symbol.to_string()))?;
+    writeln!(
+        html,
+        "<style>\nbody {{ font-family: sans-serif; margin: 2em; }}\nsection {{ page-break-after: always; break-after: page; }}\ntable {{ border-collapse: collapse; }}\ntd {{ padding: 0.2em 1em 0.2em 0; }}\nimg {{ max-width: 100%; }}\npre {{ font-size: 0.8em; white-space: pre-wrap; }}\n</style>\n</head>\n<body>"
+    )?;
+    writeln!(html, "<section>\n<h1>Trade book of {} ({})</h1>", escape(&config.symbol.to_string()), escape(&config.timeframe))?;
+    writeln!(
+        html,
+        "<p>{} trades, total profit {}, win rate {}, profit factor {}.</p>",
+        recap.metrics.total_trades,
+        escape(&format.money(recap.metrics.total_profit, quote)),
+        format.percent(recap.metrics.win_rate * 100.0, 2),
+        format.number(recap.metrics.profit_factor, 2)
+    )?;
+    for fingerprint in recap.fingerprints.iter() {
+        writeln!(html, "<p>Strategy {}, {}.</p>", escape(&fingerprint.strategy), escape(&fingerprint.fingerprint))?;
+    }
+    writeln!(html, "</section>")?;
+    for trade in trades {
+        let exit_date = trade.exit_date.unwrap_or(trade.entry_date);
+        let profit = trade.profit.unwrap_or(0.0);
+        writeln!(html, "<section>\n<h2>Trade {} ({:?})</h2>", escape(&trade.id), trade.direction)?;
+        if let Some(chart) = chart_path(config, Path::new(file_path), trade, charts_drawn) {
+            writeln!(html, "<img src=\"{}\" alt=\"Graph of trade {}\">", escape(&chart), escape(&trade.id))?;
+        }
+        let rows = [
+            ("Orders", format!("{} to {}", trade.entry_order, trade.exit_order.as_deref().unwrap_or("end of data"))),
+            ("Entry", format!("{} at {}", trade.entry_date, format.number(trade.entry_price, 2))),
+            ("Exit", format!("{} at {} ({})", exit_date, format.number(trade.exit_price.unwrap_or(0.0), 2), trade.exit_reason.map(|reason| reason.to_string()).unwrap_or_default())),
+            ("Duration", {
+                let seconds = (exit_date - trade.entry_date).num_seconds();
+                format!("{}d {}h", seconds / 86400, seconds % 86400 / 3600)
+            }),
+            ("Allocated", format!("{} ({} {})", format.money(trade.allocated, quote), format.number(trade.quantity().abs(), 6), config.symbol.base)),
+            ("Profit", {
+                let share = profit / trade.allocated * 100.0;
+                format!("{} ({}{})", format.money(profit, quote), if share >= 0.0 { "+" } else { "" }, format.percent(share, 2))
+            }),
+            ("Commission", format.money(trade.commission, quote)),
+            ("Financing", format.money(trade.financing, quote)),
+            ("Stop-loss", trade.stop_loss.map(|price| format.number(price, 2)).unwrap_or("none".to_string())),
+            ("Take-profit", trade.take_profit.map(|price| format.number(price, 2)).unwrap_or("none".to_string())),
+        ];
+        writeln!(html, "<table>")?;
+        for (name, value) in rows.iter() {
+            writeln!(html, "<tr><td>{}</td><td>{}</td></tr>", name, escape(value))?;
+        }
+        if trade.ambiguous_exit {
+            writeln!(html, "<tr><td>Ambiguous exit</td><td>stop-loss and take-profit both inside the exit candle</td></tr>")?;
+        }
+        writeln!(html, "</table>")?;
+        let excerpt: Vec<String> = log.iter().filter(|line| mentions(line, trade)).map(|line| escape(line)).collect();
+        if !excerpt.is_empty() {
+            writeln!(html, "<h3>Log</h3>\n<pre>{}</pre>", excerpt.join("\n"))?;
+        }
+        writeln!(html, "</section>")?;
+    }
+    writeln!(html, "</body>\n</html>")?;
+    std::fs::write(file_path, html)?;
+    Ok(())
+}